@@ -3,12 +3,57 @@ use crate::WsSink; // Import WsSink from main/lib
 use anyhow::Result;
 use base64::{engine::general_purpose::STANDARD, Engine as _};
 use common::Payload;
-use log::{error, info};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::io::AsyncReadExt;
 use tokio::process::{Child, Command};
 use tokio::sync::Mutex;
 
+// 許可リストの使用時に拒否するシェルの制御文字 (許可されていないコマンドの連結・置換を防ぐ)
+const SHELL_METACHARACTERS: &[char] = &[
+    ';', '&', '|', '$', '`', '<', '>', '(', ')', '{', '}', '\\', '\n', '\r',
+];
+
+// サーバーからのコマンド実行の制限 (設定ファイルの [command])
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct CommandPolicy {
+    // コマンドの実行を許可する
+    pub enabled: bool,
+    // 実行を許可するコマンド名 (空の場合はすべて許可)
+    pub allowed_commands: Vec<String>,
+}
+
+impl Default for CommandPolicy {
+    fn default() -> Self {
+        CommandPolicy {
+            enabled: true,
+            allowed_commands: Vec::new(),
+        }
+    }
+}
+
+impl CommandPolicy {
+    // コマンドの実行が許可されているか確認する (拒否する場合は理由を返す)
+    pub fn check(&self, command: &str) -> Result<(), String> {
+        if !self.enabled {
+            return Err("Command execution is disabled on this agent".to_string());
+        }
+        if self.allowed_commands.is_empty() {
+            return Ok(());
+        }
+        if command.contains(SHELL_METACHARACTERS) {
+            return Err("Shell control characters are not allowed".to_string());
+        }
+        let program = command.split_whitespace().next().unwrap_or_default();
+        if !self.allowed_commands.iter().any(|c| c == program) {
+            return Err(format!("Command {} is not allowed on this agent", program));
+        }
+        Ok(())
+    }
+}
+
 // Helper to send a command chunk over WebSocket
 async fn send_command_chunk(
     sink: Arc<Mutex<WsSink>>,
@@ -50,9 +95,17 @@ pub(crate) async fn handle_command_request(
     request_id: String,
     command: String,
     sink: Arc<Mutex<WsSink>>,
+    policy: Arc<CommandPolicy>,
 ) -> Result<()> {
     info!("[{}] Received command request: {}", request_id, command);
 
+    // 設定されたコマンド実行の制限を確認
+    if let Err(reason) = policy.check(&command) {
+        warn!("[{}] Rejected command request: {}", request_id, reason);
+        send_command_complete(sink.clone(), &request_id, false, None, Some(reason)).await?;
+        return Ok(());
+    }
+
     // OSに応じてコマンド実行方法を決定
     #[cfg(target_os = "windows")]
     // Windowsの場合: cmd /C を使用
@@ -201,3 +254,26 @@ pub(crate) async fn handle_command_request(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_policy() {
+        assert!(CommandPolicy::default().check("uptime; reboot").is_ok());
+        let disabled = CommandPolicy {
+            enabled: false,
+            ..Default::default()
+        };
+        assert!(disabled.check("uptime").is_err());
+        let allowlist = CommandPolicy {
+            allowed_commands: vec!["uptime".to_string(), "df".to_string()],
+            ..Default::default()
+        };
+        assert!(allowlist.check("df -h").is_ok());
+        assert!(allowlist.check("rm -rf /").is_err());
+        assert!(allowlist.check("uptime; reboot").is_err());
+        assert!(allowlist.check("df $(reboot)").is_err());
+    }
+}
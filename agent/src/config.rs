@@ -0,0 +1,299 @@
+use crate::command::CommandPolicy;
+use crate::geo::GeoSettings;
+use crate::load::Capacity;
+use crate::tcp::EgressPolicy;
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use url::Url;
+
+// --config を指定しない場合に読み込む設定ファイル (存在する場合のみ)
+const DEFAULT_CONFIG_PATH: &str = "chilsonite-agent.toml";
+// 接続先マスターサーバーのデフォルトURL
+const DEFAULT_SERVER_URL: &str = "ws://127.0.0.1:3005";
+// --print-config で秘密の値の代わりに表示する文字列
+const REDACTED: &str = "********";
+
+// 設定を上書きする環境変数 (地理情報・容量は geo / load モジュールを参照)
+const SERVER_URL_ENV: &str = "CHILSONITE_SERVER_URL";
+const ENROLLMENT_KEY_ENV: &str = "CHILSONITE_ENROLLMENT_KEY";
+const AGENT_ID_ENV: &str = "CHILSONITE_AGENT_ID";
+const LOG_LEVEL_ENV: &str = "CHILSONITE_LOG_LEVEL";
+const ALLOW_PRIVATE_NETWORKS_ENV: &str = "CHILSONITE_EGRESS_ALLOW_PRIVATE_NETWORKS";
+const ALLOWED_PORTS_ENV: &str = "CHILSONITE_EGRESS_ALLOWED_PORTS";
+const DENIED_PORTS_ENV: &str = "CHILSONITE_EGRESS_DENIED_PORTS";
+const DENIED_HOSTS_ENV: &str = "CHILSONITE_EGRESS_DENIED_HOSTS";
+const COMMAND_ENABLED_ENV: &str = "CHILSONITE_COMMAND_ENABLED";
+const ALLOWED_COMMANDS_ENV: &str = "CHILSONITE_COMMAND_ALLOWED_COMMANDS";
+
+// コマンドライン引数 (設定ファイル・環境変数より優先する)
+#[derive(Parser, Debug)]
+#[command(version, about = "Chilsonite proxy agent")]
+pub(crate) struct Cli {
+    /// CServer WebSocket URL (overrides server_url)
+    pub server_url: Option<String>,
+    /// TOML config file (default: chilsonite-agent.toml if it exists)
+    #[arg(short, long, env = "CHILSONITE_AGENT_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Agent ID to use instead of the one derived from the machine ID
+    #[arg(long)]
+    pub agent_id: Option<String>,
+    /// Enrollment key required by the CServer (prefer the config file or CHILSONITE_ENROLLMENT_KEY)
+    #[arg(long)]
+    pub enrollment_key: Option<String>,
+    /// Log filter such as "info" or "info,agent::tcp=debug" (RUST_LOG takes precedence)
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// Print the effective configuration and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+// エージェントの設定 (既定値 < 設定ファイル < 環境変数 < コマンドライン引数 の順に上書き)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct AgentConfig {
+    // 接続先マスターサーバーのWebSocket URL
+    pub server_url: String,
+    // マスターで agent_enrollment_key が設定されている場合に必要な登録キー
+    pub enrollment_key: Option<String>,
+    // マシン固有IDから生成するエージェントIDの代わりに使用するID ("agent_" で始まる)
+    pub agent_id: Option<String>,
+    // ログの出力レベル (env_logger のフィルタ形式)
+    pub log_level: String,
+    pub geo: GeoSettings,
+    pub capacity: Capacity,
+    pub egress: EgressPolicy,
+    pub command: CommandPolicy,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        AgentConfig {
+            server_url: DEFAULT_SERVER_URL.to_string(),
+            enrollment_key: None,
+            agent_id: None,
+            log_level: "info".to_string(),
+            geo: GeoSettings::default(),
+            capacity: Capacity::default(),
+            egress: EgressPolicy::default(),
+            command: CommandPolicy::default(),
+        }
+    }
+}
+
+impl AgentConfig {
+    // 設定ファイル・環境変数・コマンドライン引数から設定を読み込む
+    pub fn load(cli: &Cli) -> Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => AgentConfig::default(),
+        };
+        config.apply_env()?;
+        config.apply_cli(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&text)
+            .map_err(|e| anyhow!("Failed to parse config file {}: {}", path.display(), e))
+    }
+
+    // 環境変数で設定を上書きする (未設定・空の環境変数は無視)
+    fn apply_env(&mut self) -> Result<()> {
+        if let Some(url) = env(SERVER_URL_ENV) {
+            self.server_url = url;
+        }
+        if let Some(key) = env(ENROLLMENT_KEY_ENV) {
+            self.enrollment_key = Some(key);
+        }
+        if let Some(agent_id) = env(AGENT_ID_ENV) {
+            self.agent_id = Some(agent_id);
+        }
+        if let Some(level) = env(LOG_LEVEL_ENV) {
+            self.log_level = level;
+        }
+        if let Some(allow) = env(ALLOW_PRIVATE_NETWORKS_ENV) {
+            self.egress.allow_private_networks = parse_bool(ALLOW_PRIVATE_NETWORKS_ENV, &allow)?;
+        }
+        if let Some(ports) = env(ALLOWED_PORTS_ENV) {
+            self.egress.allowed_ports = parse_ports(ALLOWED_PORTS_ENV, &ports)?;
+        }
+        if let Some(ports) = env(DENIED_PORTS_ENV) {
+            self.egress.denied_ports = parse_ports(DENIED_PORTS_ENV, &ports)?;
+        }
+        if let Some(hosts) = env(DENIED_HOSTS_ENV) {
+            self.egress.denied_hosts = split_list(&hosts);
+        }
+        if let Some(enabled) = env(COMMAND_ENABLED_ENV) {
+            self.command.enabled = parse_bool(COMMAND_ENABLED_ENV, &enabled)?;
+        }
+        if let Some(commands) = env(ALLOWED_COMMANDS_ENV) {
+            self.command.allowed_commands = split_list(&commands);
+        }
+        self.geo.apply_env()?;
+        self.capacity.apply_env()?;
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(url) = &cli.server_url {
+            self.server_url = url.clone();
+        }
+        if let Some(agent_id) = &cli.agent_id {
+            self.agent_id = Some(agent_id.clone());
+        }
+        if let Some(key) = &cli.enrollment_key {
+            self.enrollment_key = Some(key.clone());
+        }
+        if let Some(level) = &cli.log_level {
+            self.log_level = level.clone();
+        }
+    }
+
+    // 設定値の検証
+    pub fn validate(&self) -> Result<()> {
+        let url = Url::parse(&self.server_url)
+            .map_err(|e| anyhow!("Invalid server_url {}: {}", self.server_url, e))?;
+        if !matches!(url.scheme(), "ws" | "wss") {
+            return Err(anyhow!("server_url must start with ws:// or wss://"));
+        }
+        if self.enrollment_key.as_deref().is_some_and(|k| k.is_empty()) {
+            return Err(anyhow!("enrollment_key must not be empty"));
+        }
+        if let Some(agent_id) = &self.agent_id {
+            let valid = agent_id.len() > "agent_".len()
+                && agent_id.starts_with("agent_")
+                && agent_id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !valid {
+                return Err(anyhow!(
+                    "agent_id must start with 'agent_' and contain only letters, digits, '_' and '-': {}",
+                    agent_id
+                ));
+            }
+        }
+        self.geo.validate()?;
+        self.capacity.validate()?;
+        Ok(())
+    }
+
+    // --print-config の出力 (秘密の値は伏せる)
+    pub fn to_redacted_toml(&self) -> Result<String> {
+        let mut config = self.clone();
+        if config.enrollment_key.is_some() {
+            config.enrollment_key = Some(REDACTED.to_string());
+        }
+        if config.geo.ipinfo_token.is_some() {
+            config.geo.ipinfo_token = Some(REDACTED.to_string());
+        }
+        Ok(toml::to_string_pretty(&config)?)
+    }
+}
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("{} must be true or false", name))
+}
+
+// カンマ区切りの一覧 (空の要素は無視)
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_ports(name: &str, value: &str) -> Result<Vec<u16>> {
+    split_list(value)
+        .iter()
+        .map(|p| {
+            p.parse()
+                .map_err(|_| anyhow!("{} must be a comma-separated list of ports", name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::GeoProvider;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_parsing() {
+        Cli::command().debug_assert();
+        let cli =
+            Cli::try_parse_from(["agent", "ws://example.com:3005", "--print-config"]).unwrap();
+        assert_eq!(cli.server_url.as_deref(), Some("ws://example.com:3005"));
+        assert!(cli.print_config);
+        let mut config = AgentConfig::default();
+        let cli = Cli::try_parse_from(["agent", "--agent-id", "agent_tokyo1"]).unwrap();
+        config.apply_cli(&cli);
+        assert_eq!(config.agent_id.as_deref(), Some("agent_tokyo1"));
+        assert_eq!(config.server_url, DEFAULT_SERVER_URL);
+    }
+
+    #[test]
+    fn test_parse_config_file() {
+        let config: AgentConfig = toml::from_str(
+            r#"
+            server_url = "wss://cserver.example.com:3005"
+            enrollment_key = "secret"
+
+            [geo]
+            providers = [{ type = "mmdb", path = "/tmp/a.mmdb" }, { type = "static" }]
+            static = { country_code = "JP" }
+
+            [capacity]
+            max_tunnels = 10
+
+            [egress]
+            allowed_ports = [80, 443]
+
+            [command]
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.geo.providers.len(), 2);
+        assert_eq!(config.geo.providers[1], GeoProvider::Static);
+        assert_eq!(config.geo.fixed.country_code, "JP");
+        // 省略した項目は既定値
+        assert_eq!(config.geo.timeout_seconds, 10);
+        assert_eq!(config.capacity.max_tunnels, Some(10));
+        assert_eq!(config.egress.allowed_ports, vec![80, 443]);
+        assert!(!config.command.enabled);
+        assert_eq!(config.log_level, "info");
+
+        let printed = config.to_redacted_toml().unwrap();
+        assert!(!printed.contains("secret"));
+        let reparsed: AgentConfig = toml::from_str(&printed).unwrap();
+        assert_eq!(reparsed.geo.providers, config.geo.providers);
+
+        let invalid = AgentConfig {
+            agent_id: Some("tokyo1".to_string()),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
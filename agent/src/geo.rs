@@ -0,0 +1,399 @@
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use maxminddb::{geoip2, Reader};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+use ureq::{self, Agent};
+
+// 地理情報の取得方法を上書きする環境変数
+// CHILSONITE_GEO_PROVIDERS はカンマ区切りで試行順に指定する
+// (例: "ifconfig,ipinfo:https://ipinfo.io/json,mmdb:/var/lib/GeoLite2-City.mmdb,static")
+const PROVIDERS_ENV: &str = "CHILSONITE_GEO_PROVIDERS";
+const TIMEOUT_ENV: &str = "CHILSONITE_GEO_TIMEOUT_SECONDS";
+const IPV4_ONLY_ENV: &str = "CHILSONITE_GEO_IPV4_ONLY";
+const IP_ECHO_URL_ENV: &str = "CHILSONITE_GEO_IP_ECHO_URL";
+const IPINFO_TOKEN_ENV: &str = "CHILSONITE_GEO_IPINFO_TOKEN";
+const ASN_MMDB_ENV: &str = "CHILSONITE_GEO_ASN_MMDB";
+const RECHECK_ENV: &str = "CHILSONITE_GEO_RECHECK_SECONDS";
+// static で使用する値
+const STATIC_ENV_PREFIX: &str = "CHILSONITE_GEO_";
+
+const DEFAULT_IFCONFIG_URL: &str = "https://ifconfig.co/json";
+const DEFAULT_IPINFO_URL: &str = "https://ipinfo.io/json";
+const DEFAULT_IP_ECHO_URL: &str = "https://api.ipify.org";
+
+// 地理情報・IP情報 (不明な項目は空文字列)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct GeoInfo {
+    pub ip: String,
+    pub country_code: String,
+    pub city: String,
+    pub region: String,
+    pub asn: String,
+    pub asn_org: String,
+}
+
+// 地理情報の取得方法
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum GeoProvider {
+    // ifconfig.co 形式のJSONを返すエンドポイント
+    Ifconfig { url: String },
+    // ipinfo.io 形式のJSONを返すエンドポイント
+    Ipinfo { url: String },
+    // ローカルのMaxMind形式のデータベース (公開IPは ip_echo_url または静的な値から取得)
+    Mmdb { path: String },
+    // 設定された静的な値
+    Static,
+}
+
+// 地理情報の取得設定 (設定ファイルの [geo])
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct GeoSettings {
+    // 試行順の取得方法 (いずれも失敗した場合はマスターに地理情報の補完を任せる)
+    pub providers: Vec<GeoProvider>,
+    // 取得方法ごとのタイムアウト (秒)
+    pub timeout_seconds: u64,
+    // HTTPの問い合わせにIPv4のみを使用する (マスターでの国指定にはIPv4の公開IPを使用するため)
+    pub ipv4_only: bool,
+    // mmdb で使用する公開IPを返すエンドポイント (本文がIPアドレスのみ)
+    pub ip_echo_url: String,
+    // ipinfo のアクセストークン
+    pub ipinfo_token: Option<String>,
+    // mmdb で併用するASNデータベース
+    pub asn_mmdb_path: Option<String>,
+    // 出口IPを再検出する間隔 (秒)。0 の場合は再検出しない
+    pub recheck_seconds: u64,
+    // static で使用する値 (mmdb では ip を公開IPとして使用する)
+    #[serde(rename = "static")]
+    pub fixed: GeoInfo,
+}
+
+impl Default for GeoSettings {
+    fn default() -> Self {
+        GeoSettings {
+            providers: vec![GeoProvider::Ifconfig {
+                url: DEFAULT_IFCONFIG_URL.to_string(),
+            }],
+            timeout_seconds: 10,
+            ipv4_only: true,
+            ip_echo_url: DEFAULT_IP_ECHO_URL.to_string(),
+            ipinfo_token: None,
+            asn_mmdb_path: None,
+            recheck_seconds: 300,
+            fixed: GeoInfo::default(),
+        }
+    }
+}
+
+impl GeoSettings {
+    // 環境変数で設定を上書きする (未設定・空の環境変数は無視)
+    pub fn apply_env(&mut self) -> Result<()> {
+        let env = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        if let Some(providers) = env(PROVIDERS_ENV) {
+            self.providers = parse_providers(&providers)?;
+        }
+        if let Some(timeout) = env(TIMEOUT_ENV) {
+            self.timeout_seconds = timeout
+                .parse()
+                .ok()
+                .filter(|t| *t > 0)
+                .ok_or_else(|| anyhow!("{} must be a positive integer", TIMEOUT_ENV))?;
+        }
+        if let Some(ipv4_only) = env(IPV4_ONLY_ENV) {
+            self.ipv4_only = ipv4_only
+                .parse()
+                .map_err(|_| anyhow!("{} must be true or false", IPV4_ONLY_ENV))?;
+        }
+        if let Some(recheck) = env(RECHECK_ENV) {
+            self.recheck_seconds = recheck
+                .parse()
+                .map_err(|_| anyhow!("{} must be a non-negative integer", RECHECK_ENV))?;
+        }
+        if let Some(url) = env(IP_ECHO_URL_ENV) {
+            self.ip_echo_url = url;
+        }
+        if let Some(token) = env(IPINFO_TOKEN_ENV) {
+            self.ipinfo_token = Some(token);
+        }
+        if let Some(path) = env(ASN_MMDB_ENV) {
+            self.asn_mmdb_path = Some(path);
+        }
+        let fixed = |field: &mut String, name: &str| {
+            if let Some(value) = env(&format!("{}{}", STATIC_ENV_PREFIX, name)) {
+                *field = value;
+            }
+        };
+        fixed(&mut self.fixed.ip, "IP");
+        fixed(&mut self.fixed.country_code, "COUNTRY_CODE");
+        fixed(&mut self.fixed.city, "CITY");
+        fixed(&mut self.fixed.region, "REGION");
+        fixed(&mut self.fixed.asn, "ASN");
+        fixed(&mut self.fixed.asn_org, "ASN_ORG");
+        Ok(())
+    }
+
+    // 設定値の検証
+    pub fn validate(&self) -> Result<()> {
+        if self.providers.is_empty() {
+            return Err(anyhow!("geo.providers must not be empty"));
+        }
+        if self.timeout_seconds == 0 {
+            return Err(anyhow!("geo.timeout_seconds must be positive"));
+        }
+        Ok(())
+    }
+
+    fn http_agent(&self) -> Agent {
+        let mut config =
+            Agent::config_builder().timeout_global(Some(Duration::from_secs(self.timeout_seconds)));
+        if self.ipv4_only {
+            config = config.ip_family(ureq::config::IpFamily::Ipv4Only);
+        }
+        config.build().into()
+    }
+}
+
+// "name" または "name:引数" のカンマ区切りの一覧を取得方法に変換する
+pub(crate) fn parse_providers(value: &str) -> Result<Vec<GeoProvider>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let (name, arg) = match entry.split_once(':') {
+                Some((name, arg)) => (name, Some(arg.to_string())),
+                None => (entry, None),
+            };
+            match name {
+                "ifconfig" => Ok(GeoProvider::Ifconfig {
+                    url: arg.unwrap_or_else(|| DEFAULT_IFCONFIG_URL.to_string()),
+                }),
+                "ipinfo" => Ok(GeoProvider::Ipinfo {
+                    url: arg.unwrap_or_else(|| DEFAULT_IPINFO_URL.to_string()),
+                }),
+                "mmdb" => arg
+                    .map(|path| GeoProvider::Mmdb { path })
+                    .ok_or_else(|| anyhow!("mmdb provider requires a path (mmdb:/path/to.mmdb)")),
+                "static" => Ok(GeoProvider::Static),
+                _ => Err(anyhow!("Unknown geo provider: {}", entry)),
+            }
+        })
+        .collect()
+}
+
+// 設定された順に地理情報の取得を試み、最初に成功した結果を返す
+// すべて失敗した場合は None (マスターが接続元アドレスから補完する)
+pub(crate) fn detect(settings: &GeoSettings) -> Option<GeoInfo> {
+    for provider in &settings.providers {
+        match lookup(settings, provider) {
+            Ok(geo) => {
+                info!("[Geo] Determined geo data with {:?}", provider);
+                return Some(geo);
+            }
+            Err(e) => warn!("[Geo] {:?} failed: {:#}", provider, e),
+        }
+    }
+    None
+}
+
+fn lookup(settings: &GeoSettings, provider: &GeoProvider) -> Result<GeoInfo> {
+    match provider {
+        GeoProvider::Ifconfig { url } => {
+            let data: IfconfigData = settings
+                .http_agent()
+                .get(url)
+                .call()?
+                .body_mut()
+                .read_json()?;
+            Ok(GeoInfo {
+                ip: data.ip,
+                country_code: data.country_iso,
+                city: data.city,
+                region: data.region_name,
+                asn: data.asn,
+                asn_org: data.asn_org,
+            })
+        }
+        GeoProvider::Ipinfo { url } => {
+            let mut request = settings.http_agent().get(url);
+            if let Some(token) = &settings.ipinfo_token {
+                request = request.header("Authorization", &format!("Bearer {}", token));
+            }
+            let data: IpinfoData = request.call()?.body_mut().read_json()?;
+            Ok(ipinfo_to_geo(data))
+        }
+        GeoProvider::Mmdb { path } => {
+            let ip = if settings.fixed.ip.is_empty() {
+                settings
+                    .http_agent()
+                    .get(&settings.ip_echo_url)
+                    .call()?
+                    .body_mut()
+                    .read_to_string()?
+            } else {
+                settings.fixed.ip.clone()
+            };
+            let ip: IpAddr = ip
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid public IP: {}", ip.trim()))?;
+            lookup_mmdb(path, settings.asn_mmdb_path.as_deref(), ip)
+        }
+        GeoProvider::Static => {
+            if settings.fixed.country_code.is_empty() {
+                return Err(anyhow!("No static country code is configured"));
+            }
+            Ok(settings.fixed.clone())
+        }
+    }
+}
+
+// ローカルのデータベースから公開IPの位置情報を検索する
+fn lookup_mmdb(path: &str, asn_path: Option<&str>, ip: IpAddr) -> Result<GeoInfo> {
+    let reader =
+        Reader::open_readfile(path).map_err(|e| anyhow!("Failed to open {}: {}", path, e))?;
+    let city: geoip2::City = reader
+        .lookup(ip)
+        .map_err(|e| anyhow!("{} not found in {}: {}", ip, path, e))?;
+    let english = |names: Option<std::collections::BTreeMap<&str, &str>>| {
+        names
+            .and_then(|n| n.get("en").map(|s| s.to_string()))
+            .unwrap_or_default()
+    };
+    let mut geo = GeoInfo {
+        ip: ip.to_string(),
+        country_code: city
+            .country
+            .and_then(|c| c.iso_code)
+            .unwrap_or_default()
+            .to_string(),
+        city: english(city.city.and_then(|c| c.names)),
+        region: english(
+            city.subdivisions
+                .and_then(|s| s.into_iter().next())
+                .and_then(|s| s.names),
+        ),
+        ..Default::default()
+    };
+    // ASNデータベースは任意 (見つからない場合もエラーにしない)
+    if let Some(asn_path) = asn_path {
+        match Reader::open_readfile(asn_path) {
+            Ok(reader) => {
+                if let Ok(asn) = reader.lookup::<geoip2::Asn>(ip) {
+                    geo.asn = asn
+                        .autonomous_system_number
+                        .map(|n| format!("AS{}", n))
+                        .unwrap_or_default();
+                    geo.asn_org = asn
+                        .autonomous_system_organization
+                        .unwrap_or_default()
+                        .to_string();
+                }
+            }
+            Err(e) => warn!("[Geo] Failed to open {}: {}", asn_path, e),
+        }
+    }
+    Ok(geo)
+}
+
+// ifconfig.co から取得する地理情報・IP情報
+#[derive(Debug, Deserialize)]
+struct IfconfigData {
+    ip: String,
+    #[serde(default)]
+    country_iso: String, // 国コード (ISO)
+    #[serde(default)]
+    region_name: String, // 地域名
+    #[serde(default)]
+    city: String, // 都市名
+    #[serde(default)]
+    asn: String, // ASN番号
+    #[serde(default)]
+    asn_org: String, // ASN組織名
+}
+
+// ipinfo.io 形式の地理情報 (org は "AS15169 Google LLC" の形式)
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct IpinfoData {
+    ip: String,
+    country: String,
+    city: String,
+    region: String,
+    org: String,
+}
+
+fn ipinfo_to_geo(data: IpinfoData) -> GeoInfo {
+    let (asn, asn_org) = match data.org.split_once(' ') {
+        Some((asn, org)) if asn.starts_with("AS") => (asn.to_string(), org.to_string()),
+        _ => (String::new(), data.org),
+    };
+    GeoInfo {
+        ip: data.ip,
+        country_code: data.country,
+        city: data.city,
+        region: data.region,
+        asn,
+        asn_org,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_providers() {
+        assert_eq!(
+            parse_providers("ifconfig, ipinfo:https://example.com/json ,mmdb:/tmp/a.mmdb,static")
+                .unwrap(),
+            vec![
+                GeoProvider::Ifconfig {
+                    url: DEFAULT_IFCONFIG_URL.to_string()
+                },
+                GeoProvider::Ipinfo {
+                    url: "https://example.com/json".to_string()
+                },
+                GeoProvider::Mmdb {
+                    path: "/tmp/a.mmdb".to_string()
+                },
+                GeoProvider::Static,
+            ]
+        );
+        assert!(parse_providers("mmdb").is_err());
+        assert!(parse_providers("unknown").is_err());
+    }
+
+    #[test]
+    fn test_ipinfo_to_geo() {
+        let geo = ipinfo_to_geo(IpinfoData {
+            ip: "8.8.8.8".to_string(),
+            country: "US".to_string(),
+            org: "AS15169 Google LLC".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(geo.asn, "AS15169");
+        assert_eq!(geo.asn_org, "Google LLC");
+        assert_eq!(ipinfo_to_geo(IpinfoData::default()).asn, "");
+    }
+
+    #[test]
+    fn test_static_provider() {
+        let mut settings = GeoSettings {
+            providers: vec![GeoProvider::Static],
+            ..Default::default()
+        };
+        assert_eq!(detect(&settings), None);
+        settings.fixed.country_code = "JP".to_string();
+        assert_eq!(detect(&settings).unwrap().country_code, "JP");
+    }
+}
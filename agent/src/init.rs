@@ -1,67 +1,35 @@
+use crate::geo::{self, GeoInfo, GeoSettings};
+use crate::load::Capacity;
 use crate::ws::send_message;
 use crate::WsSink;
 use anyhow::Result;
 use common::Payload;
-use log::{error, info};
+use log::{error, info, warn};
 use std::sync::Arc;
 use sysinfo::System;
 use tokio::sync::Mutex;
-use ureq::{self, Agent};
-use whoami;
-
-// ifconfig.co から取得する地理情報・IP情報の構造体
-#[derive(Debug, serde::Deserialize)]
-#[allow(dead_code)] // 未使用フィールドの警告抑制
-pub(crate) struct GeoData {
-    ip: String,
-    country: String,
-    country_iso: String, // 国コード (ISO)
-    region_name: String, // 地域名
-    city: String,        // 都市名
-    latitude: f64,
-    longitude: f64,
-    asn: String,     // ASN番号
-    asn_org: String, // ASN組織名
-    #[serde(rename = "user_agent")]
-    user_agent: UserAgent, // ifconfig.co が認識したUser-Agent情報
-}
-
-// ifconfig.co レスポンス内のUser-Agent情報
-#[derive(Debug, serde::Deserialize)]
-#[allow(dead_code)]
-struct UserAgent {
-    product: String,
-    version: String,
-    raw_value: String,
-}
 
 // エージェント起動時に初期化リクエスト(InitRequest)をマスターに送信
-// 地理情報とシステム情報を収集してペイロードに含める
-pub(crate) async fn handle_init_request(agent_id: &str, sink: Arc<Mutex<WsSink>>) -> Result<()> {
+// 地理情報 (geo_settings の取得方法で取得) とシステム情報を収集し、容量の設定とともにペイロードに含める
+// 送信した地理情報を返す (出口IPの再検出で変化の判定に使用)
+pub(crate) async fn handle_init_request(
+    agent_id: &str,
+    geo_settings: &GeoSettings,
+    capacity: Capacity,
+    enrollment_key: Option<&str>,
+    sink: Arc<Mutex<WsSink>>,
+) -> Result<GeoInfo> {
     info!("[Init] Determining geo data...");
-
-    // ureqエージェントの設定 (IPv4のみ使用)
-    let config = Agent::config_builder()
-        .ip_family(ureq::config::IpFamily::Ipv4Only)
-        .build();
-
-    let agent: Agent = config.into();
-
-    // ifconfig.co にリクエストを送信し、JSONレスポンスを取得・パース
-    let geo_data = match agent
-        .get("https://ifconfig.co/json")
-        .call()?
-        .body_mut()
-        .read_json::<GeoData>()
-    {
-        Ok(data) => data,
-        Err(e) => return Err(anyhow::anyhow!("JSON parse failed: {}", e)),
-    };
+    // 設定された取得方法を順に試す (すべて失敗した場合は空のまま送信し、マスターに補完を任せる)
+    let geo_data = geo::detect(geo_settings).unwrap_or_else(|| {
+        warn!("[Init] Could not determine geo data; the master will fill it in if it can");
+        GeoInfo::default()
+    });
 
     // 取得した地理情報をログ出力
     info!("[Init] IP: {}", geo_data.ip);
-    info!("[Init] Country Code: {}", geo_data.country_iso);
-    info!("[Init] Region: {}", geo_data.region_name);
+    info!("[Init] Country Code: {}", geo_data.country_code);
+    info!("[Init] Region: {}", geo_data.region);
     info!("[Init] City: {}", geo_data.city);
     info!("[Init] ASN: {}", geo_data.asn);
     info!("[Init] ASN Org: {}", geo_data.asn_org);
@@ -89,25 +57,29 @@ pub(crate) async fn handle_init_request(agent_id: &str, sink: Arc<Mutex<WsSink>>
     // InitRequestペイロードを作成 (地理情報 + システム情報)
     let payload = Payload::InitRequest {
         agent_id: agent_id.to_string(),
-        ip: geo_data.ip,
-        remote_host: geo_data.region_name.clone(), // remote_host は一旦 region_name を使用
-        country_code: geo_data.country_iso,
-        city: geo_data.city,
-        region: geo_data.region_name.clone(),
-        asn: geo_data.asn,
-        asn_org: geo_data.asn_org,
+        ip: geo_data.ip.clone(),
+        remote_host: geo_data.region.clone(), // remote_host は一旦 region を使用
+        country_code: geo_data.country_code.clone(),
+        city: geo_data.city.clone(),
+        region: geo_data.region.clone(),
+        asn: geo_data.asn.clone(),
+        asn_org: geo_data.asn_org.clone(),
         // システム情報をペイロードに追加
         os_type,
         os_version,
         hostname,
         kernel_version,
         username,
+        // 容量の設定
+        max_tunnels: capacity.max_tunnels,
+        max_bytes_per_second: capacity.max_bytes_per_second,
+        enrollment_key: enrollment_key.map(str::to_string),
     };
 
     // 作成したペイロードをWebSocketで送信
     send_message(sink.clone(), payload).await?;
     info!("[{}] Sent init-request to master", agent_id);
-    Ok(())
+    Ok(geo_data)
 }
 
 // マスターからの初期化レスポンス(InitResponse)を処理
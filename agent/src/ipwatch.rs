@@ -0,0 +1,91 @@
+use crate::geo::{self, GeoInfo, GeoSettings};
+use crate::ws::send_message;
+use crate::WsSink;
+use anyhow::Result;
+use common::Payload;
+use log::{debug, info, warn};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sysinfo::Networks;
+use tokio::sync::Mutex;
+
+// ネットワークインターフェースの変化を確認する間隔
+const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// 出口IPを定期的 (recheck_seconds ごと) と、ネットワークインターフェースのアドレスの変化時に再検出し、
+// 地理情報・IP情報が変わった場合は metadata-update をマスターに送信する
+// 送信に失敗した場合 (切断) は終了する
+pub(crate) async fn watch_exit_ip(
+    settings: GeoSettings,
+    initial: GeoInfo,
+    sink: Arc<Mutex<WsSink>>,
+) -> Result<()> {
+    let recheck = Duration::from_secs(settings.recheck_seconds);
+    info!(
+        "[IpWatch] Re-checking exit IP every {} seconds and on network changes",
+        recheck.as_secs()
+    );
+    let mut networks = Networks::new_with_refreshed_list();
+    let mut addresses = interface_addresses(&networks);
+    let mut current = initial;
+    let mut last_check = Instant::now();
+    let mut interval = tokio::time::interval(NETWORK_CHECK_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        networks.refresh(true);
+        let new_addresses = interface_addresses(&networks);
+        let network_changed = new_addresses != addresses;
+        if network_changed {
+            info!("[IpWatch] Network interfaces changed; re-checking exit IP");
+            addresses = new_addresses;
+        } else if last_check.elapsed() < recheck {
+            continue;
+        }
+        last_check = Instant::now();
+        // HTTPの問い合わせはブロッキングのため別スレッドで実行する
+        let geo_settings = settings.clone();
+        let detected = tokio::task::spawn_blocking(move || geo::detect(&geo_settings)).await?;
+        let Some(detected) = detected else {
+            warn!("[IpWatch] Could not re-check exit IP; keeping the previous values");
+            continue;
+        };
+        if detected == current {
+            debug!("[IpWatch] Exit IP unchanged: {}", current.ip);
+            continue;
+        }
+        info!(
+            "[IpWatch] Exit IP changed: {} ({}) -> {} ({})",
+            current.ip, current.country_code, detected.ip, detected.country_code
+        );
+        let payload = Payload::MetadataUpdate {
+            ip: detected.ip.clone(),
+            remote_host: detected.region.clone(), // init-request と同様に region を使用
+            country_code: detected.country_code.clone(),
+            city: detected.city.clone(),
+            region: detected.region.clone(),
+            asn: detected.asn.clone(),
+            asn_org: detected.asn_org.clone(),
+        };
+        send_message(sink.clone(), payload).await?;
+        current = detected;
+    }
+}
+
+// インターフェース名とアドレスの一覧 (比較用に整列)
+fn interface_addresses(networks: &Networks) -> Vec<(String, Vec<String>)> {
+    let mut result: Vec<(String, Vec<String>)> = networks
+        .iter()
+        .map(|(name, data)| {
+            let mut addrs: Vec<String> = data
+                .ip_networks()
+                .iter()
+                .map(|n| format!("{}/{}", n.addr, n.prefix))
+                .collect();
+            addrs.sort();
+            (name.clone(), addrs)
+        })
+        .collect();
+    result.sort();
+    result
+}
@@ -0,0 +1,102 @@
+use crate::ws::send_message;
+use crate::WsSink;
+use anyhow::{anyhow, Result};
+use common::Payload;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sysinfo::{Networks, System};
+use tokio::sync::Mutex;
+
+// 負荷レポートの送信間隔
+const LOAD_REPORT_INTERVAL: Duration = Duration::from_secs(15);
+
+// 容量の設定を上書きする環境変数
+const MAX_TUNNELS_ENV: &str = "CHILSONITE_MAX_TUNNELS";
+const MAX_BYTES_PER_SECOND_ENV: &str = "CHILSONITE_MAX_BYTES_PER_SECOND";
+
+// エージェントの容量 (設定ファイルの [capacity]。初期化リクエストでマスターに通知する。None は無制限)
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Capacity {
+    // 同時に使用できるトンネル数の上限
+    pub max_tunnels: Option<u32>,
+    // 全トンネルの合計転送量の上限 (バイト/秒)
+    pub max_bytes_per_second: Option<u64>,
+}
+
+impl Capacity {
+    // 環境変数で容量の設定を上書きする (未設定・空の環境変数は無視)
+    pub fn apply_env(&mut self) -> Result<()> {
+        if let Some(max) = read_env(MAX_TUNNELS_ENV)? {
+            self.max_tunnels = Some(max);
+        }
+        if let Some(max) = read_env(MAX_BYTES_PER_SECOND_ENV)? {
+            self.max_bytes_per_second = Some(max);
+        }
+        Ok(())
+    }
+
+    // 設定値の検証 (0 は無制限と紛らわしいため許可しない)
+    pub fn validate(&self) -> Result<()> {
+        if self.max_tunnels == Some(0) || self.max_bytes_per_second == Some(0) {
+            return Err(anyhow!(
+                "capacity.max_tunnels and capacity.max_bytes_per_second must be positive"
+            ));
+        }
+        Ok(())
+    }
+}
+
+// 正の整数の環境変数を読み込む (未設定・空の場合は None)
+fn read_env<T>(name: &str) -> Result<Option<T>>
+where
+    T: std::str::FromStr + PartialOrd + Default,
+{
+    match std::env::var(name) {
+        Ok(value) if !value.trim().is_empty() => match value.trim().parse::<T>() {
+            Ok(v) if v > T::default() => Ok(Some(v)),
+            _ => Err(anyhow!("{} must be a positive integer: {}", name, value)),
+        },
+        _ => Ok(None),
+    }
+}
+
+// CPU・メモリ・ネットワークの負荷を定期的にマスターに送信する
+// 送信に失敗した場合 (切断) は終了する
+pub(crate) async fn report_load(sink: Arc<Mutex<WsSink>>) -> Result<()> {
+    let mut sys = System::new();
+    let mut networks = Networks::new_with_refreshed_list();
+    // CPU使用率は前回の計測との差分で算出されるため、初回の計測をしておく
+    sys.refresh_cpu_usage();
+    let mut last_refresh = Instant::now();
+    let mut interval = tokio::time::interval(LOAD_REPORT_INTERVAL);
+    // 初回の tick は即座に完了するため読み捨てる
+    interval.tick().await;
+    info!(
+        "[Load] Reporting load every {} seconds",
+        LOAD_REPORT_INTERVAL.as_secs()
+    );
+    loop {
+        interval.tick().await;
+        sys.refresh_cpu_usage();
+        sys.refresh_memory();
+        networks.refresh(true);
+        let elapsed = last_refresh.elapsed().as_secs_f64().max(1.0);
+        last_refresh = Instant::now();
+        // 前回の refresh からの受信・送信量の合計
+        let (rx, tx) = networks.iter().fold((0u64, 0u64), |(rx, tx), (_, data)| {
+            (rx + data.received(), tx + data.transmitted())
+        });
+        let payload = Payload::LoadReport {
+            cpu_percent: sys.global_cpu_usage(),
+            memory_used_bytes: sys.used_memory(),
+            memory_total_bytes: sys.total_memory(),
+            rx_bytes_per_second: (rx as f64 / elapsed) as u64,
+            tx_bytes_per_second: (tx as f64 / elapsed) as u64,
+        };
+        debug!("[Load] Sending load-report: {:?}", payload);
+        send_message(sink.clone(), payload).await?;
+    }
+}
@@ -1,11 +1,16 @@
 // モジュールの宣言
 mod command;
+mod config;
+mod geo;
 mod init;
+mod ipwatch;
+mod load;
 mod tcp;
 mod ws;
 
 use anyhow::Result;
-use env_logger;
+use clap::Parser;
+use config::{AgentConfig, Cli};
 use futures::stream::{SplitSink, SplitStream};
 use futures::StreamExt;
 use log::{info, warn};
@@ -21,10 +26,6 @@ use tokio_tungstenite::{
     connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
 };
 use url::Url;
-use whoami;
-
-// 接続先マスターサーバーのデフォルトURL
-const DEFAULT_MASTER_URL: &str = "ws://127.0.0.1:3005";
 
 // 型エイリアス: WebSocket送信用シンク
 type WsSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
@@ -38,8 +39,19 @@ type ConnectionMap = Arc<Mutex<HashMap<String, WriteHalf<TcpStream>>>>;
 // メインエントリーポイント: エージェントの起動とマスターサーバーへの接続処理
 #[tokio::main]
 async fn main() -> Result<()> {
-    // ロガーの初期化 (環境変数 RUST_LOG またはデフォルト "info" レベル)
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    // コマンドライン引数・設定ファイル・環境変数から設定を読み込む
+    let cli = Cli::parse();
+    let config = AgentConfig::load(&cli)?;
+    if cli.print_config {
+        print!("{}", config.to_redacted_toml()?);
+        return Ok(());
+    }
+
+    // ロガーの初期化 (環境変数 RUST_LOG または設定の log_level)
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(config.log_level.as_str()),
+    )
+    .init();
 
     // エージェントIDの基となるマシン固有IDを取得
     let base_id = {
@@ -118,23 +130,23 @@ async fn main() -> Result<()> {
         .chars()
         .take(12)
         .collect::<String>();
-    // 最終的なエージェントIDを "agent_" プレフィックス付きで生成
-    let agent_id = format!("agent_{}", padded_id);
+    // 最終的なエージェントIDを "agent_" プレフィックス付きで生成 (設定で指定された場合はそのID)
+    let agent_id = config
+        .agent_id
+        .clone()
+        .unwrap_or_else(|| format!("agent_{}", padded_id));
 
-    // コマンドライン引数を取得
-    let args: Vec<String> = std::env::args().collect();
-    // 引数があればそれをマスターURLとして使用、なければデフォルトURLを使用
-    let master_url = if args.len() > 1 {
-        args[1].clone()
-    } else {
-        DEFAULT_MASTER_URL.to_string()
-    };
+    let capacity = config.capacity;
+    let geo_settings = config.geo.clone();
 
     info!("Starting AGENT with ID: {}", agent_id);
-    info!("Connecting to master at: {}", master_url);
+    info!("Connecting to master at: {}", config.server_url);
+    info!("Capacity: {:?}", capacity);
+    info!("Egress policy: {:?}", config.egress);
+    info!("Command policy: {:?}", config.command);
 
     // マスターURLをパース
-    let url = Url::parse(&master_url)?;
+    let url = Url::parse(&config.server_url)?;
     // WebSocket接続を非同期に確立
     let (ws_stream, _) = connect_async(url.as_str()).await?;
     info!("WebSocket connection established with master");
@@ -147,10 +159,40 @@ async fn main() -> Result<()> {
     let connections: ConnectionMap = Arc::new(Mutex::new(HashMap::new()));
 
     // 初期化リクエストを送信 (initモジュールの関数を使用)
-    init::handle_init_request(&agent_id, sink.clone()).await?;
+    let geo_data = init::handle_init_request(
+        &agent_id,
+        &geo_settings,
+        capacity,
+        config.enrollment_key.as_deref(),
+        sink.clone(),
+    )
+    .await?;
+
+    // 負荷レポートの定期送信と出口IPの再検出を開始 (イベントループの終了時に停止)
+    let load_reporter = tokio::spawn(load::report_load(sink.clone()));
+    let ip_watcher = (geo_settings.recheck_seconds > 0).then(|| {
+        tokio::spawn(ipwatch::watch_exit_ip(
+            geo_settings.clone(),
+            geo_data,
+            sink.clone(),
+        ))
+    });
 
     // WebSocketイベントループを開始 (wsモジュールの関数を使用)
-    ws::event_loop(stream, sink, connections).await?;
+    let result = ws::event_loop(
+        stream,
+        sink,
+        connections,
+        capacity,
+        Arc::new(config.egress.clone()),
+        Arc::new(config.command.clone()),
+    )
+    .await;
+    load_reporter.abort();
+    if let Some(ip_watcher) = ip_watcher {
+        ip_watcher.abort();
+    }
+    result?;
 
     Ok(())
 }
@@ -5,6 +5,7 @@ use base64::{engine::general_purpose::STANDARD, Engine as _};
 use common::Payload;
 // Required for collect on lookup_host iterator
 use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -92,6 +93,45 @@ fn is_private_ip(ip_str: &str) -> bool {
     }
 }
 
+// 接続先の制限 (設定ファイルの [egress])
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct EgressPolicy {
+    // ループバック・プライベートアドレス等への接続を許可する (既定ではSSRF対策として拒否)
+    pub allow_private_networks: bool,
+    // 接続を許可する宛先ポート (空の場合はすべて許可)
+    pub allowed_ports: Vec<u16>,
+    // 接続を拒否する宛先ポート
+    pub denied_ports: Vec<u16>,
+    // 接続を拒否するホスト名・IPアドレス (ホスト名はサブドメインも含む)
+    pub denied_hosts: Vec<String>,
+}
+
+impl EgressPolicy {
+    // 宛先のホスト名・ポートが許可されているか確認する (拒否する場合は理由を返す)
+    pub fn check_target(&self, host: &str, port: u16) -> Result<(), String> {
+        if (!self.allowed_ports.is_empty() && !self.allowed_ports.contains(&port))
+            || self.denied_ports.contains(&port)
+        {
+            return Err(format!("port {} is not allowed", port));
+        }
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let denied = self.denied_hosts.iter().any(|d| {
+            let d = d.trim_end_matches('.').to_ascii_lowercase();
+            host == d || host.ends_with(&format!(".{}", d))
+        });
+        if denied {
+            return Err(format!("host {} is denied", host));
+        }
+        Ok(())
+    }
+
+    // 接続先のIPアドレスが許可されているか確認する (DNS解決後のアドレスにも適用)
+    pub fn allows_ip(&self, ip: &str) -> bool {
+        self.allow_private_networks || !is_private_ip(ip)
+    }
+}
+
 /// マスターからの接続要求を受け、指定先へTCP接続を試行する
 // マスターからの接続要求(ConnectRequest)を処理
 // 指定されたターゲットへのTCP接続を試行し、結果をマスターに返す
@@ -102,12 +142,27 @@ pub(crate) async fn handle_connect_request(
     address_type: u8,
     connections: ConnectionMap,
     sink: Arc<Mutex<WsSink>>,
+    egress: Arc<EgressPolicy>,
 ) -> Result<()> {
     info!(
         "[{}] Received connect-request for {}:{} (type: {})",
         request_id, target_addr, target_port, address_type
     );
 
+    // 設定された接続先の制限を確認
+    if let Err(reason) = egress.check_target(&target_addr, target_port) {
+        warn!(
+            "[{}] Connection to {}:{} blocked by egress policy: {}",
+            request_id, target_addr, target_port, reason
+        );
+        let payload = Payload::ConnectResponse {
+            request_id: request_id.clone(),
+            success: false,
+        };
+        send_message(sink.clone(), payload).await?;
+        return Ok(());
+    }
+
     // ドメイン名解決が必要な場合
     // アドレスタイプに応じて接続先アドレスを決定 (DNS解決やSSRFチェックを含む)
     let resolved_addr = match address_type {
@@ -135,7 +190,7 @@ pub(crate) async fn handle_connect_request(
 
                             // Check if the resolved IP is private
                             // 解決後のIPアドレスがプライベートIPでないかチェック (SSRF対策)
-                            if is_private_ip(&ip_str) {
+                            if !egress.allows_ip(&ip_str) {
                                 error!(
                                     "[{}] SSRF attempt detected: resolved to private IP {}",
                                     request_id, ip_str
@@ -181,7 +236,7 @@ pub(crate) async fn handle_connect_request(
 
             // Check if the direct IP is private
             // 直接指定されたIPアドレスがプライベートIPでないかチェック (SSRF対策)
-            if is_private_ip(target_addr.as_str()) {
+            if !egress.allows_ip(target_addr.as_str()) {
                 error!(
                     "[{}] SSRF attempt detected: connection to private IP {} blocked",
                     request_id, target_addr
@@ -202,7 +257,7 @@ pub(crate) async fn handle_connect_request(
 
             // Check if the IPv6 address is private
             // 直接指定されたIPv6アドレスがプライベートIPでないかチェック (SSRF対策)
-            if is_private_ip(target_addr.as_str()) {
+            if !egress.allows_ip(target_addr.as_str()) {
                 error!(
                     "[{}] SSRF attempt detected: connection to private IPv6 {} blocked",
                     request_id, target_addr
@@ -422,6 +477,28 @@ mod tests {
         assert!(!is_private_ip("2606:4700:4700::1111")); // Cloudflare DNS
     }
 
+    #[test]
+    fn test_egress_policy() {
+        let policy = EgressPolicy {
+            allowed_ports: vec![80, 443],
+            denied_hosts: vec!["example.com".to_string()],
+            ..Default::default()
+        };
+        assert!(policy.check_target("example.org", 443).is_ok());
+        assert!(policy.check_target("example.org", 25).is_err());
+        assert!(policy.check_target("Example.com.", 80).is_err());
+        assert!(policy.check_target("www.example.com", 80).is_err());
+        assert!(policy.check_target("notexample.com", 80).is_ok());
+        assert!(!policy.allows_ip("127.0.0.1"));
+        let policy = EgressPolicy {
+            allow_private_networks: true,
+            denied_ports: vec![25],
+            ..Default::default()
+        };
+        assert!(policy.allows_ip("192.168.1.1"));
+        assert!(policy.check_target("mail.example.com", 25).is_err());
+    }
+
     #[test]
     fn test_is_private_ip_invalid() {
         // Invalid IP strings should be treated as private for safety
@@ -1,8 +1,11 @@
+use crate::command::CommandPolicy;
+use crate::load::Capacity;
+use crate::tcp::EgressPolicy;
 use crate::{command, init, tcp, ConnectionMap, WsSink, WsStream}; // Import from main/lib and other modules
 use anyhow::Result;
 use common::Payload;
 use futures::{SinkExt, StreamExt};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use std::sync::Arc;
 use tokio::io::AsyncWriteExt; // For shutdown
 use tokio::sync::Mutex;
@@ -37,6 +40,9 @@ pub(crate) async fn event_loop(
     mut stream: WsStream,
     sink: Arc<Mutex<WsSink>>,
     connections: ConnectionMap,
+    capacity: Capacity,
+    egress: Arc<EgressPolicy>,
+    command_policy: Arc<CommandPolicy>,
 ) -> Result<()> {
     // WebSocketストリームからメッセージを順次受信
     while let Some(msg) = stream.next().await {
@@ -55,6 +61,23 @@ pub(crate) async fn event_loop(
                         // ConnectRequestペイロードの処理
                         Payload::ConnectRequest { request_id, target_addr, target_port, address_type, .. } => {
                             info!("[Control] Received connect-request");
+                            // 同時トンネル数の上限に達している場合は接続を拒否
+                            if let Some(max) = capacity.max_tunnels {
+                                if connections.lock().await.len() >= max as usize {
+                                    warn!(
+                                        "[{}] Rejecting connect-request: {} tunnels in use",
+                                        request_id, max
+                                    );
+                                    let payload = Payload::ConnectResponse {
+                                        request_id,
+                                        success: false,
+                                    };
+                                    if let Err(e) = send_message(sink.clone(), payload).await {
+                                        error!("[Control] Failed to send connect-response: {}", e);
+                                    }
+                                    continue;
+                                }
+                            }
                             let req_id = request_id.clone();
                             let target = target_addr.clone();
                             spawn_ws(
@@ -66,6 +89,7 @@ pub(crate) async fn event_loop(
                                     address_type,
                                     connections.clone(),
                                     sink.clone(),
+                                    egress.clone(),
                                 ),
                             );
                         }
@@ -145,7 +169,12 @@ pub(crate) async fn event_loop(
                             let cmd = command.clone();
                             spawn_ws(
                                 req_id.clone(),
-                                command::handle_command_request(req_id.clone(), cmd, sink.clone()),
+                                command::handle_command_request(
+                                    req_id.clone(),
+                                    cmd,
+                                    sink.clone(),
+                                    command_policy.clone(),
+                                ),
                             );
                         }
                         // 上記以外のペイロードタイプ
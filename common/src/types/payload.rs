@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 
 /// エージェントとクライアント間でやり取りするメッセージのペイロード定義
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Payload {
     #[serde(rename = "init-request")]
     InitRequest {
@@ -19,6 +20,14 @@ pub enum Payload {
         hostname: String,
         kernel_version: String,
         username: String,
+        // エージェント側で設定した容量 (未設定・旧バージョンのエージェントは無制限)
+        #[serde(default)]
+        max_tunnels: Option<u32>,
+        #[serde(default)]
+        max_bytes_per_second: Option<u64>,
+        // マスターで agent_enrollment_key が設定されている場合に必要な登録キー
+        #[serde(default)]
+        enrollment_key: Option<String>,
     },
     #[serde(rename = "init-response")]
     InitResponse {
@@ -63,6 +72,28 @@ pub enum Payload {
     },
     #[serde(rename = "client-disconnect")]
     ClientDisconnect { request_id: String },
+    // エージェントが出口IPの変化を検出した場合に送信する地理情報・IP情報の更新
+    #[serde(rename = "metadata-update")]
+    MetadataUpdate {
+        ip: String,
+        remote_host: String,
+        country_code: String,
+        city: String,
+        region: String,
+        asn: String,
+        asn_org: String,
+    },
+    // エージェントが定期的に送信する負荷レポート
+    #[serde(rename = "load-report")]
+    LoadReport {
+        /// CPU使用率 (0.0〜100.0)
+        cpu_percent: f32,
+        memory_used_bytes: u64,
+        memory_total_bytes: u64,
+        /// 全ネットワークインターフェースの受信・送信量 (前回のレポートからの平均)
+        rx_bytes_per_second: u64,
+        tx_bytes_per_second: u64,
+    },
 
     // Renamed from Command, added request_id
     #[serde(rename = "command-request")]
@@ -1,21 +1,340 @@
-use crate::api::dto::AgentQuery;
+use crate::api::auth::AuthUser;
+use crate::api::dto::{AgentQuery, ErrorResponse};
+use crate::api::err;
+use crate::geoip::GeoVerification;
+use crate::health::{AgentHealth, AgentHealthReport};
+use crate::repository::{get_agents, AgentRecord, ApiKeyScope};
 use crate::{AppState, WsSink};
-use axum::{extract::Query, extract::State, Json};
+use axum::response::IntoResponse;
+use axum::{extract::Path, extract::Query, extract::State, Json};
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use serde::Serialize;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::{Mutex, Notify};
 use utoipa::ToSchema;
 
+// ラベルの最大数と最大文字数
+const MAX_LABELS: usize = 16;
+const MAX_LABEL_LENGTH: usize = 32;
+
 // エージェント接続情報（WebSocket Sinkとメタデータ）
 // SinkはMutexで保護され、スレッドセーフなアクセスを保証
 pub(crate) struct AgentConnection {
     pub sink: Mutex<WsSink>,
-    pub metadata: AgentMetadata,
+    // エージェントが報告したメタデータ (出口IPの再検出による metadata-update で置き換える)
+    metadata: RwLock<Arc<AgentMetadata>>,
+    // 管理者による無効化 (新規トンネルに使用しない。DBにも保存)
+    pub disabled: AtomicBool,
+    // ドレイン中 (新規トンネルに使用せず、既存のトンネル終了後に切断する)
+    pub draining: AtomicBool,
+    // SOCKS5ユーザー名 (label_<name>) で選択するためのラベル
+    pub labels: RwLock<Vec<String>>,
+    // 使用中のトンネル数
+    active_tunnels: AtomicUsize,
+    // この接続で処理したトンネルの累計数
+    total_tunnels: AtomicU64,
+    // この接続での転送量 (上り: クライアント -> エージェント, 下り: エージェント -> クライアント)
+    pub bytes_up: AtomicU64,
+    pub bytes_down: AtomicU64,
+    // 接続 (init-request) 日時と最後にメッセージを受信した日時
+    connected_at: DateTime<Utc>,
+    last_activity_at: AtomicI64,
+    // エージェントが申告した容量
+    pub capacity: AgentCapacity,
+    // 最新の負荷レポート
+    load: RwLock<Option<AgentLoad>>,
+    // 接続を切断するための通知
+    shutdown: Notify,
 }
 
-// エージェントのメタデータ
+impl AgentConnection {
+    pub fn new(
+        sink: WsSink,
+        metadata: AgentMetadata,
+        capacity: AgentCapacity,
+        disabled: bool,
+        labels: Vec<String>,
+    ) -> Self {
+        AgentConnection {
+            sink: Mutex::new(sink),
+            metadata: RwLock::new(Arc::new(metadata)),
+            capacity,
+            load: RwLock::new(None),
+            disabled: AtomicBool::new(disabled),
+            draining: AtomicBool::new(false),
+            labels: RwLock::new(labels),
+            active_tunnels: AtomicUsize::new(0),
+            total_tunnels: AtomicU64::new(0),
+            bytes_up: AtomicU64::new(0),
+            bytes_down: AtomicU64::new(0),
+            connected_at: Utc::now(),
+            last_activity_at: AtomicI64::new(Utc::now().timestamp()),
+            shutdown: Notify::new(),
+        }
+    }
+
+    pub fn metadata(&self) -> Arc<AgentMetadata> {
+        self.metadata
+            .read()
+            .expect("metadata lock poisoned")
+            .clone()
+    }
+
+    // メタデータを置き換え、置き換える前のメタデータを返す
+    pub fn set_metadata(&self, metadata: AgentMetadata) -> Arc<AgentMetadata> {
+        std::mem::replace(
+            &mut *self.metadata.write().expect("metadata lock poisoned"),
+            Arc::new(metadata),
+        )
+    }
+
+    // 新規トンネルに使用できるか
+    pub fn is_available(&self) -> bool {
+        !self.disabled.load(Ordering::Relaxed) && !self.draining.load(Ordering::Relaxed)
+    }
+
+    pub fn has_label(&self, label: &str) -> bool {
+        self.labels
+            .read()
+            .expect("labels lock poisoned")
+            .iter()
+            .any(|l| l == label)
+    }
+
+    pub fn active_tunnels(&self) -> usize {
+        self.active_tunnels.load(Ordering::Relaxed)
+    }
+
+    // 容量に空きがあるか (トンネル数と直近の転送量が上限未満)
+    pub fn has_capacity(&self) -> bool {
+        let bytes_per_second = self.load().map_or(0, |l| l.tunnel_bytes_per_second);
+        self.capacity
+            .max_tunnels
+            .is_none_or(|max| self.active_tunnels() < max as usize)
+            && self
+                .capacity
+                .max_bytes_per_second
+                .is_none_or(|max| bytes_per_second < max)
+    }
+
+    // トンネルの使用を開始する (ガードの破棄で終了)
+    // 容量を超える場合は None を返す
+    pub fn try_start_tunnel(self: &Arc<Self>) -> Option<TunnelGuard> {
+        if !self.has_capacity() {
+            return None;
+        }
+        let max = self.capacity.max_tunnels.map_or(usize::MAX, |m| m as usize);
+        self.active_tunnels
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()?;
+        let guard = TunnelGuard { conn: self.clone() };
+        // 加算の前に drain() が active_tunnels を読んでいた場合は切断を取りこぼすため、
+        // 加算後にドレイン中であればガードの破棄で取り消す (最後のトンネルなら切断する)
+        if self.draining.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.total_tunnels.fetch_add(1, Ordering::Relaxed);
+        Some(guard)
+    }
+
+    // 選択の重み付けに使用する負荷の係数
+    pub fn load_factor(&self) -> f64 {
+        load_factor(
+            self.active_tunnels(),
+            self.capacity.max_tunnels,
+            self.load().map(|l| l.cpu_percent),
+        )
+    }
+
+    pub fn load(&self) -> Option<AgentLoad> {
+        self.load.read().expect("load lock poisoned").clone()
+    }
+
+    // 負荷レポートを記録する
+    // トンネルの転送量は前回のレポートからの増加分で算出する
+    pub fn record_load(&self, report: LoadReport, now: DateTime<Utc>) {
+        let bytes = self.bytes_up.load(Ordering::Relaxed) + self.bytes_down.load(Ordering::Relaxed);
+        let mut load = self.load.write().expect("load lock poisoned");
+        let (since, previous_bytes) = match load.as_ref() {
+            Some(l) => (l.reported_at, l.tunnel_bytes),
+            None => (self.connected_at.timestamp(), 0),
+        };
+        let elapsed = (now.timestamp() - since).max(1) as u64;
+        *load = Some(AgentLoad {
+            cpu_percent: report.cpu_percent,
+            memory_used_bytes: report.memory_used_bytes,
+            memory_total_bytes: report.memory_total_bytes,
+            rx_bytes_per_second: report.rx_bytes_per_second,
+            tx_bytes_per_second: report.tx_bytes_per_second,
+            tunnel_bytes_per_second: bytes.saturating_sub(previous_bytes) / elapsed,
+            tunnel_bytes: bytes,
+            reported_at: now.timestamp(),
+        });
+    }
+
+    // エージェントからメッセージを受信した日時を記録する
+    pub fn touch(&self, now: DateTime<Utc>) {
+        self.last_activity_at
+            .store(now.timestamp(), Ordering::Relaxed);
+    }
+
+    // 現在の接続の統計
+    pub fn stats(&self, now: DateTime<Utc>) -> AgentStats {
+        AgentStats {
+            active_tunnels: self.active_tunnels(),
+            total_tunnels: self.total_tunnels.load(Ordering::Relaxed),
+            bytes_up: self.bytes_up.load(Ordering::Relaxed),
+            bytes_down: self.bytes_down.load(Ordering::Relaxed),
+            connected_at: self.connected_at.timestamp(),
+            uptime_seconds: (now - self.connected_at).num_seconds().max(0),
+            last_activity_at: self.last_activity_at.load(Ordering::Relaxed),
+            capacity: self.capacity.clone(),
+            load: self.load(),
+        }
+    }
+
+    // 接続を切断する
+    pub fn disconnect(&self) {
+        self.shutdown.notify_one();
+    }
+
+    // 切断の通知を待つ
+    pub async fn disconnected(&self) {
+        self.shutdown.notified().await
+    }
+
+    // ドレインを開始する (使用中のトンネルがなければ即座に切断)
+    // TunnelGuard の破棄と同時に実行されても切断を取りこぼさないよう SeqCst で読み書きする
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        if self.active_tunnels.load(Ordering::SeqCst) == 0 {
+            self.disconnect();
+        }
+    }
+}
+
+// 接続中のエージェントの負荷の統計 (再接続でリセットされる)
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct AgentStats {
+    // 使用中のトンネル数と、この接続で処理したトンネルの累計数
+    pub active_tunnels: usize,
+    pub total_tunnels: u64,
+    // 転送量 (上り: クライアント -> エージェント, 下り: エージェント -> クライアント)
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    // UNIXタイムスタンプで表現される接続日時と、接続からの経過秒数
+    pub connected_at: i64,
+    pub uptime_seconds: i64,
+    // UNIXタイムスタンプで表現される最後にメッセージを受信した日時
+    pub last_activity_at: i64,
+    // エージェントが申告した容量と最新の負荷レポート (未受信の場合は null)
+    pub capacity: AgentCapacity,
+    pub load: Option<AgentLoad>,
+}
+
+// エージェントが初期化リクエストで申告した容量 (null は無制限)
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub(crate) struct AgentCapacity {
+    // 同時に使用できるトンネル数の上限
+    pub max_tunnels: Option<u32>,
+    // 全トンネルの合計転送量の上限 (バイト/秒)
+    pub max_bytes_per_second: Option<u64>,
+}
+
+// エージェントから受信した負荷レポート
 #[derive(Debug, Clone)]
+pub(crate) struct LoadReport {
+    pub cpu_percent: f32,
+    pub memory_used_bytes: u64,
+    pub memory_total_bytes: u64,
+    pub rx_bytes_per_second: u64,
+    pub tx_bytes_per_second: u64,
+}
+
+// エージェントの最新の負荷
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct AgentLoad {
+    // CPU使用率 (0.0〜100.0) とメモリ使用量
+    pub cpu_percent: f32,
+    pub memory_used_bytes: u64,
+    pub memory_total_bytes: u64,
+    // エージェントのネットワークインターフェース全体の受信・送信量
+    pub rx_bytes_per_second: u64,
+    pub tx_bytes_per_second: u64,
+    // 前回のレポートからのトンネルの合計転送量 (容量の判定に使用)
+    pub tunnel_bytes_per_second: u64,
+    // レポート受信時点のトンネルの累計転送量
+    #[serde(skip)]
+    tunnel_bytes: u64,
+    // UNIXタイムスタンプで表現されるレポートの受信日時
+    pub reported_at: i64,
+}
+
+// 選択の重み付けに使用する負荷の係数 (0.05〜1.0)
+// トンネル数の上限に対する空きの割合と、CPUの空きの割合を掛け合わせる
+pub(crate) fn load_factor(
+    active_tunnels: usize,
+    max_tunnels: Option<u32>,
+    cpu_percent: Option<f32>,
+) -> f64 {
+    let tunnels = max_tunnels.map_or(1.0, |max| {
+        1.0 - (active_tunnels as f64 / f64::from(max.max(1))).min(1.0)
+    });
+    let cpu = cpu_percent.map_or(1.0, |p| 1.0 - (f64::from(p) / 100.0).clamp(0.0, 1.0));
+    (tunnels * cpu).max(0.05)
+}
+
+// トンネル使用中を表すガード
+// ドレイン中のエージェントは最後のトンネルの終了時に切断する
+pub(crate) struct TunnelGuard {
+    conn: Arc<AgentConnection>,
+}
+
+impl Drop for TunnelGuard {
+    fn drop(&mut self) {
+        // drain() と対になる処理のため SeqCst を使用する
+        let remaining = self.conn.active_tunnels.fetch_sub(1, Ordering::SeqCst) - 1;
+        if remaining == 0 && self.conn.draining.load(Ordering::SeqCst) {
+            self.conn.disconnect();
+        }
+    }
+}
+
+// ラベルを検証し、小文字化・重複除去した一覧を返す
+// ラベルは英小文字・数字・ハイフンのみ (SOCKS5ユーザー名に含めるため)
+pub(crate) fn normalize_labels(labels: &[String]) -> Result<Vec<String>, String> {
+    let mut result: Vec<String> = Vec::new();
+    for label in labels {
+        let label = label.trim().to_ascii_lowercase();
+        if label.is_empty()
+            || label.len() > MAX_LABEL_LENGTH
+            || !label
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        {
+            return Err(format!(
+                "Invalid label '{}': use 1-{} lowercase letters, digits or '-'",
+                label, MAX_LABEL_LENGTH
+            ));
+        }
+        if !result.contains(&label) {
+            result.push(label);
+        }
+    }
+    if result.len() > MAX_LABELS {
+        return Err(format!("At most {} labels are allowed", MAX_LABELS));
+    }
+    Ok(result)
+}
+
+// エージェントのメタデータ
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[allow(dead_code)] // 将来的に使用する可能性のあるフィールドの警告抑制
 pub(crate) struct AgentMetadata {
     pub ip: String,
@@ -31,6 +350,19 @@ pub(crate) struct AgentMetadata {
     pub hostname: String,
     pub kernel_version: String,
     pub username: String,
+    // サーバー側のGeoIPデータベースによる検証結果 (未設定・検証できない場合は None)
+    #[serde(default)]
+    pub geo: Option<GeoVerification>,
+}
+
+impl AgentMetadata {
+    // エージェントの選択に使用する国コード (検証できた場合は検証結果、それ以外は申告された値)
+    pub fn verified_country_code(&self) -> &str {
+        match &self.geo {
+            Some(geo) if !geo.location.country_code.is_empty() => &geo.location.country_code,
+            _ => &self.country_code,
+        }
+    }
 }
 
 // エージェントIDとAgentConnectionのマップ（スレッドセーフ）
@@ -53,6 +385,27 @@ pub(crate) struct AgentInfo {
     pub hostname: String,
     pub kernel_version: String,
     pub username: String,
+    // サーバー側のGeoIPデータベースによる位置情報の検証結果と、申告された値と一致しない項目
+    // (データベースが未設定、またはアドレスが見つからない場合は null)
+    pub geo: Option<GeoVerification>,
+    // 接続中か (オフラインの場合は最後に報告された情報を返す)
+    pub online: bool,
+    // UNIXタイムスタンプで表現される初回接続日時・最終接続/切断日時・現在の接続の開始日時
+    pub first_seen_at: Option<i64>,
+    pub last_seen_at: Option<i64>,
+    pub connected_at: Option<i64>,
+    // 累計接続回数と累計接続時間 (秒, 現在の接続を含む)
+    pub connection_count: i64,
+    pub total_uptime_seconds: i64,
+    // 管理者による管理状態 (無効化・ドレイン中・BAN) とラベル
+    pub disabled: bool,
+    pub draining: bool,
+    pub banned: bool,
+    pub labels: Vec<String>,
+    // 現在の接続の負荷の統計 (切断中の場合は null)
+    pub stats: Option<AgentStats>,
+    // 直近の接続成功率・接続時間・スループット・エラーに基づく健全性
+    pub health: AgentHealthReport,
 }
 
 #[utoipa::path(
@@ -60,37 +413,141 @@ pub(crate) struct AgentInfo {
     path = "/api/agents",
     params(AgentQuery),
     responses(
-        (status = 200, description = "List of agents", body = [AgentInfo])
+        (status = 200, description = "List of agents", body = [AgentInfo]),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the agents:read scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     tag = "Agent"
 )]
 #[allow(dead_code)] // Suppress dead_code warning as it might be used externally or later
 pub(crate) async fn list_agents(
     State(state): State<AppState>,
-    // クエリパラメータ (例: /api/agents?country=JP)
+    auth: AuthUser,
+    // クエリパラメータ (例: /api/agents?country=JP&include_offline=true)
     Query(query): Query<AgentQuery>,
-) -> Json<Vec<AgentInfo>> {
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::AgentsRead) {
+        return resp;
+    }
+    // 永続化された接続履歴 (オフラインのエージェントを含む)
+    let records: HashMap<String, AgentRecord> = match get_agents(&state.db_pool).await {
+        Ok(recs) => recs.into_iter().map(|rec| (rec.id.clone(), rec)).collect(),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let now = Utc::now();
+    let country_matches = |meta: &AgentMetadata| {
+        query
+            .country
+            .as_ref()
+            .is_none_or(|country| meta.verified_country_code() == country)
+    };
     let mut result = Vec::new();
     // AgentMapをイテレート
     for entry in state.agents.iter() {
-        let meta = &entry.value().metadata;
-        if let Some(ref country) = query.country {
-            if &meta.country_code != country {
+        let meta = entry.value().metadata();
+        if !country_matches(&meta) {
+            continue;
+        }
+        result.push(agent_info(
+            entry.key(),
+            &meta,
+            records.get(entry.key()),
+            Some(entry.value()),
+            &state.agent_health,
+            now,
+        ));
+    }
+    if query.include_offline {
+        for rec in records.values() {
+            if state.agents.contains_key(&rec.id) || !country_matches(&rec.metadata) {
                 continue;
             }
+            result.push(agent_info(
+                &rec.id,
+                &rec.metadata,
+                Some(rec),
+                None,
+                &state.agent_health,
+                now,
+            ));
         }
-        // Use From implementation to construct AgentInfo
-        result.push((entry.key(), meta).into());
     }
+    result.sort_by(|a, b| b.online.cmp(&a.online).then(a.agent_id.cmp(&b.agent_id)));
     // 結果をJSON形式で返す
-    Json(result)
+    (StatusCode::OK, Json(result)).into_response()
+}
+
+// 接続中のエージェントの負荷の統計を取得するエンドポイント
+#[utoipa::path(
+    get,
+    path = "/api/agents/{agent_id}/stats",
+    params(("agent_id" = String, Path, description = "Agent ID")),
+    responses(
+        (status = 200, description = "Live statistics of the agent", body = AgentStats),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the agents:read scope", body = ErrorResponse),
+        (status = 404, description = "Agent is not connected", body = ErrorResponse)
+    ),
+    tag = "Agent"
+)]
+pub(crate) async fn get_agent_stats(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::AgentsRead) {
+        return resp;
+    }
+    match state.agents.get(&agent_id) {
+        Some(conn) => (StatusCode::OK, Json(conn.stats(Utc::now()))).into_response(),
+        None => err(StatusCode::NOT_FOUND, "Agent is not connected"),
+    }
 }
 
-// Allow conversion from DashMap entry (key and metadata) to AgentInfo
-impl From<(&String, &AgentMetadata)> for AgentInfo {
-    fn from((agent_id, meta): (&String, &AgentMetadata)) -> Self {
+// エージェントのメタデータと永続化された状態から AgentInfo を構築する
+// conn は接続中の場合のみ指定する
+pub(crate) fn agent_info(
+    agent_id: &str,
+    meta: &AgentMetadata,
+    rec: Option<&AgentRecord>,
+    conn: Option<&AgentConnection>,
+    health: &AgentHealth,
+    now: DateTime<Utc>,
+) -> AgentInfo {
+    let online = conn.is_some();
+    // 接続中の場合は現在の接続時間を累計に含める
+    let connected_at = rec.and_then(|r| r.connected_at).filter(|_| online);
+    let current_uptime = connected_at.map_or(0, |t| (now - t).num_seconds().max(0));
+    AgentInfo {
+        online,
+        first_seen_at: rec.map(|r| r.first_seen_at.timestamp()),
+        last_seen_at: rec.map(|r| r.last_seen_at.timestamp()),
+        connected_at: connected_at.map(|t| t.timestamp()),
+        connection_count: rec.map_or(0, |r| r.connection_count),
+        total_uptime_seconds: rec.map_or(0, |r| r.total_uptime_seconds) + current_uptime,
+        // 接続中の場合はメモリ上の状態、切断中の場合はDBの状態を返す
+        disabled: match conn {
+            Some(c) => c.disabled.load(Ordering::Relaxed),
+            None => rec.is_some_and(|r| r.disabled_at.is_some()),
+        },
+        draining: conn.is_some_and(|c| c.draining.load(Ordering::Relaxed)),
+        banned: rec.is_some_and(|r| r.banned_at.is_some()),
+        labels: match conn {
+            Some(c) => c.labels.read().expect("labels lock poisoned").clone(),
+            None => rec.map(|r| r.labels.clone()).unwrap_or_default(),
+        },
+        stats: conn.map(|c| c.stats(now)),
+        health: health.report(agent_id, now.timestamp()),
+        ..AgentInfo::from((agent_id, meta))
+    }
+}
+
+// Allow conversion from agent ID and metadata to AgentInfo (接続履歴の項目は空)
+impl From<(&str, &AgentMetadata)> for AgentInfo {
+    fn from((agent_id, meta): (&str, &AgentMetadata)) -> Self {
         AgentInfo {
-            agent_id: agent_id.clone(),
+            agent_id: agent_id.to_string(),
             ip: meta.ip.clone(),
             remote_host: meta.remote_host.clone(),
             country_code: meta.country_code.clone(),
@@ -103,6 +560,53 @@ impl From<(&String, &AgentMetadata)> for AgentInfo {
             hostname: meta.hostname.clone(),
             kernel_version: meta.kernel_version.clone(),
             username: meta.username.clone(),
+            geo: meta.geo.clone(),
+            online: false,
+            first_seen_at: None,
+            last_seen_at: None,
+            connected_at: None,
+            connection_count: 0,
+            total_uptime_seconds: 0,
+            disabled: false,
+            draining: false,
+            banned: false,
+            labels: Vec::new(),
+            stats: None,
+            health: AgentHealthReport::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_normalize_labels() {
+        assert_eq!(
+            normalize_labels(&labels(&[" Residential ", "tokyo-1", "residential"])),
+            Ok(labels(&["residential", "tokyo-1"]))
+        );
+        assert_eq!(normalize_labels(&[]), Ok(Vec::new()));
+        assert!(normalize_labels(&labels(&[""])).is_err());
+        assert!(normalize_labels(&labels(&["has space"])).is_err());
+        assert!(normalize_labels(&labels(&["under_score"])).is_err());
+        assert!(normalize_labels(&labels(&[&"a".repeat(MAX_LABEL_LENGTH + 1)])).is_err());
+        let many: Vec<String> = (0..=MAX_LABELS).map(|i| format!("l{}", i)).collect();
+        assert!(normalize_labels(&many).is_err());
+    }
+
+    #[test]
+    fn test_load_factor() {
+        assert_eq!(load_factor(5, None, None), 1.0);
+        assert_eq!(load_factor(0, Some(4), None), 1.0);
+        assert_eq!(load_factor(1, Some(4), Some(50.0)), 0.375);
+        // 上限に達した・CPUが飽和した場合も最小値を返す
+        assert_eq!(load_factor(4, Some(4), None), 0.05);
+        assert_eq!(load_factor(0, None, Some(120.0)), 0.05);
+    }
+}
@@ -1,31 +1,32 @@
 use std::convert::Infallible;
 
+use crate::agent;
 use crate::api::dto::{
     CommandRequestParams, CurrentUserResponse, ErrorResponse, LoginRequest, LoginResponse,
     RegisterRequest, RegisterResponse,
 };
+use crate::api_key::{create_user_api_key, delete_api_key, list_user_api_keys};
+use crate::config::RegistrationMode;
+use crate::maintenance::{list_maintenance_runs, run_maintenance};
 use crate::repository::get_user_tokens;
-use crate::repository::UserRole;
-use crate::repository::{create_user, get_user_by_id, get_user_by_username, get_user_points};
-use crate::token::generate_token;
+use crate::repository::get_user_totp;
+use crate::repository::{get_user_by_id, get_user_by_username, get_user_points, register_user};
+use crate::repository::{ApiKeyScope, UserRecord, UserRole};
 use crate::token::TokenResponse;
+use crate::token::{generate_token, revoke_all_tokens, revoke_token, rotate_user_token};
 use crate::websocket::send_message;
 use crate::AppState;
-use crate::{agent, repository::create_user_points};
 use anyhow::Result;
-use argon2::password_hash::rand_core::OsRng;
-use argon2::{
-    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Argon2,
-};
+use auth::AuthUser;
+use axum::http::header::RETRY_AFTER;
 use axum::response::sse::{Event, KeepAlive};
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, State},
     response::{IntoResponse, Response, Sse},
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Json, Router,
 };
-use axum_extra::extract::cookie::{Cookie, CookieJar};
+use axum_extra::extract::cookie::CookieJar;
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine; // for STANDARD.decode
 use chrono::Utc;
@@ -33,47 +34,187 @@ use common::Payload;
 use encoding_rs::SHIFT_JIS;
 use futures::stream::{self, StreamExt};
 use hyper::StatusCode;
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use password::{
+    change_password, create_password_reset, hash_password, reset_password, verify_password,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use session::{issue_login_session, logout, logout_all, refresh};
+use std::net::{IpAddr, SocketAddr};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
+use totp::{
+    disable_totp, enroll_totp, get_totp_status, regenerate_recovery_codes, two_factor_error,
+    verify_second_factor, verify_totp,
+};
 use uuid::Uuid;
 
 // Expose centralized DTO types
 pub mod dto;
+// Cookie / Bearer JWT / APIキーによる認証エクストラクタ
+pub(crate) mod auth;
+// アクセストークン (JWT) の失効リスト
+pub(crate) mod revocation;
+// ログインセッション (リフレッシュ・ログアウト)
+pub(crate) mod session;
+// パスワードの変更・リセットとパスワードポリシー
+pub(crate) mod password;
+// TOTPによる二要素認証
+pub(crate) mod totp;
+// 管理者向けユーザ管理
+pub(crate) mod users;
+// 招待コード
+pub(crate) mod invites;
+// 組織 (ポイント残高の共有)
+pub(crate) mod organizations;
+// SOCKS5セッションの利用履歴
+pub(crate) mod usage;
+// 管理者向けエージェント管理
+pub(crate) mod agents;
+// ダッシュボード向けのリアルタイムイベント配信 (SSE)
+pub(crate) mod events;
 
 // Insert common error response helper
-fn err(code: StatusCode, msg: &str) -> Response {
+pub(crate) fn err(code: StatusCode, msg: &str) -> Response {
     (code, Json(json!({ "error": msg }))).into_response()
 }
 
-// Insert common auth helper
-fn authenticate(state: &AppState, jar: &CookieJar) -> Result<Claims, Response> {
-    let token = jar
-        .get("session")
-        .map(|c| c.value().to_string())
-        .ok_or_else(|| err(StatusCode::UNAUTHORIZED, "Unauthenticated"))?;
-    let data = decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret(state.jwt_secret.as_ref()),
-        &Validation::default(),
-    )
-    .map_err(|_| err(StatusCode::UNAUTHORIZED, "Invalid token"))?;
-    Ok(data.claims)
-}
-
 // APIルーターを構築する関数
 pub(crate) fn build_api_router(state: AppState) -> Router {
     Router::new()
         .route("/api/register", post(register))
         .route("/api/login", post(login))
+        .route("/api/refresh", post(refresh))
+        .route("/api/logout", post(logout))
+        .route("/api/logout-all", post(logout_all))
+        .route("/api/password-reset", post(reset_password))
+        .route("/api/me/password", post(change_password))
+        .route("/api/me/2fa", get(get_totp_status).delete(disable_totp))
+        .route("/api/me/2fa/enroll", post(enroll_totp))
+        .route("/api/me/2fa/verify", post(verify_totp))
+        .route(
+            "/api/me/2fa/recovery-codes",
+            post(regenerate_recovery_codes),
+        )
+        .route("/api/admin/users", get(users::list_users))
+        .route(
+            "/api/admin/invites",
+            get(invites::list_invites).post(invites::create_invite),
+        )
+        .route(
+            "/api/admin/invites/{invite_id}",
+            delete(invites::remove_invite),
+        )
+        .route(
+            "/api/admin/users/{user_id}",
+            get(users::get_user).delete(users::remove_user),
+        )
+        .route(
+            "/api/admin/users/{user_id}/role",
+            put(users::update_user_role),
+        )
+        .route(
+            "/api/admin/users/{user_id}/suspension",
+            post(users::suspend_user).delete(users::unsuspend_user),
+        )
+        .route(
+            "/api/admin/users/{user_id}/points",
+            get(users::list_point_ledger).post(users::adjust_points),
+        )
+        .route(
+            "/api/admin/users/{user_id}/password-reset",
+            post(create_password_reset),
+        )
+        .route(
+            "/api/orgs",
+            get(organizations::list_organizations).post(organizations::create_organization),
+        )
+        .route(
+            "/api/orgs/{org_id}",
+            get(organizations::get_organization).delete(organizations::remove_organization),
+        )
+        .route(
+            "/api/orgs/{org_id}/members",
+            post(organizations::add_member),
+        )
+        .route(
+            "/api/orgs/{org_id}/members/{user_id}",
+            put(organizations::update_member_role).delete(organizations::remove_member),
+        )
+        .route(
+            "/api/orgs/{org_id}/tokens",
+            get(organizations::list_organization_tokens)
+                .post(organizations::generate_organization_token),
+        )
+        .route(
+            "/api/orgs/{org_id}/tokens/{token_id}",
+            delete(organizations::revoke_organization_token),
+        )
+        .route(
+            "/api/orgs/{org_id}/usage",
+            get(organizations::get_organization_usage),
+        )
+        .route(
+            "/api/orgs/{org_id}/points",
+            get(organizations::list_organization_ledger).post(organizations::transfer_points),
+        )
+        .route(
+            "/api/orgs/{org_id}/statements/{month}",
+            get(organizations::get_organization_statement),
+        )
         .route("/api/token", post(generate_token))
         .route("/api/agents", get(agent::list_agents))
+        .route("/api/agents/{agent_id}/stats", get(agent::get_agent_stats))
+        .route("/api/events", get(events::stream_events))
         .route("/api/command", post(execute_command))
         .route("/api/me", get(get_current_user))
-        .route("/api/me/tokens", get(list_tokens))
+        .route("/api/me/tokens", get(list_tokens).delete(revoke_all_tokens))
+        .route("/api/me/tokens/{token_id}", delete(revoke_token))
+        .route("/api/me/tokens/{token_id}/rotate", post(rotate_user_token))
+        .route(
+            "/api/me/api-keys",
+            get(list_user_api_keys).post(create_user_api_key),
+        )
+        .route("/api/me/api-keys/{key_id}", delete(delete_api_key))
+        .route("/api/me/sessions", get(usage::list_sessions))
+        .route("/api/me/usage/daily", get(usage::get_daily_usage))
+        .route("/api/me/usage/export", get(usage::export_usage))
+        .route("/api/me/statements/{month}", get(usage::get_statement))
+        .route("/api/admin/usage/export", get(usage::export_all_usage))
+        .route("/api/admin/statements/{month}", get(usage::list_statements))
+        .route(
+            "/api/admin/agents/{agent_id}",
+            get(agents::get_agent_detail),
+        )
+        .route(
+            "/api/admin/agents/{agent_id}/disable",
+            post(agents::disable_agent).delete(agents::enable_agent),
+        )
+        .route(
+            "/api/admin/agents/{agent_id}/drain",
+            post(agents::drain_agent),
+        )
+        .route(
+            "/api/admin/agents/{agent_id}/kick",
+            post(agents::kick_agent),
+        )
+        .route(
+            "/api/admin/agents/{agent_id}/ban",
+            post(agents::ban_agent).delete(agents::unban_agent),
+        )
+        .route(
+            "/api/admin/agents/{agent_id}/labels",
+            put(agents::update_agent_labels),
+        )
+        .route(
+            "/api/admin/agents/{agent_id}/ip-history",
+            get(agents::get_agent_ip_history),
+        )
+        .route(
+            "/api/admin/maintenance/runs",
+            get(list_maintenance_runs).post(run_maintenance),
+        )
         .with_state(state)
 }
 
@@ -87,13 +228,9 @@ pub(crate) fn build_api_router(state: AppState) -> Router {
     ),
     tag = "Auth"
 )]
-async fn get_current_user(State(state): State<AppState>, jar: CookieJar) -> impl IntoResponse {
-    let claims = match authenticate(&state, &jar) {
-        Ok(c) => c,
-        Err(resp) => return resp.into_response(),
-    };
+async fn get_current_user(State(state): State<AppState>, auth: AuthUser) -> impl IntoResponse {
     // ユーザID取得
-    let user_id = Uuid::parse_str(&claims.sub).unwrap();
+    let user_id = auth.user_id;
     // ユーザ情報取得
     match get_user_by_id(&state.db_pool, user_id).await {
         Ok(Some(user)) => {
@@ -129,16 +266,12 @@ async fn get_current_user(State(state): State<AppState>, jar: CookieJar) -> impl
 )]
 async fn execute_command(
     State(state): State<AppState>,
-    jar: CookieJar,
+    auth: AuthUser,
     Json(req): Json<CommandRequestParams>,
 ) -> impl IntoResponse {
-    let claims = match authenticate(&state, &jar) {
-        Ok(c) => c,
-        Err(resp) => return resp,
-    };
     // 管理者権限チェック
-    if claims.role != UserRole::Admin {
-        return err(StatusCode::FORBIDDEN, "管理者のみ実行可能");
+    if let Err(resp) = auth.require_admin() {
+        return resp;
     }
     let request_id = Uuid::now_v7().to_string(); // Use now_v7() for current time
     info!(
@@ -233,7 +366,7 @@ async fn execute_command(
                                 .map(|line| {
                                     // SSEデータフィールドは改行を含められないため、trim_end_matches('\r')で末尾の\rを削除
                                     let trimmed_line = line.trim_end_matches('\r');
-                                    Ok(Event::default().event(event_type).data(trimmed_line.to_string()))
+                                    Ok(Event::default().event(event_type).data(trimmed_line))
                                 })
                                 .collect();
 
@@ -260,7 +393,7 @@ async fn execute_command(
                         // 失敗時はエラーメッセージを行ごとに分割して "error" イベントとして送信
                         let error_str = error_message.unwrap_or_else(|| "Unknown error".to_string());
                         for line in error_str.lines() {
-                             events.push(Ok(Event::default().event("error").data(line.trim_end_matches('\r').to_string())));
+                             events.push(Ok(Event::default().event("error").data(line.trim_end_matches('\r'))));
                         }
                         // 失敗時も終了コードがあれば "done" イベントとして送信
                         events.push(Ok(Event::default().event("done").data(format!("ExitCode: {:?}", exit_code))));
@@ -286,7 +419,7 @@ async fn execute_command(
     // 指定されたエージェントが見つからない場合
     error!("[{}] Agent not found: {}", request_id, req.agent_id);
     // 404 Not Found エラーを返す (SSEではなく通常のJSONレスポンス)
-    return err(StatusCode::NOT_FOUND, "Agent not found");
+    err(StatusCode::NOT_FOUND, "Agent not found")
 }
 
 // JWTのクレーム
@@ -294,16 +427,27 @@ async fn execute_command(
 pub struct Claims {
     pub sub: String,
     pub role: UserRole,
+    // 発行日時・有効期限 (UNIXタイムスタンプ)
+    pub iat: usize,
     pub exp: usize,
+    // JWT ID (ログアウト時の失効に使用)
+    pub jti: String,
+    // 二要素認証の登録のみ許可するセッションか
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub enrollment_only: bool,
 }
 
 // ユーザ登録エンドポイント
+// registration_mode に応じて登録を制限し、招待コードがあれば付与ポイントを加算する
 #[utoipa::path(
     post,
     path = "/api/register",
     request_body = RegisterRequest,
     responses(
         (status = 201, description = "User created", body = RegisterResponse),
+        (status = 400, description = "Password violates the password policy, or invalid invite code", body = ErrorResponse),
+        (status = 403, description = "Registration is disabled or requires an invite code", body = ErrorResponse),
+        (status = 409, description = "Username already taken", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     tag = "Auth"
@@ -312,96 +456,182 @@ async fn register(
     State(state): State<AppState>,
     Json(req): Json<RegisterRequest>,
 ) -> impl IntoResponse {
-    let user_id = Uuid::now_v7();
-    // Generate salt and hash password
-    let salt = SaltString::generate(OsRng);
-    let password_hash = Argon2::default()
-        .hash_password(req.password.as_bytes(), &salt)
-        .unwrap()
-        .to_string();
+    let invite_code = req
+        .invite_code
+        .as_deref()
+        .map(str::trim)
+        .filter(|c| !c.is_empty());
+    match state.settings.registration_mode {
+        RegistrationMode::Disabled => {
+            return err(StatusCode::FORBIDDEN, "Registration is disabled");
+        }
+        RegistrationMode::InviteOnly if invite_code.is_none() => {
+            return err(StatusCode::FORBIDDEN, "An invite code is required");
+        }
+        _ => {}
+    }
+    // パスワードポリシーの検証
+    if let Err(msg) = state
+        .settings
+        .password_policy
+        .validate(&req.username, &req.password)
+    {
+        return err(StatusCode::BAD_REQUEST, &msg);
+    }
     let now = Utc::now();
-    // users テーブルに登録
-    if let Err(e) = create_user(
+    let invalid_invite = || err(StatusCode::BAD_REQUEST, "Invalid or expired invite code");
+    let invite = match invite_code {
+        Some(code) => match invites::find_valid_invite(&state, code, now).await {
+            Ok(Some(rec)) => Some(rec),
+            Ok(None) => return invalid_invite(),
+            Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        },
+        None => None,
+    };
+    let user = UserRecord {
+        id: Uuid::now_v7(),
+        username: req.username,
+        password_hash: hash_password(&req.password),
+        role: UserRole::User,
+        created_at: now,
+        suspended_at: None,
+        suspension_reason: None,
+    };
+    // 初期ポイント + 招待コードの付与ポイント
+    let points = state
+        .settings
+        .starting_points
+        .saturating_add(invite.as_ref().map_or(0, |i| i.points));
+    let reason = match &invite {
+        Some(i) => format!("Registration (invite {})", i.code_prefix),
+        None => "Registration".to_string(),
+    };
+    match register_user(
         &state.db_pool,
-        user_id,
-        &req.username,
-        &password_hash,
-        UserRole::User,
-        now,
+        &user,
+        points,
+        invite.as_ref().map(|i| i.id),
+        &reason,
     )
     .await
     {
-        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+        Ok(true) => {}
+        // 検証後に他のリクエストで使用された場合
+        Ok(false) => return invalid_invite(),
+        Err(e)
+            if e.as_database_error()
+                .is_some_and(|d| d.is_unique_violation()) =>
+        {
+            return err(StatusCode::CONFLICT, "Username already taken");
+        }
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
     }
-    // user_points 初期化
-    // 初期段階では1000ポイントを付与
-    if let Err(e) = create_user_points(&state.db_pool, user_id, 1000, now).await {
-        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    if let Some(i) = &invite {
+        info!("User {} registered with invite code {}", user.id, i.id);
     }
-    return (
+    (
         StatusCode::CREATED,
-        Json(json!({ "user_id": user_id.to_string() })),
+        Json(json!({ "user_id": user.id.to_string() })),
     )
-        .into_response();
+        .into_response()
 }
 
 // ログインエンドポイント
+// 接続元IP・ユーザ名ごとに失敗回数を数え、上限を超えると一定時間ログインを拒否する
 #[utoipa::path(
     post,
     path = "/api/login",
     request_body = LoginRequest,
     responses(
         (status = 200, description = "Login successful", body = LoginResponse),
-        (status = 401, description = "Invalid credentials", body = ErrorResponse)
+        (status = 401, description = "Invalid credentials or missing/invalid two-factor code", body = ErrorResponse),
+        (status = 403, description = "Account suspended", body = ErrorResponse),
+        (status = 429, description = "Too many failed attempts; see the Retry-After header", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     tag = "Auth"
 )]
 async fn login(
     State(state): State<AppState>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
     jar: CookieJar,
     Json(req): Json<LoginRequest>,
-) -> Result<(CookieJar, Json<serde_json::Value>), (StatusCode, Json<serde_json::Value>)> {
-    // ユーザ取得
+) -> Response {
+    let ip = client_addr.ip();
+    let now = Utc::now().timestamp();
+    // ロック中の場合はパスワードを検証せずに拒否
+    let locked_for = state.lockouts.login_ips.locked_for(&ip, now).max(
+        state
+            .lockouts
+            .login_usernames
+            .locked_for(&req.username, now),
+    );
+    if let Some(retry_after) = locked_for {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, retry_after.to_string())],
+            Json(json!({"error": "Too many failed login attempts"})),
+        )
+            .into_response();
+    }
+    // ユーザ取得とパスワード検証
     let user = match get_user_by_username(&state.db_pool, &req.username).await {
-        Ok(Some(u)) => u,
-        _ => {
-            return Err((
-                StatusCode::UNAUTHORIZED,
-                Json(json!({"error":"Invalid credentials"})),
-            ))
+        Ok(Some(u)) if verify_password(&req.password, &u.password_hash) => u,
+        Ok(_) => {
+            record_login_failure(&state, ip, &req.username, now);
+            return err(StatusCode::UNAUTHORIZED, "Invalid credentials");
         }
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
     };
-    // パスワード検証
-    let parsed = PasswordHash::new(&user.password_hash).unwrap();
-    if Argon2::default()
-        .verify_password(req.password.as_bytes(), &parsed)
-        .is_err()
-    {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(json!({"error":"Invalid credentials"})),
-        ));
+    // 停止中のアカウントはログイン不可
+    if user.suspended_at.is_some() {
+        return err(StatusCode::FORBIDDEN, "Account suspended");
     }
-    // JWT生成
-    let exp = (Utc::now().timestamp() + 3600) as usize;
-    let claims = Claims {
-        sub: user.id.to_string(),
-        role: user.role.clone(),
-        exp,
+    // 二要素認証が有効な場合はコードを検証
+    let totp = match get_user_totp(&state.db_pool, user.id).await {
+        Ok(totp) => totp,
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
     };
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(state.jwt_secret.as_ref()),
-    )
-    .unwrap();
-    // Cookieに設定
-    let cookie = Cookie::build(("session", token))
-        .path("/")
-        .http_only(true)
-        .build();
-    let jar = jar.add(cookie);
-    Ok((jar, Json(json!({"success": true}))))
+    if let Some(totp) = totp.filter(|t| t.enabled_at.is_some()) {
+        let Some(code) = req.totp_code.as_deref().filter(|c| !c.trim().is_empty()) else {
+            return two_factor_error("Two-factor authentication code required").into_response();
+        };
+        match verify_second_factor(&state, &totp, code).await {
+            Ok(true) => {}
+            Ok(false) => {
+                record_login_failure(&state, ip, &req.username, now);
+                return two_factor_error("Invalid two-factor authentication code").into_response();
+            }
+            Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        }
+    }
+    state.lockouts.login_usernames.record_success(&req.username);
+    // アクセストークンとリフレッシュトークンを発行してCookieに設定
+    match issue_login_session(&state, jar, user.id, user.role).await {
+        Ok((jar, resp)) => (jar, Json(resp)).into_response(),
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// ログインの失敗を記録し、ロックした場合はログに残す
+fn record_login_failure(state: &AppState, ip: IpAddr, username: &str, now: i64) {
+    warn!("Failed login for user {:?} from {}", username, ip);
+    if let Some(seconds) = state.lockouts.login_ips.record_failure(ip, now) {
+        warn!(
+            "Locking out logins from {} for {} seconds after repeated failures",
+            ip, seconds
+        );
+    }
+    if let Some(seconds) = state
+        .lockouts
+        .login_usernames
+        .record_failure(username.to_string(), now)
+    {
+        warn!(
+            "Locking out logins for user {:?} for {} seconds after repeated failures",
+            username, seconds
+        );
+    }
 }
 
 // 新規: 認証済みユーザの有効トークン一覧取得エンドポイント
@@ -411,29 +641,26 @@ async fn login(
     responses(
         (status = 200, description = "List of tokens", body = [TokenResponse]),
         (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the tokens:manage scope", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     tag = "Auth"
 )]
-async fn list_tokens(State(state): State<AppState>, jar: CookieJar) -> impl IntoResponse {
-    let claims = match authenticate(&state, &jar) {
-        Ok(c) => c,
-        Err(resp) => return resp,
-    };
-    let user_id = Uuid::parse_str(&claims.sub).unwrap();
+async fn list_tokens(State(state): State<AppState>, auth: AuthUser) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::TokensManage) {
+        return resp;
+    }
+    let user_id = auth.user_id;
 
     // DBからトークン取得
     match get_user_tokens(&state.db_pool, user_id).await {
         Ok(recs) => {
             let resp: Vec<TokenResponse> = recs
                 .into_iter()
-                .map(|r| TokenResponse {
-                    token: r.token,
-                    expires_at: r.expires_at.timestamp(),
-                })
+                .map(|r| TokenResponse::from_record(r, &state.sessions))
                 .collect();
             (StatusCode::OK, Json(resp)).into_response()
         }
-        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
     }
 }
@@ -0,0 +1,451 @@
+use super::auth::AuthUser;
+use super::dto::ErrorResponse;
+use super::err;
+use super::users::validate_reason;
+use crate::agent::{agent_info, normalize_labels, AgentConnection, AgentInfo};
+use crate::repository::{
+    get_agent, get_agent_ip_changes, set_agent_banned, set_agent_disabled, set_agent_labels,
+    AgentRecord,
+};
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::Utc;
+use hyper::StatusCode;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// エージェントBANリクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct BanAgentRequest {
+    pub reason: Option<String>,
+}
+
+// ラベル設定リクエスト (既存のラベルを置き換える)
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct AgentLabelsRequest {
+    pub labels: Vec<String>,
+}
+
+// ラベル設定のレスポンス
+#[derive(Serialize, ToSchema)]
+pub(crate) struct AgentLabelsResponse {
+    pub agent_id: String,
+    pub labels: Vec<String>,
+}
+
+// 出口IPの変更履歴の項目
+#[derive(Serialize, ToSchema)]
+pub(crate) struct AgentIpChange {
+    // 変更を検出した接続
+    pub connection_id: Uuid,
+    // UNIXタイムスタンプで表現される変更日時
+    pub changed_at: i64,
+    pub old_ip: String,
+    pub new_ip: String,
+    // 変更後の国コード (GeoIPで検証できた場合は検証結果)
+    pub country_code: String,
+}
+
+// 返却する出口IPの変更履歴の件数
+const IP_HISTORY_LIMIT: i64 = 100;
+
+// 永続化された状態と接続中の接続を取得する (どちらもない場合は 404)
+async fn find_agent(
+    state: &AppState,
+    agent_id: &str,
+) -> Result<(Option<AgentRecord>, Option<Arc<AgentConnection>>), Response> {
+    let rec = get_agent(&state.db_pool, agent_id)
+        .await
+        .map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()))?;
+    let conn = state.agents.get(agent_id).map(|e| e.value().clone());
+    if rec.is_none() && conn.is_none() {
+        return Err(err(StatusCode::NOT_FOUND, "Agent not found"));
+    }
+    Ok((rec, conn))
+}
+
+// 接続中のエージェントを取得する (切断中の場合は 404)
+#[allow(clippy::result_large_err)]
+fn find_connected_agent(
+    state: &AppState,
+    agent_id: &str,
+) -> Result<Arc<AgentConnection>, Response> {
+    state
+        .agents
+        .get(agent_id)
+        .map(|e| e.value().clone())
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "Agent is not connected"))
+}
+
+// エージェントの詳細 (管理状態を含む) を取得するエンドポイント (管理者のみ)
+#[utoipa::path(
+    get,
+    path = "/api/admin/agents/{agent_id}",
+    params(("agent_id" = String, Path, description = "Agent ID")),
+    responses(
+        (status = 200, description = "Agent details", body = AgentInfo),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn get_agent_detail(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let (rec, conn) = match find_agent(&state, &agent_id).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    let live_meta = conn.as_ref().map(|c| c.metadata());
+    let meta = match (&live_meta, &rec) {
+        (Some(m), _) => m.as_ref(),
+        (None, Some(r)) => &r.metadata,
+        (None, None) => unreachable!("find_agent returns 404 when the agent is unknown"),
+    };
+    let info = agent_info(
+        &agent_id,
+        meta,
+        rec.as_ref(),
+        conn.as_deref(),
+        &state.agent_health,
+        Utc::now(),
+    );
+    (StatusCode::OK, Json(info)).into_response()
+}
+
+// エージェントを無効化するエンドポイント (管理者のみ)
+// 新規トンネルに使用しなくなる (使用中のトンネルは継続)。再接続後も維持される
+#[utoipa::path(
+    post,
+    path = "/api/admin/agents/{agent_id}/disable",
+    params(("agent_id" = String, Path, description = "Agent ID")),
+    responses(
+        (status = 204, description = "Agent disabled"),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn disable_agent(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+) -> impl IntoResponse {
+    set_disabled(state, auth, agent_id, true).await
+}
+
+// エージェントの無効化を解除するエンドポイント (管理者のみ)
+#[utoipa::path(
+    delete,
+    path = "/api/admin/agents/{agent_id}/disable",
+    params(("agent_id" = String, Path, description = "Agent ID")),
+    responses(
+        (status = 204, description = "Agent enabled"),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn enable_agent(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+) -> impl IntoResponse {
+    set_disabled(state, auth, agent_id, false).await
+}
+
+// 無効化の状態をDBと接続中のエージェントに反映する
+async fn set_disabled(
+    state: AppState,
+    auth: AuthUser,
+    agent_id: String,
+    disabled: bool,
+) -> Response {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let (_, conn) = match find_agent(&state, &agent_id).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    let disabled_at = disabled.then(Utc::now);
+    if let Err(e) = set_agent_disabled(&state.db_pool, &agent_id, disabled_at).await {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    if let Some(conn) = conn {
+        conn.disabled.store(disabled, Ordering::Relaxed);
+    }
+    info!(
+        "Admin {} {} agent {}",
+        auth.user_id,
+        if disabled { "disabled" } else { "enabled" },
+        agent_id
+    );
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// エージェントをドレインするエンドポイント (管理者のみ)
+// 新規トンネルに使用せず、使用中のトンネルがすべて終了した時点で切断する
+#[utoipa::path(
+    post,
+    path = "/api/admin/agents/{agent_id}/drain",
+    params(("agent_id" = String, Path, description = "Agent ID")),
+    responses(
+        (status = 202, description = "Agent is draining", body = AgentInfo),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Agent is not connected", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn drain_agent(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let conn = match find_connected_agent(&state, &agent_id) {
+        Ok(conn) => conn,
+        Err(resp) => return resp,
+    };
+    let rec = match get_agent(&state.db_pool, &agent_id).await {
+        Ok(rec) => rec,
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    conn.drain();
+    info!(
+        "Admin {} started draining agent {} ({} active tunnels)",
+        auth.user_id,
+        agent_id,
+        conn.active_tunnels()
+    );
+    let info = agent_info(
+        &agent_id,
+        &conn.metadata(),
+        rec.as_ref(),
+        Some(&conn),
+        &state.agent_health,
+        Utc::now(),
+    );
+    (StatusCode::ACCEPTED, Json(info)).into_response()
+}
+
+// エージェントを強制的に切断するエンドポイント (管理者のみ)
+// エージェントは再接続できるため、接続させない場合は無効化またはBANを使用する
+#[utoipa::path(
+    post,
+    path = "/api/admin/agents/{agent_id}/kick",
+    params(("agent_id" = String, Path, description = "Agent ID")),
+    responses(
+        (status = 204, description = "Agent disconnected"),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Agent is not connected", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn kick_agent(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let conn = match find_connected_agent(&state, &agent_id) {
+        Ok(conn) => conn,
+        Err(resp) => return resp,
+    };
+    conn.disconnect();
+    info!("Admin {} disconnected agent {}", auth.user_id, agent_id);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// エージェントIDをBANするエンドポイント (管理者のみ)
+// 接続中の場合は切断し、以降の接続を拒否する
+#[utoipa::path(
+    post,
+    path = "/api/admin/agents/{agent_id}/ban",
+    params(("agent_id" = String, Path, description = "Agent ID")),
+    request_body = BanAgentRequest,
+    responses(
+        (status = 204, description = "Agent banned"),
+        (status = 400, description = "Reason too long", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn ban_agent(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+    Json(req): Json<BanAgentRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let reason = req
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|r| !r.is_empty());
+    if let Err(resp) = validate_reason(reason.unwrap_or_default()) {
+        return resp;
+    }
+    match set_agent_banned(&state.db_pool, &agent_id, Some(Utc::now()), reason).await {
+        Ok(0) => return err(StatusCode::NOT_FOUND, "Agent not found"),
+        Ok(_) => {}
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+    if let Some(conn) = state.agents.get(&agent_id) {
+        conn.disconnect();
+    }
+    info!("Admin {} banned agent {}", auth.user_id, agent_id);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// エージェントIDのBANを解除するエンドポイント (管理者のみ)
+#[utoipa::path(
+    delete,
+    path = "/api/admin/agents/{agent_id}/ban",
+    params(("agent_id" = String, Path, description = "Agent ID")),
+    responses(
+        (status = 204, description = "Agent unbanned"),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn unban_agent(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    match set_agent_banned(&state.db_pool, &agent_id, None, None).await {
+        Ok(0) => err(StatusCode::NOT_FOUND, "Agent not found"),
+        Ok(_) => {
+            info!("Admin {} unbanned agent {}", auth.user_id, agent_id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// エージェントのラベルを設定するエンドポイント (管理者のみ)
+// ラベルは SOCKS5 のユーザー名 label_<name> でエージェントを選択する際に使用する
+#[utoipa::path(
+    put,
+    path = "/api/admin/agents/{agent_id}/labels",
+    params(("agent_id" = String, Path, description = "Agent ID")),
+    request_body = AgentLabelsRequest,
+    responses(
+        (status = 200, description = "Labels updated", body = AgentLabelsResponse),
+        (status = 400, description = "Invalid labels", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn update_agent_labels(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+    Json(req): Json<AgentLabelsRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let labels = match normalize_labels(&req.labels) {
+        Ok(labels) => labels,
+        Err(msg) => return err(StatusCode::BAD_REQUEST, &msg),
+    };
+    let (_, conn) = match find_agent(&state, &agent_id).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    if let Err(e) = set_agent_labels(&state.db_pool, &agent_id, &labels).await {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    if let Some(conn) = conn {
+        *conn.labels.write().expect("labels lock poisoned") = labels.clone();
+    }
+    info!(
+        "Admin {} set labels of agent {} to {:?}",
+        auth.user_id, agent_id, labels
+    );
+    let resp = AgentLabelsResponse { agent_id, labels };
+    (StatusCode::OK, Json(resp)).into_response()
+}
+
+// エージェントの出口IPの変更履歴 (新しい順、最大100件) を取得するエンドポイント (管理者のみ)
+#[utoipa::path(
+    get,
+    path = "/api/admin/agents/{agent_id}/ip-history",
+    params(("agent_id" = String, Path, description = "Agent ID")),
+    responses(
+        (status = 200, description = "Exit IP changes, newest first", body = [AgentIpChange]),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn get_agent_ip_history(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    if let Err(resp) = find_agent(&state, &agent_id).await {
+        return resp;
+    }
+    match get_agent_ip_changes(&state.db_pool, &agent_id, IP_HISTORY_LIMIT).await {
+        Ok(recs) => {
+            let changes: Vec<AgentIpChange> = recs
+                .into_iter()
+                .map(|r| AgentIpChange {
+                    connection_id: r.connection_id,
+                    changed_at: r.changed_at.timestamp(),
+                    old_ip: r.old_ip,
+                    new_ip: r.new_ip,
+                    country_code: r.country_code,
+                })
+                .collect();
+            (StatusCode::OK, Json(changes)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
@@ -0,0 +1,250 @@
+use super::revocation::issued_at_millis;
+use super::session::SESSION_COOKIE;
+use super::{err, Claims};
+use crate::api_key::API_KEY_PREFIX;
+use crate::repository::{
+    get_api_key, get_user_api_keys, get_user_by_id, touch_api_key, ApiKeyScope, UserRole,
+};
+use crate::AppState;
+use axum::extract::FromRequestParts;
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use axum::response::Response;
+use axum_extra::extract::cookie::CookieJar;
+use chrono::Utc;
+use hyper::StatusCode;
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use log::{error, warn};
+use uuid::Uuid;
+
+// APIキーを指定する専用ヘッダー
+const API_KEY_HEADER: &str = "x-api-key";
+// 二要素認証の登録専用セッションで利用できるパス
+const ENROLLMENT_ONLY_PATHS: &[&str] = &["/api/me", "/api/logout"];
+const ENROLLMENT_PATH_PREFIX: &str = "/api/me/2fa";
+
+// 認証済みのリクエスト主体を表すエクストラクタ
+// 以下のいずれかで認証する
+// - セッションCookie (session)
+// - Authorization: Bearer <JWT>
+// - APIキー (Authorization: Bearer chk_... または X-API-Key ヘッダー)
+pub(crate) struct AuthUser {
+    pub user_id: Uuid,
+    pub role: UserRole,
+    pub credential: Credential,
+}
+
+// 認証に使用された資格情報
+pub(crate) enum Credential {
+    // ログインセッションのJWT (セッションCookie / Bearer JWT)
+    Session {
+        jti: Uuid,
+        // JWTの発行日時 (UNIXタイムスタンプ, ミリ秒。失効リストの照会に使用)
+        issued_at: i64,
+        // JWTの有効期限 (UNIXタイムスタンプ)
+        expires_at: i64,
+    },
+    // APIキー
+    ApiKey(ApiKeyAuth),
+}
+
+// APIキー認証の情報
+pub(crate) struct ApiKeyAuth {
+    pub id: Uuid,
+    pub scopes: Vec<ApiKeyScope>,
+    // キーの有効期限 (UNIXタイムスタンプ, None は無期限)
+    pub expires_at: Option<i64>,
+}
+
+impl AuthUser {
+    // スコープが不足している場合は 403 を返す
+    // JWT認証 (セッションCookie / Bearer JWT) の場合は全スコープを持つ
+    #[allow(clippy::result_large_err)]
+    pub fn require_scope(&self, scope: ApiKeyScope) -> Result<(), Response> {
+        match &self.credential {
+            Credential::ApiKey(key) if !key.scopes.contains(&scope) => {
+                warn!("API key {} lacks the {:?} scope", key.id, scope);
+                Err(err(
+                    StatusCode::FORBIDDEN,
+                    "API key lacks the required scope",
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // スコープを持つか (JWT認証の場合は常に true)
+    pub fn has_scope(&self, scope: ApiKeyScope) -> bool {
+        match &self.credential {
+            Credential::ApiKey(key) => key.scopes.contains(&scope),
+            Credential::Session { .. } => true,
+        }
+    }
+
+    // 資格情報の有効期限 (UNIXタイムスタンプ, None は無期限)
+    pub fn expires_at(&self) -> Option<i64> {
+        match &self.credential {
+            Credential::Session { expires_at, .. } => Some(*expires_at),
+            Credential::ApiKey(key) => key.expires_at,
+        }
+    }
+
+    // ログインセッションが失効リストに含まれるか (APIキーの場合は false)
+    // 全セッションのログアウト・アカウント停止・ロール変更はいずれも失効リストに反映される
+    pub fn is_session_revoked(&self, state: &AppState) -> bool {
+        match &self.credential {
+            Credential::Session { jti, issued_at, .. } => {
+                state.revocations.is_revoked(*jti, self.user_id, *issued_at)
+            }
+            Credential::ApiKey(_) => false,
+        }
+    }
+
+    // 認証時の状態のまま有効か確認する (長時間続く接続の再検証に使用)
+    // APIキーの場合は、キーの削除・期限切れ、ユーザの停止・削除・ロール変更をDBで確認する
+    pub async fn is_still_authorized(&self, state: &AppState) -> sqlx::Result<bool> {
+        let now = Utc::now().timestamp();
+        if self.expires_at().is_some_and(|exp| exp <= now) || self.is_session_revoked(state) {
+            return Ok(false);
+        }
+        let Credential::ApiKey(key) = &self.credential else {
+            return Ok(true);
+        };
+        let key_exists = get_user_api_keys(&state.db_pool, self.user_id)
+            .await?
+            .iter()
+            .any(|k| k.id == key.id);
+        let user = get_user_by_id(&state.db_pool, self.user_id).await?;
+        Ok(key_exists && user.is_some_and(|u| u.suspended_at.is_none() && u.role == self.role))
+    }
+
+    // 管理者ユーザ (APIキーの場合は admin スコープも必要) でなければ 403 を返す
+    #[allow(clippy::result_large_err)]
+    pub fn require_admin(&self) -> Result<(), Response> {
+        if self.role != UserRole::Admin {
+            return Err(err(StatusCode::FORBIDDEN, "管理者のみ実行可能"));
+        }
+        self.require_scope(ApiKeyScope::Admin)
+    }
+
+    // APIキー以外 (ログインセッション) での認証を要求し、JWT ID と有効期限を返す
+    #[allow(clippy::result_large_err)]
+    pub fn require_session(&self) -> Result<(Uuid, i64), Response> {
+        match &self.credential {
+            Credential::Session {
+                jti, expires_at, ..
+            } => Ok((*jti, *expires_at)),
+            Credential::ApiKey(_) => Err(err(
+                StatusCode::FORBIDDEN,
+                "This operation is not available with an API key",
+            )),
+        }
+    }
+}
+
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Response> {
+        // Authorization: Bearer ... を優先し、なければ X-API-Key、セッションCookie の順に参照
+        let bearer = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|v| v.trim().to_string());
+        let api_key_header = parts
+            .headers
+            .get(API_KEY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string());
+
+        match (bearer, api_key_header) {
+            (Some(key), _) if key.starts_with(API_KEY_PREFIX) => {
+                authenticate_api_key(state, &key).await
+            }
+            (Some(jwt), _) => authenticate_jwt(state, &jwt, parts.uri.path()),
+            (None, Some(key)) => authenticate_api_key(state, &key).await,
+            (None, None) => {
+                let jar = CookieJar::from_headers(&parts.headers);
+                let jwt = jar
+                    .get(SESSION_COOKIE)
+                    .map(|c| c.value().to_string())
+                    .ok_or_else(|| err(StatusCode::UNAUTHORIZED, "Unauthenticated"))?;
+                authenticate_jwt(state, &jwt, parts.uri.path())
+            }
+        }
+    }
+}
+
+// JWTを検証する (署名・有効期限に加え、失効リストも確認する)
+// 二要素認証の登録専用セッションは、登録に必要なパス以外では 403 を返す
+#[allow(clippy::result_large_err)]
+fn authenticate_jwt(state: &AppState, jwt: &str, path: &str) -> Result<AuthUser, Response> {
+    let claims = decode::<Claims>(
+        jwt,
+        &DecodingKey::from_secret(state.jwt_secret.as_ref()),
+        &Validation::default(),
+    )
+    .map_err(|_| err(StatusCode::UNAUTHORIZED, "Invalid token"))?
+    .claims;
+    let invalid = || err(StatusCode::UNAUTHORIZED, "Invalid token");
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| invalid())?;
+    let jti = Uuid::parse_str(&claims.jti).map_err(|_| invalid())?;
+    let issued_at = issued_at_millis(jti, claims.iat as i64);
+    if state.revocations.is_revoked(jti, user_id, issued_at) {
+        return Err(err(StatusCode::UNAUTHORIZED, "Session has been revoked"));
+    }
+    if claims.enrollment_only
+        && !ENROLLMENT_ONLY_PATHS.contains(&path)
+        && !path.starts_with(ENROLLMENT_PATH_PREFIX)
+    {
+        return Err(err(
+            StatusCode::FORBIDDEN,
+            "Two-factor authentication enrollment required",
+        ));
+    }
+    Ok(AuthUser {
+        user_id,
+        role: claims.role,
+        credential: Credential::Session {
+            jti,
+            issued_at,
+            expires_at: claims.exp as i64,
+        },
+    })
+}
+
+// APIキーを検証する (ロールはDB上の現在値を使用)
+async fn authenticate_api_key(state: &AppState, key: &str) -> Result<AuthUser, Response> {
+    let invalid = || err(StatusCode::UNAUTHORIZED, "Invalid API key");
+    let rec = match get_api_key(&state.db_pool, &state.token_hasher.hash(key)).await {
+        Ok(Some(rec)) => rec,
+        Ok(None) => return Err(invalid()),
+        Err(e) => return Err(err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())),
+    };
+    let now = Utc::now();
+    if rec.expires_at.is_some_and(|exp| exp <= now) {
+        return Err(invalid());
+    }
+    let user = match get_user_by_id(&state.db_pool, rec.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(invalid()),
+        Err(e) => return Err(err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())),
+    };
+    if user.suspended_at.is_some() {
+        return Err(err(StatusCode::FORBIDDEN, "Account suspended"));
+    }
+    if let Err(e) = touch_api_key(&state.db_pool, rec.id, now).await {
+        error!("Failed to update last use of API key {}: {}", rec.id, e);
+    }
+    Ok(AuthUser {
+        user_id: user.id,
+        role: user.role,
+        credential: Credential::ApiKey(ApiKeyAuth {
+            id: rec.id,
+            scopes: rec.scopes,
+            expires_at: rec.expires_at.map(|exp| exp.timestamp()),
+        }),
+    })
+}
@@ -16,18 +16,26 @@ pub struct RegisterResponse {
 #[derive(Serialize, ToSchema)]
 pub struct LoginResponse {
     pub success: bool,
+    // 二要素認証の登録が必要な場合は true (登録以外の操作はできないセッションが発行される)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub two_factor_enrollment_required: bool,
 }
 
 #[derive(Deserialize, ToSchema)]
 pub struct RegisterRequest {
     pub username: String,
     pub password: String,
+    // 招待コード (registration_mode = "invite_only" の場合は必須)
+    #[serde(default)]
+    pub invite_code: Option<String>,
 }
 
 #[derive(Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
+    // 二要素認証が有効な場合のコード (認証アプリのコードまたはリカバリーコード)
+    pub totp_code: Option<String>,
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -39,6 +47,9 @@ pub struct CommandRequestParams {
 #[derive(Deserialize, ToSchema, IntoParams)]
 pub struct AgentQuery {
     pub country: Option<String>,
+    // 切断中のエージェントも最後に報告された情報で含める (デフォルト false)
+    #[serde(default)]
+    pub include_offline: bool,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -0,0 +1,135 @@
+use super::auth::AuthUser;
+use super::dto::ErrorResponse;
+use super::err;
+use crate::events::EventEnvelope;
+use crate::repository::{get_user_memberships, ApiKeyScope};
+use crate::AppState;
+use axum::extract::State;
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::response::IntoResponse;
+use chrono::Utc;
+use futures::{future, stream};
+use hyper::StatusCode;
+use log::{debug, warn};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval_at, sleep_until, Instant};
+
+// 購読中に資格情報の有効性を再確認する間隔 (秒)
+// ログインセッションの失効はイベントごとにも確認する
+const AUTH_RECHECK_SECONDS: u64 = 30;
+
+// ダッシュボード向けのイベントを Server-Sent Events で配信するエンドポイント
+// 管理者は全イベント、一般ユーザはエージェントのイベントと自分のトンネル・残高 (所属組織の残高を含む) を受信する
+// APIキーの場合、トンネル・残高のイベントは usage:read スコープを持つ場合のみ配信する
+// 所属組織は接続時点のものを使用する (変更を反映するには再接続する)
+// 資格情報の期限切れ・失効 (ログアウト・アカウント停止・ロール変更) で配信を終了する
+#[utoipa::path(
+    get,
+    path = "/api/events",
+    responses(
+        (status = 200, description = "Server-Sent Events stream. The event name is the `type` of the data", content_type = "text/event-stream", body = EventEnvelope),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the agents:read scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Events"
+)]
+pub(crate) async fn stream_events(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::AgentsRead) {
+        return resp;
+    }
+    let is_admin = auth.require_admin().is_ok();
+    let usage_events = auth.has_scope(ApiKeyScope::UsageRead);
+    let organization_ids: Vec<_> = if is_admin {
+        Vec::new()
+    } else {
+        match get_user_memberships(&state.db_pool, auth.user_id).await {
+            Ok(recs) => recs.into_iter().map(|m| m.organization_id).collect(),
+            Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        }
+    };
+    let user_id = auth.user_id;
+    debug!("User {} subscribed to events", user_id);
+    // 資格情報の有効期限に配信を終了する
+    let expires_at = auth.expires_at().map(|exp| {
+        let remaining = (exp - Utc::now().timestamp()).max(0) as u64;
+        Instant::now() + Duration::from_secs(remaining)
+    });
+    let recheck = interval_at(
+        Instant::now() + Duration::from_secs(AUTH_RECHECK_SECONDS),
+        Duration::from_secs(AUTH_RECHECK_SECONDS),
+    );
+    let subscription = (state.events.subscribe(), recheck, state, auth);
+    let events = stream::unfold(subscription, move |(mut rx, mut recheck, state, auth)| {
+        let organization_ids = organization_ids.clone();
+        async move {
+            loop {
+                let expired = async {
+                    match expires_at {
+                        Some(at) => sleep_until(at).await,
+                        None => future::pending().await,
+                    }
+                };
+                let received = tokio::select! {
+                    received = rx.recv() => received,
+                    _ = recheck.tick() => {
+                        match auth.is_still_authorized(&state).await {
+                            Ok(true) => continue,
+                            Ok(false) => debug!(
+                                "Closing event stream of user {}: credential is no longer valid",
+                                user_id
+                            ),
+                            Err(e) => warn!("Closing event stream of user {}: {}", user_id, e),
+                        }
+                        return None;
+                    }
+                    _ = expired => {
+                        debug!(
+                            "Closing event stream of user {}: credential expired",
+                            user_id
+                        );
+                        return None;
+                    }
+                };
+                match received {
+                    Ok(envelope) => {
+                        if auth.is_session_revoked(&state) {
+                            debug!("Closing event stream of user {}: session revoked", user_id);
+                            return None;
+                        }
+                        if !usage_events && envelope.event.is_usage_event() {
+                            continue;
+                        }
+                        if !is_admin && !envelope.event.is_visible_to(user_id, &organization_ids) {
+                            continue;
+                        }
+                        let event = SseEvent::default()
+                            .id(envelope.id.to_string())
+                            .event(envelope.event.name())
+                            .json_data(&*envelope)
+                            .expect("event serialization failed");
+                        return Some((Ok::<_, Infallible>(event), (rx, recheck, state, auth)));
+                    }
+                    // 配信が追いつかず破棄されたイベントがある場合は lagged を通知する
+                    // (ダッシュボードは一覧を取得し直す)
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Event subscriber of user {} lagged by {}", user_id, skipped);
+                        let event = SseEvent::default()
+                            .event("lagged")
+                            .data(skipped.to_string());
+                        return Some((Ok(event), (rx, recheck, state, auth)));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
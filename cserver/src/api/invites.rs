@@ -0,0 +1,235 @@
+use super::auth::AuthUser;
+use super::dto::ErrorResponse;
+use super::err;
+use crate::repository::{
+    create_invite_code, delete_invite_code, get_invite_code_by_hash, get_invite_codes,
+    InviteCodeRecord,
+};
+use crate::token::hash::{generate_secret, visible_prefix};
+use crate::AppState;
+use axum::extract::{Path, Query, State};
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use log::info;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+// 招待コードの接頭辞
+const INVITE_CODE_PREFIX: &str = "chi_";
+// 招待コード一覧のデフォルト件数と上限
+const DEFAULT_INVITES_LIMIT: i64 = 50;
+const MAX_INVITES_LIMIT: i64 = 500;
+// メモの最大文字数
+const MAX_NOTE_LENGTH: usize = 256;
+
+// 招待コード発行リクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct InviteRequest {
+    // 登録時に初期ポイントへ追加で付与するポイント (省略時は0)
+    pub points: Option<i32>,
+    // 有効期間 (秒)。省略時は無期限
+    pub ttl_seconds: Option<i64>,
+    // 管理用のメモ (例: 配布先)
+    pub note: Option<String>,
+}
+
+// 招待コード一覧のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct InvitesQuery {
+    // 使用済みのコードも含めるか (デフォルト false)
+    #[serde(default)]
+    pub include_used: bool,
+    // 取得件数 (デフォルト50, 最大500)
+    pub limit: Option<i64>,
+}
+
+// APIレスポンス用の招待コード情報
+#[derive(Serialize, ToSchema)]
+pub(crate) struct InviteResponse {
+    pub id: Uuid,
+    // コード本体 (発行時のみ返却。以降は再表示できない)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    // 識別用のコード先頭部分
+    pub prefix: String,
+    pub points: i32,
+    pub note: Option<String>,
+    pub created_by: Option<Uuid>,
+    pub used_by: Option<Uuid>,
+    // UNIXタイムスタンプで表現される発行日時・有効期限・使用日時
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub used_at: Option<i64>,
+}
+
+impl From<InviteCodeRecord> for InviteResponse {
+    fn from(rec: InviteCodeRecord) -> Self {
+        InviteResponse {
+            id: rec.id,
+            code: None,
+            prefix: rec.code_prefix,
+            points: rec.points,
+            note: rec.note,
+            created_by: rec.created_by,
+            used_by: rec.used_by,
+            created_at: rec.created_at.timestamp(),
+            expires_at: rec.expires_at.map(|t| t.timestamp()),
+            used_at: rec.used_at.map(|t| t.timestamp()),
+        }
+    }
+}
+
+// 招待コードから、未使用かつ有効期限内のレコードを取得する
+pub(crate) async fn find_valid_invite(
+    state: &AppState,
+    code: &str,
+    now: DateTime<Utc>,
+) -> sqlx::Result<Option<InviteCodeRecord>> {
+    let hash = state.token_hasher.hash(code.trim());
+    let rec = get_invite_code_by_hash(&state.db_pool, &hash).await?;
+    Ok(rec.filter(|r| r.used_at.is_none() && r.expires_at.is_none_or(|t| t > now)))
+}
+
+// 招待コードを発行するエンドポイント (管理者のみ)
+#[utoipa::path(
+    post,
+    path = "/api/admin/invites",
+    request_body = InviteRequest,
+    responses(
+        (status = 201, description = "Invite code created", body = InviteResponse),
+        (status = 400, description = "Invalid points, TTL or note", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn create_invite(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<InviteRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let points = req.points.unwrap_or(0);
+    if points < 0 {
+        return err(StatusCode::BAD_REQUEST, "points must not be negative");
+    }
+    if matches!(req.ttl_seconds, Some(ttl) if ttl <= 0) {
+        return err(StatusCode::BAD_REQUEST, "ttl_seconds must be positive");
+    }
+    let note = req.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    if note.is_some_and(|n| n.chars().count() > MAX_NOTE_LENGTH) {
+        return err(
+            StatusCode::BAD_REQUEST,
+            &format!("note must be at most {} characters", MAX_NOTE_LENGTH),
+        );
+    }
+    let now = Utc::now();
+    // 有効期限 (極端に大きい ttl_seconds は日時の範囲を超えるため拒否)
+    let expires_at = match req.ttl_seconds {
+        Some(ttl) => {
+            match chrono::TimeDelta::try_seconds(ttl).and_then(|d| now.checked_add_signed(d)) {
+                Some(expires_at) => Some(expires_at),
+                None => return err(StatusCode::BAD_REQUEST, "ttl_seconds is too large"),
+            }
+        }
+        None => None,
+    };
+    let code = generate_secret(INVITE_CODE_PREFIX);
+    let rec = InviteCodeRecord {
+        id: Uuid::now_v7(),
+        code_prefix: visible_prefix(&code),
+        points,
+        note: note.map(str::to_string),
+        created_by: Some(auth.user_id),
+        created_at: now,
+        expires_at,
+        used_by: None,
+        used_at: None,
+    };
+    if let Err(e) = create_invite_code(&state.db_pool, &rec, &state.token_hasher.hash(&code)).await
+    {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    info!(
+        "Admin {} created invite code {} ({} points)",
+        auth.user_id, rec.id, points
+    );
+    // コード本体を返すのはこの一度のみ
+    let resp = InviteResponse {
+        code: Some(code),
+        ..InviteResponse::from(rec)
+    };
+    (StatusCode::CREATED, Json(resp)).into_response()
+}
+
+// 招待コードの一覧を取得するエンドポイント (管理者のみ)
+#[utoipa::path(
+    get,
+    path = "/api/admin/invites",
+    params(InvitesQuery),
+    responses(
+        (status = 200, description = "List of invite codes", body = [InviteResponse]),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn list_invites(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<InvitesQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_INVITES_LIMIT)
+        .clamp(1, MAX_INVITES_LIMIT);
+    match get_invite_codes(&state.db_pool, query.include_used, limit).await {
+        Ok(recs) => {
+            let resp: Vec<InviteResponse> = recs.into_iter().map(Into::into).collect();
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 招待コードを削除 (無効化) するエンドポイント (管理者のみ)
+#[utoipa::path(
+    delete,
+    path = "/api/admin/invites/{invite_id}",
+    params(("invite_id" = Uuid, Path, description = "Invite code ID")),
+    responses(
+        (status = 204, description = "Invite code deleted"),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Invite code not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn remove_invite(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(invite_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    match delete_invite_code(&state.db_pool, invite_id).await {
+        Ok(0) => err(StatusCode::NOT_FOUND, "Invite code not found"),
+        Ok(_) => {
+            info!("Admin {} deleted invite code {}", auth.user_id, invite_id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
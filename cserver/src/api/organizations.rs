@@ -0,0 +1,959 @@
+use super::auth::AuthUser;
+use super::dto::ErrorResponse;
+use super::err;
+use super::usage::{account_statement, statement_response, StatementQuery};
+use crate::events::Event;
+use crate::repository::{
+    add_organization_member, create_organization as insert_organization, delete_organization,
+    delete_token, get_membership, get_organization_ledger, get_organization_member_usage,
+    get_organization_members, get_organization_token, get_organization_tokens,
+    get_user_by_username, get_user_memberships, remove_organization_member,
+    revoke_member_organization_tokens, set_organization_member_role,
+    transfer_points_to_organization, ApiKeyScope, MemberUsageRecord, MembershipRecord, OrgRole,
+    OrganizationLedgerRecord, OrganizationMemberRecord,
+};
+use crate::token::{issue_token, RevokeTokensResponse, TokenRequest, TokenResponse};
+use crate::AppState;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::Utc;
+use hyper::StatusCode;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+// 組織名の最大文字数
+const MAX_NAME_LENGTH: usize = 64;
+// ポイント履歴のデフォルト件数と上限
+const DEFAULT_LEDGER_LIMIT: i64 = 50;
+const MAX_LEDGER_LIMIT: i64 = 500;
+
+// 組織作成リクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct OrganizationRequest {
+    pub name: String,
+}
+
+// メンバー追加リクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct AddMemberRequest {
+    pub username: String,
+    // 省略時は member
+    pub role: Option<OrgRole>,
+}
+
+// メンバーのロール変更リクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct UpdateMemberRoleRequest {
+    pub role: OrgRole,
+}
+
+// 自分の残高から組織の残高へポイントを移すリクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct TransferPointsRequest {
+    pub amount: i32,
+}
+
+// ポイント履歴のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct OrganizationLedgerQuery {
+    // 取得件数 (デフォルト50, 最大500)
+    pub limit: Option<i64>,
+}
+
+// APIレスポンス用の組織情報 (自分のロールと共有残高を含む)
+#[derive(Serialize, ToSchema)]
+pub(crate) struct OrganizationResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub role: OrgRole,
+    pub points: i32,
+    // UNIXタイムスタンプで表現される作成日時
+    pub created_at: i64,
+}
+
+impl From<MembershipRecord> for OrganizationResponse {
+    fn from(rec: MembershipRecord) -> Self {
+        OrganizationResponse {
+            id: rec.organization_id,
+            name: rec.name,
+            role: rec.role,
+            points: rec.points,
+            created_at: rec.created_at.timestamp(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct OrganizationMemberResponse {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: OrgRole,
+    // UNIXタイムスタンプで表現される参加日時
+    pub joined_at: i64,
+}
+
+impl From<OrganizationMemberRecord> for OrganizationMemberResponse {
+    fn from(rec: OrganizationMemberRecord) -> Self {
+        OrganizationMemberResponse {
+            user_id: rec.user_id,
+            username: rec.username,
+            role: rec.role,
+            joined_at: rec.joined_at.timestamp(),
+        }
+    }
+}
+
+// 組織の詳細 (メンバー一覧を含む)
+#[derive(Serialize, ToSchema)]
+pub(crate) struct OrganizationDetailResponse {
+    pub organization: OrganizationResponse,
+    pub members: Vec<OrganizationMemberResponse>,
+}
+
+// メンバーごとの組織トークンの利用状況
+#[derive(Serialize, ToSchema)]
+pub(crate) struct MemberUsageResponse {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: OrgRole,
+    // 有効期限内のトークン数と接続中のSOCKS5セッション数
+    pub active_tokens: i64,
+    pub active_sessions: usize,
+    // 組織トークンの累計転送量 (バイト) と消費ポイント
+    pub bytes_used: i64,
+    pub points_used: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct OrganizationUsageResponse {
+    pub points: i32,
+    pub members: Vec<MemberUsageResponse>,
+}
+
+// ポイント移動後の残高
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PointTransferResponse {
+    pub user_points: i32,
+    pub organization_points: i32,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct OrganizationLedgerResponse {
+    pub id: Uuid,
+    pub amount: i32,
+    pub balance_after: i32,
+    pub reason: String,
+    pub created_by: Option<Uuid>,
+    // UNIXタイムスタンプで表現される記録日時
+    pub created_at: i64,
+}
+
+impl From<OrganizationLedgerRecord> for OrganizationLedgerResponse {
+    fn from(rec: OrganizationLedgerRecord) -> Self {
+        OrganizationLedgerResponse {
+            id: rec.id,
+            amount: rec.amount,
+            balance_after: rec.balance_after,
+            reason: rec.reason,
+            created_by: rec.created_by,
+            created_at: rec.created_at.timestamp(),
+        }
+    }
+}
+
+// 自分の所属を取得する (メンバーでない場合は組織の存在を明かさず404)
+#[allow(clippy::result_large_err)]
+async fn find_membership(
+    state: &AppState,
+    auth: &AuthUser,
+    organization_id: Uuid,
+) -> Result<MembershipRecord, Response> {
+    match get_membership(&state.db_pool, organization_id, auth.user_id).await {
+        Ok(Some(m)) => Ok(m),
+        Ok(None) => Err(err(StatusCode::NOT_FOUND, "Organization not found")),
+        Err(e) => Err(err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())),
+    }
+}
+
+// 操作ごとに許可する所属ロール
+// 組織の削除、メンバーの管理、他のメンバーのトークンの失効
+const OWNER_ONLY: &[OrgRole] = &[OrgRole::Owner];
+// 組織のトークンの発行と利用
+const MANAGE_TOKENS: &[OrgRole] = &[OrgRole::Owner, OrgRole::Member];
+// 残高の入金と利用状況の参照
+const MANAGE_BALANCE: &[OrgRole] = &[OrgRole::Owner, OrgRole::Billing];
+
+// 所属ロールが許可されたロールのいずれかであることを要求する
+#[allow(clippy::result_large_err)]
+fn require_org_role(membership: &MembershipRecord, allowed: &[OrgRole]) -> Result<(), Response> {
+    if allowed.contains(&membership.role) {
+        Ok(())
+    } else {
+        Err(err(
+            StatusCode::FORBIDDEN,
+            "Your organization role does not allow this operation",
+        ))
+    }
+}
+
+// 組織トークンを失効させ、使用中のセッションを終了する (終了したセッション数を返す)
+fn terminate_tokens(state: &AppState, ids: &[Uuid]) -> usize {
+    ids.iter().map(|id| state.sessions.revoke(*id)).sum()
+}
+
+// 自分が所属する組織の一覧を取得するエンドポイント
+#[utoipa::path(
+    get,
+    path = "/api/orgs",
+    responses(
+        (status = 200, description = "Organizations of the current user", body = [OrganizationResponse]),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the usage:read scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn list_organizations(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::UsageRead) {
+        return resp;
+    }
+    match get_user_memberships(&state.db_pool, auth.user_id).await {
+        Ok(recs) => {
+            let resp: Vec<OrganizationResponse> = recs.into_iter().map(Into::into).collect();
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 組織を作成するエンドポイント (作成者がオーナーになる)
+#[utoipa::path(
+    post,
+    path = "/api/orgs",
+    request_body = OrganizationRequest,
+    responses(
+        (status = 201, description = "Organization created", body = OrganizationResponse),
+        (status = 400, description = "Invalid name", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Not available with an API key", body = ErrorResponse),
+        (status = 409, description = "Organization name already taken", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn create_organization(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<OrganizationRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let name = req.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return err(
+            StatusCode::BAD_REQUEST,
+            &format!("name must be 1-{} characters", MAX_NAME_LENGTH),
+        );
+    }
+    let id = Uuid::now_v7();
+    let now = Utc::now();
+    match insert_organization(&state.db_pool, id, name, auth.user_id, now).await {
+        Ok(()) => {}
+        Err(e)
+            if e.as_database_error()
+                .is_some_and(|d| d.is_unique_violation()) =>
+        {
+            return err(StatusCode::CONFLICT, "Organization name already taken");
+        }
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+    info!(
+        "User {} created organization {} ({})",
+        auth.user_id, id, name
+    );
+    let resp = OrganizationResponse {
+        id,
+        name: name.to_string(),
+        role: OrgRole::Owner,
+        points: 0,
+        created_at: now.timestamp(),
+    };
+    (StatusCode::CREATED, Json(resp)).into_response()
+}
+
+// 組織の詳細とメンバー一覧を取得するエンドポイント (メンバーのみ)
+#[utoipa::path(
+    get,
+    path = "/api/orgs/{org_id}",
+    params(("org_id" = Uuid, Path, description = "Organization ID")),
+    responses(
+        (status = 200, description = "Organization details", body = OrganizationDetailResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the usage:read scope", body = ErrorResponse),
+        (status = 404, description = "Organization not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn get_organization(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(org_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::UsageRead) {
+        return resp;
+    }
+    let membership = match find_membership(&state, &auth, org_id).await {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    match get_organization_members(&state.db_pool, org_id).await {
+        Ok(members) => {
+            let resp = OrganizationDetailResponse {
+                organization: membership.into(),
+                members: members.into_iter().map(Into::into).collect(),
+            };
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 組織を削除するエンドポイント (オーナーのみ)
+// 組織のトークンも削除され、残高は破棄される
+#[utoipa::path(
+    delete,
+    path = "/api/orgs/{org_id}",
+    params(("org_id" = Uuid, Path, description = "Organization ID")),
+    responses(
+        (status = 200, description = "Organization deleted", body = RevokeTokensResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Organization not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn remove_organization(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(org_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let membership = match find_membership(&state, &auth, org_id).await {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_org_role(&membership, OWNER_ONLY) {
+        return resp;
+    }
+    match delete_organization(&state.db_pool, org_id).await {
+        Ok(ids) => {
+            let terminated_sessions = terminate_tokens(&state, &ids);
+            info!(
+                "User {} deleted organization {} ({} points forfeited, {} tokens revoked)",
+                auth.user_id,
+                org_id,
+                membership.points,
+                ids.len()
+            );
+            (
+                StatusCode::OK,
+                Json(RevokeTokensResponse {
+                    revoked: ids.len(),
+                    terminated_sessions,
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// メンバーを追加するエンドポイント (オーナーのみ)
+#[utoipa::path(
+    post,
+    path = "/api/orgs/{org_id}/members",
+    params(("org_id" = Uuid, Path, description = "Organization ID")),
+    request_body = AddMemberRequest,
+    responses(
+        (status = 201, description = "Member added", body = OrganizationMemberResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Organization or user not found", body = ErrorResponse),
+        (status = 409, description = "User is already a member", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn add_member(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(org_id): Path<Uuid>,
+    Json(req): Json<AddMemberRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let membership = match find_membership(&state, &auth, org_id).await {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_org_role(&membership, OWNER_ONLY) {
+        return resp;
+    }
+    let user = match get_user_by_username(&state.db_pool, req.username.trim()).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return err(StatusCode::NOT_FOUND, "User not found"),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let role = req.role.unwrap_or(OrgRole::Member);
+    let now = Utc::now();
+    match add_organization_member(&state.db_pool, org_id, user.id, role, now).await {
+        Ok(_) => {}
+        Err(e)
+            if e.as_database_error()
+                .is_some_and(|d| d.is_unique_violation()) =>
+        {
+            return err(StatusCode::CONFLICT, "User is already a member");
+        }
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+    info!(
+        "User {} added {} to organization {} as {:?}",
+        auth.user_id, user.id, org_id, role
+    );
+    let resp = OrganizationMemberResponse {
+        user_id: user.id,
+        username: user.username,
+        role,
+        joined_at: now.timestamp(),
+    };
+    (StatusCode::CREATED, Json(resp)).into_response()
+}
+
+// メンバーのロールを変更するエンドポイント (オーナーのみ)
+// billing に変更した場合、そのメンバーが発行した組織トークンは失効する
+#[utoipa::path(
+    put,
+    path = "/api/orgs/{org_id}/members/{user_id}",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        ("user_id" = Uuid, Path, description = "User ID")
+    ),
+    request_body = UpdateMemberRoleRequest,
+    responses(
+        (status = 204, description = "Role updated"),
+        (status = 400, description = "Cannot demote the last owner", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Organization or member not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn update_member_role(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((org_id, user_id)): Path<(Uuid, Uuid)>,
+    Json(req): Json<UpdateMemberRoleRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let membership = match find_membership(&state, &auth, org_id).await {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_org_role(&membership, OWNER_ONLY) {
+        return resp;
+    }
+    match get_membership(&state.db_pool, org_id, user_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return err(StatusCode::NOT_FOUND, "Member not found"),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+    match set_organization_member_role(&state.db_pool, org_id, user_id, req.role).await {
+        Ok(0) => return err(StatusCode::BAD_REQUEST, "Cannot demote the last owner"),
+        Ok(_) => {}
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+    let mut revoked = 0;
+    if req.role == OrgRole::Billing {
+        match revoke_member_organization_tokens(&state.db_pool, org_id, user_id).await {
+            Ok(ids) => {
+                terminate_tokens(&state, &ids);
+                revoked = ids.len();
+            }
+            Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        }
+    }
+    info!(
+        "User {} changed the role of {} in organization {} to {:?} ({} tokens revoked)",
+        auth.user_id, user_id, org_id, req.role, revoked
+    );
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// メンバーを組織から外すエンドポイント (オーナー、または自分自身の脱退)
+// そのメンバーが発行した組織トークンは失効する
+#[utoipa::path(
+    delete,
+    path = "/api/orgs/{org_id}/members/{user_id}",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        ("user_id" = Uuid, Path, description = "User ID")
+    ),
+    responses(
+        (status = 200, description = "Member removed", body = RevokeTokensResponse),
+        (status = 400, description = "Cannot remove the last owner", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Organization or member not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn remove_member(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((org_id, user_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let membership = match find_membership(&state, &auth, org_id).await {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    if user_id != auth.user_id {
+        if let Err(resp) = require_org_role(&membership, OWNER_ONLY) {
+            return resp;
+        }
+    }
+    match get_membership(&state.db_pool, org_id, user_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return err(StatusCode::NOT_FOUND, "Member not found"),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+    match remove_organization_member(&state.db_pool, org_id, user_id).await {
+        Ok(Some(ids)) => {
+            let terminated_sessions = terminate_tokens(&state, &ids);
+            info!(
+                "User {} removed {} from organization {} ({} tokens revoked)",
+                auth.user_id,
+                user_id,
+                org_id,
+                ids.len()
+            );
+            (
+                StatusCode::OK,
+                Json(RevokeTokensResponse {
+                    revoked: ids.len(),
+                    terminated_sessions,
+                }),
+            )
+                .into_response()
+        }
+        Ok(None) => err(StatusCode::BAD_REQUEST, "Cannot remove the last owner"),
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 組織のトークン一覧を取得するエンドポイント (メンバーのみ)
+#[utoipa::path(
+    get,
+    path = "/api/orgs/{org_id}/tokens",
+    params(("org_id" = Uuid, Path, description = "Organization ID")),
+    responses(
+        (status = 200, description = "Active tokens of the organization", body = [TokenResponse]),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the tokens:manage scope", body = ErrorResponse),
+        (status = 404, description = "Organization not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn list_organization_tokens(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(org_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::TokensManage) {
+        return resp;
+    }
+    if let Err(resp) = find_membership(&state, &auth, org_id).await {
+        return resp;
+    }
+    match get_organization_tokens(&state.db_pool, org_id).await {
+        Ok(recs) => {
+            let resp: Vec<TokenResponse> = recs
+                .into_iter()
+                .map(|rec| TokenResponse::from_record(rec, &state.sessions))
+                .collect();
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 組織所有のトークンを発行するエンドポイント (オーナーとメンバー)
+// ポイントは組織の共有残高から消費される
+#[utoipa::path(
+    post,
+    path = "/api/orgs/{org_id}/tokens",
+    params(("org_id" = Uuid, Path, description = "Organization ID")),
+    request_body(content = Option<TokenRequest>, content_type = "application/json"),
+    responses(
+        (status = 201, description = "Token generated", body = TokenResponse),
+        (status = 400, description = "Invalid restrictions, TTL or label", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Organization not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn generate_organization_token(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(org_id): Path<Uuid>,
+    body: Bytes,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::TokensManage) {
+        return resp;
+    }
+    let membership = match find_membership(&state, &auth, org_id).await {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_org_role(&membership, MANAGE_TOKENS) {
+        return resp;
+    }
+    issue_token(&state, auth.user_id, Some(org_id), &body).await
+}
+
+// 組織のトークンを失効させるエンドポイント (オーナー、または発行したメンバー)
+#[utoipa::path(
+    delete,
+    path = "/api/orgs/{org_id}/tokens/{token_id}",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        ("token_id" = Uuid, Path, description = "Token ID")
+    ),
+    responses(
+        (status = 200, description = "Token revoked", body = RevokeTokensResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Organization or token not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn revoke_organization_token(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((org_id, token_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::TokensManage) {
+        return resp;
+    }
+    let membership = match find_membership(&state, &auth, org_id).await {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    let token = match get_organization_token(&state.db_pool, org_id, token_id).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return err(StatusCode::NOT_FOUND, "Token not found"),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    if token.user_id != auth.user_id {
+        if let Err(resp) = require_org_role(&membership, OWNER_ONLY) {
+            return resp;
+        }
+    }
+    match delete_token(&state.db_pool, token_id).await {
+        Ok(0) => err(StatusCode::NOT_FOUND, "Token not found"),
+        Ok(_) => {
+            let terminated_sessions = state.sessions.revoke(token_id);
+            info!(
+                "User {} revoked token {} of organization {} ({} sessions terminated)",
+                auth.user_id, token_id, org_id, terminated_sessions
+            );
+            (
+                StatusCode::OK,
+                Json(RevokeTokensResponse {
+                    revoked: 1,
+                    terminated_sessions,
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// メンバーごとの利用状況を取得するエンドポイント (オーナーと billing)
+#[utoipa::path(
+    get,
+    path = "/api/orgs/{org_id}/usage",
+    params(("org_id" = Uuid, Path, description = "Organization ID")),
+    responses(
+        (status = 200, description = "Per-member usage", body = OrganizationUsageResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Organization not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn get_organization_usage(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(org_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::UsageRead) {
+        return resp;
+    }
+    let membership = match find_membership(&state, &auth, org_id).await {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_org_role(&membership, MANAGE_BALANCE) {
+        return resp;
+    }
+    let usage = match get_organization_member_usage(&state.db_pool, org_id, Utc::now()).await {
+        Ok(usage) => usage,
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let tokens = match get_organization_tokens(&state.db_pool, org_id).await {
+        Ok(tokens) => tokens,
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    // メンバーごとの接続中セッション数
+    let mut sessions: HashMap<Uuid, usize> = HashMap::new();
+    for token in &tokens {
+        *sessions.entry(token.user_id).or_default() += state.sessions.active_sessions(token.id);
+    }
+    let members = usage
+        .into_iter()
+        .map(|rec: MemberUsageRecord| MemberUsageResponse {
+            active_sessions: sessions.get(&rec.user_id).copied().unwrap_or(0),
+            user_id: rec.user_id,
+            username: rec.username,
+            role: rec.role,
+            active_tokens: rec.active_tokens,
+            bytes_used: rec.bytes_used,
+            points_used: rec.points_used,
+        })
+        .collect();
+    let resp = OrganizationUsageResponse {
+        points: membership.points,
+        members,
+    };
+    (StatusCode::OK, Json(resp)).into_response()
+}
+
+// 自分の残高から組織の残高へポイントを移すエンドポイント (オーナーと billing)
+#[utoipa::path(
+    post,
+    path = "/api/orgs/{org_id}/points",
+    params(("org_id" = Uuid, Path, description = "Organization ID")),
+    request_body = TransferPointsRequest,
+    responses(
+        (status = 200, description = "Points transferred", body = PointTransferResponse),
+        (status = 400, description = "Invalid amount or insufficient points", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Organization not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn transfer_points(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(org_id): Path<Uuid>,
+    Json(req): Json<TransferPointsRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let membership = match find_membership(&state, &auth, org_id).await {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_org_role(&membership, MANAGE_BALANCE) {
+        return resp;
+    }
+    if req.amount <= 0 {
+        return err(StatusCode::BAD_REQUEST, "amount must be positive");
+    }
+    match transfer_points_to_organization(
+        &state.db_pool,
+        auth.user_id,
+        org_id,
+        req.amount,
+        &format!("Transfer to organization {}", membership.name),
+        "Transfer from member",
+        Utc::now(),
+    )
+    .await
+    {
+        Ok(Some((user_points, organization_points))) => {
+            info!(
+                "User {} transferred {} points to organization {}",
+                auth.user_id, req.amount, org_id
+            );
+            state.events.publish(Event::UserBalanceChanged {
+                user_id: auth.user_id,
+                points: user_points,
+            });
+            state.events.publish(Event::OrganizationBalanceChanged {
+                organization_id: org_id,
+                points: organization_points,
+            });
+            let resp = PointTransferResponse {
+                user_points,
+                organization_points,
+            };
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Ok(None) => err(StatusCode::BAD_REQUEST, "Insufficient points"),
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 組織のポイント増減履歴を取得するエンドポイント (オーナーと billing)
+#[utoipa::path(
+    get,
+    path = "/api/orgs/{org_id}/points",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        OrganizationLedgerQuery
+    ),
+    responses(
+        (status = 200, description = "Point ledger of the organization", body = [OrganizationLedgerResponse]),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Organization not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn list_organization_ledger(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(org_id): Path<Uuid>,
+    Query(query): Query<OrganizationLedgerQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::UsageRead) {
+        return resp;
+    }
+    let membership = match find_membership(&state, &auth, org_id).await {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_org_role(&membership, MANAGE_BALANCE) {
+        return resp;
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LEDGER_LIMIT)
+        .clamp(1, MAX_LEDGER_LIMIT);
+    match get_organization_ledger(&state.db_pool, org_id, limit).await {
+        Ok(recs) => {
+            let resp: Vec<OrganizationLedgerResponse> = recs.into_iter().map(Into::into).collect();
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 組織の月次明細を取得するエンドポイント (オーナーと billing)
+#[utoipa::path(
+    get,
+    path = "/api/orgs/{org_id}/statements/{month}",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        ("month" = String, Path, description = "Month in YYYY-MM format (UTC)"),
+        StatementQuery
+    ),
+    responses(
+        (status = 200, description = "Monthly statement (JSON or CSV file)", body = super::usage::MonthlyStatement),
+        (status = 400, description = "Invalid month", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Organization not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Organization"
+)]
+pub(crate) async fn get_organization_statement(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((org_id, month)): Path<(Uuid, String)>,
+    Query(query): Query<StatementQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::UsageRead) {
+        return resp;
+    }
+    let membership = match find_membership(&state, &auth, org_id).await {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_org_role(&membership, MANAGE_BALANCE) {
+        return resp;
+    }
+    match account_statement(&state, &month, org_id, true, Some(membership.name)).await {
+        Ok(statement) => statement_response(
+            statement,
+            query.format,
+            &format!("statement-{}-{}.csv", org_id, month),
+        ),
+        Err(resp) => resp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn membership(role: OrgRole) -> MembershipRecord {
+        MembershipRecord {
+            organization_id: Uuid::now_v7(),
+            name: "acme".to_string(),
+            created_at: Utc::now(),
+            role,
+            points: 0,
+        }
+    }
+
+    #[test]
+    fn test_require_org_role() {
+        let allowed = |role, ops| require_org_role(&membership(role), ops).is_ok();
+        assert!(allowed(OrgRole::Owner, OWNER_ONLY));
+        assert!(allowed(OrgRole::Owner, MANAGE_TOKENS));
+        assert!(allowed(OrgRole::Owner, MANAGE_BALANCE));
+        assert!(!allowed(OrgRole::Member, OWNER_ONLY));
+        assert!(allowed(OrgRole::Member, MANAGE_TOKENS));
+        assert!(!allowed(OrgRole::Member, MANAGE_BALANCE));
+        assert!(!allowed(OrgRole::Billing, OWNER_ONLY));
+        assert!(!allowed(OrgRole::Billing, MANAGE_TOKENS));
+        assert!(allowed(OrgRole::Billing, MANAGE_BALANCE));
+
+        let resp = require_org_role(&membership(OrgRole::Billing), MANAGE_TOKENS).unwrap_err();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+}
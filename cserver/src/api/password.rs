@@ -0,0 +1,344 @@
+use super::auth::AuthUser;
+use super::dto::{ErrorResponse, LoginResponse};
+use super::err;
+use super::session::issue_login_session;
+use crate::repository::{
+    create_password_reset_token, delete_user_password_reset_tokens, get_password_reset_token,
+    get_user_by_id, revoke_user_refresh_tokens, update_user_password, use_password_reset_token,
+    PasswordResetTokenRecord,
+};
+use crate::token::hash::generate_secret;
+use crate::AppState;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::extract::{Path, State};
+use axum::response::IntoResponse;
+use axum::Json;
+use axum_extra::extract::cookie::CookieJar;
+use chrono::Utc;
+use hyper::StatusCode;
+use log::info;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// パスワードリセットトークンの接頭辞
+const RESET_TOKEN_PREFIX: &str = "chp_";
+
+// パスワードポリシー (設定ファイルの [password_policy] で変更可能)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct PasswordPolicy {
+    // 最小・最大文字数
+    pub min_length: usize,
+    pub max_length: usize,
+    // 英大文字と英小文字の両方を必須にするか
+    pub require_mixed_case: bool,
+    // 数字を必須にするか
+    pub require_digit: bool,
+    // 英数字以外の記号を必須にするか
+    pub require_symbol: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 128,
+            require_mixed_case: false,
+            require_digit: false,
+            require_symbol: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    // パスワードがポリシーを満たすか検証する
+    pub fn validate(&self, username: &str, password: &str) -> Result<(), String> {
+        let length = password.chars().count();
+        if length < self.min_length || length > self.max_length {
+            return Err(format!(
+                "Password must be {}-{} characters",
+                self.min_length, self.max_length
+            ));
+        }
+        if self.require_mixed_case
+            && !(password.chars().any(|c| c.is_uppercase())
+                && password.chars().any(|c| c.is_lowercase()))
+        {
+            return Err("Password must contain both upper and lower case letters".to_string());
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            return Err("Password must contain a digit".to_string());
+        }
+        if self.require_symbol && password.chars().all(|c| c.is_alphanumeric()) {
+            return Err("Password must contain a symbol".to_string());
+        }
+        if password.eq_ignore_ascii_case(username) {
+            return Err("Password must not be the same as the username".to_string());
+        }
+        Ok(())
+    }
+}
+
+// パスワードをArgon2でハッシュ化する
+pub(crate) fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .unwrap()
+        .to_string()
+}
+
+// パスワードがハッシュと一致するか検証する
+pub(crate) fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|parsed| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok()
+    })
+}
+
+// パスワード変更リクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+// パスワードリセットトークン発行レスポンス
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PasswordResetTokenResponse {
+    // リセットトークン (この一度のみ返却)
+    pub token: String,
+    // UNIXタイムスタンプで表現される有効期限
+    pub expires_at: i64,
+}
+
+// パスワードリセットリクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+// パスワード変更エンドポイント
+// 変更後は他の全セッションを失効させ、現在のクライアントには新しいセッションを発行する
+#[utoipa::path(
+    post,
+    path = "/api/me/password",
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 200, description = "Password changed", body = LoginResponse),
+        (status = 400, description = "New password violates the password policy", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated or wrong current password", body = ErrorResponse),
+        (status = 403, description = "Not available with an API key", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn change_password(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    jar: CookieJar,
+    Json(req): Json<ChangePasswordRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let user = match get_user_by_id(&state.db_pool, auth.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return err(StatusCode::UNAUTHORIZED, "Unauthenticated"),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    if !verify_password(&req.current_password, &user.password_hash) {
+        return err(StatusCode::UNAUTHORIZED, "Invalid credentials");
+    }
+    if let Err(msg) = state
+        .settings
+        .password_policy
+        .validate(&user.username, &req.new_password)
+    {
+        return err(StatusCode::BAD_REQUEST, &msg);
+    }
+    if let Err(e) = set_password_and_revoke_sessions(&state, user.id, &req.new_password).await {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    info!("User {} changed their password", user.id);
+    match issue_login_session(&state, jar, user.id, user.role).await {
+        Ok((jar, resp)) => (jar, Json(resp)).into_response(),
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 管理者がユーザのパスワードリセットトークンを発行するエンドポイント
+// 未使用の既存トークンは無効になる
+#[utoipa::path(
+    post,
+    path = "/api/admin/users/{user_id}/password-reset",
+    params(("user_id" = Uuid, Path, description = "User ID")),
+    responses(
+        (status = 201, description = "Reset token issued", body = PasswordResetTokenResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn create_password_reset(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    // 管理者権限チェック
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    match get_user_by_id(&state.db_pool, user_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return err(StatusCode::NOT_FOUND, "User not found"),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+    if let Err(e) = delete_user_password_reset_tokens(&state.db_pool, user_id).await {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    let token = generate_secret(RESET_TOKEN_PREFIX);
+    let now = Utc::now();
+    let rec = PasswordResetTokenRecord {
+        id: Uuid::now_v7(),
+        user_id,
+        created_by: Some(auth.user_id),
+        created_at: now,
+        expires_at: now + chrono::Duration::seconds(state.settings.password_reset_ttl_seconds),
+        used_at: None,
+    };
+    if let Err(e) =
+        create_password_reset_token(&state.db_pool, &rec, &state.token_hasher.hash(&token)).await
+    {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    info!(
+        "Admin {} issued a password reset token for user {}",
+        auth.user_id, user_id
+    );
+    (
+        StatusCode::CREATED,
+        Json(PasswordResetTokenResponse {
+            token,
+            expires_at: rec.expires_at.timestamp(),
+        }),
+    )
+        .into_response()
+}
+
+// リセットトークンでパスワードを再設定するエンドポイント (認証不要)
+#[utoipa::path(
+    post,
+    path = "/api/password-reset",
+    request_body = ResetPasswordRequest,
+    responses(
+        (status = 200, description = "Password reset", body = LoginResponse),
+        (status = 400, description = "Invalid or expired token, or password policy violation", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn reset_password(
+    State(state): State<AppState>,
+    Json(req): Json<ResetPasswordRequest>,
+) -> impl IntoResponse {
+    let invalid = || err(StatusCode::BAD_REQUEST, "Invalid or expired reset token");
+    let hash = state.token_hasher.hash(req.token.trim());
+    let rec = match get_password_reset_token(&state.db_pool, &hash).await {
+        Ok(Some(rec)) if rec.used_at.is_none() && rec.expires_at > Utc::now() => rec,
+        Ok(_) => return invalid(),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let user = match get_user_by_id(&state.db_pool, rec.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return invalid(),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    if let Err(msg) = state
+        .settings
+        .password_policy
+        .validate(&user.username, &req.new_password)
+    {
+        return err(StatusCode::BAD_REQUEST, &msg);
+    }
+    // 同じトークンの同時使用を防ぐため、使用済みへの更新に成功した場合のみ続行
+    match use_password_reset_token(&state.db_pool, rec.id, Utc::now()).await {
+        Ok(0) => return invalid(),
+        Ok(_) => {}
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+    if let Err(e) = set_password_and_revoke_sessions(&state, user.id, &req.new_password).await {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    info!("User {} reset their password", user.id);
+    (
+        StatusCode::OK,
+        Json(LoginResponse {
+            success: true,
+            two_factor_enrollment_required: false,
+        }),
+    )
+        .into_response()
+}
+
+// パスワードを更新し、既存の全セッションを失効させる
+async fn set_password_and_revoke_sessions(
+    state: &AppState,
+    user_id: Uuid,
+    password: &str,
+) -> sqlx::Result<()> {
+    update_user_password(&state.db_pool, user_id, &hash_password(password)).await?;
+    let now = Utc::now();
+    state
+        .revocations
+        .revoke_user(&state.db_pool, user_id, now)
+        .await?;
+    revoke_user_refresh_tokens(&state.db_pool, user_id, now).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy() {
+        let policy = PasswordPolicy::default();
+        assert!(policy.validate("alice", "").is_err());
+        assert!(policy.validate("alice", "short").is_err());
+        assert!(policy.validate("alice", &"x".repeat(129)).is_err());
+        assert!(policy.validate("alice12345", "ALICE12345").is_err());
+        assert!(policy.validate("alice", "correct horse").is_ok());
+    }
+
+    #[test]
+    fn test_character_class_requirements() {
+        let policy = PasswordPolicy {
+            require_mixed_case: true,
+            require_digit: true,
+            require_symbol: true,
+            ..Default::default()
+        };
+        assert!(policy.validate("alice", "password1!").is_err());
+        assert!(policy.validate("alice", "Password!!").is_err());
+        assert!(policy.validate("alice", "Password11").is_err());
+        assert!(policy.validate("alice", "Password1!").is_ok());
+    }
+
+    #[test]
+    fn test_verify_password() {
+        let hash = hash_password("correct horse");
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+    }
+}
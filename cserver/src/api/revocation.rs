@@ -0,0 +1,135 @@
+use crate::repository::{
+    create_revoked_access_token, get_revoked_access_tokens, get_users_sessions_revoked_since,
+    set_user_sessions_revoked_at,
+};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+// 有効期限前に失効させたアクセストークン (JWT) の失効リスト（スレッドセーフ）
+// DBに永続化し、認証時の照会はメモリ上のコピーで行う
+#[derive(Clone, Default)]
+pub(crate) struct RevocationList {
+    // 失効させたJWT ID と、そのJWTの有効期限 (UNIXタイムスタンプ)
+    access_tokens: Arc<DashMap<Uuid, i64>>,
+    // 「全セッションからログアウト」したユーザと実行日時 (UNIXタイムスタンプ, ミリ秒)
+    // これ以前に発行されたJWTは無効 (同じ秒に発行されたJWTも失効させるためミリ秒で比較する)
+    users: Arc<DashMap<Uuid, i64>>,
+}
+
+impl RevocationList {
+    // DBから有効な失効情報を読み込む
+    // 全セッションのログアウトは、アクセストークンの有効期間内のもののみ保持すれば十分
+    pub async fn load(pool: &PgPool, access_token_ttl_seconds: i64) -> sqlx::Result<Self> {
+        let list = Self::default();
+        let now = Utc::now();
+        for (jti, expires_at) in get_revoked_access_tokens(pool, now).await? {
+            list.access_tokens.insert(jti, expires_at.timestamp());
+        }
+        let since = now - chrono::Duration::seconds(access_token_ttl_seconds);
+        for (user_id, revoked_at) in get_users_sessions_revoked_since(pool, since).await? {
+            list.users.insert(user_id, revoked_at.timestamp_millis());
+        }
+        Ok(list)
+    }
+
+    // JWTが失効しているか (issued_at は issued_at_millis で求めた発行日時)
+    pub fn is_revoked(&self, jti: Uuid, user_id: Uuid, issued_at: i64) -> bool {
+        self.access_tokens.contains_key(&jti)
+            || self
+                .users
+                .get(&user_id)
+                .is_some_and(|revoked_at| issued_at <= *revoked_at)
+    }
+
+    // アクセストークンを1件失効させる
+    pub async fn revoke_access_token(
+        &self,
+        pool: &PgPool,
+        jti: Uuid,
+        user_id: Uuid,
+        expires_at: i64,
+    ) -> sqlx::Result<()> {
+        let expires = DateTime::from_timestamp(expires_at, 0).unwrap_or_else(Utc::now);
+        create_revoked_access_token(pool, jti, user_id, expires).await?;
+        self.access_tokens.insert(jti, expires_at);
+        Ok(())
+    }
+
+    // ユーザのこれまでに発行された全アクセストークンを失効させる
+    pub async fn revoke_user(
+        &self,
+        pool: &PgPool,
+        user_id: Uuid,
+        revoked_at: DateTime<Utc>,
+    ) -> sqlx::Result<()> {
+        set_user_sessions_revoked_at(pool, user_id, revoked_at).await?;
+        self.users.insert(user_id, revoked_at.timestamp_millis());
+        Ok(())
+    }
+
+    // 有効期限を過ぎて不要になったエントリを削除し、削除件数を返す
+    pub fn prune(&self, now: i64, access_token_ttl_seconds: i64) -> usize {
+        let before = self.access_tokens.len() + self.users.len();
+        self.access_tokens.retain(|_, expires_at| *expires_at > now);
+        self.users
+            .retain(|_, revoked_at| *revoked_at + access_token_ttl_seconds * 1000 > now * 1000);
+        before - (self.access_tokens.len() + self.users.len())
+    }
+}
+
+// JWTの発行日時 (UNIXタイムスタンプ, ミリ秒)
+// JWT ID は発行時に生成した UUIDv7 のため、その時刻を使用する (iat は秒単位)
+pub(crate) fn issued_at_millis(jti: Uuid, iat: i64) -> i64 {
+    match jti.get_timestamp() {
+        Some(ts) => {
+            let (secs, nanos) = ts.to_unix();
+            secs as i64 * 1000 + (nanos / 1_000_000) as i64
+        }
+        None => iat * 1000,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_revoked() {
+        let list = RevocationList::default();
+        let (jti, user_id) = (Uuid::now_v7(), Uuid::now_v7());
+        assert!(!list.is_revoked(jti, user_id, 100));
+
+        list.access_tokens.insert(jti, 200);
+        assert!(list.is_revoked(jti, user_id, 100));
+
+        // 全セッションのログアウト以前に発行されたJWTのみ失効
+        list.users.insert(user_id, 150_000);
+        assert!(list.is_revoked(Uuid::now_v7(), user_id, 149_999));
+        assert!(list.is_revoked(Uuid::now_v7(), user_id, 150_000));
+        assert!(!list.is_revoked(Uuid::now_v7(), user_id, 150_001));
+    }
+
+    #[test]
+    fn test_issued_at_millis() {
+        let now = Utc::now();
+        let issued_at = issued_at_millis(Uuid::now_v7(), now.timestamp());
+        assert!((issued_at - now.timestamp_millis()).abs() < 1000);
+        // UUIDv7 以外の JWT ID は iat を使用する
+        assert_eq!(issued_at_millis(Uuid::nil(), 150), 150_000);
+    }
+
+    #[test]
+    fn test_prune() {
+        let list = RevocationList::default();
+        list.access_tokens.insert(Uuid::now_v7(), 100);
+        list.access_tokens.insert(Uuid::now_v7(), 300);
+        list.users.insert(Uuid::now_v7(), 50_000);
+        list.users.insert(Uuid::now_v7(), 250_000);
+        assert_eq!(list.prune(200, 100), 2);
+        assert_eq!(list.access_tokens.len(), 1);
+        assert_eq!(list.users.len(), 1);
+    }
+}
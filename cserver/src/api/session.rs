@@ -0,0 +1,301 @@
+use super::auth::AuthUser;
+use super::dto::{ErrorResponse, LoginResponse};
+use super::totp::totp_required;
+use super::{err, Claims};
+use crate::config::{CookieSameSite, Settings};
+use crate::repository::{
+    create_refresh_token, get_refresh_token, get_user_by_id, get_user_totp, revoke_refresh_token,
+    revoke_user_refresh_tokens, RefreshTokenRecord, UserRole,
+};
+use crate::token::hash::generate_secret;
+use crate::AppState;
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::Json;
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use chrono::Utc;
+use hyper::StatusCode;
+use jsonwebtoken::{encode, EncodingKey, Header};
+use log::{error, info, warn};
+use uuid::Uuid;
+
+// アクセストークン (JWT) を保持するCookie
+pub(crate) const SESSION_COOKIE: &str = "session";
+// リフレッシュトークンを保持するCookie (APIのパスにのみ送信)
+const REFRESH_COOKIE: &str = "refresh";
+const REFRESH_COOKIE_PATH: &str = "/api";
+// リフレッシュトークンの接頭辞
+const REFRESH_TOKEN_PREFIX: &str = "chr_";
+
+// 設定に従ってセッション用Cookieを構築する
+fn build_cookie(
+    settings: &Settings,
+    name: &'static str,
+    value: String,
+    path: &'static str,
+    max_age_seconds: i64,
+) -> Cookie<'static> {
+    let same_site = match settings.cookie_same_site {
+        CookieSameSite::Strict => SameSite::Strict,
+        CookieSameSite::Lax => SameSite::Lax,
+        CookieSameSite::None => SameSite::None,
+    };
+    let mut cookie = Cookie::build((name, value))
+        .path(path)
+        .http_only(true)
+        .secure(settings.cookie_secure)
+        .same_site(same_site)
+        .max_age(cookie::time::Duration::seconds(max_age_seconds))
+        .build();
+    if let Some(domain) = &settings.cookie_domain {
+        cookie.set_domain(domain.clone());
+    }
+    cookie
+}
+
+// アクセストークンとリフレッシュトークンを発行し、Cookieに設定する
+// 二要素認証が必須で未登録のユーザには、登録のみ行えるセッションを発行する
+pub(crate) async fn issue_login_session(
+    state: &AppState,
+    jar: CookieJar,
+    user_id: Uuid,
+    role: UserRole,
+) -> sqlx::Result<(CookieJar, LoginResponse)> {
+    let settings = &state.settings;
+    let enrollment_only = totp_required(settings, role)
+        && get_user_totp(&state.db_pool, user_id)
+            .await?
+            .is_none_or(|t| t.enabled_at.is_none());
+    let now = Utc::now();
+    // JWT生成
+    let claims = Claims {
+        sub: user_id.to_string(),
+        role,
+        iat: now.timestamp() as usize,
+        exp: (now.timestamp() + settings.access_token_ttl_seconds) as usize,
+        jti: Uuid::now_v7().to_string(),
+        enrollment_only,
+    };
+    let access_token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(state.jwt_secret.as_ref()),
+    )
+    .unwrap();
+    // リフレッシュトークン生成 (DBにはハッシュのみ保存)
+    let refresh_token = generate_secret(REFRESH_TOKEN_PREFIX);
+    let rec = RefreshTokenRecord {
+        id: Uuid::now_v7(),
+        user_id,
+        created_at: now,
+        expires_at: now + chrono::Duration::seconds(settings.refresh_token_ttl_seconds),
+        revoked_at: None,
+    };
+    create_refresh_token(
+        &state.db_pool,
+        &rec,
+        &state.token_hasher.hash(&refresh_token),
+    )
+    .await?;
+    let jar = jar
+        .add(build_cookie(
+            settings,
+            SESSION_COOKIE,
+            access_token,
+            "/",
+            settings.access_token_ttl_seconds,
+        ))
+        .add(build_cookie(
+            settings,
+            REFRESH_COOKIE,
+            refresh_token,
+            REFRESH_COOKIE_PATH,
+            settings.refresh_token_ttl_seconds,
+        ));
+    Ok((
+        jar,
+        LoginResponse {
+            success: true,
+            two_factor_enrollment_required: enrollment_only,
+        },
+    ))
+}
+
+// セッション用Cookieを削除する
+fn clear_login_cookies(settings: &Settings, jar: CookieJar) -> CookieJar {
+    jar.remove(build_cookie(
+        settings,
+        SESSION_COOKIE,
+        String::new(),
+        "/",
+        0,
+    ))
+    .remove(build_cookie(
+        settings,
+        REFRESH_COOKIE,
+        String::new(),
+        REFRESH_COOKIE_PATH,
+        0,
+    ))
+}
+
+// リフレッシュトークンで新しいアクセストークンを発行するエンドポイント
+// リフレッシュトークンは使用のたびにローテーションし、失効済みトークンの再利用を検知した場合は
+// 盗難の可能性があるため、そのユーザの全リフレッシュトークンを失効させる
+#[utoipa::path(
+    post,
+    path = "/api/refresh",
+    responses(
+        (status = 200, description = "Session refreshed", body = LoginResponse),
+        (status = 401, description = "Missing, invalid or expired refresh token", body = ErrorResponse),
+        (status = 403, description = "Account suspended", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn refresh(State(state): State<AppState>, jar: CookieJar) -> impl IntoResponse {
+    let token = match jar.get(REFRESH_COOKIE) {
+        Some(c) => c.value().to_string(),
+        None => return err(StatusCode::UNAUTHORIZED, "Missing refresh token"),
+    };
+    let rec = match get_refresh_token(&state.db_pool, &state.token_hasher.hash(&token)).await {
+        Ok(Some(rec)) => rec,
+        Ok(None) => return err(StatusCode::UNAUTHORIZED, "Invalid refresh token"),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let now = Utc::now();
+    if rec.expires_at <= now {
+        return err(StatusCode::UNAUTHORIZED, "Refresh token expired");
+    }
+    let rotated = match rec.revoked_at {
+        Some(_) => false,
+        None => match revoke_refresh_token(&state.db_pool, rec.id, now).await {
+            Ok(n) => n > 0,
+            Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        },
+    };
+    if !rotated {
+        warn!(
+            "Reuse of revoked refresh token {} detected for user {}; revoking all refresh tokens",
+            rec.id, rec.user_id
+        );
+        if let Err(e) = revoke_user_refresh_tokens(&state.db_pool, rec.user_id, now).await {
+            error!(
+                "Failed to revoke refresh tokens of user {}: {}",
+                rec.user_id, e
+            );
+        }
+        return err(StatusCode::UNAUTHORIZED, "Invalid refresh token");
+    }
+    // ロール変更を反映するため、ユーザ情報はDBから取得し直す
+    let user = match get_user_by_id(&state.db_pool, rec.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return err(StatusCode::UNAUTHORIZED, "Invalid refresh token"),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    if user.suspended_at.is_some() {
+        return err(StatusCode::FORBIDDEN, "Account suspended");
+    }
+    match issue_login_session(&state, jar, user.id, user.role).await {
+        Ok((jar, resp)) => (jar, Json(resp)).into_response(),
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// ログアウトエンドポイント
+// 現在のアクセストークンを失効リストに追加し、リフレッシュトークンを失効させる
+#[utoipa::path(
+    post,
+    path = "/api/logout",
+    responses(
+        (status = 200, description = "Logged out", body = LoginResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Not available with an API key", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn logout(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let (jti, expires_at) = match auth.require_session() {
+        Ok(session) => session,
+        Err(resp) => return resp,
+    };
+    if let Err(e) = state
+        .revocations
+        .revoke_access_token(&state.db_pool, jti, auth.user_id, expires_at)
+        .await
+    {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    if let Some(c) = jar.get(REFRESH_COOKIE) {
+        let hash = state.token_hasher.hash(c.value());
+        match get_refresh_token(&state.db_pool, &hash).await {
+            Ok(Some(rec)) if rec.user_id == auth.user_id => {
+                if let Err(e) = revoke_refresh_token(&state.db_pool, rec.id, Utc::now()).await {
+                    return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+                }
+            }
+            Ok(_) => {}
+            Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        }
+    }
+    info!("User {} logged out", auth.user_id);
+    (
+        clear_login_cookies(&state.settings, jar),
+        Json(LoginResponse {
+            success: true,
+            two_factor_enrollment_required: false,
+        }),
+    )
+        .into_response()
+}
+
+// 全セッションからログアウトするエンドポイント
+// これまでに発行された全アクセストークンと全リフレッシュトークンを失効させる
+#[utoipa::path(
+    post,
+    path = "/api/logout-all",
+    responses(
+        (status = 200, description = "Logged out of all sessions", body = LoginResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Not available with an API key", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn logout_all(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let now = Utc::now();
+    if let Err(e) = state
+        .revocations
+        .revoke_user(&state.db_pool, auth.user_id, now)
+        .await
+    {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    match revoke_user_refresh_tokens(&state.db_pool, auth.user_id, now).await {
+        Ok(n) => info!(
+            "User {} logged out of all sessions ({} refresh tokens revoked)",
+            auth.user_id, n
+        ),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+    (
+        clear_login_cookies(&state.settings, jar),
+        Json(LoginResponse {
+            success: true,
+            two_factor_enrollment_required: false,
+        }),
+    )
+        .into_response()
+}
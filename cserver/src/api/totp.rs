@@ -0,0 +1,488 @@
+use super::auth::AuthUser;
+use super::dto::ErrorResponse;
+use super::err;
+use super::session::issue_login_session;
+use crate::config::Settings;
+use crate::repository::{
+    count_unused_recovery_codes, delete_user_totp, enable_user_totp, get_user_by_id, get_user_totp,
+    replace_recovery_codes, set_totp_last_used_step, upsert_pending_totp, use_recovery_code,
+    UserRole, UserTotpRecord,
+};
+use crate::AppState;
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::Json;
+use axum_extra::extract::cookie::CookieJar;
+use chrono::Utc;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use hyper::StatusCode;
+use log::info;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use utoipa::ToSchema;
+
+// 認証アプリに表示する発行者名
+const TOTP_ISSUER: &str = "Chilsonite";
+// RFC 6238 のパラメータ (SHA-1, 6桁, 30秒)
+const TOTP_DIGITS: usize = 6;
+const TOTP_PERIOD_SECONDS: i64 = 30;
+// 時計のずれを許容する前後のステップ数
+const TOTP_ALLOWED_SKEW_STEPS: i64 = 1;
+// 共有シークレットのバイト数 (RFC 4226 推奨の160ビット)
+const TOTP_SECRET_BYTES: usize = 20;
+// 発行するリカバリーコードの数とバイト数 (Base32で8文字)
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_BYTES: usize = 5;
+
+// 指定したタイムステップのTOTPコードを計算する (RFC 4226 の動的切り詰め)
+fn totp_code(secret: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(TOTP_DIGITS as u32),
+        width = TOTP_DIGITS
+    )
+}
+
+// UNIXタイムスタンプに対応するタイムステップ
+fn time_step(timestamp: i64) -> i64 {
+    timestamp.div_euclid(TOTP_PERIOD_SECONDS)
+}
+
+// コードが前後の許容範囲内のいずれかのステップに一致すれば、そのステップを返す
+fn match_totp_code(secret: &[u8], code: &str, timestamp: i64) -> Option<i64> {
+    if code.len() != TOTP_DIGITS || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let current = time_step(timestamp);
+    (current - TOTP_ALLOWED_SKEW_STEPS..=current + TOTP_ALLOWED_SKEW_STEPS)
+        .find(|step| totp_code(secret, *step) == code)
+}
+
+// ランダムなバイト列をBase32 (パディングなし) で返す
+fn random_base32(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::rng().fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+// リカバリーコードを生成する (例: "ABCD-EFGH")
+fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = random_base32(RECOVERY_CODE_BYTES);
+            format!("{}-{}", &code[..4], &code[4..])
+        })
+        .collect()
+}
+
+// 入力揺れ (小文字・区切り文字・空白) を吸収したリカバリーコード
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+// 認証アプリ登録用の otpauth URI (QRコードにして読み取らせる)
+fn otpauth_uri(secret: &str, username: &str) -> String {
+    let encode = |s: &str| url::form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode(TOTP_ISSUER),
+        encode(username).replace('+', "%20"),
+        secret,
+        encode(TOTP_ISSUER),
+        TOTP_DIGITS,
+        TOTP_PERIOD_SECONDS
+    )
+}
+
+// ロールに対して二要素認証が必須か
+pub(crate) fn totp_required(settings: &Settings, role: UserRole) -> bool {
+    settings.require_totp_for_admins && role == UserRole::Admin
+}
+
+// 二要素認証のコード (TOTPコードまたはリカバリーコード) を検証する
+// 一度使用したTOTPコードとリカバリーコードは再利用できない
+pub(crate) async fn verify_second_factor(
+    state: &AppState,
+    totp: &UserTotpRecord,
+    code: &str,
+) -> sqlx::Result<bool> {
+    let code = code.trim();
+    if code.len() == TOTP_DIGITS && code.chars().all(|c| c.is_ascii_digit()) {
+        let Ok(secret) = BASE32_NOPAD.decode(totp.secret.as_bytes()) else {
+            return Ok(false);
+        };
+        return match match_totp_code(&secret, code, Utc::now().timestamp()) {
+            Some(step) => {
+                Ok(set_totp_last_used_step(&state.db_pool, totp.user_id, step).await? > 0)
+            }
+            None => Ok(false),
+        };
+    }
+    let hash = state.token_hasher.hash(&normalize_recovery_code(code));
+    Ok(use_recovery_code(&state.db_pool, totp.user_id, &hash, Utc::now()).await? > 0)
+}
+
+// リカバリーコードを生成し、DB保存用のハッシュと組で返す
+fn new_recovery_codes(state: &AppState) -> (Vec<String>, Vec<String>) {
+    let codes = generate_recovery_codes();
+    let hashes = codes
+        .iter()
+        .map(|c| state.token_hasher.hash(&normalize_recovery_code(c)))
+        .collect();
+    (codes, hashes)
+}
+
+// 二要素認証の状態
+#[derive(Serialize, ToSchema)]
+pub(crate) struct TotpStatusResponse {
+    // 有効化済みか
+    pub enabled: bool,
+    // ロールにより必須か (必須の場合は無効化できない)
+    pub required: bool,
+    // 未使用のリカバリーコード数
+    pub recovery_codes_remaining: i64,
+}
+
+// 二要素認証の登録開始レスポンス
+#[derive(Serialize, ToSchema)]
+pub(crate) struct TotpEnrollResponse {
+    // 共有シークレット (Base32。手入力用)
+    pub secret: String,
+    // 認証アプリ登録用のURI (ダッシュボードでQRコードとして表示する)
+    pub otpauth_uri: String,
+}
+
+// 二要素認証コードを指定するリクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct TotpCodeRequest {
+    // 認証アプリの6桁のコード (有効化後はリカバリーコードも可)
+    pub code: String,
+}
+
+// リカバリーコードのレスポンス
+#[derive(Serialize, ToSchema)]
+pub(crate) struct RecoveryCodesResponse {
+    // リカバリーコード (この一度のみ返却。各コードは1回のみ使用可能)
+    pub recovery_codes: Vec<String>,
+}
+
+// 二要素認証の状態を取得するエンドポイント
+#[utoipa::path(
+    get,
+    path = "/api/me/2fa",
+    responses(
+        (status = 200, description = "Two-factor authentication status", body = TotpStatusResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Not available with an API key", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn get_totp_status(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let enabled = match get_user_totp(&state.db_pool, auth.user_id).await {
+        Ok(totp) => totp.is_some_and(|t| t.enabled_at.is_some()),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let recovery_codes_remaining =
+        match count_unused_recovery_codes(&state.db_pool, auth.user_id).await {
+            Ok(n) => n,
+            Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        };
+    let resp = TotpStatusResponse {
+        enabled,
+        required: totp_required(&state.settings, auth.role),
+        recovery_codes_remaining,
+    };
+    (StatusCode::OK, Json(resp)).into_response()
+}
+
+// 二要素認証の登録を開始するエンドポイント
+// 新しいシークレットを発行し、/api/me/2fa/verify でコードを確認するまでは有効にならない
+#[utoipa::path(
+    post,
+    path = "/api/me/2fa/enroll",
+    responses(
+        (status = 200, description = "Enrollment started", body = TotpEnrollResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Not available with an API key", body = ErrorResponse),
+        (status = 409, description = "Two-factor authentication is already enabled", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn enroll_totp(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let user = match get_user_by_id(&state.db_pool, auth.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return err(StatusCode::UNAUTHORIZED, "Unauthenticated"),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let secret = random_base32(TOTP_SECRET_BYTES);
+    // 有効化済みの場合は上書きされない
+    match upsert_pending_totp(&state.db_pool, user.id, &secret, Utc::now()).await {
+        Ok(0) => {
+            return err(
+                StatusCode::CONFLICT,
+                "Two-factor authentication is already enabled",
+            )
+        }
+        Ok(_) => {}
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+    let resp = TotpEnrollResponse {
+        otpauth_uri: otpauth_uri(&secret, &user.username),
+        secret,
+    };
+    (StatusCode::OK, Json(resp)).into_response()
+}
+
+// 認証アプリのコードを確認して二要素認証を有効化するエンドポイント
+// リカバリーコードを返し、二要素認証済みの新しいセッションを発行する
+#[utoipa::path(
+    post,
+    path = "/api/me/2fa/verify",
+    request_body = TotpCodeRequest,
+    responses(
+        (status = 200, description = "Two-factor authentication enabled", body = RecoveryCodesResponse),
+        (status = 400, description = "Invalid code or no pending enrollment", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Not available with an API key", body = ErrorResponse),
+        (status = 409, description = "Two-factor authentication is already enabled", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn verify_totp(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    jar: CookieJar,
+    Json(req): Json<TotpCodeRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let totp = match get_user_totp(&state.db_pool, auth.user_id).await {
+        Ok(Some(totp)) if totp.enabled_at.is_some() => {
+            return err(
+                StatusCode::CONFLICT,
+                "Two-factor authentication is already enabled",
+            )
+        }
+        Ok(Some(totp)) => totp,
+        Ok(None) => {
+            return err(
+                StatusCode::BAD_REQUEST,
+                "Two-factor authentication enrollment has not been started",
+            )
+        }
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    // 有効化時はリカバリーコードではなく、認証アプリのコードのみ受け付ける
+    let step = BASE32_NOPAD
+        .decode(totp.secret.as_bytes())
+        .ok()
+        .and_then(|secret| match_totp_code(&secret, req.code.trim(), Utc::now().timestamp()));
+    let Some(step) = step else {
+        return err(StatusCode::BAD_REQUEST, "Invalid code");
+    };
+    let (codes, hashes) = new_recovery_codes(&state);
+    match enable_user_totp(&state.db_pool, auth.user_id, Utc::now(), step, &hashes).await {
+        Ok(0) => {
+            return err(
+                StatusCode::CONFLICT,
+                "Two-factor authentication is already enabled",
+            )
+        }
+        Ok(_) => {}
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+    info!("User {} enabled two-factor authentication", auth.user_id);
+    // 登録専用セッションを通常のセッションに置き換える
+    match issue_login_session(&state, jar, auth.user_id, auth.role).await {
+        Ok((jar, _)) => (
+            jar,
+            Json(RecoveryCodesResponse {
+                recovery_codes: codes,
+            }),
+        )
+            .into_response(),
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// リカバリーコードを再発行するエンドポイント (既存のコードは全て無効になる)
+#[utoipa::path(
+    post,
+    path = "/api/me/2fa/recovery-codes",
+    request_body = TotpCodeRequest,
+    responses(
+        (status = 200, description = "Recovery codes regenerated", body = RecoveryCodesResponse),
+        (status = 400, description = "Invalid code or two-factor authentication not enabled", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Not available with an API key", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<TotpCodeRequest>,
+) -> impl IntoResponse {
+    let totp = match enabled_totp_with_code(&state, &auth, &req.code).await {
+        Ok(totp) => totp,
+        Err(resp) => return resp,
+    };
+    let (codes, hashes) = new_recovery_codes(&state);
+    if let Err(e) = replace_recovery_codes(&state.db_pool, totp.user_id, &hashes).await {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    info!("User {} regenerated recovery codes", auth.user_id);
+    (
+        StatusCode::OK,
+        Json(RecoveryCodesResponse {
+            recovery_codes: codes,
+        }),
+    )
+        .into_response()
+}
+
+// 二要素認証を無効化するエンドポイント
+#[utoipa::path(
+    delete,
+    path = "/api/me/2fa",
+    request_body = TotpCodeRequest,
+    responses(
+        (status = 204, description = "Two-factor authentication disabled"),
+        (status = 400, description = "Invalid code or two-factor authentication not enabled", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Required for this role, or not available with an API key", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn disable_totp(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<TotpCodeRequest>,
+) -> impl IntoResponse {
+    if totp_required(&state.settings, auth.role) {
+        return err(
+            StatusCode::FORBIDDEN,
+            "Two-factor authentication is required for this role",
+        );
+    }
+    let totp = match enabled_totp_with_code(&state, &auth, &req.code).await {
+        Ok(totp) => totp,
+        Err(resp) => return resp,
+    };
+    if let Err(e) = delete_user_totp(&state.db_pool, totp.user_id).await {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    info!("User {} disabled two-factor authentication", auth.user_id);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// ログインセッションで、有効化済みの二要素認証のコードを確認する
+async fn enabled_totp_with_code(
+    state: &AppState,
+    auth: &AuthUser,
+    code: &str,
+) -> Result<UserTotpRecord, axum::response::Response> {
+    auth.require_session()?;
+    let totp = match get_user_totp(&state.db_pool, auth.user_id).await {
+        Ok(Some(totp)) if totp.enabled_at.is_some() => totp,
+        Ok(_) => {
+            return Err(err(
+                StatusCode::BAD_REQUEST,
+                "Two-factor authentication is not enabled",
+            ))
+        }
+        Err(e) => return Err(err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())),
+    };
+    match verify_second_factor(state, &totp, code).await {
+        Ok(true) => Ok(totp),
+        Ok(false) => Err(err(StatusCode::BAD_REQUEST, "Invalid code")),
+        Err(e) => Err(err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())),
+    }
+}
+
+// ログイン時に二要素認証のコードが必要な場合のレスポンス
+pub(crate) fn two_factor_error(msg: &str) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(serde_json::json!({ "error": msg, "two_factor_required": true })),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 付録B のテストベクタ (SHA-1, 下位6桁)
+    #[test]
+    fn test_totp_code_rfc6238() {
+        let secret = b"12345678901234567890";
+        assert_eq!(totp_code(secret, time_step(59)), "287082");
+        assert_eq!(totp_code(secret, time_step(1111111109)), "081804");
+        assert_eq!(totp_code(secret, time_step(1234567890)), "005924");
+        assert_eq!(totp_code(secret, time_step(2000000000)), "279037");
+    }
+
+    #[test]
+    fn test_match_totp_code_window() {
+        let secret = b"12345678901234567890";
+        let now = 1111111109;
+        let step = time_step(now);
+        assert_eq!(match_totp_code(secret, "081804", now), Some(step));
+        // 前後1ステップまで許容
+        let previous = totp_code(secret, step - 1);
+        assert_eq!(match_totp_code(secret, &previous, now), Some(step - 1));
+        let stale = totp_code(secret, step - 2);
+        assert_eq!(match_totp_code(secret, &stale, now), None);
+        assert_eq!(match_totp_code(secret, "81804", now), None);
+        assert_eq!(match_totp_code(secret, "08180a", now), None);
+    }
+
+    #[test]
+    fn test_recovery_codes_and_uri() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(codes[0].len(), 9);
+        assert_eq!(
+            normalize_recovery_code(&codes[0].to_lowercase()),
+            normalize_recovery_code(&codes[0])
+        );
+        assert_eq!(normalize_recovery_code(" abcd-efgh "), "ABCDEFGH");
+        assert_eq!(
+            otpauth_uri("JBSWY3DP", "alice smith"),
+            "otpauth://totp/Chilsonite:alice%20smith?secret=JBSWY3DP&issuer=Chilsonite&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
@@ -0,0 +1,828 @@
+use super::auth::AuthUser;
+use super::dto::ErrorResponse;
+use super::err;
+use crate::repository::{
+    get_usage_monthly, get_usage_sessions_for_export, get_user_by_id, get_user_usage_daily,
+    get_user_usage_sessions, ApiKeyScope, SessionOutcome, UsageDailyRecord, UsageExportRecord,
+    UsageMonthlyRecord, UsageSessionRecord,
+};
+use crate::AppState;
+use axum::extract::{Path, Query, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, Datelike, Days, NaiveDate, SecondsFormat, Utc};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+// セッション履歴のデフォルト件数と上限
+const DEFAULT_SESSIONS_LIMIT: i64 = 50;
+const MAX_SESSIONS_LIMIT: i64 = 500;
+// 日次集計のデフォルト期間と最大期間 (日)
+const DEFAULT_DAILY_DAYS: i64 = 30;
+const MAX_DAILY_DAYS: i64 = 366;
+// エクスポートの最大件数 (超える場合は期間を狭めるよう求める)
+const MAX_EXPORT_ROWS: i64 = 100_000;
+// 月次明細に含める国の数
+const TOP_COUNTRIES: usize = 5;
+
+// エクスポート・明細の出力形式
+#[derive(Deserialize, ToSchema, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+// セッション履歴のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct SessionsQuery {
+    // 開始日時の範囲 (UNIXタイムスタンプ, from <= started_at < to)
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    // 特定のトークンのセッションのみに絞り込む
+    pub token_id: Option<Uuid>,
+    // 取得件数 (デフォルト50, 最大500) と読み飛ばす件数
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+// 日次集計のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct DailyUsageQuery {
+    // 集計期間 (UNIXタイムスタンプ。UTCの日付単位で両端を含む)
+    // 省略時は直近30日間
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+// セッション履歴エクスポートのクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct ExportQuery {
+    // 開始日時の範囲 (UNIXタイムスタンプ, from <= started_at < to)
+    // 省略時は直近30日間
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+// 管理者向けセッション履歴エクスポートのクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct AdminExportQuery {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    #[serde(default)]
+    pub format: ExportFormat,
+    // 特定のユーザ・組織のセッションのみに絞り込む
+    pub user_id: Option<Uuid>,
+    pub organization_id: Option<Uuid>,
+}
+
+// 月次明細のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct StatementQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+// APIレスポンス用のセッション情報
+#[derive(Serialize, ToSchema)]
+pub(crate) struct UsageSessionResponse {
+    pub id: Uuid,
+    pub token_id: Uuid,
+    pub token_prefix: String,
+    pub organization_id: Option<Uuid>,
+    pub agent_id: Option<String>,
+    pub target_host: Option<String>,
+    pub target_port: Option<i32>,
+    pub client_ip: String,
+    // UNIXタイムスタンプで表現される開始・終了日時
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub bytes_up: i64,
+    pub bytes_down: i64,
+    pub outcome: SessionOutcome,
+    pub points_charged: i32,
+}
+
+impl From<UsageSessionRecord> for UsageSessionResponse {
+    fn from(rec: UsageSessionRecord) -> Self {
+        UsageSessionResponse {
+            id: rec.id,
+            token_id: rec.token_id,
+            token_prefix: rec.token_prefix,
+            organization_id: rec.organization_id,
+            agent_id: rec.agent_id,
+            target_host: rec.target_host,
+            target_port: rec.target_port,
+            client_ip: rec.client_ip,
+            started_at: rec.started_at.timestamp(),
+            ended_at: rec.ended_at.timestamp(),
+            bytes_up: rec.bytes_up,
+            bytes_down: rec.bytes_down,
+            outcome: rec.outcome,
+            points_charged: rec.points_charged,
+        }
+    }
+}
+
+// セッション履歴の一覧 (total は絞り込み条件に一致する全件数)
+#[derive(Serialize, ToSchema)]
+pub(crate) struct UsageSessionListResponse {
+    pub sessions: Vec<UsageSessionResponse>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// 日次利用量 (UTC)
+#[derive(Serialize, ToSchema)]
+pub(crate) struct DailyUsageResponse {
+    // YYYY-MM-DD 形式の日付
+    pub day: String,
+    pub sessions: i64,
+    pub bytes_up: i64,
+    pub bytes_down: i64,
+    pub points_charged: i64,
+}
+
+impl From<UsageDailyRecord> for DailyUsageResponse {
+    fn from(rec: UsageDailyRecord) -> Self {
+        DailyUsageResponse {
+            day: rec.day.to_string(),
+            sessions: rec.sessions,
+            bytes_up: rec.bytes_up,
+            bytes_down: rec.bytes_down,
+            points_charged: rec.points_charged,
+        }
+    }
+}
+
+// エクスポート用のセッション情報 (CSVの1行に対応)
+// 日時は RFC 3339 形式 (UTC)
+#[derive(Serialize, ToSchema)]
+pub(crate) struct UsageExportRow {
+    pub id: Uuid,
+    // 削除済みユーザのセッションは null
+    pub user_id: Option<Uuid>,
+    pub username: Option<String>,
+    pub token_prefix: String,
+    pub organization_id: Option<Uuid>,
+    pub agent_id: Option<String>,
+    pub agent_country: Option<String>,
+    pub target_host: Option<String>,
+    pub target_port: Option<i32>,
+    pub client_ip: String,
+    pub started_at: String,
+    pub ended_at: String,
+    pub bytes_up: i64,
+    pub bytes_down: i64,
+    pub outcome: SessionOutcome,
+    pub points_charged: i32,
+}
+
+impl From<UsageExportRecord> for UsageExportRow {
+    fn from(rec: UsageExportRecord) -> Self {
+        let s = rec.session;
+        UsageExportRow {
+            id: s.id,
+            user_id: s.user_id,
+            username: rec.username,
+            token_prefix: s.token_prefix,
+            organization_id: s.organization_id,
+            agent_id: s.agent_id,
+            agent_country: s.agent_country,
+            target_host: s.target_host,
+            target_port: s.target_port,
+            client_ip: s.client_ip,
+            started_at: s.started_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            ended_at: s.ended_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            bytes_up: s.bytes_up,
+            bytes_down: s.bytes_down,
+            outcome: s.outcome,
+            points_charged: s.points_charged,
+        }
+    }
+}
+
+// 国ごとの利用量
+#[derive(Serialize, ToSchema, Debug, PartialEq, Eq)]
+pub(crate) struct CountryUsage {
+    // エージェントの国コード (不明な場合は null)
+    pub country: Option<String>,
+    pub sessions: i64,
+    pub bytes: i64,
+}
+
+// 請求先 (ユーザまたは組織) の月次明細
+// ユーザの明細は個人トークンの利用分のみで、組織トークンの利用分は組織の明細に含まれる
+#[derive(Serialize, ToSchema, Debug, PartialEq, Eq)]
+pub(crate) struct MonthlyStatement {
+    // YYYY-MM 形式の対象月 (UTC)
+    pub month: String,
+    // "user" または "organization"
+    pub account_type: String,
+    pub account_id: Uuid,
+    // ユーザ名または組織名 (削除済みの場合は null)
+    pub account_name: Option<String>,
+    pub sessions: i64,
+    pub bytes_up: i64,
+    pub bytes_down: i64,
+    pub points_spent: i64,
+    // 転送量の多い順の国 (最大5件)
+    pub top_countries: Vec<CountryUsage>,
+}
+
+// CSV出力用の月次明細 (上位の国は "JP:1024;US:512" の形式で1列にまとめる)
+#[derive(Serialize)]
+struct StatementCsvRow<'a> {
+    month: &'a str,
+    account_type: &'a str,
+    account_id: Uuid,
+    account_name: Option<&'a str>,
+    sessions: i64,
+    bytes_up: i64,
+    bytes_down: i64,
+    points_spent: i64,
+    top_countries: String,
+}
+
+impl<'a> From<&'a MonthlyStatement> for StatementCsvRow<'a> {
+    fn from(st: &'a MonthlyStatement) -> Self {
+        let top_countries = st
+            .top_countries
+            .iter()
+            .map(|c| format!("{}:{}", c.country.as_deref().unwrap_or("unknown"), c.bytes))
+            .collect::<Vec<_>>()
+            .join(";");
+        StatementCsvRow {
+            month: &st.month,
+            account_type: &st.account_type,
+            account_id: st.account_id,
+            account_name: st.account_name.as_deref(),
+            sessions: st.sessions,
+            bytes_up: st.bytes_up,
+            bytes_down: st.bytes_down,
+            points_spent: st.points_spent,
+            top_countries,
+        }
+    }
+}
+
+// YYYY-MM 形式の月を月初日に変換する
+fn parse_month(month: &str) -> Option<NaiveDate> {
+    if month.len() != 7 {
+        return None;
+    }
+    NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok()
+}
+
+// 国ごとの月次集計を請求先ごとの明細にまとめる (recs は請求先ごとに連続している前提)
+pub(crate) fn build_statements(
+    month: NaiveDate,
+    recs: Vec<UsageMonthlyRecord>,
+) -> Vec<MonthlyStatement> {
+    let month = format!("{:04}-{:02}", month.year(), month.month());
+    let mut statements: Vec<MonthlyStatement> = Vec::new();
+    for rec in recs {
+        let same_account = statements.last().is_some_and(|st| {
+            st.account_id == rec.account_id
+                && (st.account_type == "organization") == rec.is_organization
+        });
+        if !same_account {
+            statements.push(MonthlyStatement {
+                month: month.clone(),
+                account_type: if rec.is_organization {
+                    "organization"
+                } else {
+                    "user"
+                }
+                .to_string(),
+                account_id: rec.account_id,
+                account_name: rec.account_name.clone(),
+                sessions: 0,
+                bytes_up: 0,
+                bytes_down: 0,
+                points_spent: 0,
+                top_countries: Vec::new(),
+            });
+        }
+        let st = statements.last_mut().expect("statement was pushed above");
+        st.sessions += rec.sessions;
+        st.bytes_up += rec.bytes_up;
+        st.bytes_down += rec.bytes_down;
+        st.points_spent += rec.points_charged;
+        st.top_countries.push(CountryUsage {
+            country: Some(rec.country).filter(|c| !c.is_empty()),
+            sessions: rec.sessions,
+            bytes: rec.bytes_up + rec.bytes_down,
+        });
+    }
+    for st in &mut statements {
+        st.top_countries
+            .sort_by(|a, b| b.bytes.cmp(&a.bytes).then(b.sessions.cmp(&a.sessions)));
+        st.top_countries.truncate(TOP_COUNTRIES);
+    }
+    statements
+}
+
+// 1つの請求先の月次明細を取得する (利用がない月は0件の明細を返す)
+pub(crate) async fn account_statement(
+    state: &AppState,
+    month: &str,
+    account_id: Uuid,
+    is_organization: bool,
+    account_name: Option<String>,
+) -> Result<MonthlyStatement, Response> {
+    let Some(first_day) = parse_month(month) else {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            "month must be in YYYY-MM format",
+        ));
+    };
+    let recs = get_usage_monthly(
+        &state.db_pool,
+        first_day,
+        Some((account_id, is_organization)),
+    )
+    .await
+    .map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()))?;
+    let statement = build_statements(first_day, recs).pop();
+    Ok(statement.unwrap_or_else(|| MonthlyStatement {
+        month: month.to_string(),
+        account_type: if is_organization {
+            "organization"
+        } else {
+            "user"
+        }
+        .to_string(),
+        account_id,
+        account_name,
+        sessions: 0,
+        bytes_up: 0,
+        bytes_down: 0,
+        points_spent: 0,
+        top_countries: Vec::new(),
+    }))
+}
+
+// 行を CSV の添付ファイルとして返す
+fn csv_response<T: Serialize>(filename: &str, rows: impl IntoIterator<Item = T>) -> Response {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        if let Err(e) = writer.serialize(row) {
+            return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+        }
+    }
+    match writer.into_inner() {
+        Ok(body) => (
+            StatusCode::OK,
+            [
+                (CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (
+                    CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", filename),
+                ),
+            ],
+            body,
+        )
+            .into_response(),
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 明細を指定の形式で返す
+pub(crate) fn statement_response(
+    statement: MonthlyStatement,
+    format: ExportFormat,
+    filename: &str,
+) -> Response {
+    match format {
+        ExportFormat::Json => (StatusCode::OK, Json(statement)).into_response(),
+        ExportFormat::Csv => csv_response(filename, [StatementCsvRow::from(&statement)]),
+    }
+}
+
+// セッション履歴をエクスポートする (期間の省略時は直近30日間)
+async fn export_sessions(
+    state: &AppState,
+    user_id: Option<Uuid>,
+    organization_id: Option<Uuid>,
+    from: Option<i64>,
+    to: Option<i64>,
+    format: ExportFormat,
+) -> Response {
+    let from = match parse_timestamp(from, "from") {
+        Ok(from) => from,
+        Err(resp) => return resp,
+    };
+    let to = match parse_timestamp(to, "to") {
+        Ok(to) => to.unwrap_or_else(Utc::now),
+        Err(resp) => return resp,
+    };
+    let from = match from {
+        Some(from) => from,
+        None => match to.checked_sub_signed(chrono::Duration::days(DEFAULT_DAILY_DAYS)) {
+            Some(from) => from,
+            None => return err(StatusCode::BAD_REQUEST, "to is out of range"),
+        },
+    };
+    if from > to {
+        return err(StatusCode::BAD_REQUEST, "from must not be after to");
+    }
+    let recs = match get_usage_sessions_for_export(
+        &state.db_pool,
+        user_id,
+        organization_id,
+        from,
+        to,
+        MAX_EXPORT_ROWS + 1,
+    )
+    .await
+    {
+        Ok(recs) => recs,
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    if recs.len() as i64 > MAX_EXPORT_ROWS {
+        return err(
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "More than {} sessions in range; narrow the time range",
+                MAX_EXPORT_ROWS
+            ),
+        );
+    }
+    let rows = recs.into_iter().map(UsageExportRow::from);
+    match format {
+        ExportFormat::Json => (StatusCode::OK, Json(rows.collect::<Vec<_>>())).into_response(),
+        ExportFormat::Csv => csv_response(
+            &format!(
+                "usage-{}-{}.csv",
+                from.format("%Y%m%d"),
+                to.format("%Y%m%d")
+            ),
+            rows,
+        ),
+    }
+}
+
+// UNIXタイムスタンプを日時に変換する (範囲外の値は 400)
+#[allow(clippy::result_large_err)]
+fn parse_timestamp(value: Option<i64>, name: &str) -> Result<Option<DateTime<Utc>>, Response> {
+    match value {
+        None => Ok(None),
+        Some(ts) => DateTime::from_timestamp(ts, 0).map(Some).ok_or_else(|| {
+            err(
+                StatusCode::BAD_REQUEST,
+                &format!("{} is not a valid timestamp", name),
+            )
+        }),
+    }
+}
+
+// 日次集計の期間 (UTCの日付) を決定する
+fn daily_range(
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<(NaiveDate, NaiveDate), String> {
+    let to = to.unwrap_or(now).date_naive();
+    let from = match from {
+        Some(from) => from.date_naive(),
+        None => to
+            .checked_sub_days(Days::new(DEFAULT_DAILY_DAYS as u64 - 1))
+            .ok_or_else(|| "to is out of range".to_string())?,
+    };
+    if from > to {
+        return Err("from must not be after to".to_string());
+    }
+    if (to - from).num_days() >= MAX_DAILY_DAYS {
+        return Err(format!("The range must be at most {} days", MAX_DAILY_DAYS));
+    }
+    Ok((from, to))
+}
+
+// 自身のSOCKS5セッション履歴を新しい順に取得するエンドポイント
+#[utoipa::path(
+    get,
+    path = "/api/me/sessions",
+    params(SessionsQuery),
+    responses(
+        (status = 200, description = "Session history, newest first", body = UsageSessionListResponse),
+        (status = 400, description = "Invalid time range", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the usage:read scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Usage"
+)]
+pub(crate) async fn list_sessions(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<SessionsQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::UsageRead) {
+        return resp;
+    }
+    let from = match parse_timestamp(query.from, "from") {
+        Ok(from) => from,
+        Err(resp) => return resp,
+    };
+    let to = match parse_timestamp(query.to, "to") {
+        Ok(to) => to,
+        Err(resp) => return resp,
+    };
+    if matches!((from, to), (Some(from), Some(to)) if from > to) {
+        return err(StatusCode::BAD_REQUEST, "from must not be after to");
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SESSIONS_LIMIT)
+        .clamp(1, MAX_SESSIONS_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+    match get_user_usage_sessions(
+        &state.db_pool,
+        auth.user_id,
+        from,
+        to,
+        query.token_id,
+        limit,
+        offset,
+    )
+    .await
+    {
+        Ok((recs, total)) => {
+            let resp = UsageSessionListResponse {
+                sessions: recs.into_iter().map(Into::into).collect(),
+                total,
+                limit,
+                offset,
+            };
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 自身の日次利用量 (グラフ表示用) を取得するエンドポイント
+// 利用のなかった日は含まれない
+#[utoipa::path(
+    get,
+    path = "/api/me/usage/daily",
+    params(DailyUsageQuery),
+    responses(
+        (status = 200, description = "Daily usage in ascending order of day", body = [DailyUsageResponse]),
+        (status = 400, description = "Invalid time range", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the usage:read scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Usage"
+)]
+pub(crate) async fn get_daily_usage(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<DailyUsageQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::UsageRead) {
+        return resp;
+    }
+    let from = match parse_timestamp(query.from, "from") {
+        Ok(from) => from,
+        Err(resp) => return resp,
+    };
+    let to = match parse_timestamp(query.to, "to") {
+        Ok(to) => to,
+        Err(resp) => return resp,
+    };
+    let (from, to) = match daily_range(from, to, Utc::now()) {
+        Ok(range) => range,
+        Err(msg) => return err(StatusCode::BAD_REQUEST, &msg),
+    };
+    match get_user_usage_daily(&state.db_pool, auth.user_id, from, to).await {
+        Ok(recs) => {
+            let resp: Vec<DailyUsageResponse> = recs.into_iter().map(Into::into).collect();
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 自身のセッション履歴をCSV/JSONでエクスポートするエンドポイント
+#[utoipa::path(
+    get,
+    path = "/api/me/usage/export",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Sessions in ascending order of start time (JSON array or CSV file)", body = [UsageExportRow]),
+        (status = 400, description = "Invalid time range or too many sessions", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the usage:read scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Usage"
+)]
+pub(crate) async fn export_usage(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<ExportQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::UsageRead) {
+        return resp;
+    }
+    export_sessions(
+        &state,
+        Some(auth.user_id),
+        None,
+        query.from,
+        query.to,
+        query.format,
+    )
+    .await
+}
+
+// 全ユーザのセッション履歴をエクスポートするエンドポイント (管理者のみ)
+#[utoipa::path(
+    get,
+    path = "/api/admin/usage/export",
+    params(AdminExportQuery),
+    responses(
+        (status = 200, description = "Sessions in ascending order of start time (JSON array or CSV file)", body = [UsageExportRow]),
+        (status = 400, description = "Invalid time range or too many sessions", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn export_all_usage(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<AdminExportQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    export_sessions(
+        &state,
+        query.user_id,
+        query.organization_id,
+        query.from,
+        query.to,
+        query.format,
+    )
+    .await
+}
+
+// 自身の月次明細 (個人トークンの利用分) を取得するエンドポイント
+#[utoipa::path(
+    get,
+    path = "/api/me/statements/{month}",
+    params(("month" = String, Path, description = "Month in YYYY-MM format (UTC)"), StatementQuery),
+    responses(
+        (status = 200, description = "Monthly statement (JSON or CSV file)", body = MonthlyStatement),
+        (status = 400, description = "Invalid month", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the usage:read scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Usage"
+)]
+pub(crate) async fn get_statement(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(month): Path<String>,
+    Query(query): Query<StatementQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::UsageRead) {
+        return resp;
+    }
+    let username = match get_user_by_id(&state.db_pool, auth.user_id).await {
+        Ok(user) => user.map(|u| u.username),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    match account_statement(&state, &month, auth.user_id, false, username).await {
+        Ok(statement) => {
+            statement_response(statement, query.format, &format!("statement-{}.csv", month))
+        }
+        Err(resp) => resp,
+    }
+}
+
+// 全請求先 (ユーザ・組織) の月次明細を取得するエンドポイント (管理者のみ)
+// 対象月に利用のあった請求先のみを返す
+#[utoipa::path(
+    get,
+    path = "/api/admin/statements/{month}",
+    params(("month" = String, Path, description = "Month in YYYY-MM format (UTC)"), StatementQuery),
+    responses(
+        (status = 200, description = "Monthly statements of all accounts (JSON array or CSV file)", body = [MonthlyStatement]),
+        (status = 400, description = "Invalid month", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn list_statements(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(month): Path<String>,
+    Query(query): Query<StatementQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let Some(first_day) = parse_month(&month) else {
+        return err(StatusCode::BAD_REQUEST, "month must be in YYYY-MM format");
+    };
+    let statements = match get_usage_monthly(&state.db_pool, first_day, None).await {
+        Ok(recs) => build_statements(first_day, recs),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    match query.format {
+        ExportFormat::Json => (StatusCode::OK, Json(statements)).into_response(),
+        ExportFormat::Csv => csv_response(
+            &format!("statements-{}.csv", month),
+            statements.iter().map(StatementCsvRow::from),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_daily_range() {
+        let now = at("2025-07-26T12:00:00Z");
+        let day = |s: &str| s.parse::<NaiveDate>().unwrap();
+        assert_eq!(
+            daily_range(None, None, now).unwrap(),
+            (day("2025-06-27"), day("2025-07-26"))
+        );
+        assert_eq!(
+            daily_range(Some(at("2025-07-01T23:59:59Z")), None, now).unwrap(),
+            (day("2025-07-01"), day("2025-07-26"))
+        );
+        assert!(daily_range(Some(now), Some(at("2025-07-25T00:00:00Z")), now).is_err());
+        assert!(daily_range(Some(at("2024-01-01T00:00:00Z")), None, now).is_err());
+        // 日付の範囲外になる場合はエラー (パニックしない)
+        assert!(daily_range(None, Some(DateTime::<Utc>::MIN_UTC), now).is_err());
+    }
+
+    #[test]
+    fn test_parse_month() {
+        assert_eq!(parse_month("2025-07"), "2025-07-01".parse().ok());
+        assert_eq!(parse_month("2025-13"), None);
+        assert_eq!(parse_month("2025-7"), None);
+        assert_eq!(parse_month("2025-07-01"), None);
+    }
+
+    #[test]
+    fn test_build_statements() {
+        let user = Uuid::now_v7();
+        let org = Uuid::now_v7();
+        let rec = |id, is_org, country: &str, sessions, bytes, points| UsageMonthlyRecord {
+            account_id: id,
+            is_organization: is_org,
+            account_name: Some("name".to_string()),
+            country: country.to_string(),
+            sessions,
+            bytes_up: bytes,
+            bytes_down: bytes,
+            points_charged: points,
+        };
+        let month = "2025-07-01".parse().unwrap();
+        let statements = build_statements(
+            month,
+            vec![
+                rec(user, false, "", 1, 0, 0),
+                rec(user, false, "JP", 2, 100, 20),
+                rec(user, false, "US", 3, 300, 30),
+                rec(org, true, "JP", 1, 50, 10),
+            ],
+        );
+        assert_eq!(statements.len(), 2);
+        let st = &statements[0];
+        assert_eq!(st.month, "2025-07");
+        assert_eq!(st.account_type, "user");
+        assert_eq!((st.sessions, st.bytes_up, st.points_spent), (6, 400, 50));
+        let countries: Vec<_> = st
+            .top_countries
+            .iter()
+            .map(|c| c.country.as_deref())
+            .collect();
+        assert_eq!(countries, [Some("US"), Some("JP"), None]);
+        assert_eq!(statements[1].account_type, "organization");
+        assert_eq!(statements[1].top_countries[0].bytes, 100);
+    }
+}
@@ -0,0 +1,594 @@
+use super::auth::AuthUser;
+use super::dto::ErrorResponse;
+use super::err;
+use crate::events::Event;
+use crate::repository::{
+    adjust_user_points, delete_user, get_point_ledger, get_user_by_id, get_user_bytes_used,
+    get_user_points, get_user_tokens, get_users, revoke_user_refresh_tokens, set_user_role,
+    set_user_suspended, PointLedgerRecord, UserRecord, UserRole, UserSummaryRecord,
+};
+use crate::token::TokenResponse;
+use crate::AppState;
+use axum::extract::{Path, Query, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::Utc;
+use hyper::StatusCode;
+use log::info;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+// ユーザ一覧のデフォルト件数と上限
+const DEFAULT_USERS_LIMIT: i64 = 50;
+const MAX_USERS_LIMIT: i64 = 200;
+// ポイント履歴のデフォルト件数と上限
+const DEFAULT_LEDGER_LIMIT: i64 = 50;
+const MAX_LEDGER_LIMIT: i64 = 500;
+// 停止理由・ポイント増減理由の最大文字数
+const MAX_REASON_LENGTH: usize = 256;
+
+// ユーザ一覧のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct UsersQuery {
+    // ユーザ名の部分一致検索 (大文字小文字を区別しない)
+    pub search: Option<String>,
+    // 取得件数 (デフォルト50, 最大200)
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+// ポイント履歴のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct LedgerQuery {
+    // 取得件数 (デフォルト50, 最大500)
+    pub limit: Option<i64>,
+}
+
+// 管理者向けのユーザ情報
+#[derive(Serialize, ToSchema)]
+pub(crate) struct AdminUserResponse {
+    pub id: Uuid,
+    pub username: String,
+    pub role: UserRole,
+    pub points: i32,
+    // UNIXタイムスタンプで表現される登録日時・停止日時
+    pub created_at: i64,
+    pub suspended_at: Option<i64>,
+    pub suspension_reason: Option<String>,
+}
+
+impl From<UserSummaryRecord> for AdminUserResponse {
+    fn from(rec: UserSummaryRecord) -> Self {
+        AdminUserResponse {
+            id: rec.id,
+            username: rec.username,
+            role: rec.role,
+            points: rec.points,
+            created_at: rec.created_at.timestamp(),
+            suspended_at: rec.suspended_at.map(|t| t.timestamp()),
+            suspension_reason: rec.suspension_reason,
+        }
+    }
+}
+
+impl AdminUserResponse {
+    fn from_record(rec: UserRecord, points: i32) -> Self {
+        AdminUserResponse {
+            id: rec.id,
+            username: rec.username,
+            role: rec.role,
+            points,
+            created_at: rec.created_at.timestamp(),
+            suspended_at: rec.suspended_at.map(|t| t.timestamp()),
+            suspension_reason: rec.suspension_reason,
+        }
+    }
+}
+
+// ユーザ一覧のレスポンス
+#[derive(Serialize, ToSchema)]
+pub(crate) struct UserListResponse {
+    pub users: Vec<AdminUserResponse>,
+    // 検索条件に一致する総件数
+    pub total: i64,
+}
+
+// ユーザの利用状況
+#[derive(Serialize, ToSchema)]
+pub(crate) struct UserUsage {
+    // 有効なトークン数
+    pub active_tokens: usize,
+    // 同時接続中のSOCKS5セッション数
+    pub active_sessions: usize,
+    // 全トークン (削除済みを除く) の累計転送量 (バイト)
+    pub bytes_used: i64,
+}
+
+// ユーザ詳細のレスポンス
+#[derive(Serialize, ToSchema)]
+pub(crate) struct AdminUserDetailResponse {
+    pub user: AdminUserResponse,
+    pub tokens: Vec<TokenResponse>,
+    pub usage: UserUsage,
+}
+
+// ロール変更リクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct UpdateRoleRequest {
+    pub role: UserRole,
+}
+
+// アカウント停止リクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct SuspendUserRequest {
+    pub reason: Option<String>,
+}
+
+// ポイント増減リクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct AdjustPointsRequest {
+    // 増減量 (付与は正、減算は負)
+    pub amount: i32,
+    // 理由 (履歴に記録される)
+    pub reason: String,
+}
+
+// ポイント履歴の1件
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PointLedgerResponse {
+    pub id: Uuid,
+    pub amount: i32,
+    pub balance_after: i32,
+    pub reason: String,
+    pub created_by: Option<Uuid>,
+    pub created_at: i64,
+}
+
+impl From<PointLedgerRecord> for PointLedgerResponse {
+    fn from(rec: PointLedgerRecord) -> Self {
+        PointLedgerResponse {
+            id: rec.id,
+            amount: rec.amount,
+            balance_after: rec.balance_after,
+            reason: rec.reason,
+            created_by: rec.created_by,
+            created_at: rec.created_at.timestamp(),
+        }
+    }
+}
+
+// 対象ユーザを取得する (存在しない場合は 404)
+async fn find_user(state: &AppState, user_id: Uuid) -> Result<UserRecord, Response> {
+    match get_user_by_id(&state.db_pool, user_id).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(err(StatusCode::NOT_FOUND, "User not found")),
+        Err(e) => Err(err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())),
+    }
+}
+
+// 自分自身に対する操作を禁止する (管理者が締め出されるのを防ぐ)
+#[allow(clippy::result_large_err)]
+fn forbid_self(auth: &AuthUser, user_id: Uuid, action: &str) -> Result<(), Response> {
+    if auth.user_id == user_id {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            &format!("Cannot {} your own account", action),
+        ));
+    }
+    Ok(())
+}
+
+// 理由の文字列を検証する
+#[allow(clippy::result_large_err)]
+pub(crate) fn validate_reason(reason: &str) -> Result<(), Response> {
+    if reason.chars().count() > MAX_REASON_LENGTH {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            &format!("reason must be at most {} characters", MAX_REASON_LENGTH),
+        ));
+    }
+    Ok(())
+}
+
+// ユーザの全ログインセッションとSOCKS5セッションを終了させる
+// 戻り値は強制終了したSOCKS5セッション数
+async fn terminate_user_access(state: &AppState, user_id: Uuid) -> sqlx::Result<usize> {
+    let now = Utc::now();
+    state
+        .revocations
+        .revoke_user(&state.db_pool, user_id, now)
+        .await?;
+    revoke_user_refresh_tokens(&state.db_pool, user_id, now).await?;
+    let tokens = get_user_tokens(&state.db_pool, user_id).await?;
+    Ok(tokens.iter().map(|t| state.sessions.revoke(t.id)).sum())
+}
+
+// ユーザ一覧を取得するエンドポイント (管理者のみ)
+#[utoipa::path(
+    get,
+    path = "/api/admin/users",
+    params(UsersQuery),
+    responses(
+        (status = 200, description = "List of users", body = UserListResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn list_users(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<UsersQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_USERS_LIMIT)
+        .clamp(1, MAX_USERS_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let search = query
+        .search
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty());
+    match get_users(&state.db_pool, search, limit, offset).await {
+        Ok((recs, total)) => {
+            let resp = UserListResponse {
+                users: recs.into_iter().map(Into::into).collect(),
+                total,
+            };
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// ユーザの詳細 (トークンと利用状況) を取得するエンドポイント (管理者のみ)
+#[utoipa::path(
+    get,
+    path = "/api/admin/users/{user_id}",
+    params(("user_id" = Uuid, Path, description = "User ID")),
+    responses(
+        (status = 200, description = "User details", body = AdminUserDetailResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn get_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let user = match find_user(&state, user_id).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    let points = match get_user_points(&state.db_pool, user_id).await {
+        Ok(rec) => rec.map(|r| r.points).unwrap_or(0),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let tokens = match get_user_tokens(&state.db_pool, user_id).await {
+        Ok(recs) => recs
+            .into_iter()
+            .map(|r| TokenResponse::from_record(r, &state.sessions))
+            .collect::<Vec<_>>(),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let bytes_used = match get_user_bytes_used(&state.db_pool, user_id).await {
+        Ok(n) => n,
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let usage = UserUsage {
+        active_tokens: tokens.len(),
+        active_sessions: tokens.iter().map(|t| t.active_sessions).sum(),
+        bytes_used,
+    };
+    let resp = AdminUserDetailResponse {
+        user: AdminUserResponse::from_record(user, points),
+        tokens,
+        usage,
+    };
+    (StatusCode::OK, Json(resp)).into_response()
+}
+
+// ユーザのロールを変更するエンドポイント (管理者のみ)
+// 古いロールのJWTが使われないよう、対象ユーザのログインセッションを失効させる
+#[utoipa::path(
+    put,
+    path = "/api/admin/users/{user_id}/role",
+    params(("user_id" = Uuid, Path, description = "User ID")),
+    request_body = UpdateRoleRequest,
+    responses(
+        (status = 204, description = "Role updated"),
+        (status = 400, description = "Cannot change your own role", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn update_user_role(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<UpdateRoleRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    if let Err(resp) = forbid_self(&auth, user_id, "change the role of") {
+        return resp;
+    }
+    let user = match find_user(&state, user_id).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if user.role == req.role {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    if let Err(e) = set_user_role(&state.db_pool, user_id, req.role).await {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    // リフレッシュ時はDB上のロールが使われるため、リフレッシュトークンは失効させない
+    if let Err(e) = state
+        .revocations
+        .revoke_user(&state.db_pool, user_id, Utc::now())
+        .await
+    {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    info!(
+        "Admin {} changed role of user {} from {:?} to {:?}",
+        auth.user_id, user_id, user.role, req.role
+    );
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// アカウントを停止するエンドポイント (管理者のみ)
+// 停止中はログイン・APIキー・SOCKS5認証が拒否され、既存のセッションも終了する
+#[utoipa::path(
+    post,
+    path = "/api/admin/users/{user_id}/suspension",
+    params(("user_id" = Uuid, Path, description = "User ID")),
+    request_body = SuspendUserRequest,
+    responses(
+        (status = 204, description = "User suspended"),
+        (status = 400, description = "Cannot suspend your own account, or reason too long", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn suspend_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<SuspendUserRequest>,
+) -> impl IntoResponse {
+    let reason = req
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|r| !r.is_empty());
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    if let Err(resp) = forbid_self(&auth, user_id, "suspend") {
+        return resp;
+    }
+    if let Err(resp) = validate_reason(reason.unwrap_or_default()) {
+        return resp;
+    }
+    if let Err(resp) = find_user(&state, user_id).await {
+        return resp;
+    }
+    if let Err(e) = set_user_suspended(&state.db_pool, user_id, Some(Utc::now()), reason).await {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    match terminate_user_access(&state, user_id).await {
+        Ok(terminated) => {
+            info!(
+                "Admin {} suspended user {} ({} sessions terminated)",
+                auth.user_id, user_id, terminated
+            );
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// アカウントの停止を解除するエンドポイント (管理者のみ)
+#[utoipa::path(
+    delete,
+    path = "/api/admin/users/{user_id}/suspension",
+    params(("user_id" = Uuid, Path, description = "User ID")),
+    responses(
+        (status = 204, description = "User unsuspended"),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn unsuspend_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    match set_user_suspended(&state.db_pool, user_id, None, None).await {
+        Ok(0) => err(StatusCode::NOT_FOUND, "User not found"),
+        Ok(_) => {
+            info!("Admin {} unsuspended user {}", auth.user_id, user_id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// ユーザを削除するエンドポイント (管理者のみ)
+// トークン・ポイント・APIキー等も削除され、既存のセッションは終了する
+#[utoipa::path(
+    delete,
+    path = "/api/admin/users/{user_id}",
+    params(("user_id" = Uuid, Path, description = "User ID")),
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 400, description = "Cannot delete your own account", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn remove_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    if let Err(resp) = forbid_self(&auth, user_id, "delete") {
+        return resp;
+    }
+    if let Err(resp) = find_user(&state, user_id).await {
+        return resp;
+    }
+    // 削除前にセッションを終了させる (失効リストはメモリ上にも保持される)
+    let terminated = match terminate_user_access(&state, user_id).await {
+        Ok(n) => n,
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    match delete_user(&state.db_pool, user_id).await {
+        Ok(0) => err(StatusCode::NOT_FOUND, "User not found"),
+        Ok(_) => {
+            info!(
+                "Admin {} deleted user {} ({} sessions terminated)",
+                auth.user_id, user_id, terminated
+            );
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// ポイントを付与・減算するエンドポイント (管理者のみ)
+#[utoipa::path(
+    post,
+    path = "/api/admin/users/{user_id}/points",
+    params(("user_id" = Uuid, Path, description = "User ID")),
+    request_body = AdjustPointsRequest,
+    responses(
+        (status = 201, description = "Points adjusted", body = PointLedgerResponse),
+        (status = 400, description = "Invalid amount or reason, or insufficient points", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn adjust_points(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<AdjustPointsRequest>,
+) -> impl IntoResponse {
+    let reason = req.reason.trim();
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    if let Err(resp) = validate_reason(reason) {
+        return resp;
+    }
+    if req.amount == 0 {
+        return err(StatusCode::BAD_REQUEST, "amount must not be zero");
+    }
+    if reason.is_empty() {
+        return err(StatusCode::BAD_REQUEST, "reason is required");
+    }
+    if let Err(resp) = find_user(&state, user_id).await {
+        return resp;
+    }
+    let rec = PointLedgerRecord {
+        id: Uuid::now_v7(),
+        user_id,
+        amount: req.amount,
+        balance_after: 0,
+        reason: reason.to_string(),
+        created_by: Some(auth.user_id),
+        created_at: Utc::now(),
+    };
+    match adjust_user_points(&state.db_pool, &rec).await {
+        Ok(Some(rec)) => {
+            info!(
+                "Admin {} adjusted points of user {} by {} ({}): balance {}",
+                auth.user_id, user_id, rec.amount, rec.reason, rec.balance_after
+            );
+            state.events.publish(Event::UserBalanceChanged {
+                user_id,
+                points: rec.balance_after,
+            });
+            (StatusCode::CREATED, Json(PointLedgerResponse::from(rec))).into_response()
+        }
+        Ok(None) => err(StatusCode::BAD_REQUEST, "Insufficient points"),
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// ポイントの増減履歴を取得するエンドポイント (管理者のみ)
+#[utoipa::path(
+    get,
+    path = "/api/admin/users/{user_id}/points",
+    params(("user_id" = Uuid, Path, description = "User ID"), LedgerQuery),
+    responses(
+        (status = 200, description = "Point ledger, newest first", body = [PointLedgerResponse]),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn list_point_ledger(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+    Query(query): Query<LedgerQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    if let Err(resp) = find_user(&state, user_id).await {
+        return resp;
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LEDGER_LIMIT)
+        .clamp(1, MAX_LEDGER_LIMIT);
+    match get_point_ledger(&state.db_pool, user_id, limit).await {
+        Ok(recs) => {
+            let resp: Vec<PointLedgerResponse> = recs.into_iter().map(Into::into).collect();
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
@@ -0,0 +1,201 @@
+use crate::api::auth::AuthUser;
+use crate::api::dto::ErrorResponse;
+use crate::api::err;
+use crate::repository::{
+    create_api_key, delete_user_api_key, get_user_api_keys, ApiKeyRecord, ApiKeyScope, UserRole,
+};
+use crate::token::hash::{generate_secret, visible_prefix};
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::{response::IntoResponse, Json};
+use chrono::Utc;
+use hyper::StatusCode;
+use log::info;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// APIキーの接頭辞 (Bearer でJWTと区別するために使用)
+pub(crate) const API_KEY_PREFIX: &str = "chk_";
+// 名前の最大文字数
+const MAX_NAME_LENGTH: usize = 64;
+
+// APIキー作成リクエスト
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ApiKeyRequest {
+    // 識別用の名前 (例: "dashboard-backend")
+    pub name: String,
+    // 許可するスコープ (1つ以上)
+    pub scopes: Vec<ApiKeyScope>,
+    // 有効期間 (秒)。省略時は無期限
+    pub ttl_seconds: Option<i64>,
+}
+
+// APIレスポンス用のAPIキー情報
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ApiKeyResponse {
+    pub id: Uuid,
+    // キー本体 (作成時のみ返却。以降は再表示できない)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    // 識別用のキー先頭部分
+    pub prefix: String,
+    pub name: String,
+    pub scopes: Vec<ApiKeyScope>,
+    // UNIXタイムスタンプで表現される作成日時・有効期限・最終利用日時
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+}
+
+impl From<ApiKeyRecord> for ApiKeyResponse {
+    fn from(rec: ApiKeyRecord) -> Self {
+        ApiKeyResponse {
+            id: rec.id,
+            key: None,
+            prefix: rec.key_prefix,
+            name: rec.name,
+            scopes: rec.scopes,
+            created_at: rec.created_at.timestamp(),
+            expires_at: rec.expires_at.map(|t| t.timestamp()),
+            last_used_at: rec.last_used_at.map(|t| t.timestamp()),
+        }
+    }
+}
+
+// APIキーを作成するAPIハンドラ
+// 権限の昇格を防ぐため、APIキーの管理はログインセッションでのみ行える
+#[utoipa::path(
+    post,
+    path = "/api/me/api-keys",
+    request_body = ApiKeyRequest,
+    responses(
+        (status = 201, description = "API key created", body = ApiKeyResponse),
+        (status = 400, description = "Invalid name, scopes or TTL", body = ErrorResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn create_user_api_key(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<ApiKeyRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    let name = req.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return err(
+            StatusCode::BAD_REQUEST,
+            &format!("name must be 1-{} characters", MAX_NAME_LENGTH),
+        );
+    }
+    if req.scopes.is_empty() {
+        return err(StatusCode::BAD_REQUEST, "At least one scope is required");
+    }
+    // admin スコープは管理者のみ付与可能
+    if req.scopes.contains(&ApiKeyScope::Admin) && auth.role != UserRole::Admin {
+        return err(StatusCode::FORBIDDEN, "管理者のみ実行可能");
+    }
+    if matches!(req.ttl_seconds, Some(ttl) if ttl <= 0) {
+        return err(StatusCode::BAD_REQUEST, "ttl_seconds must be positive");
+    }
+    let mut scopes = req.scopes;
+    scopes.sort();
+    scopes.dedup();
+    let now = Utc::now();
+    // 有効期限 (極端に大きい ttl_seconds は日時の範囲を超えるため拒否)
+    let expires_at = match req.ttl_seconds {
+        Some(ttl) => {
+            match chrono::TimeDelta::try_seconds(ttl).and_then(|d| now.checked_add_signed(d)) {
+                Some(expires_at) => Some(expires_at),
+                None => return err(StatusCode::BAD_REQUEST, "ttl_seconds is too large"),
+            }
+        }
+        None => None,
+    };
+    let key = generate_secret(API_KEY_PREFIX);
+    let rec = ApiKeyRecord {
+        id: Uuid::now_v7(),
+        user_id: auth.user_id,
+        key_prefix: visible_prefix(&key),
+        name: name.to_string(),
+        scopes,
+        created_at: now,
+        expires_at,
+        last_used_at: None,
+    };
+    if let Err(e) = create_api_key(&state.db_pool, &rec, &state.token_hasher.hash(&key)).await {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    info!("User {} created API key {}", auth.user_id, rec.id);
+    // キー本体を返すのはこの一度のみ
+    let resp = ApiKeyResponse {
+        key: Some(key),
+        ..ApiKeyResponse::from(rec)
+    };
+    (StatusCode::CREATED, Json(resp)).into_response()
+}
+
+// 自分のAPIキー一覧を取得するAPIハンドラ
+#[utoipa::path(
+    get,
+    path = "/api/me/api-keys",
+    responses(
+        (status = 200, description = "List of API keys", body = [ApiKeyResponse]),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn list_user_api_keys(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    match get_user_api_keys(&state.db_pool, auth.user_id).await {
+        Ok(recs) => {
+            let resp: Vec<ApiKeyResponse> = recs.into_iter().map(Into::into).collect();
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 自分のAPIキーを削除するAPIハンドラ
+#[utoipa::path(
+    delete,
+    path = "/api/me/api-keys/{key_id}",
+    params(("key_id" = Uuid, Path, description = "API key ID")),
+    responses(
+        (status = 204, description = "API key deleted"),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "API key not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn delete_api_key(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(key_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_session() {
+        return resp;
+    }
+    match delete_user_api_key(&state.db_pool, auth.user_id, key_id).await {
+        Ok(0) => err(StatusCode::NOT_FOUND, "API key not found"),
+        Ok(_) => {
+            info!("User {} deleted API key {}", auth.user_id, key_id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
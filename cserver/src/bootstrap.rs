@@ -0,0 +1,191 @@
+use crate::api::password::hash_password;
+use crate::config::Settings;
+use crate::repository::{get_user_by_username, register_user, set_user_role, UserRecord, UserRole};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+use log::{info, warn};
+use sqlx::PgPool;
+use std::env;
+use std::io::BufRead;
+use uuid::Uuid;
+
+// 初回起動時に管理者を作成するための環境変数
+const ADMIN_USERNAME_ENV: &str = "CHILSONITE_ADMIN_USERNAME";
+const ADMIN_PASSWORD_ENV: &str = "CHILSONITE_ADMIN_PASSWORD";
+
+// コマンドライン引数 (サブコマンドなしの場合はサーバーを起動する)
+#[derive(Parser, Debug)]
+#[command(version, about = "Chilsonite proxy server")]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Manage admin accounts
+    Admin {
+        #[command(subcommand)]
+        action: AdminCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum AdminCommand {
+    /// Create an admin account (no-op if it already exists as an admin)
+    Create {
+        #[arg(long)]
+        username: String,
+        /// Read the password from the first line of stdin instead of CHILSONITE_ADMIN_PASSWORD
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Promote an existing user to admin
+    Promote {
+        #[arg(long)]
+        username: String,
+    },
+}
+
+// 管理者作成の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnsureAdmin {
+    Created(Uuid),
+    AlreadyAdmin(Uuid),
+}
+
+// 管理者を作成する (同名の管理者が存在する場合は何もしない)
+// 同名の一般ユーザが存在する場合は、登録済みアカウントの乗っ取りを防ぐため昇格せずエラーとする
+async fn ensure_admin(
+    pool: &PgPool,
+    settings: &Settings,
+    username: &str,
+    password: &str,
+) -> Result<EnsureAdmin> {
+    if let Some(user) = get_user_by_username(pool, username).await? {
+        return match user.role {
+            UserRole::Admin => Ok(EnsureAdmin::AlreadyAdmin(user.id)),
+            UserRole::User => Err(anyhow!(
+                "User '{}' already exists and is not an admin; use `cserver admin promote` to grant the role",
+                username
+            )),
+        };
+    }
+    settings
+        .password_policy
+        .validate(username, password)
+        .map_err(|msg| anyhow!("Invalid admin password: {}", msg))?;
+    let user = UserRecord {
+        id: Uuid::now_v7(),
+        username: username.to_string(),
+        password_hash: hash_password(password),
+        role: UserRole::Admin,
+        created_at: Utc::now(),
+        suspended_at: None,
+        suspension_reason: None,
+    };
+    register_user(pool, &user, settings.starting_points, None, "Registration").await?;
+    Ok(EnsureAdmin::Created(user.id))
+}
+
+// 管理者サブコマンドを実行する
+pub(crate) async fn run_admin_command(
+    pool: &PgPool,
+    settings: &Settings,
+    action: AdminCommand,
+) -> Result<()> {
+    match action {
+        AdminCommand::Create {
+            username,
+            password_stdin,
+        } => {
+            let password = if password_stdin {
+                let mut line = String::new();
+                std::io::stdin().lock().read_line(&mut line)?;
+                line.trim_end_matches(['\r', '\n']).to_string()
+            } else {
+                env::var(ADMIN_PASSWORD_ENV).map_err(|_| {
+                    anyhow!(
+                        "Set {} or pass --password-stdin to provide the password",
+                        ADMIN_PASSWORD_ENV
+                    )
+                })?
+            };
+            match ensure_admin(pool, settings, username.trim(), &password).await? {
+                EnsureAdmin::Created(id) => info!("Created admin '{}' ({})", username, id),
+                EnsureAdmin::AlreadyAdmin(id) => {
+                    info!("Admin '{}' already exists ({})", username, id)
+                }
+            }
+        }
+        AdminCommand::Promote { username } => {
+            let user = get_user_by_username(pool, username.trim())
+                .await?
+                .ok_or_else(|| anyhow!("User '{}' not found", username))?;
+            if user.role == UserRole::Admin {
+                info!("User '{}' is already an admin", username);
+            } else {
+                set_user_role(pool, user.id, UserRole::Admin).await?;
+                info!("Promoted '{}' ({}) to admin", username, user.id);
+            }
+        }
+    }
+    Ok(())
+}
+
+// 環境変数が設定されていれば、起動時に管理者を作成する (既に存在する場合は何もしない)
+pub(crate) async fn bootstrap_admin_from_env(pool: &PgPool, settings: &Settings) -> Result<()> {
+    let Ok(username) = env::var(ADMIN_USERNAME_ENV) else {
+        return Ok(());
+    };
+    let password = env::var(ADMIN_PASSWORD_ENV).map_err(|_| {
+        anyhow!(
+            "{} is set but {} is not",
+            ADMIN_USERNAME_ENV,
+            ADMIN_PASSWORD_ENV
+        )
+    })?;
+    match ensure_admin(pool, settings, username.trim(), &password).await {
+        Ok(EnsureAdmin::Created(id)) => {
+            info!("Bootstrapped admin '{}' ({})", username, id);
+            warn!(
+                "Remove {} from the environment now that the admin exists",
+                ADMIN_PASSWORD_ENV
+            );
+        }
+        Ok(EnsureAdmin::AlreadyAdmin(_)) => {}
+        // 起動は継続する (同名の一般ユーザが存在する場合など)
+        Err(e) => warn!("Admin bootstrap skipped: {}", e),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_parsing() {
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from(["cserver"]).unwrap();
+        assert!(cli.command.is_none());
+        let cli = Cli::try_parse_from([
+            "cserver",
+            "admin",
+            "create",
+            "--username",
+            "root",
+            "--password-stdin",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Admin {
+                action: AdminCommand::Create { ref username, password_stdin: true }
+            }) if username == "root"
+        ));
+        assert!(Cli::try_parse_from(["cserver", "admin", "promote"]).is_err());
+    }
+}
@@ -1,3 +1,7 @@
+use crate::api::password::PasswordPolicy;
+use crate::geoip::GeoIpSettings;
+use crate::health::HealthSettings;
+use crate::lockout::LockoutSettings;
 use anyhow::{anyhow, Result};
 use config::Config;
 use serde::Deserialize;
@@ -9,6 +13,136 @@ pub(crate) struct Settings {
     pub socks5_port: u16,
     pub bind_address: String,
     pub connect_timeout_seconds: u64,
+    // プロキシトークンの有効期間 (秒)
+    // 生成時に指定がない場合は default を使用し、指定値は min〜max の範囲に制限する
+    #[serde(default = "default_token_ttl_seconds")]
+    pub token_default_ttl_seconds: i64,
+    #[serde(default = "default_token_min_ttl_seconds")]
+    pub token_min_ttl_seconds: i64,
+    #[serde(default = "default_token_max_ttl_seconds")]
+    pub token_max_ttl_seconds: i64,
+    // 定期メンテナンス (期限切れデータの削除) の実行間隔 (秒)
+    #[serde(default = "default_maintenance_interval_seconds")]
+    pub maintenance_interval_seconds: u64,
+    // 有効期限切れのトークンを削除するまでの保持期間 (秒)
+    #[serde(default = "default_expired_token_retention_seconds")]
+    pub expired_token_retention_seconds: i64,
+    // メンテナンス実行履歴の保持期間 (秒)
+    #[serde(default = "default_maintenance_run_retention_seconds")]
+    pub maintenance_run_retention_seconds: i64,
+    // SOCKS5セッション利用履歴の保持期間 (秒)。日次集計は削除しない
+    #[serde(default = "default_usage_session_retention_seconds")]
+    pub usage_session_retention_seconds: i64,
+    // ログインセッションのアクセストークン (JWT) とリフレッシュトークンの有効期間 (秒)
+    #[serde(default = "default_access_token_ttl_seconds")]
+    pub access_token_ttl_seconds: i64,
+    #[serde(default = "default_refresh_token_ttl_seconds")]
+    pub refresh_token_ttl_seconds: i64,
+    // セッションCookieの属性 (HTTPS終端の有無やドメイン構成に合わせて設定)
+    #[serde(default = "default_cookie_secure")]
+    pub cookie_secure: bool,
+    #[serde(default)]
+    pub cookie_same_site: CookieSameSite,
+    #[serde(default)]
+    pub cookie_domain: Option<String>,
+    // 登録・パスワード変更時に適用するパスワードポリシー
+    #[serde(default)]
+    pub password_policy: PasswordPolicy,
+    // 管理者が発行するパスワードリセットトークンの有効期間 (秒)
+    #[serde(default = "default_password_reset_ttl_seconds")]
+    pub password_reset_ttl_seconds: i64,
+    // 管理者のダッシュボードログインにTOTP二要素認証を必須とするか
+    #[serde(default)]
+    pub require_totp_for_admins: bool,
+    // ログインとSOCKS5認証の連続失敗に対するロックアウト
+    #[serde(default)]
+    pub lockout: LockoutSettings,
+    // エージェントの健全性スコアと隔離
+    #[serde(default)]
+    pub agent_health: HealthSettings,
+    // エージェントの位置情報のサーバー側での検証
+    #[serde(default)]
+    pub geoip: GeoIpSettings,
+    // エージェントの登録キー (設定した場合、同じキーを送信したエージェントのみ接続できる)
+    #[serde(default)]
+    pub agent_enrollment_key: Option<String>,
+    // ユーザ登録の受付方式
+    #[serde(default)]
+    pub registration_mode: RegistrationMode,
+    // 新規ユーザに付与する初期ポイント (招待コードの付与ポイントは別途加算)
+    #[serde(default = "default_starting_points")]
+    pub starting_points: i32,
+}
+
+// ユーザ登録の受付方式
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RegistrationMode {
+    // 誰でも登録可能 (招待コードは任意)
+    #[default]
+    Open,
+    // 有効な招待コードが必要
+    InviteOnly,
+    // 新規登録を受け付けない
+    Disabled,
+}
+
+// Cookie の SameSite 属性
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CookieSameSite {
+    Strict,
+    #[default]
+    Lax,
+    None,
+}
+
+fn default_token_ttl_seconds() -> i64 {
+    24 * 60 * 60
+}
+
+fn default_token_min_ttl_seconds() -> i64 {
+    5 * 60
+}
+
+fn default_token_max_ttl_seconds() -> i64 {
+    30 * 24 * 60 * 60
+}
+
+fn default_maintenance_interval_seconds() -> u64 {
+    60 * 60
+}
+
+fn default_expired_token_retention_seconds() -> i64 {
+    7 * 24 * 60 * 60
+}
+
+fn default_maintenance_run_retention_seconds() -> i64 {
+    30 * 24 * 60 * 60
+}
+
+fn default_usage_session_retention_seconds() -> i64 {
+    90 * 24 * 60 * 60
+}
+
+fn default_access_token_ttl_seconds() -> i64 {
+    60 * 60
+}
+
+fn default_refresh_token_ttl_seconds() -> i64 {
+    30 * 24 * 60 * 60
+}
+
+fn default_cookie_secure() -> bool {
+    true
+}
+
+fn default_password_reset_ttl_seconds() -> i64 {
+    60 * 60
+}
+
+fn default_starting_points() -> i32 {
+    1000
 }
 
 // 設定ファイル（例: cserver.toml）を読み込む関数
@@ -19,7 +153,68 @@ pub(crate) async fn load_config() -> Result<Settings> {
         .build()?;
 
     // 読み込んだ設定を Settings 構造体にデシリアライズ
-    config
+    let settings: Settings = config
         .try_deserialize()
-        .map_err(|e| anyhow!("Failed to parse config: {}", e))
+        .map_err(|e| anyhow!("Failed to parse config: {}", e))?;
+    if settings.token_min_ttl_seconds <= 0
+        || settings.token_min_ttl_seconds > settings.token_max_ttl_seconds
+        || !(settings.token_min_ttl_seconds..=settings.token_max_ttl_seconds)
+            .contains(&settings.token_default_ttl_seconds)
+    {
+        return Err(anyhow!(
+            "Invalid token TTL settings: default must be within min..=max and min must be positive"
+        ));
+    }
+    if settings.maintenance_interval_seconds == 0
+        || settings.expired_token_retention_seconds < 0
+        || settings.maintenance_run_retention_seconds < 0
+        || settings.usage_session_retention_seconds < 0
+    {
+        return Err(anyhow!(
+            "Invalid maintenance settings: interval must be positive and retention periods must not be negative"
+        ));
+    }
+    if settings.access_token_ttl_seconds <= 0
+        || settings.refresh_token_ttl_seconds < settings.access_token_ttl_seconds
+    {
+        return Err(anyhow!(
+            "Invalid session settings: access token TTL must be positive and not exceed the refresh token TTL"
+        ));
+    }
+    let policy = &settings.password_policy;
+    if policy.min_length == 0 || policy.min_length > policy.max_length {
+        return Err(anyhow!(
+            "Invalid password policy: min_length must be positive and not exceed max_length"
+        ));
+    }
+    if settings.password_reset_ttl_seconds <= 0 {
+        return Err(anyhow!(
+            "Invalid password_reset_ttl_seconds: must be positive"
+        ));
+    }
+    if settings.starting_points < 0 {
+        return Err(anyhow!("Invalid starting_points: must not be negative"));
+    }
+    if let Err(msg) = settings.lockout.validate() {
+        return Err(anyhow!("Invalid lockout settings: {}", msg));
+    }
+    if let Err(msg) = settings.agent_health.validate() {
+        return Err(anyhow!("Invalid agent_health settings: {}", msg));
+    }
+    if settings
+        .agent_enrollment_key
+        .as_deref()
+        .is_some_and(|k| k.trim().is_empty())
+    {
+        return Err(anyhow!("Invalid agent_enrollment_key: must not be empty"));
+    }
+    if let Err(msg) = settings.geoip.validate() {
+        return Err(anyhow!("Invalid geoip settings: {}", msg));
+    }
+    if matches!(settings.cookie_same_site, CookieSameSite::None) && !settings.cookie_secure {
+        return Err(anyhow!(
+            "Invalid cookie settings: cookie_same_site = \"none\" requires cookie_secure = true"
+        ));
+    }
+    Ok(settings)
 }
@@ -0,0 +1,200 @@
+use crate::repository::SessionOutcome;
+use chrono::Utc;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// 購読者ごとに保持する未配信イベントの上限 (超えた場合は古いイベントから破棄される)
+const EVENT_BUS_CAPACITY: usize = 1024;
+
+// ダッシュボードに配信するイベント
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Event {
+    // エージェントの接続・切断
+    AgentConnected {
+        agent_id: String,
+        country_code: String,
+    },
+    AgentDisconnected {
+        agent_id: String,
+    },
+    // 健全性スコアの低下によるエージェントの隔離
+    AgentQuarantined {
+        agent_id: String,
+        score: f64,
+        // UNIXタイムスタンプで表現される隔離の解除日時
+        until: i64,
+    },
+    // 接続中のエージェントの出口IPの変更 (エージェントの再検出による)
+    AgentIpChanged {
+        agent_id: String,
+        old_ip: String,
+        new_ip: String,
+        country_code: String,
+    },
+    // SOCKS5トンネルの開始・終了
+    TunnelOpened {
+        tunnel_id: String,
+        user_id: Uuid,
+        token_id: Uuid,
+        agent_id: String,
+        target_host: String,
+        target_port: u16,
+    },
+    TunnelClosed {
+        tunnel_id: String,
+        user_id: Uuid,
+        token_id: Uuid,
+        agent_id: String,
+        outcome: SessionOutcome,
+        bytes_up: u64,
+        bytes_down: u64,
+        points_charged: i32,
+    },
+    // ユーザ・組織のポイント残高の変更
+    UserBalanceChanged {
+        user_id: Uuid,
+        points: i32,
+    },
+    OrganizationBalanceChanged {
+        organization_id: Uuid,
+        points: i32,
+    },
+}
+
+impl Event {
+    // SSE のイベント名
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::AgentConnected { .. } => "agent_connected",
+            Event::AgentDisconnected { .. } => "agent_disconnected",
+            Event::AgentQuarantined { .. } => "agent_quarantined",
+            Event::AgentIpChanged { .. } => "agent_ip_changed",
+            Event::TunnelOpened { .. } => "tunnel_opened",
+            Event::TunnelClosed { .. } => "tunnel_closed",
+            Event::UserBalanceChanged { .. } => "user_balance_changed",
+            Event::OrganizationBalanceChanged { .. } => "organization_balance_changed",
+        }
+    }
+
+    // アカウントの利用状況 (トンネル・残高) のイベントか
+    // APIキーでの購読には usage:read スコープが必要
+    pub fn is_usage_event(&self) -> bool {
+        !matches!(
+            self,
+            Event::AgentConnected { .. }
+                | Event::AgentDisconnected { .. }
+                | Event::AgentQuarantined { .. }
+                | Event::AgentIpChanged { .. }
+        )
+    }
+
+    // 一般ユーザに配信するか
+    // エージェントのイベントは全員、トンネルと残高は本人 (組織の残高は組織のメンバー) のみ
+    pub fn is_visible_to(&self, user_id: Uuid, organization_ids: &[Uuid]) -> bool {
+        match self {
+            Event::AgentConnected { .. }
+            | Event::AgentDisconnected { .. }
+            | Event::AgentQuarantined { .. }
+            | Event::AgentIpChanged { .. } => true,
+            Event::TunnelOpened { user_id: owner, .. }
+            | Event::TunnelClosed { user_id: owner, .. }
+            | Event::UserBalanceChanged { user_id: owner, .. } => *owner == user_id,
+            Event::OrganizationBalanceChanged {
+                organization_id, ..
+            } => organization_ids.contains(organization_id),
+        }
+    }
+}
+
+// 配信するイベントと連番・発生日時
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct EventEnvelope {
+    // サーバー起動からの連番 (SSE の id)
+    pub id: u64,
+    // UNIXタイムスタンプで表現される発生日時
+    pub at: i64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+// websocket / socks5 / API からイベントを受け取り、購読者に配信するイベントバス
+#[derive(Clone)]
+pub(crate) struct EventBus {
+    sender: broadcast::Sender<Arc<EventEnvelope>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        EventBus {
+            sender,
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    // イベントを配信する (購読者がいない場合は破棄する)
+    pub fn publish(&self, event: Event) {
+        let envelope = EventEnvelope {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            at: Utc::now().timestamp(),
+            event,
+        };
+        let _ = self.sender.send(Arc::new(envelope));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<EventEnvelope>> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_visibility() {
+        let (alice, bob, org) = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+        let agent = Event::AgentDisconnected {
+            agent_id: "agent_a".to_string(),
+        };
+        assert!(agent.is_visible_to(alice, &[]));
+        assert!(!agent.is_usage_event());
+        let balance = Event::UserBalanceChanged {
+            user_id: alice,
+            points: 10,
+        };
+        assert!(balance.is_visible_to(alice, &[]));
+        assert!(balance.is_usage_event());
+        assert!(!balance.is_visible_to(bob, &[org]));
+        let org_balance = Event::OrganizationBalanceChanged {
+            organization_id: org,
+            points: 10,
+        };
+        assert!(org_balance.is_visible_to(bob, &[org]));
+        assert!(!org_balance.is_visible_to(alice, &[]));
+    }
+
+    #[test]
+    fn test_publish_and_subscribe() {
+        let bus = EventBus::new();
+        // 購読者がいない場合も失敗しない
+        bus.publish(Event::AgentDisconnected {
+            agent_id: "agent_a".to_string(),
+        });
+        let mut rx = bus.subscribe();
+        bus.publish(Event::AgentDisconnected {
+            agent_id: "agent_b".to_string(),
+        });
+        let envelope = rx.try_recv().unwrap();
+        assert_eq!(envelope.id, 2);
+        let json = serde_json::to_value(&*envelope).unwrap();
+        assert_eq!(json["type"], "agent_disconnected");
+        assert_eq!(json["agent_id"], "agent_b");
+    }
+}
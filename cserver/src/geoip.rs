@@ -0,0 +1,295 @@
+use crate::agent::AgentMetadata;
+use anyhow::{anyhow, Result};
+use log::info;
+use maxminddb::{geoip2, Reader};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+// サーバー側のGeoIP検証の設定 (設定ファイルの [geoip] で変更可能)
+// データベースを指定しない場合は検証せず、エージェントの申告した値を使用する
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct GeoIpSettings {
+    // MaxMind形式の国・都市データベース (GeoLite2-City.mmdb, GeoLite2-Country.mmdb など)
+    pub database_path: Option<String>,
+    // MaxMind形式のASNデータベース (GeoLite2-ASN.mmdb など)
+    pub asn_database_path: Option<String>,
+    // 申告された国コードが検証結果と一致しないエージェントの接続を拒否する
+    pub reject_country_mismatch: bool,
+}
+
+impl GeoIpSettings {
+    // 設定値の検証
+    pub fn validate(&self) -> Result<(), String> {
+        if self.reject_country_mismatch && self.database_path.is_none() {
+            return Err("reject_country_mismatch requires database_path".to_string());
+        }
+        Ok(())
+    }
+}
+
+// データベースから引いた位置情報 (不明な項目は空文字列)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub(crate) struct GeoLocation {
+    pub country_code: String,
+    pub city: String,
+    pub region: String,
+    // "AS" で始まるASN番号 (例: AS2516)
+    pub asn: String,
+    pub asn_org: String,
+}
+
+// 検証に使用したアドレス
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum GeoSource {
+    // WebSocket接続の接続元アドレス
+    Peer,
+    // 接続元アドレスがデータベースにない場合 (私設アドレスなど) はエージェントが申告したIP
+    ReportedIp,
+}
+
+// 申告された値と検証結果が一致しない項目
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum GeoMismatch {
+    Ip,
+    CountryCode,
+    City,
+    Asn,
+}
+
+// エージェントの位置情報の検証結果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub(crate) struct GeoVerification {
+    pub ip: String,
+    pub source: GeoSource,
+    pub location: GeoLocation,
+    pub mismatches: Vec<GeoMismatch>,
+}
+
+// MaxMind形式のデータベースによる位置情報の検索
+pub(crate) struct GeoIp {
+    city: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    // 設定されたデータベースを読み込む
+    pub fn open(settings: &GeoIpSettings) -> Result<Self> {
+        let open = |path: &Option<String>| -> Result<Option<Reader<Vec<u8>>>> {
+            let Some(path) = path else {
+                return Ok(None);
+            };
+            let reader = Reader::open_readfile(path)
+                .map_err(|e| anyhow!("Failed to open GeoIP database {}: {}", path, e))?;
+            info!(
+                "Loaded GeoIP database {} ({})",
+                path, reader.metadata.database_type
+            );
+            Ok(Some(reader))
+        };
+        Ok(GeoIp {
+            city: open(&settings.database_path)?,
+            asn: open(&settings.asn_database_path)?,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.city.is_some() || self.asn.is_some()
+    }
+
+    // アドレスの位置情報を検索する (どのデータベースにもない場合は None)
+    pub fn lookup(&self, ip: IpAddr) -> Option<GeoLocation> {
+        let mut location = GeoLocation::default();
+        let mut found = false;
+        if let Some(city) = self
+            .city
+            .as_ref()
+            .and_then(|r| r.lookup::<geoip2::City>(ip).ok())
+        {
+            found = true;
+            let english = |names: Option<std::collections::BTreeMap<&str, &str>>| {
+                names
+                    .and_then(|n| n.get("en").map(|s| s.to_string()))
+                    .unwrap_or_default()
+            };
+            location.country_code = city
+                .country
+                .and_then(|c| c.iso_code)
+                .unwrap_or_default()
+                .to_string();
+            location.city = english(city.city.and_then(|c| c.names));
+            location.region = english(
+                city.subdivisions
+                    .and_then(|s| s.into_iter().next())
+                    .and_then(|s| s.names),
+            );
+        }
+        if let Some(asn) = self
+            .asn
+            .as_ref()
+            .and_then(|r| r.lookup::<geoip2::Asn>(ip).ok())
+        {
+            found = true;
+            location.asn = asn
+                .autonomous_system_number
+                .map(|n| format!("AS{}", n))
+                .unwrap_or_default();
+            location.asn_org = asn
+                .autonomous_system_organization
+                .unwrap_or_default()
+                .to_string();
+        }
+        found.then_some(location)
+    }
+
+    // 接続元アドレス (なければ申告されたIP) を検索し、申告された値と照合する
+    // データベースが未設定、またはどちらのアドレスも見つからない場合は None
+    pub fn verify(&self, peer: Option<IpAddr>, meta: &AgentMetadata) -> Option<GeoVerification> {
+        let reported = meta.ip.parse::<IpAddr>().ok().map(|ip| ip.to_canonical());
+        let peer = peer.map(|ip| ip.to_canonical());
+        let (ip, source, location) = peer
+            .and_then(|ip| Some((ip, GeoSource::Peer, self.lookup(ip)?)))
+            .or_else(|| {
+                reported.and_then(|ip| Some((ip, GeoSource::ReportedIp, self.lookup(ip)?)))
+            })?;
+        let ip_mismatch = source == GeoSource::Peer && reported.is_some_and(|r| r != ip);
+        Some(GeoVerification {
+            ip: ip.to_string(),
+            source,
+            mismatches: find_mismatches(meta, &location, ip_mismatch),
+            location,
+        })
+    }
+}
+
+impl GeoVerification {
+    // エージェントが取得できなかった (空の) 項目を検証結果で補完する
+    // 補完した項目があれば true を返す
+    pub fn fill_missing(&self, meta: &mut AgentMetadata) -> bool {
+        let mut filled = false;
+        let mut fill = |field: &mut String, value: &str| {
+            if field.trim().is_empty() && !value.is_empty() {
+                *field = value.to_string();
+                filled = true;
+            }
+        };
+        // 申告されたIPの検索結果の場合は ip も申告された値
+        if self.source == GeoSource::Peer {
+            fill(&mut meta.ip, &self.ip);
+        }
+        fill(&mut meta.country_code, &self.location.country_code);
+        fill(&mut meta.city, &self.location.city);
+        fill(&mut meta.region, &self.location.region);
+        fill(&mut meta.asn, &self.location.asn);
+        fill(&mut meta.asn_org, &self.location.asn_org);
+        filled
+    }
+}
+
+// 申告された値と検証結果を照合する (検証結果・申告された値のどちらかが空の項目は照合しない)
+fn find_mismatches(meta: &AgentMetadata, location: &GeoLocation, ip: bool) -> Vec<GeoMismatch> {
+    let differs = |verified: &str, reported: &str| {
+        !verified.is_empty()
+            && !reported.trim().is_empty()
+            && !verified.eq_ignore_ascii_case(reported.trim())
+    };
+    // ASNは "AS" の有無を区別しない
+    let asn_number = |asn: &str| {
+        asn.trim()
+            .trim_start_matches(['A', 'S', 'a', 's'])
+            .to_string()
+    };
+    let mut mismatches = Vec::new();
+    if ip {
+        mismatches.push(GeoMismatch::Ip);
+    }
+    if differs(&location.country_code, &meta.country_code) {
+        mismatches.push(GeoMismatch::CountryCode);
+    }
+    if differs(&location.city, &meta.city) {
+        mismatches.push(GeoMismatch::City);
+    }
+    if differs(&asn_number(&location.asn), &asn_number(&meta.asn)) {
+        mismatches.push(GeoMismatch::Asn);
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(country_code: &str, city: &str, asn: &str) -> AgentMetadata {
+        AgentMetadata {
+            ip: "203.0.113.7".to_string(),
+            remote_host: String::new(),
+            country_code: country_code.to_string(),
+            city: city.to_string(),
+            region: String::new(),
+            asn: asn.to_string(),
+            asn_org: String::new(),
+            os_type: String::new(),
+            os_version: String::new(),
+            hostname: String::new(),
+            kernel_version: String::new(),
+            username: String::new(),
+            geo: None,
+        }
+    }
+
+    #[test]
+    fn test_find_mismatches() {
+        let location = GeoLocation {
+            country_code: "JP".to_string(),
+            city: "Tokyo".to_string(),
+            asn: "AS2516".to_string(),
+            ..Default::default()
+        };
+        assert!(find_mismatches(&metadata("jp", "Tokyo", "2516"), &location, false).is_empty());
+        assert_eq!(
+            find_mismatches(&metadata("US", "Tokyo", "AS15169"), &location, true),
+            vec![GeoMismatch::Ip, GeoMismatch::CountryCode, GeoMismatch::Asn]
+        );
+        // データベースにない項目は照合しない
+        let country_only = GeoLocation {
+            country_code: "JP".to_string(),
+            ..Default::default()
+        };
+        assert!(find_mismatches(&metadata("JP", "Osaka", "AS1"), &country_only, false).is_empty());
+    }
+
+    #[test]
+    fn test_fill_missing() {
+        let verification = GeoVerification {
+            ip: "198.51.100.1".to_string(),
+            source: GeoSource::Peer,
+            location: GeoLocation {
+                country_code: "JP".to_string(),
+                city: "Tokyo".to_string(),
+                ..Default::default()
+            },
+            mismatches: Vec::new(),
+        };
+        let mut meta = metadata("", "Osaka", "");
+        meta.ip = String::new();
+        assert!(verification.fill_missing(&mut meta));
+        assert_eq!(meta.ip, "198.51.100.1");
+        assert_eq!(meta.country_code, "JP");
+        // 申告された値は上書きしない
+        assert_eq!(meta.city, "Osaka");
+        assert!(!verification.fill_missing(&mut meta));
+        // 空の値は照合しない
+        assert!(find_mismatches(&metadata("", "", ""), &verification.location, false).is_empty());
+    }
+
+    #[test]
+    fn test_verify_without_database() {
+        let geoip = GeoIp::open(&GeoIpSettings::default()).unwrap();
+        assert!(!geoip.is_enabled());
+        let peer = "198.51.100.1".parse().ok();
+        assert_eq!(geoip.verify(peer, &metadata("JP", "", "")), None);
+    }
+}
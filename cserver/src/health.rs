@@ -0,0 +1,413 @@
+use crate::events::{Event, EventBus};
+use dashmap::DashMap;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+// データが少ないエージェントのスコアが極端にならないよう、成功したとみなして加える試行回数
+const PRIOR_SAMPLES: f64 = 2.0;
+// 接続時間によるスコアの減点の上限 (最大で半分まで)
+const MIN_LATENCY_FACTOR: f64 = 0.5;
+// スコアの低いエージェントにも稀に割り当てるための最小の重み
+const MIN_SELECTION_WEIGHT: f64 = 0.05;
+
+// エージェントの健全性スコアの設定 (設定ファイルの [agent_health] で変更可能)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct HealthSettings {
+    // スコアの算出に使用する直近の期間 (秒)
+    pub window_seconds: i64,
+    // 隔離を判定するまでに必要な試行回数 (接続と転送の合計)
+    pub min_samples: u32,
+    // スコアがこの値を下回ったエージェントを隔離する (0.0〜1.0)
+    pub quarantine_score: f64,
+    // 隔離期間 (秒)。解除後はスコアを算出し直す
+    pub quarantine_seconds: i64,
+    // 平均接続時間がこの値 (ミリ秒) を超えるとスコアを下げる
+    pub latency_target_ms: u64,
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        HealthSettings {
+            window_seconds: 10 * 60,
+            min_samples: 5,
+            quarantine_score: 0.3,
+            quarantine_seconds: 5 * 60,
+            latency_target_ms: 1000,
+        }
+    }
+}
+
+impl HealthSettings {
+    // 設定値の検証
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window_seconds <= 0 || self.quarantine_seconds <= 0 {
+            return Err("window_seconds and quarantine_seconds must be positive".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.quarantine_score) {
+            return Err("quarantine_score must be between 0.0 and 1.0".to_string());
+        }
+        if self.min_samples == 0 || self.latency_target_ms == 0 {
+            return Err("min_samples and latency_target_ms must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+// トンネルのエラー種別
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HealthError {
+    // 接続先への接続がエージェントから失敗と報告された (接続先の問題の可能性があるためスコアには含めない)
+    Refused,
+    // 接続応答のタイムアウト
+    Timeout,
+    // エージェントへのリクエスト送信の失敗
+    SendFailed,
+    // 応答前にエージェントが切断された
+    Disconnected,
+    // 想定外の応答
+    Protocol,
+    // 接続後のデータ転送の失敗
+    Transfer,
+}
+
+// エージェントごとに記録するトンネルの結果
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum HealthEvent {
+    // 接続成功と接続にかかった時間
+    Connected { latency_ms: u64 },
+    // 転送の完了と転送量・トンネルの継続時間
+    Transferred { bytes: u64, millis: u64 },
+    Failed(HealthError),
+}
+
+// エラー種別ごとの発生回数
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct HealthErrorCount {
+    pub kind: HealthError,
+    pub count: u32,
+    // UNIXタイムスタンプで表現される最後の発生日時
+    pub last_at: i64,
+}
+
+// APIで返すエージェントの健全性
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct AgentHealthReport {
+    // 健全性スコア (0.0〜1.0)。データがない場合は 1.0
+    pub score: f64,
+    // 隔離中 (国・ラベル・ランダムの選択対象外) か
+    pub quarantined: bool,
+    pub quarantined_until: Option<i64>,
+    // 直近の期間の接続試行回数・成功率・平均接続時間 (Refused を除く)
+    pub connect_attempts: u32,
+    pub connect_success_rate: Option<f64>,
+    pub avg_connect_latency_ms: Option<u64>,
+    // 直近の期間に終了したトンネルの平均スループット (バイト/秒)
+    pub throughput_bytes_per_second: Option<u64>,
+    // 直近の期間のエラー種別ごとの回数
+    pub recent_errors: Vec<HealthErrorCount>,
+}
+
+impl Default for AgentHealthReport {
+    fn default() -> Self {
+        AgentHealthReport {
+            score: 1.0,
+            quarantined: false,
+            quarantined_until: None,
+            connect_attempts: 0,
+            connect_success_rate: None,
+            avg_connect_latency_ms: None,
+            throughput_bytes_per_second: None,
+            recent_errors: Vec::new(),
+        }
+    }
+}
+
+// 期間内のイベントの集計
+#[derive(Debug, Default)]
+struct HealthSummary {
+    connects: u32,
+    connect_failures: u32,
+    transfers: u32,
+    transfer_failures: u32,
+    latency_ms_total: u64,
+    bytes_total: u64,
+    millis_total: u64,
+}
+
+impl HealthSummary {
+    fn from_events<'a>(events: impl Iterator<Item = &'a (i64, HealthEvent)>) -> Self {
+        let mut s = HealthSummary::default();
+        for (_, event) in events {
+            match *event {
+                HealthEvent::Connected { latency_ms } => {
+                    s.connects += 1;
+                    s.latency_ms_total += latency_ms;
+                }
+                HealthEvent::Transferred { bytes, millis } => {
+                    s.transfers += 1;
+                    s.bytes_total += bytes;
+                    s.millis_total += millis;
+                }
+                HealthEvent::Failed(HealthError::Refused) => {}
+                HealthEvent::Failed(HealthError::Transfer) => s.transfer_failures += 1,
+                HealthEvent::Failed(_) => s.connect_failures += 1,
+            }
+        }
+        s
+    }
+
+    // スコアの算出に使用した試行回数
+    fn samples(&self) -> u32 {
+        self.connects + self.connect_failures + self.transfers + self.transfer_failures
+    }
+
+    fn avg_latency_ms(&self) -> Option<u64> {
+        (self.connects > 0).then(|| self.latency_ms_total / self.connects as u64)
+    }
+
+    // 接続成功率 × 転送成功率 × 接続時間による係数
+    fn score(&self, latency_target_ms: u64) -> f64 {
+        let rate = |ok: u32, failed: u32| {
+            (ok as f64 + PRIOR_SAMPLES) / ((ok + failed) as f64 + PRIOR_SAMPLES)
+        };
+        let latency_factor = match self.avg_latency_ms() {
+            Some(avg) if avg > latency_target_ms => {
+                (latency_target_ms as f64 / avg as f64).max(MIN_LATENCY_FACTOR)
+            }
+            _ => 1.0,
+        };
+        rate(self.connects, self.connect_failures)
+            * rate(self.transfers, self.transfer_failures)
+            * latency_factor
+    }
+}
+
+// エージェントごとの直近のイベントと隔離状態
+#[derive(Debug, Default)]
+struct HealthWindow {
+    // (UNIXタイムスタンプ, イベント) を古い順に保持する
+    events: VecDeque<(i64, HealthEvent)>,
+    // 隔離の解除日時。解除後のスコアはこれ以降のイベントから算出する
+    quarantined_until: i64,
+    // 隔離中に返すスコアの算出開始日時 (前回の隔離の解除日時)
+    scored_since: i64,
+}
+
+impl HealthWindow {
+    fn expire(&mut self, now: i64, window_seconds: i64) {
+        while self
+            .events
+            .front()
+            .is_some_and(|(at, _)| *at + window_seconds <= now)
+        {
+            self.events.pop_front();
+        }
+    }
+
+    // 前回の隔離の解除以降のイベントの集計 (隔離中は隔離時点までの期間を含む)
+    fn scored(&self, now: i64) -> HealthSummary {
+        let since = if self.quarantined_until > now {
+            self.scored_since
+        } else {
+            self.quarantined_until
+        };
+        HealthSummary::from_events(self.events.iter().filter(|(at, _)| *at >= since))
+    }
+}
+
+// エージェントIDごとの接続成功率・接続時間・スループット・エラーを追跡する (スレッドセーフ)
+// 再接続してもスコアと隔離状態を引き継ぐよう、接続ではなくエージェントIDに紐づける
+#[derive(Clone)]
+pub(crate) struct AgentHealth {
+    settings: HealthSettings,
+    windows: Arc<DashMap<String, HealthWindow>>,
+    // 隔離を通知するイベントバス
+    events: EventBus,
+}
+
+impl AgentHealth {
+    pub fn new(settings: &HealthSettings, events: EventBus) -> Self {
+        AgentHealth {
+            settings: settings.clone(),
+            windows: Arc::new(DashMap::new()),
+            events,
+        }
+    }
+
+    // イベントを記録し、スコアが基準を下回った場合は隔離する
+    pub fn record(&self, agent_id: &str, event: HealthEvent, now: i64) {
+        let settings = &self.settings;
+        let mut window = self.windows.entry(agent_id.to_string()).or_default();
+        window.expire(now, settings.window_seconds);
+        window.events.push_back((now, event));
+        if window.quarantined_until > now {
+            return;
+        }
+        let summary = window.scored(now);
+        let score = summary.score(settings.latency_target_ms);
+        if summary.samples() >= settings.min_samples && score < settings.quarantine_score {
+            window.scored_since = window.quarantined_until;
+            window.quarantined_until = now + settings.quarantine_seconds;
+            warn!(
+                "Quarantined agent {} for {}s (health score {:.2})",
+                agent_id, settings.quarantine_seconds, score
+            );
+            self.events.publish(Event::AgentQuarantined {
+                agent_id: agent_id.to_string(),
+                score: (score * 100.0).round() / 100.0,
+                until: window.quarantined_until,
+            });
+        }
+    }
+
+    // 選択時の重み (隔離中の場合は None)
+    pub fn selection_weight(&self, agent_id: &str, now: i64) -> Option<f64> {
+        let Some(window) = self.windows.get(agent_id) else {
+            return Some(1.0);
+        };
+        if window.quarantined_until > now {
+            return None;
+        }
+        let score = window.scored(now).score(self.settings.latency_target_ms);
+        Some(score.max(MIN_SELECTION_WEIGHT))
+    }
+
+    // APIで返す健全性の集計
+    pub fn report(&self, agent_id: &str, now: i64) -> AgentHealthReport {
+        let Some(mut window) = self.windows.get_mut(agent_id) else {
+            return AgentHealthReport::default();
+        };
+        window.expire(now, self.settings.window_seconds);
+        let all = HealthSummary::from_events(window.events.iter());
+        let mut errors: BTreeMap<HealthError, HealthErrorCount> = BTreeMap::new();
+        for (at, event) in &window.events {
+            if let HealthEvent::Failed(kind) = *event {
+                let entry = errors.entry(kind).or_insert(HealthErrorCount {
+                    kind,
+                    count: 0,
+                    last_at: *at,
+                });
+                entry.count += 1;
+                entry.last_at = *at;
+            }
+        }
+        let attempts = all.connects + all.connect_failures;
+        let score = window.scored(now).score(self.settings.latency_target_ms);
+        let quarantined = window.quarantined_until > now;
+        AgentHealthReport {
+            score: (score * 100.0).round() / 100.0,
+            quarantined,
+            quarantined_until: quarantined.then_some(window.quarantined_until),
+            connect_attempts: attempts,
+            connect_success_rate: (attempts > 0)
+                .then(|| (all.connects as f64 / attempts as f64 * 100.0).round() / 100.0),
+            avg_connect_latency_ms: all.avg_latency_ms(),
+            throughput_bytes_per_second: (all.millis_total > 0)
+                .then(|| all.bytes_total * 1000 / all.millis_total),
+            recent_errors: errors.into_values().collect(),
+        }
+    }
+
+    // 直近の期間にイベントがなく、隔離中でもないエントリを削除し、削除件数を返す
+    pub fn prune(&self, now: i64) -> usize {
+        let before = self.windows.len();
+        let window_seconds = self.settings.window_seconds;
+        self.windows.retain(|_, w| {
+            w.expire(now, window_seconds);
+            !w.events.is_empty() || w.quarantined_until > now
+        });
+        before - self.windows.len()
+    }
+}
+
+// 重みに比例した確率でインデックスを選択する (r は 0.0 以上 1.0 未満の乱数)
+pub(crate) fn weighted_index(weights: &[f64], r: f64) -> Option<usize> {
+    let total: f64 = weights.iter().sum();
+    if weights.is_empty() || total <= 0.0 {
+        return None;
+    }
+    let mut target = r * total;
+    for (i, w) in weights.iter().enumerate() {
+        if target < *w {
+            return Some(i);
+        }
+        target -= w;
+    }
+    Some(weights.len() - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health() -> AgentHealth {
+        AgentHealth::new(
+            &HealthSettings {
+                window_seconds: 100,
+                min_samples: 4,
+                quarantine_score: 0.5,
+                quarantine_seconds: 30,
+                latency_target_ms: 100,
+            },
+            EventBus::new(),
+        )
+    }
+
+    #[test]
+    fn test_score() {
+        let h = health();
+        assert_eq!(h.selection_weight("a", 0), Some(1.0));
+        h.record("a", HealthEvent::Connected { latency_ms: 50 }, 0);
+        h.record("a", HealthEvent::Failed(HealthError::Refused), 1);
+        assert_eq!(h.selection_weight("a", 1), Some(1.0));
+        // 接続時間が目標の2倍になるとスコアは半分になる
+        h.record("a", HealthEvent::Connected { latency_ms: 350 }, 2);
+        assert_eq!(h.selection_weight("a", 2), Some(0.5));
+        // 転送成功率は (0 + 2) / (1 + 2)
+        h.record("a", HealthEvent::Failed(HealthError::Transfer), 3);
+        let report = h.report("a", 3);
+        assert_eq!(report.score, 0.33);
+        assert_eq!(report.connect_attempts, 2);
+        assert_eq!(report.connect_success_rate, Some(1.0));
+        assert_eq!(report.avg_connect_latency_ms, Some(200));
+        assert_eq!(report.recent_errors.len(), 2);
+        assert_eq!(report.recent_errors[0].kind, HealthError::Refused);
+        // 期間外のイベントは集計しない
+        assert_eq!(h.report("a", 103).connect_attempts, 0);
+        assert_eq!(h.prune(103), 1);
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let h = health();
+        for t in 0..3 {
+            h.record("a", HealthEvent::Failed(HealthError::Timeout), t);
+        }
+        assert!(h.selection_weight("a", 3).is_some());
+        h.record("a", HealthEvent::Failed(HealthError::Timeout), 3);
+        assert_eq!(h.selection_weight("a", 3), None);
+        let report = h.report("a", 10);
+        assert!(report.quarantined);
+        assert_eq!(report.quarantined_until, Some(33));
+        assert_eq!(report.score, 0.33);
+        // 解除後はスコアを算出し直す
+        assert_eq!(h.selection_weight("a", 33), Some(1.0));
+        assert!(!h.report("a", 33).quarantined);
+        assert_eq!(h.report("a", 33).recent_errors[0].count, 4);
+    }
+
+    #[test]
+    fn test_weighted_index() {
+        assert_eq!(weighted_index(&[], 0.5), None);
+        assert_eq!(weighted_index(&[0.0, 0.0], 0.5), None);
+        assert_eq!(weighted_index(&[1.0, 3.0], 0.0), Some(0));
+        assert_eq!(weighted_index(&[1.0, 3.0], 0.24), Some(0));
+        assert_eq!(weighted_index(&[1.0, 3.0], 0.26), Some(1));
+        assert_eq!(weighted_index(&[1.0, 3.0], 0.99), Some(1));
+    }
+}
@@ -0,0 +1,235 @@
+use dashmap::DashMap;
+use serde::Deserialize;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+// 認証失敗によるロックアウトの設定 (設定ファイルの [lockout] で変更可能)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct LockoutSettings {
+    // ログイン: 接続元IP・ユーザ名ごとにロックするまでの失敗回数
+    pub login_max_failures: u32,
+    // ログイン: 最初のロック時間 (秒)。以降は失敗のたびに倍増する
+    pub login_lockout_seconds: i64,
+    pub login_max_lockout_seconds: i64,
+    // SOCKS5: 接続元IPを一時BANするまでの不正トークン回数
+    pub socks_max_failures: u32,
+    // SOCKS5: 最初のBAN時間 (秒)。以降は失敗のたびに倍増する
+    pub socks_ban_seconds: i64,
+    pub socks_max_ban_seconds: i64,
+    // ロック解除後、この期間失敗がなければ失敗回数をリセットする (秒)
+    pub failure_window_seconds: i64,
+}
+
+impl Default for LockoutSettings {
+    fn default() -> Self {
+        LockoutSettings {
+            login_max_failures: 5,
+            login_lockout_seconds: 30,
+            login_max_lockout_seconds: 60 * 60,
+            socks_max_failures: 10,
+            socks_ban_seconds: 5 * 60,
+            socks_max_ban_seconds: 24 * 60 * 60,
+            failure_window_seconds: 60 * 60,
+        }
+    }
+}
+
+impl LockoutSettings {
+    // 設定値の検証
+    pub fn validate(&self) -> Result<(), String> {
+        if self.login_max_failures == 0 || self.socks_max_failures == 0 {
+            return Err("max failures must be positive".to_string());
+        }
+        if self.login_lockout_seconds <= 0
+            || self.login_lockout_seconds > self.login_max_lockout_seconds
+            || self.socks_ban_seconds <= 0
+            || self.socks_ban_seconds > self.socks_max_ban_seconds
+        {
+            return Err(
+                "lockout durations must be positive and not exceed their maximum".to_string(),
+            );
+        }
+        if self.failure_window_seconds <= 0 {
+            return Err("failure_window_seconds must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+// 1種類のキーに対するロックアウト方針
+#[derive(Debug, Clone, Copy)]
+struct LockoutPolicy {
+    max_failures: u32,
+    base_seconds: i64,
+    max_seconds: i64,
+    window_seconds: i64,
+}
+
+impl LockoutPolicy {
+    // 失敗回数に対するロック時間 (上限未満の場合は None)
+    fn lockout_seconds(&self, failures: u32) -> Option<i64> {
+        let excess = failures.checked_sub(self.max_failures)?;
+        // 2^excess 倍 (上限で打ち止め)
+        let factor = 1i64.checked_shl(excess.min(62)).unwrap_or(i64::MAX);
+        Some(
+            self.base_seconds
+                .saturating_mul(factor)
+                .min(self.max_seconds),
+        )
+    }
+}
+
+// キーごとの失敗状況
+#[derive(Debug, Clone, Copy)]
+struct FailureEntry {
+    failures: u32,
+    // 最後の失敗とロック解除の日時 (UNIXタイムスタンプ)
+    last_failure: i64,
+    locked_until: i64,
+}
+
+impl FailureEntry {
+    // 失敗回数のリセット対象か
+    fn is_stale(&self, now: i64, window_seconds: i64) -> bool {
+        self.last_failure.max(self.locked_until) + window_seconds <= now
+    }
+}
+
+// キーごとの認証失敗回数を数え、上限を超えたら指数的に延びるロックをかける（スレッドセーフ）
+#[derive(Clone)]
+pub(crate) struct FailureTracker<K: Eq + Hash> {
+    policy: LockoutPolicy,
+    entries: Arc<DashMap<K, FailureEntry>>,
+}
+
+impl<K: Eq + Hash + Clone> FailureTracker<K> {
+    fn new(policy: LockoutPolicy) -> Self {
+        FailureTracker {
+            policy,
+            entries: Arc::new(DashMap::new()),
+        }
+    }
+
+    // ロック中であれば残り秒数を返す
+    pub fn locked_for(&self, key: &K, now: i64) -> Option<i64> {
+        self.entries
+            .get(key)
+            .map(|e| e.locked_until - now)
+            .filter(|remaining| *remaining > 0)
+    }
+
+    // 失敗を記録し、この失敗でロックされた場合はロック時間 (秒) を返す
+    pub fn record_failure(&self, key: K, now: i64) -> Option<i64> {
+        let policy = self.policy;
+        let mut entry = self.entries.entry(key).or_insert(FailureEntry {
+            failures: 0,
+            last_failure: now,
+            locked_until: 0,
+        });
+        if entry.is_stale(now, policy.window_seconds) {
+            entry.failures = 0;
+        }
+        entry.failures = entry.failures.saturating_add(1);
+        entry.last_failure = now;
+        let seconds = policy.lockout_seconds(entry.failures)?;
+        entry.locked_until = now + seconds;
+        Some(seconds)
+    }
+
+    // 認証成功時に失敗回数をリセットする
+    pub fn record_success(&self, key: &K) {
+        self.entries.remove(key);
+    }
+
+    // ロックが解除され、失敗回数のリセット対象となったエントリを削除し、削除件数を返す
+    pub fn prune(&self, now: i64) -> usize {
+        let before = self.entries.len();
+        let window = self.policy.window_seconds;
+        self.entries.retain(|_, e| !e.is_stale(now, window));
+        before - self.entries.len()
+    }
+}
+
+// ログインとSOCKS5認証の失敗を追跡するトラッカー一式
+#[derive(Clone)]
+pub(crate) struct Lockouts {
+    // ログイン失敗 (接続元IPごと・ユーザ名ごと)
+    pub login_ips: FailureTracker<IpAddr>,
+    pub login_usernames: FailureTracker<String>,
+    // SOCKS5の不正トークン (接続元IPごと。ロック中のIPは接続を即座に切断する)
+    pub socks_ips: FailureTracker<IpAddr>,
+}
+
+impl Lockouts {
+    pub fn new(settings: &LockoutSettings) -> Self {
+        let login = LockoutPolicy {
+            max_failures: settings.login_max_failures,
+            base_seconds: settings.login_lockout_seconds,
+            max_seconds: settings.login_max_lockout_seconds,
+            window_seconds: settings.failure_window_seconds,
+        };
+        let socks = LockoutPolicy {
+            max_failures: settings.socks_max_failures,
+            base_seconds: settings.socks_ban_seconds,
+            max_seconds: settings.socks_max_ban_seconds,
+            window_seconds: settings.failure_window_seconds,
+        };
+        Lockouts {
+            login_ips: FailureTracker::new(login),
+            login_usernames: FailureTracker::new(login),
+            socks_ips: FailureTracker::new(socks),
+        }
+    }
+
+    // 不要になったエントリを削除し、削除件数を返す
+    pub fn prune(&self, now: i64) -> usize {
+        self.login_ips.prune(now) + self.login_usernames.prune(now) + self.socks_ips.prune(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> FailureTracker<&'static str> {
+        FailureTracker::new(LockoutPolicy {
+            max_failures: 3,
+            base_seconds: 10,
+            max_seconds: 35,
+            window_seconds: 100,
+        })
+    }
+
+    #[test]
+    fn test_exponential_lockout() {
+        let t = tracker();
+        assert_eq!(t.record_failure("a", 0), None);
+        assert_eq!(t.record_failure("a", 1), None);
+        assert_eq!(t.locked_for(&"a", 1), None);
+        assert_eq!(t.record_failure("a", 2), Some(10));
+        assert_eq!(t.locked_for(&"a", 5), Some(7));
+        assert_eq!(t.locked_for(&"a", 12), None);
+        // ロック解除後の失敗はロック時間が倍増し、上限で打ち止め
+        assert_eq!(t.record_failure("a", 20), Some(20));
+        assert_eq!(t.record_failure("a", 40), Some(35));
+        assert_eq!(t.locked_for(&"b", 40), None);
+    }
+
+    #[test]
+    fn test_reset_and_prune() {
+        let t = tracker();
+        t.record_failure("a", 0);
+        t.record_failure("a", 0);
+        t.record_success(&"a");
+        assert_eq!(t.record_failure("a", 0), None);
+        // 期間内に失敗がなければ失敗回数をリセット
+        t.record_failure("b", 0);
+        t.record_failure("b", 0);
+        assert_eq!(t.record_failure("b", 100), None);
+        assert_eq!(t.prune(150), 1);
+        assert_eq!(t.prune(200), 1);
+        assert!(t.entries.is_empty());
+    }
+}
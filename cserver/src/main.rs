@@ -1,17 +1,25 @@
 mod agent;
 mod api;
+mod api_key;
+mod bootstrap;
 mod config;
+mod events;
+mod geoip;
+mod health;
+mod lockout;
+mod maintenance;
 mod repository;
+mod session;
 mod socks5;
 mod token;
 mod websocket;
 
 use anyhow::{anyhow, Result};
+use clap::Parser;
 use dashmap::DashMap;
 use dotenvy::dotenv;
-use env_logger;
 use futures::stream::{SplitSink, SplitStream};
-use log::{error, info};
+use log::{error, info, warn};
 // dummy import to align loc
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
@@ -27,7 +35,15 @@ use utoipa_swagger_ui::SwaggerUi;
 
 // モジュールから型をインポート
 use agent::AgentMap;
+use api::revocation::RevocationList;
 use config::Settings;
+use events::EventBus;
+use geoip::GeoIp;
+use health::AgentHealth;
+use lockout::Lockouts;
+use maintenance::Maintenance;
+use session::SessionRegistry;
+use token::hash::TokenHasher;
 
 // WebSocket送信ストリームの型エイリアス
 type WsSink = SplitSink<
@@ -60,6 +76,14 @@ struct AppState {
     jwt_secret: String,
     agents: Arc<AgentMap>,
     command_responses: CommandResponseMap,
+    sessions: SessionRegistry,
+    token_hasher: TokenHasher,
+    revocations: RevocationList,
+    lockouts: Lockouts,
+    agent_health: AgentHealth,
+    events: EventBus,
+    maintenance: Maintenance,
+    settings: Arc<Settings>,
 }
 
 #[derive(OpenApi)]
@@ -67,11 +91,72 @@ struct AppState {
     paths(
         api::register,
         api::login,
+        api::session::refresh,
+        api::session::logout,
+        api::session::logout_all,
+        api::password::change_password,
+        api::password::create_password_reset,
+        api::password::reset_password,
+        api::totp::get_totp_status,
+        api::totp::enroll_totp,
+        api::totp::verify_totp,
+        api::totp::regenerate_recovery_codes,
+        api::totp::disable_totp,
+        api::users::list_users,
+        api::users::get_user,
+        api::users::update_user_role,
+        api::users::suspend_user,
+        api::users::unsuspend_user,
+        api::users::remove_user,
+        api::users::adjust_points,
+        api::users::list_point_ledger,
+        api::invites::create_invite,
+        api::invites::list_invites,
+        api::invites::remove_invite,
+        api::organizations::list_organizations,
+        api::organizations::create_organization,
+        api::organizations::get_organization,
+        api::organizations::remove_organization,
+        api::organizations::add_member,
+        api::organizations::update_member_role,
+        api::organizations::remove_member,
+        api::organizations::list_organization_tokens,
+        api::organizations::generate_organization_token,
+        api::organizations::revoke_organization_token,
+        api::organizations::get_organization_usage,
+        api::organizations::transfer_points,
+        api::organizations::list_organization_ledger,
+        api::organizations::get_organization_statement,
+        api::usage::list_sessions,
+        api::usage::get_daily_usage,
+        api::usage::export_usage,
+        api::usage::get_statement,
+        api::usage::export_all_usage,
+        api::usage::list_statements,
+        api::agents::get_agent_detail,
+        api::agents::disable_agent,
+        api::agents::enable_agent,
+        api::agents::drain_agent,
+        api::agents::kick_agent,
+        api::agents::ban_agent,
+        api::agents::unban_agent,
+        api::agents::update_agent_labels,
+        api::agents::get_agent_ip_history,
         token::generate_token,
+        token::revoke_token,
+        token::revoke_all_tokens,
+        token::rotate_user_token,
         api::get_current_user,
         api::list_tokens,
         api::execute_command,
-        agent::list_agents
+        agent::list_agents,
+        agent::get_agent_stats,
+        api::events::stream_events,
+        api_key::create_user_api_key,
+        api_key::list_user_api_keys,
+        api_key::delete_api_key,
+        maintenance::list_maintenance_runs,
+        maintenance::run_maintenance
     ),
     components(
         schemas(
@@ -79,13 +164,77 @@ struct AppState {
             api::dto::LoginRequest,
             api::dto::CommandRequestParams,
             api::dto::CurrentUserResponse,
+            token::TokenRequest,
             token::TokenResponse,
-            agent::AgentInfo
+            token::RevokeTokensResponse,
+            token::restriction::TokenRestrictions,
+            agent::AgentInfo,
+            agent::AgentStats,
+            agent::AgentCapacity,
+            agent::AgentLoad,
+            geoip::GeoVerification,
+            geoip::GeoLocation,
+            geoip::GeoSource,
+            geoip::GeoMismatch,
+            maintenance::MaintenanceRunResponse,
+            api::password::ChangePasswordRequest,
+            api::password::ResetPasswordRequest,
+            api::password::PasswordResetTokenResponse,
+            api::totp::TotpStatusResponse,
+            api::totp::TotpEnrollResponse,
+            api::totp::TotpCodeRequest,
+            api::totp::RecoveryCodesResponse,
+            api::users::AdminUserResponse,
+            api::users::UserListResponse,
+            api::users::UserUsage,
+            api::users::AdminUserDetailResponse,
+            api::users::UpdateRoleRequest,
+            api::users::SuspendUserRequest,
+            api::users::AdjustPointsRequest,
+            api::users::PointLedgerResponse,
+            api::invites::InviteRequest,
+            api::invites::InviteResponse,
+            api::organizations::OrganizationRequest,
+            api::organizations::AddMemberRequest,
+            api::organizations::UpdateMemberRoleRequest,
+            api::organizations::TransferPointsRequest,
+            api::organizations::OrganizationResponse,
+            api::organizations::OrganizationMemberResponse,
+            api::organizations::OrganizationDetailResponse,
+            api::organizations::MemberUsageResponse,
+            api::organizations::OrganizationUsageResponse,
+            api::organizations::PointTransferResponse,
+            api::organizations::OrganizationLedgerResponse,
+            api::usage::UsageSessionResponse,
+            api::usage::UsageSessionListResponse,
+            api::usage::DailyUsageResponse,
+            api::usage::ExportFormat,
+            api::usage::UsageExportRow,
+            api::usage::CountryUsage,
+            api::usage::MonthlyStatement,
+            api::agents::BanAgentRequest,
+            api::agents::AgentLabelsRequest,
+            api::agents::AgentLabelsResponse,
+            api::agents::AgentIpChange,
+            api_key::ApiKeyRequest,
+            api_key::ApiKeyResponse,
+            repository::ApiKeyScope,
+            repository::OrgRole,
+            repository::SessionOutcome,
+            health::AgentHealthReport,
+            health::HealthErrorCount,
+            health::HealthError,
+            events::Event,
+            events::EventEnvelope
         )
     ),
     tags(
         (name = "Auth", description = "Authentication operations"),
-        (name = "Agent", description = "Agent management")
+        (name = "Agent", description = "Agent management"),
+        (name = "Organization", description = "Organizations with shared point balances"),
+        (name = "Usage", description = "SOCKS5 session history and usage statistics"),
+        (name = "Events", description = "Real-time events for the dashboard"),
+        (name = "Admin", description = "Administrative operations")
     )
 )]
 struct ApiDoc;
@@ -93,6 +242,8 @@ struct ApiDoc;
 // アプリケーションのエントリーポイント
 #[tokio::main]
 async fn main() -> Result<()> {
+    // コマンドライン引数の解析 (サブコマンドなしの場合はサーバーを起動)
+    let cli = bootstrap::Cli::parse();
     // .env を読み込む
     dotenv().expect("`.env`の読み込みに失敗しました");
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
@@ -108,19 +259,75 @@ async fn main() -> Result<()> {
     // ロガーの初期化
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
+    // 管理用サブコマンドの実行 (サーバーは起動しない)
+    if let Some(bootstrap::Command::Admin { action }) = cli.command {
+        let settings = config::load_config().await?;
+        return bootstrap::run_admin_command(&db_pool, &settings, action).await;
+    }
+
+    // トークンハッシュ用の鍵読み込み (未設定時はJWTシークレットを流用)
+    let token_hash_key = env::var("TOKEN_HASH_KEY").unwrap_or_else(|_| {
+        warn!("TOKEN_HASH_KEY is not set; falling back to JWT_SECRET");
+        jwt_secret.clone()
+    });
+    let token_hasher = TokenHasher::new(&token_hash_key);
+    // 平文で保存されている旧形式トークンをハッシュ化
+    let migrated = token::migrate_plaintext_tokens(&db_pool, &token_hasher).await?;
+    if migrated > 0 {
+        info!("Hashed {} legacy plaintext tokens", migrated);
+    }
+
     // 共有状態の初期化
     let agents: Arc<AgentMap> = Arc::new(DashMap::new());
     let pending: PendingMap = Arc::new(Mutex::new(HashMap::new()));
     let command_responses: CommandResponseMap = Arc::new(Mutex::new(HashMap::new()));
+    let sessions = SessionRegistry::new();
 
     // 設定ファイルの読み込み
     let settings = Arc::new(config::load_config().await?);
+    // 環境変数で指定された管理者を作成 (初回起動時のみ)
+    bootstrap::bootstrap_admin_from_env(&db_pool, &settings).await?;
+    // 前回の停止時に接続中だったエージェントをオフラインにする
+    let stale = repository::close_stale_agent_connections(&db_pool).await?;
+    if stale > 0 {
+        info!("Marked {} agents from the previous run as offline", stale);
+    }
+    // 定期メンテナンス (保持期間を過ぎたデータの削除) をバックグラウンドで開始
+    // アクセストークンの失効リストを読み込み
+    let revocations = RevocationList::load(&db_pool, settings.access_token_ttl_seconds).await?;
+    // ログイン・SOCKS5認証の失敗回数トラッカー
+    let lockouts = Lockouts::new(&settings.lockout);
+    // ダッシュボードへのリアルタイム配信用のイベントバス
+    let events = EventBus::new();
+    // エージェントの位置情報を検証するGeoIPデータベース
+    let geoip = Arc::new(GeoIp::open(&settings.geoip)?);
+    if !geoip.is_enabled() {
+        info!("GeoIP database is not configured; agent locations are not verified");
+    }
+    // エージェントごとの健全性スコア (SOCKS5のエージェント選択に使用)
+    let agent_health = AgentHealth::new(&settings.agent_health, events.clone());
+    let maintenance = Maintenance::new(
+        db_pool.clone(),
+        settings.clone(),
+        revocations.clone(),
+        lockouts.clone(),
+        agent_health.clone(),
+    );
+    tokio::spawn(maintenance.clone().run_periodically());
     // AppStateの作成
     let app_state = AppState {
         db_pool: db_pool.clone(),
         jwt_secret: jwt_secret.clone(),
         agents: agents.clone(),
         command_responses: command_responses.clone(),
+        sessions: sessions.clone(),
+        token_hasher: token_hasher.clone(),
+        revocations,
+        lockouts: lockouts.clone(),
+        agent_health: agent_health.clone(),
+        events: events.clone(),
+        maintenance,
+        settings: settings.clone(),
     };
 
     // APIルーターの構築
@@ -152,7 +359,7 @@ async fn main() -> Result<()> {
     // WebSocketサーバー、SOCKS5サーバー、APIサーバーを並行して実行
     tokio::select! {
         // WebSocketサーバーの実行
-        res = websocket::run_websocket_server(agents.clone(), pending.clone(), command_responses.clone(), settings.clone()) => {
+        res = websocket::run_websocket_server(db_pool.clone(), agents.clone(), pending.clone(), command_responses.clone(), events.clone(), geoip.clone(), settings.clone()) => {
             if let Err(e) = res {
                 error!("WebSocket server failed: {:?}", e);
             } else {
@@ -164,6 +371,11 @@ async fn main() -> Result<()> {
             db_pool.clone(),
             agents.clone(),
             pending.clone(),
+            sessions.clone(),
+            token_hasher.clone(),
+            lockouts.clone(),
+            agent_health.clone(),
+            events.clone(),
             settings.clone(),
         ) => {
             if let Err(e) = res {
@@ -173,7 +385,7 @@ async fn main() -> Result<()> {
             }
         },
         // Axum APIサーバーの実行
-        res = axum::serve(listener, app.into_make_service()) => {
+        res = axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>()) => {
             if let Err(e) = res {
                 error!("Axum API server failed: {:?}", e);
             } else {
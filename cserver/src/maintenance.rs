@@ -0,0 +1,302 @@
+use crate::api::auth::AuthUser;
+use crate::api::dto::ErrorResponse;
+use crate::api::err;
+use crate::api::revocation::RevocationList;
+use crate::health::AgentHealth;
+use crate::lockout::Lockouts;
+use crate::repository::{
+    create_maintenance_run, delete_expired_invite_codes, delete_expired_password_reset_tokens,
+    delete_expired_refresh_tokens, delete_expired_revoked_access_tokens, delete_expired_tokens,
+    delete_maintenance_runs_before, delete_usage_sessions_before, get_maintenance_runs,
+    MaintenanceRunRecord,
+};
+use crate::{AppState, Settings};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json as SqlJson;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+// 実行履歴一覧のデフォルト件数と上限
+const DEFAULT_RUNS_LIMIT: i64 = 20;
+const MAX_RUNS_LIMIT: i64 = 100;
+
+// メンテナンスで実行する削除タスク
+#[derive(Debug, Clone, Copy)]
+enum CleanupTask {
+    // 保持期間を過ぎた有効期限切れトークン
+    ExpiredTokens,
+    // 保持期間を過ぎた有効期限切れリフレッシュトークン
+    ExpiredRefreshTokens,
+    // 有効期限切れで不要になったアクセストークンの失効リスト
+    RevokedAccessTokens,
+    // 保持期間を過ぎた有効期限切れパスワードリセットトークン
+    ExpiredPasswordResetTokens,
+    // 保持期間を過ぎた未使用の有効期限切れ招待コード
+    ExpiredInviteCodes,
+    // 保持期間を過ぎたSOCKS5セッション利用履歴
+    UsageSessions,
+    // 保持期間を過ぎたメンテナンス実行履歴
+    MaintenanceRuns,
+    // ロックが解除され不要になった認証失敗回数 (メモリ上のみ)
+    FailureCounters,
+    // 直近の期間にイベントがないエージェントの健全性 (メモリ上のみ)
+    AgentHealth,
+}
+
+// 実行順に並べた削除タスク一覧
+const CLEANUP_TASKS: &[CleanupTask] = &[
+    CleanupTask::ExpiredTokens,
+    CleanupTask::ExpiredRefreshTokens,
+    CleanupTask::RevokedAccessTokens,
+    CleanupTask::ExpiredPasswordResetTokens,
+    CleanupTask::ExpiredInviteCodes,
+    CleanupTask::UsageSessions,
+    CleanupTask::MaintenanceRuns,
+    CleanupTask::FailureCounters,
+    CleanupTask::AgentHealth,
+];
+
+impl CleanupTask {
+    // 実行履歴に記録するタスク名
+    fn name(self) -> &'static str {
+        match self {
+            CleanupTask::ExpiredTokens => "expired_tokens",
+            CleanupTask::ExpiredRefreshTokens => "expired_refresh_tokens",
+            CleanupTask::RevokedAccessTokens => "revoked_access_tokens",
+            CleanupTask::ExpiredPasswordResetTokens => "expired_password_reset_tokens",
+            CleanupTask::ExpiredInviteCodes => "expired_invite_codes",
+            CleanupTask::UsageSessions => "usage_sessions",
+            CleanupTask::MaintenanceRuns => "maintenance_runs",
+            CleanupTask::FailureCounters => "failure_counters",
+            CleanupTask::AgentHealth => "agent_health",
+        }
+    }
+
+    // タスクを実行し、削除件数を返す
+    async fn run(self, maintenance: &Maintenance, now: DateTime<Utc>) -> sqlx::Result<u64> {
+        let pool = &maintenance.pool;
+        let settings = &maintenance.settings;
+        match self {
+            CleanupTask::ExpiredTokens => {
+                let before =
+                    now - chrono::Duration::seconds(settings.expired_token_retention_seconds);
+                delete_expired_tokens(pool, before).await
+            }
+            CleanupTask::ExpiredRefreshTokens => {
+                let before =
+                    now - chrono::Duration::seconds(settings.expired_token_retention_seconds);
+                delete_expired_refresh_tokens(pool, before).await
+            }
+            CleanupTask::RevokedAccessTokens => {
+                maintenance
+                    .revocations
+                    .prune(now.timestamp(), settings.access_token_ttl_seconds);
+                delete_expired_revoked_access_tokens(pool, now).await
+            }
+            CleanupTask::ExpiredPasswordResetTokens => {
+                let before =
+                    now - chrono::Duration::seconds(settings.expired_token_retention_seconds);
+                delete_expired_password_reset_tokens(pool, before).await
+            }
+            CleanupTask::ExpiredInviteCodes => {
+                let before =
+                    now - chrono::Duration::seconds(settings.expired_token_retention_seconds);
+                delete_expired_invite_codes(pool, before).await
+            }
+            CleanupTask::UsageSessions => {
+                let before =
+                    now - chrono::Duration::seconds(settings.usage_session_retention_seconds);
+                delete_usage_sessions_before(pool, before).await
+            }
+            CleanupTask::MaintenanceRuns => {
+                let before =
+                    now - chrono::Duration::seconds(settings.maintenance_run_retention_seconds);
+                delete_maintenance_runs_before(pool, before).await
+            }
+            CleanupTask::FailureCounters => Ok(maintenance.lockouts.prune(now.timestamp()) as u64),
+            CleanupTask::AgentHealth => Ok(maintenance.agent_health.prune(now.timestamp()) as u64),
+        }
+    }
+}
+
+// 定期メンテナンス (保持期間を過ぎたデータの削除) の実行を管理する
+// 定期実行と管理者による手動実行が同時に走らないようロックで直列化する
+#[derive(Clone)]
+pub(crate) struct Maintenance {
+    pool: PgPool,
+    settings: Arc<Settings>,
+    revocations: RevocationList,
+    lockouts: Lockouts,
+    agent_health: AgentHealth,
+    lock: Arc<Mutex<()>>,
+}
+
+impl Maintenance {
+    pub fn new(
+        pool: PgPool,
+        settings: Arc<Settings>,
+        revocations: RevocationList,
+        lockouts: Lockouts,
+        agent_health: AgentHealth,
+    ) -> Self {
+        Maintenance {
+            pool,
+            settings,
+            revocations,
+            lockouts,
+            agent_health,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    // 全タスクを1回実行し、結果を実行履歴として保存する
+    // 1つのタスクが失敗しても残りのタスクは実行する
+    pub async fn run_once(&self) -> sqlx::Result<MaintenanceRunRecord> {
+        let _guard = self.lock.lock().await;
+        let started_at = Utc::now();
+        let mut deleted = BTreeMap::new();
+        let mut errors = Vec::new();
+        for task in CLEANUP_TASKS {
+            match task.run(self, started_at).await {
+                Ok(count) => {
+                    deleted.insert(task.name().to_string(), count as i64);
+                }
+                Err(e) => {
+                    error!("Maintenance task {} failed: {}", task.name(), e);
+                    errors.push(format!("{}: {}", task.name(), e));
+                }
+            }
+        }
+        let rec = MaintenanceRunRecord {
+            id: Uuid::now_v7(),
+            started_at,
+            finished_at: Utc::now(),
+            success: errors.is_empty(),
+            deleted: SqlJson(deleted),
+            error: (!errors.is_empty()).then(|| errors.join("; ")),
+        };
+        create_maintenance_run(&self.pool, &rec).await?;
+        info!("Maintenance run finished: {:?}", rec.deleted.0);
+        Ok(rec)
+    }
+
+    // 設定された間隔でメンテナンスを実行し続ける
+    pub async fn run_periodically(self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            self.settings.maintenance_interval_seconds,
+        ));
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.run_once().await {
+                error!("Failed to record maintenance run: {}", e);
+            }
+        }
+    }
+}
+
+// 実行履歴一覧のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct MaintenanceRunsQuery {
+    // 取得件数 (デフォルト20, 最大100)
+    pub limit: Option<i64>,
+}
+
+// APIレスポンス用のメンテナンス実行履歴
+#[derive(Serialize, ToSchema)]
+pub(crate) struct MaintenanceRunResponse {
+    pub id: Uuid,
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub success: bool,
+    // タスク名ごとの削除件数
+    pub deleted: BTreeMap<String, i64>,
+    pub error: Option<String>,
+}
+
+impl From<MaintenanceRunRecord> for MaintenanceRunResponse {
+    fn from(rec: MaintenanceRunRecord) -> Self {
+        MaintenanceRunResponse {
+            id: rec.id,
+            started_at: rec.started_at.timestamp(),
+            finished_at: rec.finished_at.timestamp(),
+            success: rec.success,
+            deleted: rec.deleted.0,
+            error: rec.error,
+        }
+    }
+}
+
+// メンテナンス実行履歴を取得するAPIハンドラ (管理者のみ)
+#[utoipa::path(
+    get,
+    path = "/api/admin/maintenance/runs",
+    params(MaintenanceRunsQuery),
+    responses(
+        (status = 200, description = "Recent maintenance runs", body = [MaintenanceRunResponse]),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn list_maintenance_runs(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<MaintenanceRunsQuery>,
+) -> impl IntoResponse {
+    // 管理者権限チェック
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_RUNS_LIMIT)
+        .clamp(1, MAX_RUNS_LIMIT);
+    match get_maintenance_runs(&state.db_pool, limit).await {
+        Ok(recs) => {
+            let resp: Vec<MaintenanceRunResponse> = recs.into_iter().map(Into::into).collect();
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// メンテナンスを即時実行するAPIハンドラ (管理者のみ)
+#[utoipa::path(
+    post,
+    path = "/api/admin/maintenance/runs",
+    responses(
+        (status = 200, description = "Maintenance run result", body = MaintenanceRunResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Admin"
+)]
+pub(crate) async fn run_maintenance(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> impl IntoResponse {
+    // 管理者権限チェック
+    if let Err(resp) = auth.require_admin() {
+        return resp;
+    }
+    info!("Maintenance run triggered by admin {}", auth.user_id);
+    match state.maintenance.run_once().await {
+        Ok(rec) => (StatusCode::OK, Json(MaintenanceRunResponse::from(rec))).into_response(),
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
@@ -0,0 +1,10 @@
+-- トークンの利用制限カラムを追加 (NULL は制限なし)
+ALTER TABLE tokens
+    ADD COLUMN allowed_countries TEXT[],              -- 利用可能なエージェントの国コード
+    ADD COLUMN allowed_agents TEXT[],                 -- 利用可能なエージェントID
+    ADD COLUMN allowed_ports INTEGER[],               -- 接続先として許可するポート
+    ADD COLUMN allowed_domains TEXT[],                -- 接続先として許可するドメイン ("*.example.com" 形式も可)
+    ADD COLUMN allowed_cidrs TEXT[],                  -- 接続元クライアントとして許可するCIDR
+    ADD COLUMN max_bytes BIGINT CHECK (max_bytes > 0),       -- 総転送量の上限 (バイト)
+    ADD COLUMN max_sessions INTEGER CHECK (max_sessions > 0), -- 同時セッション数の上限
+    ADD COLUMN bytes_used BIGINT NOT NULL DEFAULT 0;  -- これまでの総転送量 (バイト)
@@ -0,0 +1,2 @@
+-- トークンに識別用のラベルを追加
+ALTER TABLE tokens ADD COLUMN label TEXT;
@@ -0,0 +1,18 @@
+-- トークンを平文ではなくハッシュで保存する
+-- 既存の平文トークンは cserver 起動時にハッシュ化され、token カラムは NULL になる
+
+-- 主キーをトークン文字列から ID に変更
+ALTER TABLE tokens ADD COLUMN id UUID;
+UPDATE tokens SET id = gen_random_uuid();
+ALTER TABLE tokens ALTER COLUMN id SET NOT NULL;
+ALTER TABLE tokens DROP CONSTRAINT tokens_pkey;
+ALTER TABLE tokens ADD PRIMARY KEY (id);
+
+-- 旧形式の平文トークン (移行完了後は NULL)
+ALTER TABLE tokens ALTER COLUMN token DROP NOT NULL;
+
+-- 鍵付きハッシュ (HMAC-SHA256, 16進数) と識別用の先頭数文字
+ALTER TABLE tokens ADD COLUMN token_hash TEXT UNIQUE;
+ALTER TABLE tokens ADD COLUMN token_prefix TEXT;
+UPDATE tokens SET token_prefix = left(token, 12);
+ALTER TABLE tokens ALTER COLUMN token_prefix SET NOT NULL;
@@ -0,0 +1,11 @@
+-- 定期メンテナンス (期限切れデータの削除) の実行履歴
+CREATE TABLE maintenance_runs (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    started_at TIMESTAMPTZ NOT NULL,         -- 開始日時
+    finished_at TIMESTAMPTZ NOT NULL,        -- 終了日時
+    success BOOLEAN NOT NULL,                -- 全タスクが成功したか
+    deleted JSONB NOT NULL DEFAULT '{}',     -- タスク名ごとの削除件数
+    error TEXT                               -- 失敗時のエラーメッセージ
+);
+
+CREATE INDEX idx_maintenance_runs_started_at ON maintenance_runs(started_at);
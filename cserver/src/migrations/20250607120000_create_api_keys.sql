@@ -0,0 +1,17 @@
+-- APIキーのスコープ
+CREATE TYPE api_key_scope AS ENUM ('agents:read', 'tokens:manage', 'admin');
+
+-- REST API 用の長期APIキー (キー本体は保存せず、鍵付きハッシュのみ保存する)
+CREATE TABLE api_keys (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- 所有ユーザー
+    key_hash TEXT UNIQUE NOT NULL,           -- キーの鍵付きハッシュ (HMAC-SHA256)
+    key_prefix TEXT NOT NULL,                -- 識別用のキー先頭部分
+    name TEXT NOT NULL,                      -- 識別用の名前
+    scopes api_key_scope[] NOT NULL,         -- 許可されたスコープ
+    created_at TIMESTAMPTZ NOT NULL,         -- 作成日時
+    expires_at TIMESTAMPTZ,                  -- 有効期限 (NULL は無期限)
+    last_used_at TIMESTAMPTZ                 -- 最終利用日時
+);
+
+CREATE INDEX idx_api_keys_user_id ON api_keys(user_id);
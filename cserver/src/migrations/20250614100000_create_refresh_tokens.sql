@@ -0,0 +1,22 @@
+-- ログインセッションのリフレッシュトークン (トークン本体は保存せず、鍵付きハッシュのみ保存する)
+CREATE TABLE refresh_tokens (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- 所有ユーザー
+    token_hash TEXT UNIQUE NOT NULL,         -- トークンの鍵付きハッシュ (HMAC-SHA256)
+    created_at TIMESTAMPTZ NOT NULL,         -- 発行日時
+    expires_at TIMESTAMPTZ NOT NULL,         -- 有効期限
+    revoked_at TIMESTAMPTZ                   -- 失効日時 (ローテーション・ログアウト時に設定)
+);
+
+CREATE INDEX idx_refresh_tokens_user_id ON refresh_tokens(user_id);
+CREATE INDEX idx_refresh_tokens_expires_at ON refresh_tokens(expires_at);
+
+-- 有効期限前に失効させたアクセストークン (JWT) の失効リスト
+CREATE TABLE revoked_access_tokens (
+    jti UUID PRIMARY KEY,                    -- JWT ID
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- 所有ユーザー
+    expires_at TIMESTAMPTZ NOT NULL          -- JWTの有効期限 (以降はリストから削除可能)
+);
+
+-- 「全セッションからログアウト」の実行日時 (これより前に発行されたJWTは無効)
+ALTER TABLE users ADD COLUMN sessions_revoked_at TIMESTAMPTZ;
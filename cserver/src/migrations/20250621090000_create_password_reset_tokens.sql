@@ -0,0 +1,12 @@
+-- 管理者が発行するワンタイムのパスワードリセットトークン (トークン本体は保存せず、鍵付きハッシュのみ保存する)
+CREATE TABLE password_reset_tokens (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- 対象ユーザー
+    token_hash TEXT UNIQUE NOT NULL,         -- トークンの鍵付きハッシュ (HMAC-SHA256)
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL, -- 発行した管理者
+    created_at TIMESTAMPTZ NOT NULL,         -- 発行日時
+    expires_at TIMESTAMPTZ NOT NULL,         -- 有効期限
+    used_at TIMESTAMPTZ                      -- 使用日時 (未使用の場合は NULL)
+);
+
+CREATE INDEX idx_password_reset_tokens_user_id ON password_reset_tokens(user_id);
@@ -0,0 +1,17 @@
+-- TOTP 二要素認証の設定 (ユーザーごとに1件)
+CREATE TABLE user_totp (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE, -- ユーザーID
+    secret TEXT NOT NULL,                    -- 共有シークレット (Base32)
+    created_at TIMESTAMPTZ NOT NULL,         -- 登録開始日時
+    enabled_at TIMESTAMPTZ,                  -- 有効化日時 (確認コードの検証前は NULL)
+    last_used_step BIGINT                    -- 最後に使用したタイムステップ (コードの再利用防止)
+);
+
+-- TOTP のリカバリーコード (コード本体は保存せず、鍵付きハッシュのみ保存する)
+CREATE TABLE totp_recovery_codes (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- ユーザーID
+    code_hash TEXT NOT NULL,                 -- コードの鍵付きハッシュ (HMAC-SHA256)
+    used_at TIMESTAMPTZ,                     -- 使用日時 (未使用の場合は NULL)
+    UNIQUE (user_id, code_hash)
+);
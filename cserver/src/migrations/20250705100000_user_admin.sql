@@ -0,0 +1,16 @@
+-- アカウント停止 (停止中はログイン・APIキー・SOCKS5認証を拒否する)
+ALTER TABLE users ADD COLUMN suspended_at TIMESTAMPTZ;   -- 停止日時 (停止中でない場合は NULL)
+ALTER TABLE users ADD COLUMN suspension_reason TEXT;     -- 停止理由
+
+-- ポイントの増減履歴
+CREATE TABLE point_ledger (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- 対象ユーザーID
+    amount INTEGER NOT NULL,                 -- 増減量 (付与は正、減算は負)
+    balance_after INTEGER NOT NULL,          -- 増減後の残高
+    reason TEXT NOT NULL,                    -- 理由
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL, -- 実行した管理者
+    created_at TIMESTAMPTZ NOT NULL          -- 記録日時
+);
+
+CREATE INDEX idx_point_ledger_user_id_created_at ON point_ledger(user_id, created_at);
@@ -0,0 +1,15 @@
+-- 招待コード (コード本体は保存せず、鍵付きハッシュのみ保存する)
+CREATE TABLE invite_codes (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    code_hash TEXT UNIQUE NOT NULL,          -- コードの鍵付きハッシュ (HMAC-SHA256)
+    code_prefix TEXT NOT NULL,               -- 識別用のコード先頭部分
+    points INTEGER NOT NULL DEFAULT 0 CHECK (points >= 0), -- 登録時に追加で付与するポイント
+    note TEXT,                               -- 管理用のメモ
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL, -- 発行した管理者
+    created_at TIMESTAMPTZ NOT NULL,         -- 発行日時
+    expires_at TIMESTAMPTZ,                  -- 有効期限 (NULL は無期限)
+    used_by UUID REFERENCES users(id) ON DELETE SET NULL,    -- 使用したユーザー
+    used_at TIMESTAMPTZ                      -- 使用日時 (未使用の場合は NULL)
+);
+
+CREATE INDEX idx_invite_codes_expires_at ON invite_codes(expires_at);
@@ -0,0 +1,48 @@
+-- 組織内のロール (owner: 全権限, member: トークンの利用, billing: 残高と利用状況の管理)
+CREATE TYPE org_role AS ENUM ('owner', 'member', 'billing');
+
+-- 組織 (メンバーでポイント残高を共有する)
+CREATE TABLE organizations (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    name TEXT UNIQUE NOT NULL,               -- 組織名
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL, -- 作成したユーザー
+    created_at TIMESTAMPTZ NOT NULL          -- 作成日時
+);
+
+-- 組織のメンバー
+CREATE TABLE organization_members (
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role org_role NOT NULL DEFAULT 'member', -- 組織内のロール
+    joined_at TIMESTAMPTZ NOT NULL,          -- 参加日時
+    PRIMARY KEY (organization_id, user_id)
+);
+
+CREATE INDEX idx_organization_members_user_id ON organization_members(user_id);
+
+-- 組織の共有ポイント残高 (user_points と同じ構成)
+CREATE TABLE organization_points (
+    organization_id UUID PRIMARY KEY REFERENCES organizations(id) ON DELETE CASCADE,
+    points INTEGER NOT NULL DEFAULT 0 CHECK (points >= 0), -- 保有ポイント (負数不可)
+    updated_at TIMESTAMPTZ NOT NULL          -- 更新日時
+);
+
+-- 組織のポイント増減履歴 (SOCKS5での消費は記録しない)
+CREATE TABLE organization_point_ledger (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    amount INTEGER NOT NULL,                 -- 増減量 (付与は正、減算は負)
+    balance_after INTEGER NOT NULL,          -- 増減後の残高
+    reason TEXT NOT NULL,                    -- 理由
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL, -- 実行したユーザー
+    created_at TIMESTAMPTZ NOT NULL          -- 記録日時
+);
+
+CREATE INDEX idx_organization_point_ledger_org_created_at ON organization_point_ledger(organization_id, created_at);
+
+-- 組織所有のトークン (user_id は発行したメンバー)。組織の残高からポイントを消費する
+ALTER TABLE tokens ADD COLUMN organization_id UUID REFERENCES organizations(id) ON DELETE CASCADE;
+-- 組織の残高から消費したポイントの累計 (メンバーごとの利用状況の集計に使用)
+ALTER TABLE tokens ADD COLUMN points_used BIGINT NOT NULL DEFAULT 0;
+
+CREATE INDEX idx_tokens_organization_id ON tokens(organization_id);
@@ -0,0 +1,43 @@
+-- SOCKS5セッションの結果
+CREATE TYPE session_outcome AS ENUM (
+    'completed',       -- 転送が正常に終了
+    'rejected',        -- トークンの制限・残高不足などで拒否
+    'no_agent',        -- 利用可能なエージェントなし
+    'connect_failed',  -- エージェントから接続先への接続に失敗
+    'revoked',         -- トークンの失効により切断
+    'limit_exceeded',  -- 転送量の上限到達により切断
+    'error'            -- その他のエラー
+);
+
+-- SOCKS5セッション (トンネル) の利用履歴
+CREATE TABLE usage_sessions (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL, -- トークンの所有者 (組織トークンは発行したメンバー。ユーザ削除後も履歴は残す)
+    token_id UUID NOT NULL,                  -- 使用したトークン (トークン削除後も履歴は残す)
+    token_prefix TEXT NOT NULL,              -- 識別用のトークン先頭部分
+    organization_id UUID REFERENCES organizations(id) ON DELETE SET NULL, -- 組織トークンの場合は組織ID
+    agent_id TEXT,                           -- 使用したエージェント (選択前に終了した場合は NULL)
+    target_host TEXT,                        -- 接続先 (CONNECT要求の前に終了した場合は NULL)
+    target_port INTEGER,
+    client_ip TEXT NOT NULL,                 -- 接続元IP
+    started_at TIMESTAMPTZ NOT NULL,         -- 開始日時
+    ended_at TIMESTAMPTZ NOT NULL,           -- 終了日時
+    bytes_up BIGINT NOT NULL DEFAULT 0,      -- クライアント -> 接続先の転送量
+    bytes_down BIGINT NOT NULL DEFAULT 0,    -- 接続先 -> クライアントの転送量
+    outcome session_outcome NOT NULL,        -- 結果
+    points_charged INTEGER NOT NULL DEFAULT 0 -- 消費したポイント
+);
+
+CREATE INDEX idx_usage_sessions_user_id_started_at ON usage_sessions(user_id, started_at);
+CREATE INDEX idx_usage_sessions_started_at ON usage_sessions(started_at);
+
+-- ユーザごとの日次集計 (UTC)。セッション履歴の削除後もグラフ表示用に保持する
+CREATE TABLE usage_daily (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    sessions BIGINT NOT NULL DEFAULT 0,      -- セッション数 (拒否・失敗を含む)
+    bytes_up BIGINT NOT NULL DEFAULT 0,
+    bytes_down BIGINT NOT NULL DEFAULT 0,
+    points_charged BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (user_id, day)
+);
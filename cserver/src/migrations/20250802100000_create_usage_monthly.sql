@@ -0,0 +1,26 @@
+-- セッション時点のエージェントの国コード (エージェント選択前に終了した場合は NULL)
+ALTER TABLE usage_sessions ADD COLUMN agent_country TEXT;
+
+-- 請求先 (個人トークンはユーザ、組織トークンは組織) ごとの月次集計 (UTC)
+-- 月次明細の生成に使用し、セッション履歴の削除後も保持する
+CREATE TABLE usage_monthly (
+    account_id UUID NOT NULL,                -- ユーザIDまたは組織ID (請求先の削除後も明細のため保持する)
+    is_organization BOOLEAN NOT NULL,        -- account_id が組織IDの場合 TRUE
+    month DATE NOT NULL,                     -- 月初日
+    country TEXT NOT NULL DEFAULT '',        -- エージェントの国コード (不明な場合は空文字)
+    sessions BIGINT NOT NULL DEFAULT 0,
+    bytes_up BIGINT NOT NULL DEFAULT 0,
+    bytes_down BIGINT NOT NULL DEFAULT 0,
+    points_charged BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (account_id, is_organization, month, country)
+);
+
+CREATE INDEX idx_usage_monthly_month ON usage_monthly(month);
+
+-- 既存のセッション履歴から集計を作成する
+INSERT INTO usage_monthly (account_id, is_organization, month, sessions, bytes_up, bytes_down, points_charged)
+SELECT COALESCE(organization_id, user_id), organization_id IS NOT NULL,
+       date_trunc('month', started_at AT TIME ZONE 'UTC')::date,
+       COUNT(*), SUM(bytes_up), SUM(bytes_down), SUM(points_charged)
+FROM usage_sessions
+GROUP BY 1, 2, 3;
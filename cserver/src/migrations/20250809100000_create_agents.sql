@@ -0,0 +1,33 @@
+-- 接続したことのあるエージェント (最新の状態)
+CREATE TABLE agents (
+    id TEXT PRIMARY KEY,                     -- エージェントID (agent_ で始まる)
+    first_seen_at TIMESTAMPTZ NOT NULL,      -- 初回接続日時
+    last_seen_at TIMESTAMPTZ NOT NULL,       -- 最終接続・切断日時
+    connected_at TIMESTAMPTZ,                -- 現在の接続の開始日時 (オフラインの場合は NULL)
+    current_connection_id UUID,              -- 現在の接続 (オフラインの場合は NULL)
+    connection_count BIGINT NOT NULL DEFAULT 0, -- 累計接続回数
+    total_uptime_seconds BIGINT NOT NULL DEFAULT 0, -- 切断済みの接続の累計接続時間 (秒)
+    last_ip TEXT NOT NULL,                   -- 最後に報告された出口IP
+    metadata JSONB NOT NULL                  -- 最後に報告されたメタデータ (地域・OSなど)
+);
+
+-- エージェントの接続履歴
+CREATE TABLE agent_connections (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    agent_id TEXT NOT NULL REFERENCES agents(id) ON DELETE CASCADE,
+    remote_addr TEXT,                        -- WebSocket接続元アドレス
+    connected_at TIMESTAMPTZ NOT NULL,
+    disconnected_at TIMESTAMPTZ              -- 接続中の場合は NULL
+);
+
+CREATE INDEX idx_agent_connections_agent_id ON agent_connections(agent_id, connected_at);
+
+-- エージェントのメタデータの変更履歴 (変更があった場合のみ記録)
+CREATE TABLE agent_metadata_history (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    agent_id TEXT NOT NULL REFERENCES agents(id) ON DELETE CASCADE,
+    recorded_at TIMESTAMPTZ NOT NULL,
+    metadata JSONB NOT NULL
+);
+
+CREATE INDEX idx_agent_metadata_history_agent_id ON agent_metadata_history(agent_id, recorded_at);
@@ -0,0 +1,6 @@
+-- 管理者によるエージェントの管理状態
+ALTER TABLE agents
+    ADD COLUMN disabled_at TIMESTAMPTZ,          -- 無効化日時 (無効化中は新規トンネルに使用しない)
+    ADD COLUMN banned_at TIMESTAMPTZ,            -- BAN日時 (BAN中は接続を拒否する)
+    ADD COLUMN ban_reason TEXT,
+    ADD COLUMN labels TEXT[] NOT NULL DEFAULT '{}'; -- SOCKS5ユーザー名 (label_<name>) で選択するためのラベル
@@ -0,0 +1,12 @@
+-- エージェントの出口IPの変更履歴 (接続中の再検出と、前回と異なるIPでの再接続)
+CREATE TABLE agent_ip_changes (
+    id UUID PRIMARY KEY,                     -- UUID v7 (アプリケーション側で生成)
+    agent_id TEXT NOT NULL REFERENCES agents(id) ON DELETE CASCADE,
+    connection_id UUID NOT NULL,             -- 変更を検出した接続
+    changed_at TIMESTAMPTZ NOT NULL,
+    old_ip TEXT NOT NULL,
+    new_ip TEXT NOT NULL,
+    country_code TEXT NOT NULL               -- 変更後の国コード (GeoIPで検証できた場合は検証結果)
+);
+
+CREATE INDEX idx_agent_ip_changes_agent_id ON agent_ip_changes(agent_id, changed_at);
@@ -0,0 +1,2 @@
+-- 利用履歴・利用量・明細の参照用のAPIキーのスコープ
+ALTER TYPE api_key_scope ADD VALUE 'usage:read';
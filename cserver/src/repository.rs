@@ -1,13 +1,18 @@
 #![allow(dead_code)]
 // repository module: SQLx-based data access for users, tokens, and user_points
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use sqlx::types::Json;
 use sqlx::Type;
 use sqlx::{query, query_as, FromRow, PgPool}; // for deriving SQLx types
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
+use crate::agent::AgentMetadata;
+use crate::token::restriction::TokenRestrictions;
+
 // Define Rust enum matching Postgres user_role type
 #[derive(Type, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "lowercase")]
@@ -17,6 +22,57 @@ pub enum UserRole {
     User,
 }
 
+// Postgres の api_key_scope 型に対応するAPIキーのスコープ
+#[derive(
+    Type, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+#[sqlx(type_name = "api_key_scope")]
+pub enum ApiKeyScope {
+    // エージェント情報の参照
+    #[serde(rename = "agents:read")]
+    #[sqlx(rename = "agents:read")]
+    AgentsRead,
+    // プロキシトークンの管理
+    #[serde(rename = "tokens:manage")]
+    #[sqlx(rename = "tokens:manage")]
+    TokensManage,
+    // 利用履歴・利用量・明細の参照
+    #[serde(rename = "usage:read")]
+    #[sqlx(rename = "usage:read")]
+    UsageRead,
+    // 管理者向けAPI (管理者ユーザのみ付与可能)
+    #[serde(rename = "admin")]
+    #[sqlx(rename = "admin")]
+    Admin,
+}
+
+// Postgres の org_role 型に対応する組織内のロール
+#[derive(Type, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "org_role", rename_all = "lowercase")]
+pub enum OrgRole {
+    // メンバー管理・トークン利用・残高管理のすべて
+    Owner,
+    // 組織のトークンの発行と利用
+    Member,
+    // 残高の入金と利用状況の参照
+    Billing,
+}
+
+// Postgres の session_outcome 型に対応するSOCKS5セッションの結果
+#[derive(Type, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "session_outcome", rename_all = "snake_case")]
+pub enum SessionOutcome {
+    Completed,
+    Rejected,
+    NoAgent,
+    ConnectFailed,
+    Revoked,
+    LimitExceeded,
+    Error,
+}
+
 #[derive(Debug, FromRow)]
 pub struct UserRecord {
     pub id: Uuid,
@@ -24,16 +80,260 @@ pub struct UserRecord {
     pub password_hash: String,
     pub role: UserRole,
     pub created_at: DateTime<Utc>,
+    // 停止日時と理由 (停止中でない場合は None)
+    pub suspended_at: Option<DateTime<Utc>>,
+    pub suspension_reason: Option<String>,
+}
+
+// 管理者向けユーザ一覧の1行 (ポイント残高を含む)
+#[derive(Debug, FromRow)]
+pub struct UserSummaryRecord {
+    pub id: Uuid,
+    pub username: String,
+    pub role: UserRole,
+    pub created_at: DateTime<Utc>,
+    pub suspended_at: Option<DateTime<Utc>>,
+    pub suspension_reason: Option<String>,
+    pub points: i32,
+}
+
+#[derive(Debug, FromRow)]
+pub struct PointLedgerRecord {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    // 増減量 (付与は正、減算は負)
+    pub amount: i32,
+    pub balance_after: i32,
+    pub reason: String,
+    // 実行した管理者
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, FromRow)]
 pub struct TokenRecord {
-    pub token: String,
+    pub id: Uuid,
+    // 識別用のトークン先頭部分 (トークン本体は保存しない)
+    pub token_prefix: String,
+    pub user_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    #[sqlx(flatten)]
+    pub restrictions: TokenRestrictions,
+    pub bytes_used: i64,
+    pub label: Option<String>,
+    // 組織所有のトークンの場合は組織ID (ポイントは組織の残高から消費する)
+    pub organization_id: Option<Uuid>,
+}
+
+// tokens テーブルから TokenRecord を取得する際のカラム一覧
+const TOKEN_COLUMNS: &str = "id, token_prefix, user_id, expires_at, created_at, \
+    allowed_countries, allowed_agents, allowed_ports, allowed_domains, allowed_cidrs, \
+    max_bytes, max_sessions, bytes_used, label, organization_id";
+
+#[derive(Debug, FromRow)]
+pub struct ApiKeyRecord {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    // 識別用のキー先頭部分 (キー本体は保存しない)
+    pub key_prefix: String,
+    pub name: String,
+    pub scopes: Vec<ApiKeyScope>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct RefreshTokenRecord {
+    pub id: Uuid,
     pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct PasswordResetTokenRecord {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    // 発行した管理者 (管理者の削除後は NULL)
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
     pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct UserTotpRecord {
+    pub user_id: Uuid,
+    // 共有シークレット (Base32)
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+    pub enabled_at: Option<DateTime<Utc>>,
+    pub last_used_step: Option<i64>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct InviteCodeRecord {
+    pub id: Uuid,
+    // 識別用のコード先頭部分 (コード本体は保存しない)
+    pub code_prefix: String,
+    // 登録時に追加で付与するポイント
+    pub points: i32,
+    pub note: Option<String>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub used_by: Option<Uuid>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+// ユーザが所属する組織 (ロールと共有残高を含む)
+#[derive(Debug, FromRow)]
+pub struct MembershipRecord {
+    pub organization_id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub role: OrgRole,
+    pub points: i32,
+}
+
+#[derive(Debug, FromRow)]
+pub struct OrganizationMemberRecord {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: OrgRole,
+    pub joined_at: DateTime<Utc>,
+}
+
+// メンバーごとの組織トークンの利用状況
+#[derive(Debug, FromRow)]
+pub struct MemberUsageRecord {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: OrgRole,
+    // 有効期限内のトークン数
+    pub active_tokens: i64,
+    pub bytes_used: i64,
+    pub points_used: i64,
+}
+
+#[derive(Debug, FromRow)]
+pub struct OrganizationLedgerRecord {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub amount: i32,
+    pub balance_after: i32,
+    pub reason: String,
+    pub created_by: Option<Uuid>,
     pub created_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Clone, FromRow)]
+pub struct UsageSessionRecord {
+    pub id: Uuid,
+    // ユーザ削除後も履歴は残すため NULL になる
+    pub user_id: Option<Uuid>,
+    pub token_id: Uuid,
+    pub token_prefix: String,
+    pub organization_id: Option<Uuid>,
+    pub agent_id: Option<String>,
+    pub agent_country: Option<String>,
+    pub target_host: Option<String>,
+    pub target_port: Option<i32>,
+    pub client_ip: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub bytes_up: i64,
+    pub bytes_down: i64,
+    pub outcome: SessionOutcome,
+    pub points_charged: i32,
+}
+
+const USAGE_SESSION_COLUMNS: &str = "s.id, s.user_id, s.token_id, s.token_prefix, \
+    s.organization_id, s.agent_id, s.agent_country, s.target_host, s.target_port, s.client_ip, \
+    s.started_at, s.ended_at, s.bytes_up, s.bytes_down, s.outcome, s.points_charged";
+
+// エクスポート用のセッション履歴 (ユーザ名付き)
+#[derive(Debug, FromRow)]
+pub struct UsageExportRecord {
+    #[sqlx(flatten)]
+    pub session: UsageSessionRecord,
+    // 削除済みユーザの場合は NULL
+    pub username: Option<String>,
+}
+
+// 請求先ごと・国ごとの月次利用量 (UTC)
+#[derive(Debug, FromRow)]
+pub struct UsageMonthlyRecord {
+    pub account_id: Uuid,
+    pub is_organization: bool,
+    // ユーザ名または組織名 (削除済みの場合は NULL)
+    pub account_name: Option<String>,
+    // エージェントの国コード (不明な場合は空文字)
+    pub country: String,
+    pub sessions: i64,
+    pub bytes_up: i64,
+    pub bytes_down: i64,
+    pub points_charged: i64,
+}
+
+// ユーザの日次利用量 (UTC)
+#[derive(Debug, FromRow)]
+pub struct UsageDailyRecord {
+    pub day: NaiveDate,
+    pub sessions: i64,
+    pub bytes_up: i64,
+    pub bytes_down: i64,
+    pub points_charged: i64,
+}
+
+// 永続化されたエージェントの状態
+#[derive(Debug, FromRow)]
+pub struct AgentRecord {
+    pub id: String,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    // 現在の接続の開始日時 (オフラインの場合は None)
+    pub connected_at: Option<DateTime<Utc>>,
+    pub connection_count: i64,
+    // 切断済みの接続の累計接続時間 (秒)
+    pub total_uptime_seconds: i64,
+    pub last_ip: String,
+    pub metadata: Json<AgentMetadata>,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub banned_at: Option<DateTime<Utc>>,
+    pub ban_reason: Option<String>,
+    pub labels: Vec<String>,
+}
+
+const AGENT_COLUMNS: &str = "id, first_seen_at, last_seen_at, connected_at, connection_count, \
+    total_uptime_seconds, last_ip, metadata, disabled_at, banned_at, ban_reason, labels";
+
+// エージェントの出口IPの変更
+#[derive(Debug, FromRow)]
+pub struct AgentIpChangeRecord {
+    pub id: Uuid,
+    pub agent_id: String,
+    pub connection_id: Uuid,
+    pub changed_at: DateTime<Utc>,
+    pub old_ip: String,
+    pub new_ip: String,
+    pub country_code: String,
+}
+
+#[derive(Debug, FromRow)]
+pub struct MaintenanceRunRecord {
+    pub id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub success: bool,
+    // タスク名ごとの削除件数
+    pub deleted: Json<BTreeMap<String, i64>>,
+    pub error: Option<String>,
+}
+
 #[derive(Debug, FromRow)]
 pub struct UserPointsRecord {
     pub user_id: Uuid,
@@ -68,7 +368,7 @@ pub async fn get_user_by_username(
     username: &str,
 ) -> sqlx::Result<Option<UserRecord>> {
     let rec = query_as::<_, UserRecord>(
-        "SELECT id, username, password_hash, role, created_at FROM users WHERE username = $1",
+        "SELECT id, username, password_hash, role, created_at, suspended_at, suspension_reason FROM users WHERE username = $1",
     )
     .bind(username)
     .fetch_optional(pool)
@@ -78,7 +378,7 @@ pub async fn get_user_by_username(
 
 pub async fn get_user_by_id(pool: &PgPool, user_id: Uuid) -> sqlx::Result<Option<UserRecord>> {
     let rec = query_as::<_, UserRecord>(
-        "SELECT id, username, password_hash, role, created_at FROM users WHERE id = $1",
+        "SELECT id, username, password_hash, role, created_at, suspended_at, suspension_reason FROM users WHERE id = $1",
     )
     .bind(user_id)
     .fetch_optional(pool)
@@ -86,41 +386,233 @@ pub async fn get_user_by_id(pool: &PgPool, user_id: Uuid) -> sqlx::Result<Option
     Ok(rec)
 }
 
-// --- Tokens ---
-pub async fn create_token(
+// ユーザのパスワードハッシュを更新する
+pub async fn update_user_password(
     pool: &PgPool,
-    token: &str,
     user_id: Uuid,
-    expires_at: DateTime<Utc>,
-    created_at: DateTime<Utc>,
+    password_hash: &str,
 ) -> sqlx::Result<u64> {
-    let result = query!(
-        r#"INSERT INTO tokens (token, user_id, expires_at, created_at)
-           VALUES ($1, $2, $3, $4)"#,
-        token,
-        user_id,
-        expires_at,
-        created_at
+    let result = query("UPDATE users SET password_hash = $2 WHERE id = $1")
+        .bind(user_id)
+        .bind(password_hash)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// ユーザ一覧をポイント残高とともに取得する (ユーザ名の部分一致で絞り込み可能)
+pub async fn get_users(
+    pool: &PgPool,
+    search: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> sqlx::Result<(Vec<UserSummaryRecord>, i64)> {
+    // LIKE のワイルドカードをエスケープ
+    let pattern = search.map(|s| {
+        format!(
+            "%{}%",
+            s.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        )
+    });
+    let recs = query_as::<_, UserSummaryRecord>(
+        r#"SELECT u.id, u.username, u.role, u.created_at, u.suspended_at, u.suspension_reason,
+                  COALESCE(p.points, 0) AS points
+           FROM users u LEFT JOIN user_points p ON p.user_id = u.id
+           WHERE $1::TEXT IS NULL OR u.username ILIKE $1
+           ORDER BY u.created_at, u.id
+           LIMIT $2 OFFSET $3"#,
+    )
+    .bind(&pattern)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+    let total: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM users WHERE $1::TEXT IS NULL OR username ILIKE $1",
+    )
+    .bind(&pattern)
+    .fetch_one(pool)
+    .await?;
+    Ok((recs, total))
+}
+
+pub async fn set_user_role(pool: &PgPool, user_id: Uuid, role: UserRole) -> sqlx::Result<u64> {
+    let result = query("UPDATE users SET role = $2 WHERE id = $1")
+        .bind(user_id)
+        .bind(role)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// アカウントを停止する (suspended_at が None の場合は停止を解除する)
+pub async fn set_user_suspended(
+    pool: &PgPool,
+    user_id: Uuid,
+    suspended_at: Option<DateTime<Utc>>,
+    reason: Option<&str>,
+) -> sqlx::Result<u64> {
+    let result = query("UPDATE users SET suspended_at = $2, suspension_reason = $3 WHERE id = $1")
+        .bind(user_id)
+        .bind(suspended_at)
+        .bind(reason)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// ユーザを削除する (トークン・ポイント等は外部キーで連鎖削除される)
+pub async fn delete_user(pool: &PgPool, user_id: Uuid) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM users WHERE id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// --- Tokens ---
+// トークン本体はハッシュ (token_hash) でのみ保存する
+pub async fn create_token(pool: &PgPool, rec: &TokenRecord, token_hash: &str) -> sqlx::Result<u64> {
+    let result = query(
+        r#"INSERT INTO tokens (id, token_hash, token_prefix, user_id, expires_at, created_at,
+               allowed_countries, allowed_agents, allowed_ports, allowed_domains, allowed_cidrs,
+               max_bytes, max_sessions, bytes_used, label, organization_id)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)"#,
     )
+    .bind(rec.id)
+    .bind(token_hash)
+    .bind(&rec.token_prefix)
+    .bind(rec.user_id)
+    .bind(rec.expires_at)
+    .bind(rec.created_at)
+    .bind(&rec.restrictions.allowed_countries)
+    .bind(&rec.restrictions.allowed_agents)
+    .bind(&rec.restrictions.allowed_ports)
+    .bind(&rec.restrictions.allowed_domains)
+    .bind(&rec.restrictions.allowed_cidrs)
+    .bind(rec.restrictions.max_bytes)
+    .bind(rec.restrictions.max_sessions)
+    .bind(rec.bytes_used)
+    .bind(&rec.label)
+    .bind(rec.organization_id)
     .execute(pool)
     .await?;
     Ok(result.rows_affected())
 }
 
-pub async fn get_token(pool: &PgPool, token: &str) -> sqlx::Result<Option<TokenRecord>> {
-    let rec = query_as!(
-        TokenRecord,
-        r#"SELECT token, user_id, expires_at, created_at
-           FROM tokens WHERE token = $1"#,
-        token
+// トークンのハッシュからレコードを取得する
+pub async fn get_token(pool: &PgPool, token_hash: &str) -> sqlx::Result<Option<TokenRecord>> {
+    let rec = query_as::<_, TokenRecord>(&format!(
+        "SELECT {} FROM tokens WHERE token_hash = $1",
+        TOKEN_COLUMNS
+    ))
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
+
+// トークンの累計転送量を取得する (トークンが削除済みの場合は 0)
+pub async fn get_token_bytes_used(pool: &PgPool, id: Uuid) -> sqlx::Result<i64> {
+    let bytes: Option<i64> = sqlx::query_scalar("SELECT bytes_used FROM tokens WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(bytes.unwrap_or(0))
+}
+
+// トークンの累計転送量を加算する
+pub async fn add_token_bytes_used(pool: &PgPool, id: Uuid, bytes: i64) -> sqlx::Result<u64> {
+    let result = query("UPDATE tokens SET bytes_used = bytes_used + $2 WHERE id = $1")
+        .bind(id)
+        .bind(bytes)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_token(pool: &PgPool, id: Uuid) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM tokens WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// 指定ユーザが所有するトークンのIDを、トークンIDまたはトークン本体のハッシュから検索する
+pub async fn find_user_token_id(
+    pool: &PgPool,
+    user_id: Uuid,
+    id_or_token: &str,
+    token_hash: &str,
+) -> sqlx::Result<Option<Uuid>> {
+    let id = sqlx::query_scalar(
+        "SELECT id FROM tokens WHERE user_id = $1 AND (id::text = $2 OR token_hash = $3)",
     )
+    .bind(user_id)
+    .bind(id_or_token)
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+    Ok(id)
+}
+
+// 指定ユーザの全トークンを削除し、削除したトークンのIDを返す
+pub async fn delete_user_tokens(pool: &PgPool, user_id: Uuid) -> sqlx::Result<Vec<Uuid>> {
+    let ids = sqlx::query_scalar("DELETE FROM tokens WHERE user_id = $1 RETURNING id")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+    Ok(ids)
+}
+
+// トークンを新しいID・ハッシュに置き換える (制限・ラベル・有効期限・累計転送量は引き継ぐ)
+pub async fn rotate_token(
+    pool: &PgPool,
+    old_id: Uuid,
+    new_id: Uuid,
+    token_hash: &str,
+    token_prefix: &str,
+) -> sqlx::Result<Option<TokenRecord>> {
+    let rec = query_as::<_, TokenRecord>(&format!(
+        "UPDATE tokens SET id = $2, token_hash = $3, token_prefix = $4 \
+         WHERE id = $1 AND expires_at > $5 RETURNING {}",
+        TOKEN_COLUMNS
+    ))
+    .bind(old_id)
+    .bind(new_id)
+    .bind(token_hash)
+    .bind(token_prefix)
+    .bind(Utc::now())
     .fetch_optional(pool)
     .await?;
     Ok(rec)
 }
 
-pub async fn delete_token(pool: &PgPool, token: &str) -> sqlx::Result<u64> {
-    let result = query!("DELETE FROM tokens WHERE token = $1", token)
+// 平文のまま保存されている旧形式トークンを取得する (ID, トークン)
+pub async fn get_plaintext_tokens(pool: &PgPool) -> sqlx::Result<Vec<(Uuid, String)>> {
+    let recs = query_as("SELECT id, token FROM tokens WHERE token IS NOT NULL")
+        .fetch_all(pool)
+        .await?;
+    Ok(recs)
+}
+
+// 旧形式トークンにハッシュを設定し、平文を削除する
+pub async fn set_token_hash(pool: &PgPool, id: Uuid, token_hash: &str) -> sqlx::Result<u64> {
+    let result = query("UPDATE tokens SET token_hash = $2, token = NULL WHERE id = $1")
+        .bind(id)
+        .bind(token_hash)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// 指定日時より前に有効期限が切れたトークンを削除する
+pub async fn delete_expired_tokens(pool: &PgPool, before: DateTime<Utc>) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM tokens WHERE expires_at < $1")
+        .bind(before)
         .execute(pool)
         .await?;
     Ok(result.rows_affected())
@@ -128,19 +620,25 @@ pub async fn delete_token(pool: &PgPool, token: &str) -> sqlx::Result<u64> {
 
 // 新規: 指定ユーザの、有効期限内のトークンを全件取得する関数
 pub async fn get_user_tokens(pool: &PgPool, user_id: Uuid) -> sqlx::Result<Vec<TokenRecord>> {
-    let recs = query_as!(
-        TokenRecord,
-        r#"SELECT token, user_id, expires_at, created_at
-           FROM tokens
-           WHERE user_id = $1 AND expires_at > $2"#,
-        user_id,
-        Utc::now()
-    )
+    let recs = query_as::<_, TokenRecord>(&format!(
+        "SELECT {} FROM tokens WHERE user_id = $1 AND expires_at > $2",
+        TOKEN_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(Utc::now())
     .fetch_all(pool)
     .await?;
     Ok(recs)
 }
 
+// ユーザの全トークン (有効期限切れを含む) の累計転送量
+pub async fn get_user_bytes_used(pool: &PgPool, user_id: Uuid) -> sqlx::Result<i64> {
+    sqlx::query_scalar("SELECT COALESCE(SUM(bytes_used), 0)::BIGINT FROM tokens WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+}
+
 // --- User Points ---
 pub async fn get_user_points(
     pool: &PgPool,
@@ -192,3 +690,1574 @@ pub async fn update_user_points(
     .await?;
     Ok(result.rows_affected())
 }
+
+// ポイントを増減し、履歴に記録する
+// 残高が負になる場合は更新せず None を返す
+pub async fn adjust_user_points(
+    pool: &PgPool,
+    rec: &PointLedgerRecord,
+) -> sqlx::Result<Option<PointLedgerRecord>> {
+    let mut tx = pool.begin().await?;
+    let balance: Option<i32> = sqlx::query_scalar(
+        r#"UPDATE user_points SET points = points + $2, updated_at = $3
+           WHERE user_id = $1 AND points + $2 >= 0
+           RETURNING points"#,
+    )
+    .bind(rec.user_id)
+    .bind(rec.amount)
+    .bind(rec.created_at)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(balance) = balance else {
+        return Ok(None);
+    };
+    let rec = query_as::<_, PointLedgerRecord>(
+        r#"INSERT INTO point_ledger (id, user_id, amount, balance_after, reason, created_by, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7)
+           RETURNING id, user_id, amount, balance_after, reason, created_by, created_at"#,
+    )
+    .bind(rec.id)
+    .bind(rec.user_id)
+    .bind(rec.amount)
+    .bind(balance)
+    .bind(&rec.reason)
+    .bind(rec.created_by)
+    .bind(rec.created_at)
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(Some(rec))
+}
+
+// プロキシの利用でユーザの残高からポイントを消費し、履歴に記録する
+// 残高が不足する場合は何もせず None を返す。消費後の残高を返す
+pub async fn charge_user_points(
+    pool: &PgPool,
+    user_id: Uuid,
+    amount: i32,
+    reason: &str,
+    now: DateTime<Utc>,
+) -> sqlx::Result<Option<i32>> {
+    let mut tx = pool.begin().await?;
+    let balance: Option<i32> = sqlx::query_scalar(
+        r#"UPDATE user_points SET points = points - $2, updated_at = $3
+           WHERE user_id = $1 AND points >= $2
+           RETURNING points"#,
+    )
+    .bind(user_id)
+    .bind(amount)
+    .bind(now)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(balance) = balance else {
+        return Ok(None);
+    };
+    query(
+        r#"INSERT INTO point_ledger (id, user_id, amount, balance_after, reason, created_by, created_at)
+           VALUES ($1, $2, $3, $4, $5, NULL, $6)"#,
+    )
+    .bind(Uuid::now_v7())
+    .bind(user_id)
+    .bind(-amount)
+    .bind(balance)
+    .bind(reason)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(Some(balance))
+}
+
+// ポイントの増減履歴を新しい順に取得する
+pub async fn get_point_ledger(
+    pool: &PgPool,
+    user_id: Uuid,
+    limit: i64,
+) -> sqlx::Result<Vec<PointLedgerRecord>> {
+    let recs = query_as::<_, PointLedgerRecord>(
+        r#"SELECT id, user_id, amount, balance_after, reason, created_by, created_at
+           FROM point_ledger WHERE user_id = $1
+           ORDER BY created_at DESC, id DESC
+           LIMIT $2"#,
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// --- API Keys ---
+// キー本体はハッシュ (key_hash) でのみ保存する
+pub async fn create_api_key(
+    pool: &PgPool,
+    rec: &ApiKeyRecord,
+    key_hash: &str,
+) -> sqlx::Result<u64> {
+    let result = query(
+        r#"INSERT INTO api_keys (id, user_id, key_hash, key_prefix, name, scopes, created_at, expires_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+    )
+    .bind(rec.id)
+    .bind(rec.user_id)
+    .bind(key_hash)
+    .bind(&rec.key_prefix)
+    .bind(&rec.name)
+    .bind(&rec.scopes)
+    .bind(rec.created_at)
+    .bind(rec.expires_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// キーのハッシュからレコードを取得する
+pub async fn get_api_key(pool: &PgPool, key_hash: &str) -> sqlx::Result<Option<ApiKeyRecord>> {
+    let rec = query_as::<_, ApiKeyRecord>(
+        r#"SELECT id, user_id, key_prefix, name, scopes, created_at, expires_at, last_used_at
+           FROM api_keys WHERE key_hash = $1"#,
+    )
+    .bind(key_hash)
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
+
+// 指定ユーザのAPIキーを全件取得する
+pub async fn get_user_api_keys(pool: &PgPool, user_id: Uuid) -> sqlx::Result<Vec<ApiKeyRecord>> {
+    let recs = query_as::<_, ApiKeyRecord>(
+        r#"SELECT id, user_id, key_prefix, name, scopes, created_at, expires_at, last_used_at
+           FROM api_keys WHERE user_id = $1 ORDER BY created_at"#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// 指定ユーザが所有するAPIキーを削除する
+pub async fn delete_user_api_key(pool: &PgPool, user_id: Uuid, id: Uuid) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM api_keys WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// APIキーの最終利用日時を更新する
+pub async fn touch_api_key(pool: &PgPool, id: Uuid, used_at: DateTime<Utc>) -> sqlx::Result<u64> {
+    let result = query("UPDATE api_keys SET last_used_at = $2 WHERE id = $1")
+        .bind(id)
+        .bind(used_at)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// --- Login Sessions ---
+// リフレッシュトークン本体はハッシュ (token_hash) でのみ保存する
+pub async fn create_refresh_token(
+    pool: &PgPool,
+    rec: &RefreshTokenRecord,
+    token_hash: &str,
+) -> sqlx::Result<u64> {
+    let result = query(
+        r#"INSERT INTO refresh_tokens (id, user_id, token_hash, created_at, expires_at, revoked_at)
+           VALUES ($1, $2, $3, $4, $5, $6)"#,
+    )
+    .bind(rec.id)
+    .bind(rec.user_id)
+    .bind(token_hash)
+    .bind(rec.created_at)
+    .bind(rec.expires_at)
+    .bind(rec.revoked_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// リフレッシュトークンのハッシュからレコードを取得する
+pub async fn get_refresh_token(
+    pool: &PgPool,
+    token_hash: &str,
+) -> sqlx::Result<Option<RefreshTokenRecord>> {
+    let rec = query_as::<_, RefreshTokenRecord>(
+        r#"SELECT id, user_id, created_at, expires_at, revoked_at
+           FROM refresh_tokens WHERE token_hash = $1"#,
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
+
+// 未失効のリフレッシュトークンを失効させる (既に失効済みの場合は 0 を返す)
+pub async fn revoke_refresh_token(
+    pool: &PgPool,
+    id: Uuid,
+    revoked_at: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result =
+        query("UPDATE refresh_tokens SET revoked_at = $2 WHERE id = $1 AND revoked_at IS NULL")
+            .bind(id)
+            .bind(revoked_at)
+            .execute(pool)
+            .await?;
+    Ok(result.rows_affected())
+}
+
+// 指定ユーザの未失効のリフレッシュトークンを全て失効させる
+pub async fn revoke_user_refresh_tokens(
+    pool: &PgPool,
+    user_id: Uuid,
+    revoked_at: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query(
+        "UPDATE refresh_tokens SET revoked_at = $2 WHERE user_id = $1 AND revoked_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(revoked_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// 指定日時より前に有効期限が切れたリフレッシュトークンを削除する
+pub async fn delete_expired_refresh_tokens(
+    pool: &PgPool,
+    before: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM refresh_tokens WHERE expires_at < $1")
+        .bind(before)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// アクセストークン (JWT) を失効リストに追加する
+pub async fn create_revoked_access_token(
+    pool: &PgPool,
+    jti: Uuid,
+    user_id: Uuid,
+    expires_at: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query(
+        r#"INSERT INTO revoked_access_tokens (jti, user_id, expires_at) VALUES ($1, $2, $3)
+           ON CONFLICT (jti) DO NOTHING"#,
+    )
+    .bind(jti)
+    .bind(user_id)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// 有効期限内の失効済みアクセストークンを取得する (JWT ID, 有効期限)
+pub async fn get_revoked_access_tokens(
+    pool: &PgPool,
+    now: DateTime<Utc>,
+) -> sqlx::Result<Vec<(Uuid, DateTime<Utc>)>> {
+    let recs = query_as("SELECT jti, expires_at FROM revoked_access_tokens WHERE expires_at > $1")
+        .bind(now)
+        .fetch_all(pool)
+        .await?;
+    Ok(recs)
+}
+
+// 有効期限切れのアクセストークンを失効リストから削除する
+pub async fn delete_expired_revoked_access_tokens(
+    pool: &PgPool,
+    now: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM revoked_access_tokens WHERE expires_at <= $1")
+        .bind(now)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// ユーザの「全セッションからログアウト」の実行日時を記録する
+pub async fn set_user_sessions_revoked_at(
+    pool: &PgPool,
+    user_id: Uuid,
+    revoked_at: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query("UPDATE users SET sessions_revoked_at = $2 WHERE id = $1")
+        .bind(user_id)
+        .bind(revoked_at)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// 指定日時以降に「全セッションからログアウト」したユーザを取得する (ユーザID, 実行日時)
+pub async fn get_users_sessions_revoked_since(
+    pool: &PgPool,
+    since: DateTime<Utc>,
+) -> sqlx::Result<Vec<(Uuid, DateTime<Utc>)>> {
+    let recs = query_as("SELECT id, sessions_revoked_at FROM users WHERE sessions_revoked_at > $1")
+        .bind(since)
+        .fetch_all(pool)
+        .await?;
+    Ok(recs)
+}
+
+// --- Password Reset Tokens ---
+// リセットトークン本体はハッシュ (token_hash) でのみ保存する
+pub async fn create_password_reset_token(
+    pool: &PgPool,
+    rec: &PasswordResetTokenRecord,
+    token_hash: &str,
+) -> sqlx::Result<u64> {
+    let result = query(
+        r#"INSERT INTO password_reset_tokens (id, user_id, token_hash, created_by, created_at, expires_at)
+           VALUES ($1, $2, $3, $4, $5, $6)"#,
+    )
+    .bind(rec.id)
+    .bind(rec.user_id)
+    .bind(token_hash)
+    .bind(rec.created_by)
+    .bind(rec.created_at)
+    .bind(rec.expires_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// リセットトークンのハッシュからレコードを取得する
+pub async fn get_password_reset_token(
+    pool: &PgPool,
+    token_hash: &str,
+) -> sqlx::Result<Option<PasswordResetTokenRecord>> {
+    let rec = query_as::<_, PasswordResetTokenRecord>(
+        r#"SELECT id, user_id, created_by, created_at, expires_at, used_at
+           FROM password_reset_tokens WHERE token_hash = $1"#,
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
+
+// 未使用のリセットトークンを使用済みにする (既に使用済みの場合は 0 を返す)
+pub async fn use_password_reset_token(
+    pool: &PgPool,
+    id: Uuid,
+    used_at: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result =
+        query("UPDATE password_reset_tokens SET used_at = $2 WHERE id = $1 AND used_at IS NULL")
+            .bind(id)
+            .bind(used_at)
+            .execute(pool)
+            .await?;
+    Ok(result.rows_affected())
+}
+
+// 指定ユーザの未使用のリセットトークンを削除する
+pub async fn delete_user_password_reset_tokens(pool: &PgPool, user_id: Uuid) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// 指定日時より前に有効期限が切れたリセットトークンを削除する
+pub async fn delete_expired_password_reset_tokens(
+    pool: &PgPool,
+    before: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM password_reset_tokens WHERE expires_at < $1")
+        .bind(before)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// --- Invite Codes ---
+// 招待コード本体はハッシュ (code_hash) でのみ保存する
+pub async fn create_invite_code(
+    pool: &PgPool,
+    rec: &InviteCodeRecord,
+    code_hash: &str,
+) -> sqlx::Result<u64> {
+    let result = query(
+        r#"INSERT INTO invite_codes (id, code_hash, code_prefix, points, note, created_by, created_at, expires_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+    )
+    .bind(rec.id)
+    .bind(code_hash)
+    .bind(&rec.code_prefix)
+    .bind(rec.points)
+    .bind(&rec.note)
+    .bind(rec.created_by)
+    .bind(rec.created_at)
+    .bind(rec.expires_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// 招待コードの一覧を新しい順に取得する (include_used が false の場合は未使用のみ)
+pub async fn get_invite_codes(
+    pool: &PgPool,
+    include_used: bool,
+    limit: i64,
+) -> sqlx::Result<Vec<InviteCodeRecord>> {
+    let recs = query_as::<_, InviteCodeRecord>(
+        r#"SELECT id, code_prefix, points, note, created_by, created_at, expires_at, used_by, used_at
+           FROM invite_codes WHERE $1 OR used_at IS NULL
+           ORDER BY created_at DESC, id DESC
+           LIMIT $2"#,
+    )
+    .bind(include_used)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// 招待コードのハッシュからレコードを取得する
+pub async fn get_invite_code_by_hash(
+    pool: &PgPool,
+    code_hash: &str,
+) -> sqlx::Result<Option<InviteCodeRecord>> {
+    let rec = query_as::<_, InviteCodeRecord>(
+        r#"SELECT id, code_prefix, points, note, created_by, created_at, expires_at, used_by, used_at
+           FROM invite_codes WHERE code_hash = $1"#,
+    )
+    .bind(code_hash)
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
+
+pub async fn delete_invite_code(pool: &PgPool, id: Uuid) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM invite_codes WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// 指定日時より前に有効期限が切れた未使用の招待コードを削除する
+pub async fn delete_expired_invite_codes(
+    pool: &PgPool,
+    before: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM invite_codes WHERE used_at IS NULL AND expires_at < $1")
+        .bind(before)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// ユーザ登録: ユーザ・初期ポイント・ポイント履歴の作成と招待コードの使用を1トランザクションで行う
+// 招待コードが使用済み・期限切れになっていた場合は何も登録せず false を返す
+pub async fn register_user(
+    pool: &PgPool,
+    user: &UserRecord,
+    points: i32,
+    invite_id: Option<Uuid>,
+    ledger_reason: &str,
+) -> sqlx::Result<bool> {
+    let mut tx = pool.begin().await?;
+    query(
+        "INSERT INTO users (id, username, password_hash, role, created_at) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(user.id)
+    .bind(&user.username)
+    .bind(&user.password_hash)
+    .bind(user.role)
+    .bind(user.created_at)
+    .execute(&mut *tx)
+    .await?;
+    if let Some(invite_id) = invite_id {
+        let used = query(
+            r#"UPDATE invite_codes SET used_by = $2, used_at = $3
+               WHERE id = $1 AND used_at IS NULL AND (expires_at IS NULL OR expires_at > $3)"#,
+        )
+        .bind(invite_id)
+        .bind(user.id)
+        .bind(user.created_at)
+        .execute(&mut *tx)
+        .await?;
+        if used.rows_affected() == 0 {
+            return Ok(false);
+        }
+    }
+    query("INSERT INTO user_points (user_id, points, updated_at) VALUES ($1, $2, $3)")
+        .bind(user.id)
+        .bind(points)
+        .bind(user.created_at)
+        .execute(&mut *tx)
+        .await?;
+    if points > 0 {
+        query(
+            r#"INSERT INTO point_ledger (id, user_id, amount, balance_after, reason, created_by, created_at)
+               VALUES ($1, $2, $3, $3, $4, NULL, $5)"#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(user.id)
+        .bind(points)
+        .bind(ledger_reason)
+        .bind(user.created_at)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+// --- TOTP ---
+// 未有効化のTOTP設定を登録する (有効化済みの場合は更新せず 0 を返す)
+pub async fn upsert_pending_totp(
+    pool: &PgPool,
+    user_id: Uuid,
+    secret: &str,
+    created_at: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query(
+        r#"INSERT INTO user_totp (user_id, secret, created_at) VALUES ($1, $2, $3)
+           ON CONFLICT (user_id) DO UPDATE
+           SET secret = EXCLUDED.secret, created_at = EXCLUDED.created_at, last_used_step = NULL
+           WHERE user_totp.enabled_at IS NULL"#,
+    )
+    .bind(user_id)
+    .bind(secret)
+    .bind(created_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn get_user_totp(pool: &PgPool, user_id: Uuid) -> sqlx::Result<Option<UserTotpRecord>> {
+    let rec = query_as::<_, UserTotpRecord>(
+        r#"SELECT user_id, secret, created_at, enabled_at, last_used_step
+           FROM user_totp WHERE user_id = $1"#,
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
+
+// TOTPを有効化し、リカバリーコードを登録する
+pub async fn enable_user_totp(
+    pool: &PgPool,
+    user_id: Uuid,
+    enabled_at: DateTime<Utc>,
+    step: i64,
+    recovery_code_hashes: &[String],
+) -> sqlx::Result<u64> {
+    let mut tx = pool.begin().await?;
+    let result = query(
+        r#"UPDATE user_totp SET enabled_at = $2, last_used_step = $3
+           WHERE user_id = $1 AND enabled_at IS NULL"#,
+    )
+    .bind(user_id)
+    .bind(enabled_at)
+    .bind(step)
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(0);
+    }
+    insert_recovery_codes(&mut tx, user_id, recovery_code_hashes).await?;
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
+
+// 使用したタイムステップを記録する (同じか古いステップの場合は 0 を返し、コードの再利用を防ぐ)
+pub async fn set_totp_last_used_step(pool: &PgPool, user_id: Uuid, step: i64) -> sqlx::Result<u64> {
+    let result = query(
+        r#"UPDATE user_totp SET last_used_step = $2
+           WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)"#,
+    )
+    .bind(user_id)
+    .bind(step)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// TOTP設定とリカバリーコードを削除する
+pub async fn delete_user_totp(pool: &PgPool, user_id: Uuid) -> sqlx::Result<u64> {
+    let mut tx = pool.begin().await?;
+    query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    let result = query("DELETE FROM user_totp WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
+
+async fn insert_recovery_codes(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+    code_hashes: &[String],
+) -> sqlx::Result<()> {
+    for code_hash in code_hashes {
+        query("INSERT INTO totp_recovery_codes (id, user_id, code_hash) VALUES ($1, $2, $3)")
+            .bind(Uuid::now_v7())
+            .bind(user_id)
+            .bind(code_hash)
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(())
+}
+
+// リカバリーコードを全て新しいものに置き換える
+pub async fn replace_recovery_codes(
+    pool: &PgPool,
+    user_id: Uuid,
+    code_hashes: &[String],
+) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+    query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    insert_recovery_codes(&mut tx, user_id, code_hashes).await?;
+    tx.commit().await
+}
+
+// 未使用のリカバリーコードを使用済みにする (該当なしの場合は 0 を返す)
+pub async fn use_recovery_code(
+    pool: &PgPool,
+    user_id: Uuid,
+    code_hash: &str,
+    used_at: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query(
+        r#"UPDATE totp_recovery_codes SET used_at = $3
+           WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL"#,
+    )
+    .bind(user_id)
+    .bind(code_hash)
+    .bind(used_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// 未使用のリカバリーコード数
+pub async fn count_unused_recovery_codes(pool: &PgPool, user_id: Uuid) -> sqlx::Result<i64> {
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM totp_recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+}
+
+// --- Organizations ---
+// 組織を作成し、作成者をオーナーとして登録する (残高は0から開始)
+pub async fn create_organization(
+    pool: &PgPool,
+    id: Uuid,
+    name: &str,
+    owner_id: Uuid,
+    created_at: DateTime<Utc>,
+) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+    query("INSERT INTO organizations (id, name, created_by, created_at) VALUES ($1, $2, $3, $4)")
+        .bind(id)
+        .bind(name)
+        .bind(owner_id)
+        .bind(created_at)
+        .execute(&mut *tx)
+        .await?;
+    query(
+        "INSERT INTO organization_members (organization_id, user_id, role, joined_at) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(id)
+    .bind(owner_id)
+    .bind(OrgRole::Owner)
+    .bind(created_at)
+    .execute(&mut *tx)
+    .await?;
+    query(
+        "INSERT INTO organization_points (organization_id, points, updated_at) VALUES ($1, 0, $2)",
+    )
+    .bind(id)
+    .bind(created_at)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// ユーザが所属する組織を全件取得する
+pub async fn get_user_memberships(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> sqlx::Result<Vec<MembershipRecord>> {
+    let recs = query_as::<_, MembershipRecord>(
+        r#"SELECT o.id AS organization_id, o.name, o.created_at, m.role, p.points
+           FROM organization_members m
+           JOIN organizations o ON o.id = m.organization_id
+           JOIN organization_points p ON p.organization_id = o.id
+           WHERE m.user_id = $1
+           ORDER BY o.name"#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// ユーザの指定組織への所属を取得する (メンバーでない場合は None)
+pub async fn get_membership(
+    pool: &PgPool,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> sqlx::Result<Option<MembershipRecord>> {
+    let rec = query_as::<_, MembershipRecord>(
+        r#"SELECT o.id AS organization_id, o.name, o.created_at, m.role, p.points
+           FROM organization_members m
+           JOIN organizations o ON o.id = m.organization_id
+           JOIN organization_points p ON p.organization_id = o.id
+           WHERE m.organization_id = $1 AND m.user_id = $2"#,
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
+
+// 組織を削除し、削除された組織トークンのIDを返す (残高も破棄される)
+pub async fn delete_organization(pool: &PgPool, organization_id: Uuid) -> sqlx::Result<Vec<Uuid>> {
+    let mut tx = pool.begin().await?;
+    let ids = sqlx::query_scalar("DELETE FROM tokens WHERE organization_id = $1 RETURNING id")
+        .bind(organization_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    query("DELETE FROM organizations WHERE id = $1")
+        .bind(organization_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(ids)
+}
+
+pub async fn get_organization_members(
+    pool: &PgPool,
+    organization_id: Uuid,
+) -> sqlx::Result<Vec<OrganizationMemberRecord>> {
+    let recs = query_as::<_, OrganizationMemberRecord>(
+        r#"SELECT m.user_id, u.username, m.role, m.joined_at
+           FROM organization_members m JOIN users u ON u.id = m.user_id
+           WHERE m.organization_id = $1
+           ORDER BY u.username"#,
+    )
+    .bind(organization_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// メンバーを追加する (既にメンバーの場合は一意制約違反となる)
+pub async fn add_organization_member(
+    pool: &PgPool,
+    organization_id: Uuid,
+    user_id: Uuid,
+    role: OrgRole,
+    joined_at: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query(
+        "INSERT INTO organization_members (organization_id, user_id, role, joined_at) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .bind(role)
+    .bind(joined_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// メンバーのロールを変更する
+// 最後のオーナーを降格させる場合は変更せず 0 を返す
+pub async fn set_organization_member_role(
+    pool: &PgPool,
+    organization_id: Uuid,
+    user_id: Uuid,
+    role: OrgRole,
+) -> sqlx::Result<u64> {
+    let mut tx = pool.begin().await?;
+    lock_organization(&mut tx, organization_id).await?;
+    let result = query(
+        r#"UPDATE organization_members SET role = $3
+           WHERE organization_id = $1 AND user_id = $2
+             AND (role <> 'owner' OR $3 = 'owner'::org_role
+                  OR (SELECT COUNT(*) FROM organization_members
+                      WHERE organization_id = $1 AND role = 'owner') > 1)"#,
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .bind(role)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
+
+// メンバーを組織から外し、そのメンバーが発行した組織トークンを削除する
+// 最後のオーナーの場合は何もせず None を返す。削除したトークンのIDを返す
+pub async fn remove_organization_member(
+    pool: &PgPool,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> sqlx::Result<Option<Vec<Uuid>>> {
+    let mut tx = pool.begin().await?;
+    lock_organization(&mut tx, organization_id).await?;
+    let removed = query(
+        r#"DELETE FROM organization_members
+           WHERE organization_id = $1 AND user_id = $2
+             AND (role <> 'owner'
+                  OR (SELECT COUNT(*) FROM organization_members
+                      WHERE organization_id = $1 AND role = 'owner') > 1)"#,
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    if removed.rows_affected() == 0 {
+        return Ok(None);
+    }
+    let ids = delete_member_organization_tokens(&mut tx, organization_id, user_id).await?;
+    tx.commit().await?;
+    Ok(Some(ids))
+}
+
+// オーナー数の確認と変更の間に他の降格・脱退が割り込まないよう、組織の行をロックする
+// (READ COMMITTED では別の行を更新する文同士はブロックし合わず、双方が2人のオーナーを数えてしまう)
+// ロックの取得後に実行した文は、先に完了したトランザクションの変更を含めて数え直す
+async fn lock_organization(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    organization_id: Uuid,
+) -> sqlx::Result<()> {
+    query("SELECT id FROM organizations WHERE id = $1 FOR UPDATE")
+        .bind(organization_id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+// 指定メンバーが発行した組織トークンを削除し、削除したトークンのIDを返す
+pub async fn revoke_member_organization_tokens(
+    pool: &PgPool,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> sqlx::Result<Vec<Uuid>> {
+    let mut tx = pool.begin().await?;
+    let ids = delete_member_organization_tokens(&mut tx, organization_id, user_id).await?;
+    tx.commit().await?;
+    Ok(ids)
+}
+
+async fn delete_member_organization_tokens(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> sqlx::Result<Vec<Uuid>> {
+    sqlx::query_scalar(
+        "DELETE FROM tokens WHERE organization_id = $1 AND user_id = $2 RETURNING id",
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .fetch_all(&mut **tx)
+    .await
+}
+
+// 組織の有効期限内のトークンを全件取得する
+pub async fn get_organization_tokens(
+    pool: &PgPool,
+    organization_id: Uuid,
+) -> sqlx::Result<Vec<TokenRecord>> {
+    let recs = query_as::<_, TokenRecord>(&format!(
+        "SELECT {} FROM tokens WHERE organization_id = $1 AND expires_at > $2 ORDER BY created_at",
+        TOKEN_COLUMNS
+    ))
+    .bind(organization_id)
+    .bind(Utc::now())
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// 組織トークンをIDで取得する
+pub async fn get_organization_token(
+    pool: &PgPool,
+    organization_id: Uuid,
+    token_id: Uuid,
+) -> sqlx::Result<Option<TokenRecord>> {
+    let rec = query_as::<_, TokenRecord>(&format!(
+        "SELECT {} FROM tokens WHERE organization_id = $1 AND id = $2",
+        TOKEN_COLUMNS
+    ))
+    .bind(organization_id)
+    .bind(token_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
+
+// メンバーごとの組織トークンの利用状況 (トークン数・累計転送量・消費ポイント)
+pub async fn get_organization_member_usage(
+    pool: &PgPool,
+    organization_id: Uuid,
+    now: DateTime<Utc>,
+) -> sqlx::Result<Vec<MemberUsageRecord>> {
+    let recs = query_as::<_, MemberUsageRecord>(
+        r#"SELECT m.user_id, u.username, m.role,
+                  COUNT(t.id) FILTER (WHERE t.expires_at > $2) AS active_tokens,
+                  COALESCE(SUM(t.bytes_used), 0)::BIGINT AS bytes_used,
+                  COALESCE(SUM(t.points_used), 0)::BIGINT AS points_used
+           FROM organization_members m
+           JOIN users u ON u.id = m.user_id
+           LEFT JOIN tokens t ON t.organization_id = m.organization_id AND t.user_id = m.user_id
+           WHERE m.organization_id = $1
+           GROUP BY m.user_id, u.username, m.role
+           ORDER BY u.username"#,
+    )
+    .bind(organization_id)
+    .bind(now)
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// 組織の残高を取得する
+pub async fn get_organization_points(
+    pool: &PgPool,
+    organization_id: Uuid,
+) -> sqlx::Result<Option<i32>> {
+    sqlx::query_scalar("SELECT points FROM organization_points WHERE organization_id = $1")
+        .bind(organization_id)
+        .fetch_optional(pool)
+        .await
+}
+
+// 組織の残高からポイントを消費し、トークンの消費ポイントに加算する
+// 残高が不足する場合は何もせず None を返す
+pub async fn charge_organization_points(
+    pool: &PgPool,
+    organization_id: Uuid,
+    token_id: Uuid,
+    amount: i32,
+    now: DateTime<Utc>,
+) -> sqlx::Result<Option<i32>> {
+    let mut tx = pool.begin().await?;
+    let balance: Option<i32> = sqlx::query_scalar(
+        r#"UPDATE organization_points SET points = points - $2, updated_at = $3
+           WHERE organization_id = $1 AND points >= $2
+           RETURNING points"#,
+    )
+    .bind(organization_id)
+    .bind(amount)
+    .bind(now)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if balance.is_none() {
+        return Ok(None);
+    }
+    query("UPDATE tokens SET points_used = points_used + $2 WHERE id = $1")
+        .bind(token_id)
+        .bind(i64::from(amount))
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(balance)
+}
+
+// ユーザの残高から組織の残高へポイントを移す (双方の履歴を記録する)
+// ユーザの残高が不足する場合は何もせず None を返す。移動後の (ユーザ残高, 組織残高) を返す
+pub async fn transfer_points_to_organization(
+    pool: &PgPool,
+    user_id: Uuid,
+    organization_id: Uuid,
+    amount: i32,
+    user_reason: &str,
+    organization_reason: &str,
+    now: DateTime<Utc>,
+) -> sqlx::Result<Option<(i32, i32)>> {
+    let mut tx = pool.begin().await?;
+    let user_balance: Option<i32> = sqlx::query_scalar(
+        r#"UPDATE user_points SET points = points - $2, updated_at = $3
+           WHERE user_id = $1 AND points >= $2
+           RETURNING points"#,
+    )
+    .bind(user_id)
+    .bind(amount)
+    .bind(now)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(user_balance) = user_balance else {
+        return Ok(None);
+    };
+    let org_balance: i32 = sqlx::query_scalar(
+        r#"UPDATE organization_points SET points = points + $2, updated_at = $3
+           WHERE organization_id = $1
+           RETURNING points"#,
+    )
+    .bind(organization_id)
+    .bind(amount)
+    .bind(now)
+    .fetch_one(&mut *tx)
+    .await?;
+    query(
+        r#"INSERT INTO point_ledger (id, user_id, amount, balance_after, reason, created_by, created_at)
+           VALUES ($1, $2, $3, $4, $5, $2, $6)"#,
+    )
+    .bind(Uuid::now_v7())
+    .bind(user_id)
+    .bind(-amount)
+    .bind(user_balance)
+    .bind(user_reason)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+    query(
+        r#"INSERT INTO organization_point_ledger (id, organization_id, amount, balance_after, reason, created_by, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+    )
+    .bind(Uuid::now_v7())
+    .bind(organization_id)
+    .bind(amount)
+    .bind(org_balance)
+    .bind(organization_reason)
+    .bind(user_id)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(Some((user_balance, org_balance)))
+}
+
+// 組織のポイント増減履歴を新しい順に取得する
+pub async fn get_organization_ledger(
+    pool: &PgPool,
+    organization_id: Uuid,
+    limit: i64,
+) -> sqlx::Result<Vec<OrganizationLedgerRecord>> {
+    let recs = query_as::<_, OrganizationLedgerRecord>(
+        r#"SELECT id, organization_id, amount, balance_after, reason, created_by, created_at
+           FROM organization_point_ledger WHERE organization_id = $1
+           ORDER BY created_at DESC, id DESC
+           LIMIT $2"#,
+    )
+    .bind(organization_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// --- Usage Sessions ---
+// SOCKS5セッションの履歴を記録し、日次集計と請求先の月次集計に加算する
+pub async fn create_usage_session(pool: &PgPool, rec: &UsageSessionRecord) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+    query(
+        r#"INSERT INTO usage_sessions (id, user_id, token_id, token_prefix, organization_id, agent_id,
+               agent_country, target_host, target_port, client_ip, started_at, ended_at, bytes_up,
+               bytes_down, outcome, points_charged)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)"#,
+    )
+    .bind(rec.id)
+    .bind(rec.user_id)
+    .bind(rec.token_id)
+    .bind(&rec.token_prefix)
+    .bind(rec.organization_id)
+    .bind(&rec.agent_id)
+    .bind(&rec.agent_country)
+    .bind(&rec.target_host)
+    .bind(rec.target_port)
+    .bind(&rec.client_ip)
+    .bind(rec.started_at)
+    .bind(rec.ended_at)
+    .bind(rec.bytes_up)
+    .bind(rec.bytes_down)
+    .bind(rec.outcome)
+    .bind(rec.points_charged)
+    .execute(&mut *tx)
+    .await?;
+    query(
+        r#"INSERT INTO usage_daily (user_id, day, sessions, bytes_up, bytes_down, points_charged)
+           VALUES ($1, ($2 AT TIME ZONE 'UTC')::date, 1, $3, $4, $5)
+           ON CONFLICT (user_id, day) DO UPDATE SET
+               sessions = usage_daily.sessions + 1,
+               bytes_up = usage_daily.bytes_up + EXCLUDED.bytes_up,
+               bytes_down = usage_daily.bytes_down + EXCLUDED.bytes_down,
+               points_charged = usage_daily.points_charged + EXCLUDED.points_charged"#,
+    )
+    .bind(rec.user_id)
+    .bind(rec.started_at)
+    .bind(rec.bytes_up)
+    .bind(rec.bytes_down)
+    .bind(i64::from(rec.points_charged))
+    .execute(&mut *tx)
+    .await?;
+    query(
+        r#"INSERT INTO usage_monthly (account_id, is_organization, month, country, sessions,
+               bytes_up, bytes_down, points_charged)
+           VALUES ($1, $2, date_trunc('month', $3 AT TIME ZONE 'UTC')::date, $4, 1, $5, $6, $7)
+           ON CONFLICT (account_id, is_organization, month, country) DO UPDATE SET
+               sessions = usage_monthly.sessions + 1,
+               bytes_up = usage_monthly.bytes_up + EXCLUDED.bytes_up,
+               bytes_down = usage_monthly.bytes_down + EXCLUDED.bytes_down,
+               points_charged = usage_monthly.points_charged + EXCLUDED.points_charged"#,
+    )
+    .bind(rec.organization_id.or(rec.user_id))
+    .bind(rec.organization_id.is_some())
+    .bind(rec.started_at)
+    .bind(rec.agent_country.as_deref().unwrap_or(""))
+    .bind(rec.bytes_up)
+    .bind(rec.bytes_down)
+    .bind(i64::from(rec.points_charged))
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// ユーザのセッション履歴を新しい順に取得する (from <= started_at < to)
+// 該当する全件数も返す
+pub async fn get_user_usage_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    token_id: Option<Uuid>,
+    limit: i64,
+    offset: i64,
+) -> sqlx::Result<(Vec<UsageSessionRecord>, i64)> {
+    const FILTER: &str = "s.user_id = $1 AND ($2::timestamptz IS NULL OR s.started_at >= $2) \
+        AND ($3::timestamptz IS NULL OR s.started_at < $3) AND ($4::uuid IS NULL OR s.token_id = $4)";
+    let recs = query_as::<_, UsageSessionRecord>(&format!(
+        r#"SELECT {} FROM usage_sessions s WHERE {}
+           ORDER BY s.started_at DESC, s.id DESC
+           LIMIT $5 OFFSET $6"#,
+        USAGE_SESSION_COLUMNS, FILTER
+    ))
+    .bind(user_id)
+    .bind(from)
+    .bind(to)
+    .bind(token_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+    let total: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM usage_sessions s WHERE {}",
+        FILTER
+    ))
+    .bind(user_id)
+    .bind(from)
+    .bind(to)
+    .bind(token_id)
+    .fetch_one(pool)
+    .await?;
+    Ok((recs, total))
+}
+
+// エクスポート用にセッション履歴を古い順に取得する (from <= started_at < to)
+// user_id / organization_id を指定した場合はそのユーザ・組織のセッションのみに絞り込む
+pub async fn get_usage_sessions_for_export(
+    pool: &PgPool,
+    user_id: Option<Uuid>,
+    organization_id: Option<Uuid>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: i64,
+) -> sqlx::Result<Vec<UsageExportRecord>> {
+    let recs = query_as::<_, UsageExportRecord>(&format!(
+        r#"SELECT {}, u.username
+           FROM usage_sessions s LEFT JOIN users u ON u.id = s.user_id
+           WHERE s.started_at >= $1 AND s.started_at < $2
+             AND ($3::uuid IS NULL OR s.user_id = $3)
+             AND ($4::uuid IS NULL OR s.organization_id = $4)
+           ORDER BY s.started_at, s.id
+           LIMIT $5"#,
+        USAGE_SESSION_COLUMNS
+    ))
+    .bind(from)
+    .bind(to)
+    .bind(user_id)
+    .bind(organization_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// 指定月の請求先ごと・国ごとの利用量を取得する
+// account を指定した場合は (請求先ID, 組織か否か) に一致する請求先のみ
+pub async fn get_usage_monthly(
+    pool: &PgPool,
+    month: NaiveDate,
+    account: Option<(Uuid, bool)>,
+) -> sqlx::Result<Vec<UsageMonthlyRecord>> {
+    let recs = query_as::<_, UsageMonthlyRecord>(
+        r#"SELECT m.account_id, m.is_organization,
+                  CASE WHEN m.is_organization THEN o.name ELSE u.username END AS account_name,
+                  m.country, m.sessions, m.bytes_up, m.bytes_down, m.points_charged
+           FROM usage_monthly m
+           LEFT JOIN users u ON NOT m.is_organization AND u.id = m.account_id
+           LEFT JOIN organizations o ON m.is_organization AND o.id = m.account_id
+           WHERE m.month = $1
+             AND ($2::uuid IS NULL OR (m.account_id = $2 AND m.is_organization = $3))
+           ORDER BY m.is_organization, account_name, m.account_id, m.country"#,
+    )
+    .bind(month)
+    .bind(account.map(|(id, _)| id))
+    .bind(account.is_some_and(|(_, is_org)| is_org))
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// ユーザの日次利用量を日付順に取得する (from <= day <= to)
+pub async fn get_user_usage_daily(
+    pool: &PgPool,
+    user_id: Uuid,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> sqlx::Result<Vec<UsageDailyRecord>> {
+    let recs = query_as::<_, UsageDailyRecord>(
+        r#"SELECT day, sessions, bytes_up, bytes_down, points_charged
+           FROM usage_daily WHERE user_id = $1 AND day >= $2 AND day <= $3
+           ORDER BY day"#,
+    )
+    .bind(user_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// 指定日時より前に開始したセッション履歴を削除する (日次集計は残す)
+pub async fn delete_usage_sessions_before(
+    pool: &PgPool,
+    before: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM usage_sessions WHERE started_at < $1")
+        .bind(before)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// --- Agents ---
+// エージェントの接続を記録する (初回接続時はエージェントを登録)
+// メタデータが前回の記録から変化した場合は変更履歴に、出口IPが変化した場合はIPの変更履歴に追加する
+pub async fn record_agent_connected(
+    pool: &PgPool,
+    agent_id: &str,
+    connection_id: Uuid,
+    remote_addr: Option<&str>,
+    metadata: &AgentMetadata,
+    now: DateTime<Utc>,
+) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+    insert_agent_ip_change(&mut tx, agent_id, connection_id, metadata, now).await?;
+    query(
+        r#"INSERT INTO agents (id, first_seen_at, last_seen_at, connected_at, current_connection_id,
+               connection_count, last_ip, metadata)
+           VALUES ($1, $2, $2, $2, $3, 1, $4, $5)
+           ON CONFLICT (id) DO UPDATE SET
+               last_seen_at = EXCLUDED.last_seen_at,
+               connected_at = EXCLUDED.connected_at,
+               current_connection_id = EXCLUDED.current_connection_id,
+               connection_count = agents.connection_count + 1,
+               last_ip = EXCLUDED.last_ip,
+               metadata = EXCLUDED.metadata"#,
+    )
+    .bind(agent_id)
+    .bind(now)
+    .bind(connection_id)
+    .bind(&metadata.ip)
+    .bind(Json(metadata))
+    .execute(&mut *tx)
+    .await?;
+    query(
+        r#"INSERT INTO agent_connections (id, agent_id, remote_addr, connected_at)
+           VALUES ($1, $2, $3, $4)"#,
+    )
+    .bind(connection_id)
+    .bind(agent_id)
+    .bind(remote_addr)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+    insert_agent_metadata_history(&mut tx, agent_id, metadata, now).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// 接続中のエージェントから受信したメタデータの更新を記録する
+pub async fn update_agent_metadata(
+    pool: &PgPool,
+    agent_id: &str,
+    connection_id: Uuid,
+    metadata: &AgentMetadata,
+    now: DateTime<Utc>,
+) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+    insert_agent_ip_change(&mut tx, agent_id, connection_id, metadata, now).await?;
+    query("UPDATE agents SET last_ip = $2, metadata = $3 WHERE id = $1")
+        .bind(agent_id)
+        .bind(&metadata.ip)
+        .bind(Json(metadata))
+        .execute(&mut *tx)
+        .await?;
+    insert_agent_metadata_history(&mut tx, agent_id, metadata, now).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// 最後に記録された出口IPと異なる場合はIPの変更履歴に追加する (agents の更新前に実行する)
+async fn insert_agent_ip_change(
+    conn: &mut sqlx::PgConnection,
+    agent_id: &str,
+    connection_id: Uuid,
+    metadata: &AgentMetadata,
+    now: DateTime<Utc>,
+) -> sqlx::Result<()> {
+    query(
+        r#"INSERT INTO agent_ip_changes (id, agent_id, connection_id, changed_at, old_ip, new_ip,
+               country_code)
+           SELECT $1, id, $3, $4, last_ip, $5, $6 FROM agents
+           WHERE id = $2 AND last_ip <> $5 AND $5 <> ''"#,
+    )
+    .bind(Uuid::now_v7())
+    .bind(agent_id)
+    .bind(connection_id)
+    .bind(now)
+    .bind(&metadata.ip)
+    .bind(metadata.verified_country_code())
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// メタデータが最新の変更履歴と異なる場合は変更履歴に追加する
+async fn insert_agent_metadata_history(
+    conn: &mut sqlx::PgConnection,
+    agent_id: &str,
+    metadata: &AgentMetadata,
+    now: DateTime<Utc>,
+) -> sqlx::Result<()> {
+    query(
+        r#"INSERT INTO agent_metadata_history (id, agent_id, recorded_at, metadata)
+           SELECT $1, $2, $3, $4
+           WHERE (SELECT metadata FROM agent_metadata_history WHERE agent_id = $2
+                  ORDER BY recorded_at DESC, id DESC LIMIT 1) IS DISTINCT FROM $4::jsonb"#,
+    )
+    .bind(Uuid::now_v7())
+    .bind(agent_id)
+    .bind(now)
+    .bind(Json(metadata))
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// エージェントの出口IPの変更履歴を新しい順に取得する
+pub async fn get_agent_ip_changes(
+    pool: &PgPool,
+    agent_id: &str,
+    limit: i64,
+) -> sqlx::Result<Vec<AgentIpChangeRecord>> {
+    let recs = query_as::<_, AgentIpChangeRecord>(
+        r#"SELECT id, agent_id, connection_id, changed_at, old_ip, new_ip, country_code
+           FROM agent_ip_changes WHERE agent_id = $1
+           ORDER BY changed_at DESC, id DESC LIMIT $2"#,
+    )
+    .bind(agent_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// エージェントの切断を記録し、接続時間を累計に加算する
+// 同じエージェントIDで既に再接続している場合、現在の接続状態は変更しない
+pub async fn record_agent_disconnected(
+    pool: &PgPool,
+    agent_id: &str,
+    connection_id: Uuid,
+    now: DateTime<Utc>,
+) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+    let connected_at: Option<DateTime<Utc>> = sqlx::query_scalar(
+        r#"UPDATE agent_connections SET disconnected_at = $2
+           WHERE id = $1 AND disconnected_at IS NULL
+           RETURNING connected_at"#,
+    )
+    .bind(connection_id)
+    .bind(now)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let uptime = connected_at.map_or(0, |t| (now - t).num_seconds().max(0));
+    query(
+        r#"UPDATE agents SET
+               last_seen_at = $3,
+               total_uptime_seconds = total_uptime_seconds + $4,
+               connected_at = CASE WHEN current_connection_id = $2 THEN NULL ELSE connected_at END,
+               current_connection_id = CASE WHEN current_connection_id = $2 THEN NULL
+                                            ELSE current_connection_id END
+           WHERE id = $1"#,
+    )
+    .bind(agent_id)
+    .bind(connection_id)
+    .bind(now)
+    .bind(uptime)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// 前回の停止時に接続中のまま残った接続を切断済みにする (起動時に実行)
+// 切断日時は不明のため最終確認日時とし、接続時間は累計に加算しない
+pub async fn close_stale_agent_connections(pool: &PgPool) -> sqlx::Result<u64> {
+    let mut tx = pool.begin().await?;
+    query(
+        r#"UPDATE agent_connections c SET disconnected_at = a.last_seen_at
+           FROM agents a
+           WHERE c.agent_id = a.id AND c.disconnected_at IS NULL"#,
+    )
+    .execute(&mut *tx)
+    .await?;
+    let result = query(
+        r#"UPDATE agents SET connected_at = NULL, current_connection_id = NULL
+           WHERE connected_at IS NOT NULL"#,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
+
+// 永続化されたエージェントの一覧を取得する
+pub async fn get_agents(pool: &PgPool) -> sqlx::Result<Vec<AgentRecord>> {
+    let recs =
+        query_as::<_, AgentRecord>(&format!("SELECT {} FROM agents ORDER BY id", AGENT_COLUMNS))
+            .fetch_all(pool)
+            .await?;
+    Ok(recs)
+}
+
+pub async fn get_agent(pool: &PgPool, agent_id: &str) -> sqlx::Result<Option<AgentRecord>> {
+    let rec = query_as::<_, AgentRecord>(&format!(
+        "SELECT {} FROM agents WHERE id = $1",
+        AGENT_COLUMNS
+    ))
+    .bind(agent_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
+
+// エージェントを無効化 (disabled_at を設定) または有効化 (None) する。更新件数を返す
+pub async fn set_agent_disabled(
+    pool: &PgPool,
+    agent_id: &str,
+    disabled_at: Option<DateTime<Utc>>,
+) -> sqlx::Result<u64> {
+    let result = query("UPDATE agents SET disabled_at = $2 WHERE id = $1")
+        .bind(agent_id)
+        .bind(disabled_at)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// エージェントをBAN (banned_at と理由を設定) または解除 (None) する。更新件数を返す
+pub async fn set_agent_banned(
+    pool: &PgPool,
+    agent_id: &str,
+    banned_at: Option<DateTime<Utc>>,
+    reason: Option<&str>,
+) -> sqlx::Result<u64> {
+    let result = query("UPDATE agents SET banned_at = $2, ban_reason = $3 WHERE id = $1")
+        .bind(agent_id)
+        .bind(banned_at)
+        .bind(reason)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// エージェントのラベルを置き換える。更新件数を返す
+pub async fn set_agent_labels(
+    pool: &PgPool,
+    agent_id: &str,
+    labels: &[String],
+) -> sqlx::Result<u64> {
+    let result = query("UPDATE agents SET labels = $2 WHERE id = $1")
+        .bind(agent_id)
+        .bind(labels)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// --- Maintenance Runs ---
+pub async fn create_maintenance_run(
+    pool: &PgPool,
+    rec: &MaintenanceRunRecord,
+) -> sqlx::Result<u64> {
+    let result = query(
+        r#"INSERT INTO maintenance_runs (id, started_at, finished_at, success, deleted, error)
+           VALUES ($1, $2, $3, $4, $5, $6)"#,
+    )
+    .bind(rec.id)
+    .bind(rec.started_at)
+    .bind(rec.finished_at)
+    .bind(rec.success)
+    .bind(&rec.deleted)
+    .bind(&rec.error)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// 新しい順にメンテナンス実行履歴を取得する
+pub async fn get_maintenance_runs(
+    pool: &PgPool,
+    limit: i64,
+) -> sqlx::Result<Vec<MaintenanceRunRecord>> {
+    let recs = query_as::<_, MaintenanceRunRecord>(
+        r#"SELECT id, started_at, finished_at, success, deleted, error
+           FROM maintenance_runs ORDER BY started_at DESC LIMIT $1"#,
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// 指定日時より前のメンテナンス実行履歴を削除する
+pub async fn delete_maintenance_runs_before(
+    pool: &PgPool,
+    before: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = query("DELETE FROM maintenance_runs WHERE started_at < $1")
+        .bind(before)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,159 @@
+use dashmap::DashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{watch, OnceCell};
+use uuid::Uuid;
+
+// トークンごとのアクティブなSOCKS5セッション情報
+struct TokenSessions {
+    // 同時接続中のセッション数
+    count: usize,
+    // トークン失効時に true を通知するチャネル
+    revoked: watch::Sender<bool>,
+    // トークンの累計転送量 (DBの値 + 接続中の全セッションの転送量)
+    // 同時接続中のセッションで転送量の上限を共有するために使用
+    bytes_used: Arc<AtomicU64>,
+    // bytes_used にDBの値を読み込み済みか
+    loaded: Arc<OnceCell<()>>,
+}
+
+// トークンIDとアクティブなSOCKS5セッションのレジストリ（スレッドセーフ）
+// 同時セッション数の制限と、トークン失効時のセッション強制終了で使用
+#[derive(Clone, Default)]
+pub(crate) struct SessionRegistry {
+    inner: Arc<DashMap<Uuid, TokenSessions>>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // セッション数の上限を超えない場合のみセッションを登録
+    pub fn acquire(&self, token_id: Uuid, max_sessions: Option<i32>) -> Option<SessionGuard> {
+        let mut entry = self.inner.entry(token_id).or_insert_with(|| TokenSessions {
+            count: 0,
+            revoked: watch::channel(false).0,
+            bytes_used: Arc::new(AtomicU64::new(0)),
+            loaded: Arc::new(OnceCell::new()),
+        });
+        if let Some(max) = max_sessions {
+            if entry.count >= max as usize {
+                return None;
+            }
+        }
+        entry.count += 1;
+        Some(SessionGuard {
+            registry: self.clone(),
+            token_id,
+            revoked: entry.revoked.subscribe(),
+            bytes_used: entry.bytes_used.clone(),
+            loaded: entry.loaded.clone(),
+        })
+    }
+
+    // トークンを失効させ、そのトークンを使用中の全セッションに終了を通知
+    // 戻り値は通知したセッション数
+    pub fn revoke(&self, token_id: Uuid) -> usize {
+        match self.inner.get(&token_id) {
+            Some(entry) => {
+                entry.revoked.send_replace(true);
+                entry.count
+            }
+            None => 0,
+        }
+    }
+
+    // トークンの同時接続中セッション数
+    pub fn active_sessions(&self, token_id: Uuid) -> usize {
+        self.inner.get(&token_id).map(|e| e.count).unwrap_or(0)
+    }
+}
+
+// セッションの登録を表すガード
+// Drop時にセッション数を減算する
+pub(crate) struct SessionGuard {
+    registry: SessionRegistry,
+    token_id: Uuid,
+    revoked: watch::Receiver<bool>,
+    bytes_used: Arc<AtomicU64>,
+    loaded: Arc<OnceCell<()>>,
+}
+
+impl SessionGuard {
+    // 同じトークンの全セッションで共有する累計転送量カウンタ
+    pub fn bytes_used(&self) -> Arc<AtomicU64> {
+        self.bytes_used.clone()
+    }
+
+    // DBに記録済みの累計転送量を共有カウンタに読み込み、トークンの累計転送量を返す
+    // 読み込むのはレジストリに登録した最初のセッションのみで、他のセッションは読み込みの完了を待つ
+    // 登録はそれ以前の全セッションが転送量をDBに記録して終了した後のため、読み込む値は最新
+    pub async fn load_bytes_used<F, Fut, E>(&self, load: F) -> Result<u64, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<i64, E>>,
+    {
+        self.loaded
+            .get_or_try_init(|| async {
+                let used = load().await?;
+                self.bytes_used
+                    .fetch_add(used.max(0) as u64, Ordering::SeqCst);
+                Ok(())
+            })
+            .await?;
+        Ok(self.total_bytes_used())
+    }
+
+    // トークンの累計転送量 (接続中の他のセッションの転送量を含む)
+    pub fn total_bytes_used(&self) -> u64 {
+        self.bytes_used.load(Ordering::SeqCst)
+    }
+
+    // トークンが失効するまで待機する
+    pub async fn revoked(&mut self) {
+        // 送信側はガードが存在する間レジストリに保持されるため、Errにはならない
+        let _ = self.revoked.wait_for(|revoked| *revoked).await;
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.registry
+            .inner
+            .remove_if_mut(&self.token_id, |_, sessions| {
+                sessions.count -= 1;
+                sessions.count == 0
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bytes_used_is_shared_between_sessions() {
+        let registry = SessionRegistry::new();
+        let token_id = Uuid::now_v7();
+        let first = registry.acquire(token_id, None).unwrap();
+        let second = registry.acquire(token_id, None).unwrap();
+        // DBの値を読み込むのは最初の1回のみ
+        let loaded = first.load_bytes_used(|| async { Ok::<_, ()>(100) });
+        assert_eq!(loaded.await, Ok(100));
+        let loaded = second.load_bytes_used(|| async { Ok::<_, ()>(999) });
+        assert_eq!(loaded.await, Ok(100));
+        first.bytes_used().fetch_add(50, Ordering::SeqCst);
+        second.bytes_used().fetch_add(30, Ordering::SeqCst);
+        assert_eq!(first.total_bytes_used(), 180);
+        assert_eq!(second.total_bytes_used(), 180);
+        drop(first);
+        drop(second);
+        // 全セッションの終了後はDBに記録された値を読み込み直す
+        let third = registry.acquire(token_id, None).unwrap();
+        let loaded = third.load_bytes_used(|| async { Ok::<_, ()>(180) });
+        assert_eq!(loaded.await, Ok(180));
+        assert_eq!(registry.active_sessions(token_id), 1);
+    }
+}
@@ -1,5 +1,15 @@
 use crate::agent::{AgentConnection, AgentMap};
-use crate::repository::{get_token, get_user_points, update_user_points};
+use crate::events::{Event, EventBus};
+use crate::health::{weighted_index, AgentHealth, HealthError, HealthEvent};
+use crate::lockout::Lockouts;
+use crate::repository::{
+    add_token_bytes_used, charge_organization_points, charge_user_points, create_usage_session,
+    get_organization_points, get_token, get_token_bytes_used, get_user_by_id, get_user_points,
+    SessionOutcome, TokenRecord, UsageSessionRecord,
+};
+use crate::session::{SessionGuard, SessionRegistry};
+use crate::token::hash::{redact_secret, TokenHasher};
+use crate::token::restriction::TokenRestrictions;
 use crate::websocket::send_message;
 use crate::{PendingMap, PendingSender, Settings};
 use anyhow::{anyhow, Result};
@@ -7,57 +17,77 @@ use base64::{engine::general_purpose::STANDARD, Engine as _};
 use chrono::Utc;
 use common::Payload;
 // Required for send_message -> lock.send
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use rand::{rng, Rng};
 use sqlx::PgPool;
 use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::io::{split, AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{mpsc, oneshot};
-use tokio::time::{timeout, Duration};
+use tokio::time::{timeout, Duration, Instant};
 use uuid::Uuid;
 
 // Define SOCKS5 response constants
 const SOCKS5_GENERAL_FAILURE: [u8; 10] = [0x05, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
 const SOCKS5_CONNECT_SUCCESS: [u8; 10] = [0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+const SOCKS5_CONNECTION_NOT_ALLOWED: [u8; 10] = [0x05, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+// ユーザー名/パスワード認証 (RFC 1929) の応答
+const SOCKS5_AUTH_SUCCESS: [u8; 2] = [0x01, 0x00];
+const SOCKS5_AUTH_FAILURE: [u8; 2] = [0x01, 0x01];
 
 // Helper to select agent based on username pattern
+// トークンの制限で許可され、無効化・ドレイン中でないエージェントのみを選択対象とする
+// 国・ラベル・ランダムの選択では隔離中・容量に空きのないエージェントを除き、健全性スコアと負荷で重み付けする
 fn choose_agent(
     agents: &AgentMap,
+    health: &AgentHealth,
     username: Option<&str>,
+    restrictions: &TokenRestrictions,
 ) -> Option<(String, Arc<AgentConnection>)> {
+    let allowed = |id: &str, conn: &AgentConnection| {
+        conn.is_available()
+            && restrictions.allows_agent(id, conn.metadata().verified_country_code())
+    };
+    let pick = |matches: &dyn Fn(&AgentConnection) -> bool| {
+        let now = Utc::now().timestamp();
+        let mut candidates = Vec::new();
+        let mut weights = Vec::new();
+        for e in agents.iter() {
+            if !matches(e.value()) || !allowed(e.key(), e.value()) {
+                continue;
+            }
+            // 容量に空きのないエージェントは除外し、負荷に応じて重み付けする
+            if !e.value().has_capacity() {
+                continue;
+            }
+            if let Some(weight) = health.selection_weight(e.key(), now) {
+                candidates.push((e.key().clone(), e.value().clone()));
+                weights.push(weight * e.value().load_factor());
+            }
+        }
+        let idx = weighted_index(&weights, rng().random())?;
+        Some(candidates.swap_remove(idx))
+    };
     match username {
+        // エージェントIDを指定した場合は隔離中でも選択する
         Some(agent_id) if agent_id.starts_with("agent_") => agents
             .get(agent_id)
+            .filter(|e| allowed(e.key(), e.value()))
             .map(|e| (agent_id.to_string(), e.value().clone())),
-        Some("all") | None => {
-            let vec: Vec<_> = agents.iter().collect();
-            if vec.is_empty() {
-                return None;
-            }
-            let mut rng = rng();
-            let idx = rng.random_range(0..vec.len());
-            let e = &vec[idx];
-            Some((e.key().clone(), e.value().clone()))
+        Some("all") | None => pick(&|_| true),
+        // 管理者が付与したラベルで選択 (例: label_residential)
+        Some(label) if label.starts_with("label_") => {
+            let label = &label["label_".len()..];
+            pick(&|conn| conn.has_label(label))
         }
         Some(country) if country.starts_with("country_") => {
-            let codes: Vec<&str> = country[8..]
-                .as_bytes()
+            let codes: Vec<&str> = country.as_bytes()[8..]
                 .chunks(2)
                 .filter_map(|c| std::str::from_utf8(c).ok())
                 .collect();
-            let filtered: Vec<_> = agents
-                .iter()
-                .filter(|e| codes.contains(&e.value().metadata.country_code.as_str()))
-                .collect();
-            if filtered.is_empty() {
-                return None;
-            }
-            let mut rng = rng();
-            let idx = rng.random_range(0..filtered.len());
-            let e = &filtered[idx];
-            Some((e.key().clone(), e.value().clone()))
+            pick(&|conn| codes.contains(&conn.metadata().verified_country_code()))
         }
         _ => None,
     }
@@ -106,10 +136,8 @@ async fn perform_socks5_handshake(
     stream.read_exact(&mut password).await?;
     let password = String::from_utf8(password)?;
 
-    // 認証成功応答
-    stream.write_all(&[0x01, 0x00]).await?;
-
     // 今後の処理のため、usernameとpasswordを返す
+    // 認証結果の応答はトークン検証後に呼び出し元が送信する
     Ok((Some(username), password))
 }
 
@@ -167,6 +195,7 @@ async fn read_socks5_connect_request(
 }
 
 // 指定のエージェントに connect-request を送信し、タイムアウト付きで connect-response を待つ
+#[allow(clippy::too_many_arguments)]
 async fn send_connect_request_and_wait_for_response(
     agent_id: &str,
     agent_conn: &AgentConnection,
@@ -175,6 +204,7 @@ async fn send_connect_request_and_wait_for_response(
     target_port: u16,
     address_type: u8,
     pending: PendingMap,
+    health: &AgentHealth,
     settings: &Settings,
 ) -> Result<Payload> {
     // oneshotチャネルで応答を待機
@@ -196,24 +226,45 @@ async fn send_connect_request_and_wait_for_response(
         "[{}] Sent connect-request (Agent: {}, Target: {}:{})",
         request_id, agent_id, target_addr, target_port
     );
+    // 応答の結果と接続時間をエージェントの健全性として記録する
+    let started = Instant::now();
+    let record = |event| health.record(agent_id, event, Utc::now().timestamp());
     // WebSocket経由でリクエストを送信
-    send_message(&agent_conn.sink, payload).await?;
+    if let Err(e) = send_message(&agent_conn.sink, payload).await {
+        pending.lock().await.remove(request_id);
+        record(HealthEvent::Failed(HealthError::SendFailed));
+        return Err(e);
+    }
     // タイムアウト付きで応答を待機
     match timeout(Duration::from_secs(settings.connect_timeout_seconds), rx).await {
         Ok(Ok(response)) => {
-            if let Payload::ConnectResponse { success, .. } = &response {
-                info!(
-                    "[{}] Received connect-response from agent {}. Success: {}",
-                    request_id, agent_id, success
-                );
+            match &response {
+                Payload::ConnectResponse { success, .. } => {
+                    info!(
+                        "[{}] Received connect-response from agent {}. Success: {}",
+                        request_id, agent_id, success
+                    );
+                    record(if *success {
+                        HealthEvent::Connected {
+                            latency_ms: started.elapsed().as_millis() as u64,
+                        }
+                    } else {
+                        HealthEvent::Failed(HealthError::Refused)
+                    });
+                }
+                _ => record(HealthEvent::Failed(HealthError::Protocol)),
             }
             Ok(response)
         }
-        Ok(Err(e)) => Err(anyhow!("Oneshot receiver error: {:?}", e)),
+        Ok(Err(e)) => {
+            record(HealthEvent::Failed(HealthError::Disconnected));
+            Err(anyhow!("Oneshot receiver error: {:?}", e))
+        }
         Err(_) => {
             let mut pending_lock = pending.lock().await;
             // PendingMapから該当リクエストIDのエントリを削除
             pending_lock.remove(request_id);
+            record(HealthEvent::Failed(HealthError::Timeout));
             Err(anyhow!(
                 "Timeout waiting for connect response from agent {}",
                 agent_id
@@ -222,14 +273,30 @@ async fn send_connect_request_and_wait_for_response(
     }
 }
 
+// データ転送の結果 (転送バイト数)
+struct TransferStats {
+    // クライアント -> エージェント
+    bytes_up: u64,
+    // エージェント -> クライアント
+    bytes_down: u64,
+    // 転送の終了理由 (正常終了/トークン失効/転送量上限)
+    outcome: SessionOutcome,
+    // エージェントが転送の失敗を報告したか
+    agent_failed: bool,
+}
+
 // クライアントとエージェント間の双方向データ転送を行う
+// byte_limit を指定した場合、同じトークンの全セッションの合計転送量が上限を超えた時点で転送を打ち切る
+// トークンが失効した場合も転送を打ち切る
 async fn handle_socks5_data_transfer(
     stream: TcpStream,
     client_addr: SocketAddr,
     request_id: String,
     agent_conn: Arc<AgentConnection>,
     pending: PendingMap,
-) -> Result<()> {
+    byte_limit: Option<u64>,
+    session: &mut SessionGuard,
+) -> TransferStats {
     info!("[{}][{}] Data transfer started", request_id, client_addr);
     // TcpStreamをreaderとwriterに分割
     let (mut reader, mut writer) = split(stream);
@@ -240,11 +307,23 @@ async fn handle_socks5_data_transfer(
         // PendingMapにmpsc senderを登録
         pending_lock.insert(request_id.clone(), PendingSender::Mpsc(tx));
     }
+    // 転送量カウンタ (上り/下り)
+    let bytes_up = Arc::new(AtomicU64::new(0));
+    let bytes_down = Arc::new(AtomicU64::new(0));
+    // トークンの累計転送量 (同時接続中の他のセッションと共有)
+    let token_bytes = session.bytes_used();
+    let limit_exceeded = {
+        let token_bytes = token_bytes.clone();
+        move || byte_limit.is_some_and(|limit| token_bytes.load(Ordering::SeqCst) > limit)
+    };
     // クライアントからのデータ受信タスク（エージェントへの送信）
     let agent_conn_clone = agent_conn.clone();
     let req_id_clone = request_id.clone();
     let client_addr_clone = client_addr;
-    let send_task = tokio::spawn(async move {
+    let bytes_up_clone = bytes_up.clone();
+    let token_bytes_up = token_bytes.clone();
+    let send_limit_exceeded = limit_exceeded.clone();
+    let mut send_task = tokio::spawn(async move {
         let mut chunk_id: u32 = 1;
         let mut buf = [0u8; 1024];
         loop {
@@ -285,6 +364,14 @@ async fn handle_socks5_data_transfer(
                         break;
                     }
                     chunk_id += 1;
+                    bytes_up_clone.fetch_add(n as u64, Ordering::Relaxed);
+                    token_bytes_up.fetch_add(n as u64, Ordering::SeqCst);
+                    agent_conn_clone
+                        .bytes_up
+                        .fetch_add(n as u64, Ordering::Relaxed);
+                    if send_limit_exceeded() {
+                        break;
+                    }
                 }
                 Err(e) => {
                     error!(
@@ -298,7 +385,13 @@ async fn handle_socks5_data_transfer(
     });
     // エージェントからのデータ受信タスク（クライアントへの書き込み）
     let request_id_clone = request_id.clone();
-    let write_task = tokio::spawn(async move {
+    let bytes_down_clone = bytes_down.clone();
+    let token_bytes_down = token_bytes.clone();
+    let write_limit_exceeded = limit_exceeded.clone();
+    let agent_failed = Arc::new(AtomicBool::new(false));
+    let agent_failed_clone = agent_failed.clone();
+    let agent_conn_write = agent_conn.clone();
+    let mut write_task = tokio::spawn(async move {
         while let Some(payload) = rx.recv().await {
             match payload {
                 Payload::DataResponseChunk {
@@ -324,6 +417,14 @@ async fn handle_socks5_data_transfer(
                                     decoded.len()
                                 );
                             }
+                            bytes_down_clone.fetch_add(decoded.len() as u64, Ordering::Relaxed);
+                            token_bytes_down.fetch_add(decoded.len() as u64, Ordering::SeqCst);
+                            agent_conn_write
+                                .bytes_down
+                                .fetch_add(decoded.len() as u64, Ordering::Relaxed);
+                            if write_limit_exceeded() {
+                                break;
+                            }
                         }
                         Err(e) => {
                             error!("Failed to decode base64 data: {:?}", e);
@@ -341,6 +442,7 @@ async fn handle_socks5_data_transfer(
                             request_id_clone, client_addr_clone
                         );
                     } else {
+                        agent_failed_clone.store(true, Ordering::Relaxed);
                         error!(
                             "[{}][{}] Transfer failed: {}",
                             request_id_clone,
@@ -361,27 +463,78 @@ async fn handle_socks5_data_transfer(
         }
     });
     // 送受信タスクの完了を待機
-    let _ = tokio::join!(send_task, write_task);
+    // 転送量の上限到達またはトークン失効時は残りのタスクを中断し、エージェント側の接続も閉じる
+    let (send_done, write_done, revoked) = tokio::select! {
+        _ = &mut send_task => (true, false, false),
+        _ = &mut write_task => (false, true, false),
+        _ = session.revoked() => (false, false, true),
+    };
+    let outcome = if revoked {
+        SessionOutcome::Revoked
+    } else if limit_exceeded() {
+        SessionOutcome::LimitExceeded
+    } else {
+        SessionOutcome::Completed
+    };
+    if outcome != SessionOutcome::Completed {
+        info!(
+            "[{}][{}] {}. Terminating transfer",
+            request_id,
+            client_addr,
+            if revoked {
+                "Token revoked"
+            } else {
+                "Byte limit exceeded"
+            }
+        );
+        send_task.abort();
+        write_task.abort();
+        let payload = Payload::ClientDisconnect {
+            request_id: request_id.clone(),
+        };
+        if let Err(e) = send_message(&agent_conn.sink, payload).await {
+            error!(
+                "[{}][{}] Failed to send client disconnect: {:?}",
+                request_id, client_addr, e
+            );
+        }
+    }
+    // select! で完了済みのタスクは再度 await できないため、残りのタスクのみ待機する
+    if !send_done {
+        let _ = send_task.await;
+    }
+    if !write_done {
+        let _ = write_task.await;
+    }
     info!("[{}][{}] Data transfer terminated", request_id, client_addr);
     // 転送終了後、PendingMapからエントリを削除
     let request_id_clone = request_id.clone();
     let mut pending_lock = pending.lock().await;
     pending_lock.remove(&request_id_clone);
-    Ok(())
+    TransferStats {
+        bytes_up: bytes_up.load(Ordering::Relaxed),
+        bytes_down: bytes_down.load(Ordering::Relaxed),
+        outcome,
+        agent_failed: agent_failed.load(Ordering::Relaxed),
+    }
 }
 
 // SOCKS5サーバーメイン処理
 // 1. ハンドシェイク処理
-// 2. 接続要求解析
-// 3. 利用可能なエージェントの選択
-// 4. エージェントへの接続要求転送
-// 5. 双方向データ転送の開始
+// 2. トークン認証
+// 3. 認証後のセッション処理と利用履歴の記録
+#[allow(clippy::too_many_arguments)]
 async fn handle_socks5_connection(
     pool: PgPool,
     mut stream: TcpStream,
     client_addr: SocketAddr,
     agents: Arc<AgentMap>,
     pending: PendingMap,
+    sessions: SessionRegistry,
+    token_hasher: TokenHasher,
+    lockouts: Lockouts,
+    agent_health: AgentHealth,
+    events: EventBus,
     settings: Arc<Settings>,
 ) {
     info!("[Control] New SOCKS5 connection from {}", client_addr);
@@ -394,28 +547,182 @@ async fn handle_socks5_connection(
         }
     };
 
-    // token に紐づくレコード取得と有効期限チェック
-    let token_rec = match get_token(&pool, &token).await {
-        Ok(Some(rec)) if rec.expires_at > Utc::now() => rec,
-        _ => {
-            error!("Invalid or expired token: {}", token);
-            let _ = stream
-                .write_all(&[0x05, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
-                .await;
+    // token のハッシュに紐づくレコード取得と有効期限チェック
+    // 不正なトークンが続いた接続元IPは一時的にBANする
+    let now = Utc::now();
+    let token_rec = match get_token(&pool, &token_hasher.hash(&token)).await {
+        Ok(Some(rec)) if rec.expires_at > now => rec,
+        Ok(_) => {
+            warn!(
+                "Invalid or expired token {} from {}",
+                redact_secret(&token),
+                client_addr
+            );
+            if let Some(seconds) = lockouts
+                .socks_ips
+                .record_failure(client_addr.ip(), now.timestamp())
+            {
+                warn!(
+                    "Banning SOCKS5 client {} for {} seconds after repeated invalid tokens",
+                    client_addr.ip(),
+                    seconds
+                );
+            }
+            let _ = stream.write_all(&SOCKS5_AUTH_FAILURE).await;
+            return;
+        }
+        Err(e) => {
+            error!("Failed to look up token from {}: {}", client_addr, e);
+            let _ = stream.write_all(&SOCKS5_AUTH_FAILURE).await;
+            return;
+        }
+    };
+    lockouts.socks_ips.record_success(&client_addr.ip());
+    // 停止中のアカウントのトークンは拒否
+    match get_user_by_id(&pool, token_rec.user_id).await {
+        Ok(Some(user)) if user.suspended_at.is_none() => {}
+        Ok(_) => {
+            warn!(
+                "Rejected token of suspended or deleted user {} from {}",
+                token_rec.user_id, client_addr
+            );
+            let _ = stream.write_all(&SOCKS5_AUTH_FAILURE).await;
             return;
         }
+        Err(e) => {
+            error!("Failed to look up user {}: {}", token_rec.user_id, e);
+            let _ = stream.write_all(&SOCKS5_AUTH_FAILURE).await;
+            return;
+        }
+    }
+    if let Err(e) = stream.write_all(&SOCKS5_AUTH_SUCCESS).await {
+        error!(
+            "Failed to send SOCKS5 auth response to {}: {:?}",
+            client_addr, e
+        );
+        return;
+    }
+    // 認証後のセッションを処理し、結果を利用履歴として記録する
+    let mut usage = UsageSessionRecord {
+        id: Uuid::now_v7(),
+        user_id: Some(token_rec.user_id),
+        token_id: token_rec.id,
+        token_prefix: token_rec.token_prefix.clone(),
+        organization_id: token_rec.organization_id,
+        agent_id: None,
+        agent_country: None,
+        target_host: None,
+        target_port: None,
+        client_ip: client_addr.ip().to_string(),
+        started_at: now,
+        ended_at: now,
+        bytes_up: 0,
+        bytes_down: 0,
+        outcome: SessionOutcome::Error,
+        points_charged: 0,
     };
+    usage.outcome = serve_authorized_session(
+        &pool,
+        stream,
+        client_addr,
+        username.as_deref(),
+        &token_rec,
+        &agents,
+        &agent_health,
+        &events,
+        pending,
+        &sessions,
+        &settings,
+        &mut usage,
+    )
+    .await;
+    usage.ended_at = Utc::now();
+    if let Err(e) = create_usage_session(&pool, &usage).await {
+        error!("Failed to record usage session {}: {}", usage.id, e);
+    }
+}
+
+// 認証済みの接続を処理する
+// 1. トークンの制限とポイント残高の確認
+// 2. 接続要求解析
+// 3. 利用可能なエージェントの選択
+// 4. エージェントへの接続要求転送
+// 5. 双方向データ転送とポイント消費
+// 処理内容は usage に記録し、セッションの結果を返す
+#[allow(clippy::too_many_arguments)]
+async fn serve_authorized_session(
+    pool: &PgPool,
+    mut stream: TcpStream,
+    client_addr: SocketAddr,
+    username: Option<&str>,
+    token_rec: &TokenRecord,
+    agents: &AgentMap,
+    health: &AgentHealth,
+    events: &EventBus,
+    pending: PendingMap,
+    sessions: &SessionRegistry,
+    settings: &Settings,
+    usage: &mut UsageSessionRecord,
+) -> SessionOutcome {
     let user_id = token_rec.user_id;
+    let restrictions = &token_rec.restrictions;
 
-    // ユーザのポイント取得
-    let pts_rec = match get_user_points(&pool, user_id).await {
-        Ok(Some(pr)) if pr.points >= 10 => pr,
+    // 接続元クライアントのIPアドレス制限
+    if !restrictions.allows_client(client_addr.ip()) {
+        error!(
+            "Client address {} is not allowed for token of user {}",
+            client_addr, user_id
+        );
+        let _ = stream.write_all(&SOCKS5_GENERAL_FAILURE).await;
+        return SessionOutcome::Rejected;
+    }
+    // 同時セッション数の上限チェック (ガードは接続終了時に解放される)
+    let mut session = match sessions.acquire(token_rec.id, restrictions.max_sessions) {
+        Some(guard) => guard,
+        None => {
+            error!("Too many concurrent sessions for token of user {}", user_id);
+            let _ = stream.write_all(&SOCKS5_GENERAL_FAILURE).await;
+            return SessionOutcome::Rejected;
+        }
+    };
+    // 総転送量の上限チェック (同時接続中の他のセッションの転送量を含む)
+    // 認証時に読み込んだ値は他のセッションの終了で古くなっている場合があるため、DBから読み込み直す
+    let bytes_used = match session
+        .load_bytes_used(|| get_token_bytes_used(pool, token_rec.id))
+        .await
+    {
+        Ok(bytes_used) => bytes_used,
+        Err(e) => {
+            error!("Failed to load bytes used for user {}: {}", user_id, e);
+            let _ = stream.write_all(&SOCKS5_GENERAL_FAILURE).await;
+            return SessionOutcome::Error;
+        }
+    };
+    let byte_limit = restrictions.max_bytes.map(|max| max.max(0) as u64);
+    if byte_limit.is_some_and(|max| bytes_used >= max) {
+        error!("Byte limit reached for token of user {}", user_id);
+        let _ = stream.write_all(&SOCKS5_GENERAL_FAILURE).await;
+        return SessionOutcome::Rejected;
+    }
+
+    // ポイント残高の確認 (組織所有のトークンは組織の共有残高を使用)
+    let balance = match token_rec.organization_id {
+        Some(org_id) => get_organization_points(pool, org_id).await,
+        None => get_user_points(pool, user_id)
+            .await
+            .map(|pr| pr.map(|pr| pr.points)),
+    };
+    let points = match balance {
+        Ok(Some(points)) if points >= 10 => points,
         _ => {
-            error!("Insufficient points for user {}", user_id);
+            error!(
+                "Insufficient points for user {} (organization: {:?})",
+                user_id, token_rec.organization_id
+            );
             let _ = stream
                 .write_all(&[0x05, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
                 .await;
-            return;
+            return SessionOutcome::Rejected;
         }
     };
 
@@ -428,19 +735,40 @@ async fn handle_socks5_connection(
                     "Failed to read SOCKS5 request header from {}: {:?}",
                     client_addr, e
                 );
-                return;
+                return SessionOutcome::Error;
             }
         };
+    usage.target_host = Some(target_addr.clone());
+    usage.target_port = Some(i32::from(target_port));
+
+    // 接続先の制限 (ポート/ドメイン)
+    if !restrictions.allows_target(&target_addr, target_port) {
+        error!(
+            "Target {}:{} is not allowed for token of user {}",
+            target_addr, target_port, user_id
+        );
+        let _ = stream.write_all(&SOCKS5_CONNECTION_NOT_ALLOWED).await;
+        return SessionOutcome::Rejected;
+    }
 
     // Agent selection based on username
-    let (agent_id, agent_conn) = match choose_agent(&agents, username.as_deref()) {
+    let (agent_id, agent_conn) = match choose_agent(agents, health, username, restrictions) {
         Some(sel) => sel,
         None => {
             error!("Invalid or no agent available for username: {:?}", username);
             let _ = stream.write_all(&SOCKS5_GENERAL_FAILURE).await;
-            return;
+            return SessionOutcome::NoAgent;
         }
     };
+    usage.agent_id = Some(agent_id.clone());
+    usage.agent_country = Some(agent_conn.metadata().verified_country_code().to_string());
+    // トンネル終了まで使用中として数える (ドレイン中のエージェントは全トンネル終了後に切断)
+    // エージェントが申告した容量を超える場合は拒否する
+    let Some(_tunnel) = agent_conn.try_start_tunnel() else {
+        error!("Agent {} is at capacity", agent_id);
+        let _ = stream.write_all(&SOCKS5_GENERAL_FAILURE).await;
+        return SessionOutcome::NoAgent;
+    };
 
     // リクエストIDを生成
     let request_id = Uuid::now_v7().to_string(); // Use now_v7() for current time
@@ -458,7 +786,8 @@ async fn handle_socks5_connection(
         target_port,
         atyp,
         pending.clone(),
-        &settings,
+        health,
+        settings,
     )
     .await
     {
@@ -471,7 +800,7 @@ async fn handle_socks5_connection(
             // SOCKS5エラー応答を送信
             let response = [0x05, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
             let _ = stream.write_all(&response).await;
-            return;
+            return SessionOutcome::ConnectFailed;
         }
     };
     // エージェントからの応答を確認
@@ -490,7 +819,7 @@ async fn handle_socks5_connection(
                     request_id, client_addr
                 );
             }
-            return;
+            return SessionOutcome::ConnectFailed;
         }
     } else {
         error!(
@@ -499,7 +828,7 @@ async fn handle_socks5_connection(
         );
         let response = [0x05, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
         let _ = stream.write_all(&response).await;
-        return;
+        return SessionOutcome::ConnectFailed;
     }
     // SOCKS5 接続成功応答をクライアントに送信
     if let Err(e) = stream.write_all(&SOCKS5_CONNECT_SUCCESS).await {
@@ -507,43 +836,121 @@ async fn handle_socks5_connection(
             "[{}] Failed to send SOCKS5 CONNECT response: {:?}",
             request_id, e
         );
-        return;
+        return SessionOutcome::Error;
     }
 
     info!(
         "[{}] Sent SOCKS5 CONNECT response to {}. Success: true",
         request_id, client_addr
     );
+    events.publish(Event::TunnelOpened {
+        tunnel_id: usage.id.to_string(),
+        user_id,
+        token_id: token_rec.id,
+        agent_id: agent_id.clone(),
+        target_host: target_addr.clone(),
+        target_port,
+    });
     // 双方向のデータ転送を開始
-    let transfer_result = handle_socks5_data_transfer(
+    let transfer_started = Instant::now();
+    let stats = handle_socks5_data_transfer(
         stream,
         client_addr,
         request_id.clone(),
         agent_conn,
-        pending.clone(),
+        pending,
+        byte_limit,
+        &mut session,
     )
     .await;
-    if let Err(e) = transfer_result {
-        error!("SOCKS5 data transfer error: {:?}, user: {}", e, user_id);
+    // 転送の結果とスループットをエージェントの健全性として記録する
+    let event = if stats.agent_failed {
+        HealthEvent::Failed(HealthError::Transfer)
     } else {
-        // 転送成功時に10ポイント消費
-        let new_points = pts_rec.points - 10;
-        if let Err(e) = update_user_points(&pool, user_id, new_points, Utc::now()).await {
-            error!("Failed to update user points for {}: {}", user_id, e);
-        } else {
-            info!(
-                "Consumed 10 points for user {}: {}->{}",
-                user_id, pts_rec.points, new_points
-            );
+        HealthEvent::Transferred {
+            bytes: stats.bytes_up + stats.bytes_down,
+            millis: transfer_started.elapsed().as_millis() as u64,
         }
+    };
+    health.record(&agent_id, event, Utc::now().timestamp());
+    usage.bytes_up = stats.bytes_up as i64;
+    usage.bytes_down = stats.bytes_down as i64;
+    // トークンの累計転送量を更新
+    let total = (stats.bytes_up + stats.bytes_down) as i64;
+    if let Err(e) = add_token_bytes_used(pool, token_rec.id, total).await {
+        error!("Failed to update bytes used for user {}: {}", user_id, e);
+    }
+    // 転送成功時に10ポイント消費
+    if let Some(org_id) = token_rec.organization_id {
+        match charge_organization_points(pool, org_id, token_rec.id, 10, Utc::now()).await {
+            Ok(Some(new_points)) => {
+                usage.points_charged = 10;
+                info!(
+                    "Consumed 10 points of organization {} for user {}: {}->{}",
+                    org_id, user_id, points, new_points
+                );
+                events.publish(Event::OrganizationBalanceChanged {
+                    organization_id: org_id,
+                    points: new_points,
+                });
+            }
+            Ok(None) => error!(
+                "Insufficient points to charge organization {} for user {}",
+                org_id, user_id
+            ),
+            Err(e) => error!("Failed to update points of organization {}: {}", org_id, e),
+        }
+    } else {
+        let reason = format!("Proxy usage (token {})", token_rec.token_prefix);
+        match charge_user_points(pool, user_id, 10, &reason, Utc::now()).await {
+            Ok(Some(new_points)) => {
+                usage.points_charged = 10;
+                info!(
+                    "Consumed 10 points for user {}: {}->{}",
+                    user_id, points, new_points
+                );
+                events.publish(Event::UserBalanceChanged {
+                    user_id,
+                    points: new_points,
+                });
+            }
+            Ok(None) => error!("Insufficient points to charge user {}", user_id),
+            Err(e) => error!("Failed to update user points for {}: {}", user_id, e),
+        }
+    }
+    events.publish(tunnel_closed_event(usage, user_id, stats.outcome));
+    stats.outcome
+}
+
+// トンネル終了のイベント (usage に記録した転送量と消費ポイントを含む)
+fn tunnel_closed_event(
+    usage: &UsageSessionRecord,
+    user_id: Uuid,
+    outcome: SessionOutcome,
+) -> Event {
+    Event::TunnelClosed {
+        tunnel_id: usage.id.to_string(),
+        user_id,
+        token_id: usage.token_id,
+        agent_id: usage.agent_id.clone().unwrap_or_default(),
+        outcome,
+        bytes_up: usage.bytes_up as u64,
+        bytes_down: usage.bytes_down as u64,
+        points_charged: usage.points_charged,
     }
 }
 
 // SOCKS5 サーバーを起動し、クライアントからの接続を待ち受ける（トークン認証付き）
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn run_socks5_server_with_token(
     pool: PgPool,
     agents: Arc<AgentMap>,
     pending: PendingMap,
+    sessions: SessionRegistry,
+    token_hasher: TokenHasher,
+    lockouts: Lockouts,
+    agent_health: AgentHealth,
+    events: EventBus,
     settings: Arc<Settings>,
 ) -> Result<()> {
     let addr = format!("{}:{}", settings.bind_address, settings.socks5_port);
@@ -551,8 +958,24 @@ pub(crate) async fn run_socks5_server_with_token(
     info!("[Control] SOCKS5 server started on {}", addr);
     loop {
         let (stream, client_addr) = listener.accept().await?;
+        // BAN中の接続元はハンドシェイクを行わずに切断する
+        if let Some(remaining) = lockouts
+            .socks_ips
+            .locked_for(&client_addr.ip(), Utc::now().timestamp())
+        {
+            debug!(
+                "[Control] Rejected SOCKS5 connection from banned client {} ({}s remaining)",
+                client_addr, remaining
+            );
+            continue;
+        }
         let agents_clone = agents.clone();
         let pending_clone = pending.clone();
+        let sessions_clone = sessions.clone();
+        let hasher_clone = token_hasher.clone();
+        let lockouts_clone = lockouts.clone();
+        let health_clone = agent_health.clone();
+        let events_clone = events.clone();
         let settings_clone = settings.clone();
         let pool_clone = pool.clone();
         // 新しい接続ごとに非同期タスクを起動
@@ -563,6 +986,11 @@ pub(crate) async fn run_socks5_server_with_token(
                 client_addr,
                 agents_clone,
                 pending_clone,
+                sessions_clone,
+                hasher_clone,
+                lockouts_clone,
+                health_clone,
+                events_clone,
                 settings_clone,
             )
             .await;
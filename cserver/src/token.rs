@@ -1,85 +1,372 @@
+use crate::api::auth::AuthUser;
 use crate::api::dto::ErrorResponse;
-use crate::{api::Claims, repository::create_token, AppState};
+use crate::api::err;
+use crate::repository::ApiKeyScope;
+use crate::repository::{
+    create_token, delete_token, delete_user_tokens, find_user_token_id, get_plaintext_tokens,
+    rotate_token, set_token_hash, TokenRecord,
+};
+use crate::session::SessionRegistry;
+use crate::AppState;
+use axum::body::Bytes;
+use axum::extract::Path;
+use axum::response::Response;
 use axum::{extract::State, response::IntoResponse, Json};
-use axum_extra::extract::CookieJar;
 use chrono::Utc;
+use hash::{generate_token_string, visible_prefix, TokenHasher};
 use hyper::StatusCode;
-use jsonwebtoken::{decode, DecodingKey, Validation};
-use serde::Serialize;
+use log::info;
+use restriction::TokenRestrictions;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+// トークンのハッシュ化
+pub(crate) mod hash;
+// トークンの利用制限
+pub(crate) mod restriction;
+
+// トークン生成リクエスト (ボディは省略可能)
+#[derive(Deserialize, Default, ToSchema)]
+#[serde(default)]
+pub(crate) struct TokenRequest {
+    // 省略時は制限なし
+    pub restrictions: TokenRestrictions,
+    // 有効期間 (秒)。省略時は設定ファイルの token_default_ttl_seconds
+    pub ttl_seconds: Option<i64>,
+    // 識別用のラベル (例: "ci-nightly")
+    pub label: Option<String>,
+}
+
+// ラベルの最大文字数
+const MAX_LABEL_LENGTH: usize = 64;
+
 // APIレスポンス用のトークン情報
 #[derive(Serialize, ToSchema)]
 pub(crate) struct TokenResponse {
-    pub token: String,
-    // UNIXタイムスタンプで表現される有効期限
+    pub id: Uuid,
+    // トークン本体 (生成・ローテーション時のみ返却。以降は再表示できない)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    // 識別用のトークン先頭部分
+    pub prefix: String,
+    pub label: Option<String>,
+    // UNIXタイムスタンプで表現される作成日時・有効期限
+    pub created_at: i64,
     pub expires_at: i64,
+    pub restrictions: TokenRestrictions,
+    // これまでの総転送量 (バイト)
+    pub bytes_used: i64,
+    // 現在接続中のSOCKS5セッション数
+    pub active_sessions: usize,
+    // 組織所有のトークンの場合は組織ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization_id: Option<Uuid>,
+}
+
+impl TokenResponse {
+    pub fn from_record(rec: TokenRecord, sessions: &SessionRegistry) -> Self {
+        TokenResponse {
+            active_sessions: sessions.active_sessions(rec.id),
+            id: rec.id,
+            token: None,
+            prefix: rec.token_prefix,
+            label: rec.label,
+            created_at: rec.created_at.timestamp(),
+            expires_at: rec.expires_at.timestamp(),
+            restrictions: rec.restrictions,
+            bytes_used: rec.bytes_used,
+            organization_id: rec.organization_id,
+        }
+    }
+}
+
+// トークン失効結果のレスポンス
+#[derive(Serialize, ToSchema)]
+pub(crate) struct RevokeTokensResponse {
+    // 削除したトークン数
+    pub revoked: usize,
+    // 強制終了したSOCKS5セッション数
+    pub terminated_sessions: usize,
 }
 
 // 新しいトークンを生成し、ストアに登録するAPIハンドラ
 #[utoipa::path(
     post,
     path = "/api/token",
+    request_body(content = Option<TokenRequest>, content_type = "application/json"),
     responses(
         (status = 201, description = "Token generated", body = TokenResponse),
+        (status = 400, description = "Invalid restrictions, TTL or label", body = ErrorResponse),
         (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the tokens:manage scope", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     tag = "Auth"
 )]
 pub(crate) async fn generate_token(
     State(state): State<AppState>,
-    jar: CookieJar,
+    auth: AuthUser,
+    body: Bytes,
 ) -> impl IntoResponse {
-    // 認証済みユーザのJWTをCookieから取得
-    let cookie = match jar.get("session") {
-        Some(c) => c.value().to_string(),
-        None => {
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(json!({"error":"Unauthenticated"})),
-            )
-                .into_response()
+    if let Err(resp) = auth.require_scope(ApiKeyScope::TokensManage) {
+        return resp;
+    }
+    issue_token(&state, auth.user_id, None, &body).await
+}
+
+// リクエストボディに従ってトークンを発行する (組織IDを指定した場合は組織所有のトークン)
+pub(crate) async fn issue_token(
+    state: &AppState,
+    user_id: Uuid,
+    organization_id: Option<Uuid>,
+    body: &Bytes,
+) -> Response {
+    // リクエストボディ (空の場合は制限なし)
+    let req: TokenRequest = if body.is_empty() {
+        TokenRequest::default()
+    } else {
+        match serde_json::from_slice(body) {
+            Ok(req) => req,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({"error": e.to_string()})),
+                )
+                    .into_response()
+            }
         }
     };
-    // JWT検証
-    let token_data = match decode::<Claims>(
-        &cookie,
-        &DecodingKey::from_secret(state.jwt_secret.as_ref()),
-        &Validation::default(),
-    ) {
-        Ok(data) => data.claims,
-        Err(_) => {
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(json!({"error":"Invalid token"})),
-            )
-                .into_response()
-        }
+    if let Err(msg) = req.restrictions.validate() {
+        return err(StatusCode::BAD_REQUEST, &msg);
+    }
+    // 有効期間は管理者が設定した範囲内のみ許可
+    let settings = &state.settings;
+    let ttl = req
+        .ttl_seconds
+        .unwrap_or(settings.token_default_ttl_seconds);
+    if !(settings.token_min_ttl_seconds..=settings.token_max_ttl_seconds).contains(&ttl) {
+        return err(
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "ttl_seconds must be between {} and {}",
+                settings.token_min_ttl_seconds, settings.token_max_ttl_seconds
+            ),
+        );
+    }
+    let label = req
+        .label
+        .as_deref()
+        .map(str::trim)
+        .filter(|l| !l.is_empty());
+    if label.is_some_and(|l| l.chars().count() > MAX_LABEL_LENGTH) {
+        return err(
+            StatusCode::BAD_REQUEST,
+            &format!("label must be at most {} characters", MAX_LABEL_LENGTH),
+        );
+    }
+    let now = Utc::now();
+    // 有効期限 (極端に大きい有効期間は日時の範囲を超えるため拒否)
+    let Some(expires_at) =
+        chrono::TimeDelta::try_seconds(ttl).and_then(|d| now.checked_add_signed(d))
+    else {
+        return err(StatusCode::BAD_REQUEST, "ttl_seconds is too large");
     };
-    // UUIDに変換
-    let user_id = Uuid::parse_str(&token_data.sub).unwrap();
     // トークン文字列を新規生成
-    let new_token = Uuid::now_v7().to_string();
-    let now = Utc::now();
-    let expires = now + chrono::Duration::hours(24);
-    // DBに保存
-    if let Err(e) = create_token(&state.db_pool, &new_token, user_id, expires, now).await {
+    let new_token = generate_token_string();
+    let rec = TokenRecord {
+        id: Uuid::now_v7(),
+        token_prefix: visible_prefix(&new_token),
+        user_id,
+        expires_at,
+        created_at: now,
+        restrictions: req.restrictions,
+        bytes_used: 0,
+        label: label.map(str::to_string),
+        organization_id,
+    };
+    // DBにはハッシュのみ保存
+    let token_hash = state.token_hasher.hash(&new_token);
+    if let Err(e) = create_token(&state.db_pool, &rec, &token_hash).await {
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(json!({"error": e.to_string()})),
         )
             .into_response();
     }
-    // レスポンス
-    (
-        StatusCode::CREATED,
-        Json(TokenResponse {
-            token: new_token.clone(),
-            expires_at: expires.timestamp(),
-        }),
+    // レスポンス (トークン本体を返すのはこの一度のみ)
+    let resp = TokenResponse {
+        token: Some(new_token),
+        ..TokenResponse::from_record(rec, &state.sessions)
+    };
+    (StatusCode::CREATED, Json(resp)).into_response()
+}
+
+// パスで指定されたトークン (IDまたはトークン本体) を、指定ユーザの所有するトークンIDに解決する
+async fn resolve_user_token_id(
+    state: &AppState,
+    user_id: Uuid,
+    id_or_token: &str,
+) -> sqlx::Result<Option<Uuid>> {
+    let token_hash = state.token_hasher.hash(id_or_token);
+    find_user_token_id(&state.db_pool, user_id, id_or_token, &token_hash).await
+}
+
+// 自分のトークンを1件失効させるAPIハンドラ
+// 失効したトークンを使用中のSOCKS5セッションも強制終了する
+#[utoipa::path(
+    delete,
+    path = "/api/me/tokens/{token_id}",
+    params(("token_id" = String, Path, description = "Token ID (or the token itself)")),
+    responses(
+        (status = 200, description = "Token revoked", body = RevokeTokensResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the tokens:manage scope", body = ErrorResponse),
+        (status = 404, description = "Token not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn revoke_token(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(token_id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::TokensManage) {
+        return resp;
+    }
+    let user_id = auth.user_id;
+    let id = match resolve_user_token_id(&state, user_id, &token_id).await {
+        Ok(Some(id)) => id,
+        Ok(None) => return err(StatusCode::NOT_FOUND, "Token not found"),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    match delete_token(&state.db_pool, id).await {
+        Ok(0) => err(StatusCode::NOT_FOUND, "Token not found"),
+        Ok(_) => {
+            let terminated_sessions = state.sessions.revoke(id);
+            info!(
+                "User {} revoked token {} ({} sessions terminated)",
+                user_id, id, terminated_sessions
+            );
+            (
+                StatusCode::OK,
+                Json(RevokeTokensResponse {
+                    revoked: 1,
+                    terminated_sessions,
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 自分の全トークンを失効させるAPIハンドラ
+#[utoipa::path(
+    delete,
+    path = "/api/me/tokens",
+    responses(
+        (status = 200, description = "All tokens revoked", body = RevokeTokensResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the tokens:manage scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn revoke_all_tokens(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::TokensManage) {
+        return resp;
+    }
+    let user_id = auth.user_id;
+    match delete_user_tokens(&state.db_pool, user_id).await {
+        Ok(ids) => {
+            let terminated_sessions = ids.iter().map(|id| state.sessions.revoke(*id)).sum();
+            info!(
+                "User {} revoked all {} tokens ({} sessions terminated)",
+                user_id,
+                ids.len(),
+                terminated_sessions
+            );
+            (
+                StatusCode::OK,
+                Json(RevokeTokensResponse {
+                    revoked: ids.len(),
+                    terminated_sessions,
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// トークンをローテーションするAPIハンドラ
+// 新しいトークンを発行し、制限・ラベル・有効期限は引き継ぐ。旧トークンは即座に無効になる
+#[utoipa::path(
+    post,
+    path = "/api/me/tokens/{token_id}/rotate",
+    params(("token_id" = String, Path, description = "Token ID (or the token itself)")),
+    responses(
+        (status = 200, description = "Token rotated", body = TokenResponse),
+        (status = 401, description = "Unauthenticated", body = ErrorResponse),
+        (status = 403, description = "API key lacks the tokens:manage scope", body = ErrorResponse),
+        (status = 404, description = "Token not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+pub(crate) async fn rotate_user_token(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(token_id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(resp) = auth.require_scope(ApiKeyScope::TokensManage) {
+        return resp;
+    }
+    let user_id = auth.user_id;
+    let old_id = match resolve_user_token_id(&state, user_id, &token_id).await {
+        Ok(Some(id)) => id,
+        Ok(None) => return err(StatusCode::NOT_FOUND, "Token not found"),
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let new_token = generate_token_string();
+    let token_hash = state.token_hasher.hash(&new_token);
+    match rotate_token(
+        &state.db_pool,
+        old_id,
+        Uuid::now_v7(),
+        &token_hash,
+        &visible_prefix(&new_token),
     )
-        .into_response()
+    .await
+    {
+        Ok(Some(rec)) => {
+            // 旧トークンを使用中のセッションを終了
+            state.sessions.revoke(old_id);
+            let resp = TokenResponse {
+                token: Some(new_token),
+                ..TokenResponse::from_record(rec, &state.sessions)
+            };
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Ok(None) => err(StatusCode::NOT_FOUND, "Token not found"),
+        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// 平文のまま保存されている旧形式トークンをハッシュ化する (起動時に実行)
+pub(crate) async fn migrate_plaintext_tokens(
+    pool: &sqlx::PgPool,
+    hasher: &TokenHasher,
+) -> sqlx::Result<usize> {
+    let tokens = get_plaintext_tokens(pool).await?;
+    for (id, token) in &tokens {
+        set_token_hash(pool, *id, &hasher.hash(token)).await?;
+    }
+    Ok(tokens.len())
 }
@@ -0,0 +1,100 @@
+use hmac::{Hmac, Mac};
+use rand::distr::{Alphanumeric, SampleString};
+use sha2::Sha256;
+use std::sync::Arc;
+
+// 新形式トークンの接頭辞
+const TOKEN_PREFIX: &str = "chl_";
+// 接頭辞を除いたランダム部分の長さ
+const TOKEN_RANDOM_LENGTH: usize = 32;
+// 識別用に保存・表示するトークン先頭の文字数
+const VISIBLE_PREFIX_LENGTH: usize = 12;
+
+// トークンの鍵付きハッシュ (HMAC-SHA256) を計算する
+// DB漏洩時にトークンを復元できないよう、平文の代わりにこのハッシュを保存する
+#[derive(Clone)]
+pub(crate) struct TokenHasher {
+    key: Arc<Vec<u8>>,
+}
+
+impl TokenHasher {
+    pub fn new(key: &str) -> Self {
+        TokenHasher {
+            key: Arc::new(key.as_bytes().to_vec()),
+        }
+    }
+
+    // トークンのハッシュを16進数文字列で返す
+    pub fn hash(&self, token: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(token.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+// 新しいトークン文字列を生成する (例: "chl_" + 英数字32文字)
+pub(crate) fn generate_token_string() -> String {
+    generate_secret(TOKEN_PREFIX)
+}
+
+// 指定した接頭辞 + 英数字32文字のランダムな秘密文字列を生成する
+pub(crate) fn generate_secret(prefix: &str) -> String {
+    format!(
+        "{}{}",
+        prefix,
+        Alphanumeric.sample_string(&mut rand::rng(), TOKEN_RANDOM_LENGTH)
+    )
+}
+
+// 識別用に表示するトークンの先頭部分
+pub(crate) fn visible_prefix(token: &str) -> String {
+    token.chars().take(VISIBLE_PREFIX_LENGTH).collect()
+}
+
+// ログ出力用に秘密文字列を伏せる
+// 既知の接頭辞を持つ場合は識別用の先頭部分のみ残し、それ以外は長さのみ示す
+pub(crate) fn redact_secret(secret: &str) -> String {
+    let known = ["chl_", "chk_", "chr_", "chp_", "chi_"]
+        .iter()
+        .any(|prefix| secret.starts_with(prefix));
+    if known && secret.chars().count() > VISIBLE_PREFIX_LENGTH {
+        format!("{}…", visible_prefix(secret))
+    } else {
+        format!("<redacted {} chars>", secret.chars().count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_is_deterministic_and_keyed() {
+        let a = TokenHasher::new("key-a");
+        let b = TokenHasher::new("key-b");
+        assert_eq!(a.hash("chl_token"), a.hash("chl_token"));
+        assert_ne!(a.hash("chl_token"), a.hash("chl_other"));
+        assert_ne!(a.hash("chl_token"), b.hash("chl_token"));
+        assert_eq!(a.hash("chl_token").len(), 64);
+    }
+
+    #[test]
+    fn test_generate_token_string() {
+        let token = generate_token_string();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + TOKEN_RANDOM_LENGTH);
+        assert_ne!(token, generate_token_string());
+        assert_eq!(visible_prefix(&token), token[..VISIBLE_PREFIX_LENGTH]);
+        assert_eq!(
+            redact_secret(&token),
+            format!("{}…", visible_prefix(&token))
+        );
+        assert_eq!(redact_secret("hunter2"), "<redacted 7 chars>");
+        assert_eq!(redact_secret("chl_short"), "<redacted 9 chars>");
+    }
+}
@@ -0,0 +1,205 @@
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+// トークンに付与する利用制限
+// 各フィールドは None の場合に「制限なし」を意味する
+#[derive(Debug, Clone, Default, PartialEq, Eq, FromRow, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct TokenRestrictions {
+    // 利用可能なエージェントの国コード (例: ["JP", "US"])
+    pub allowed_countries: Option<Vec<String>>,
+    // 利用可能なエージェントID
+    pub allowed_agents: Option<Vec<String>>,
+    // 接続先として許可するポート
+    pub allowed_ports: Option<Vec<i32>>,
+    // 接続先として許可するドメイン ("*.example.com" はサブドメインに一致)
+    pub allowed_domains: Option<Vec<String>>,
+    // 接続元クライアントとして許可するCIDR (例: "203.0.113.0/24")
+    pub allowed_cidrs: Option<Vec<String>>,
+    // 総転送量の上限 (バイト)
+    pub max_bytes: Option<i64>,
+    // 同時セッション数の上限
+    pub max_sessions: Option<i32>,
+}
+
+impl TokenRestrictions {
+    // 入力値の検証 (API でトークン作成時に使用)
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(countries) = &self.allowed_countries {
+            if let Some(c) = countries
+                .iter()
+                .find(|c| c.len() != 2 || !c.chars().all(|ch| ch.is_ascii_alphabetic()))
+            {
+                return Err(format!("Invalid country code: {}", c));
+            }
+        }
+        if let Some(agents) = &self.allowed_agents {
+            if let Some(a) = agents.iter().find(|a| !a.starts_with("agent_")) {
+                return Err(format!("Invalid agent ID: {}", a));
+            }
+        }
+        if let Some(ports) = &self.allowed_ports {
+            if let Some(p) = ports.iter().find(|p| !(1..=65535).contains(*p)) {
+                return Err(format!("Invalid port: {}", p));
+            }
+        }
+        if let Some(domains) = &self.allowed_domains {
+            if domains
+                .iter()
+                .any(|d| d.trim_start_matches("*.").is_empty())
+            {
+                return Err("Domain must not be empty".to_string());
+            }
+        }
+        if let Some(cidrs) = &self.allowed_cidrs {
+            for cidr in cidrs {
+                parse_cidr(cidr).ok_or_else(|| format!("Invalid CIDR: {}", cidr))?;
+            }
+        }
+        if matches!(self.max_bytes, Some(b) if b <= 0) {
+            return Err("max_bytes must be positive".to_string());
+        }
+        if matches!(self.max_sessions, Some(s) if s <= 0) {
+            return Err("max_sessions must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    // 接続元クライアントのIPアドレスが許可されているか
+    pub fn allows_client(&self, ip: IpAddr) -> bool {
+        match &self.allowed_cidrs {
+            Some(cidrs) => cidrs
+                .iter()
+                .filter_map(|c| parse_cidr(c))
+                .any(|net| net.contains(&ip)),
+            None => true,
+        }
+    }
+
+    // エージェントが利用可能か (エージェントIDと国コードで判定)
+    pub fn allows_agent(&self, agent_id: &str, country_code: &str) -> bool {
+        let agent_ok = match &self.allowed_agents {
+            Some(agents) => agents.iter().any(|a| a == agent_id),
+            None => true,
+        };
+        let country_ok = match &self.allowed_countries {
+            Some(countries) => countries
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(country_code)),
+            None => true,
+        };
+        agent_ok && country_ok
+    }
+
+    // 接続先 (アドレス/ポート) が許可されているか
+    pub fn allows_target(&self, target_addr: &str, target_port: u16) -> bool {
+        let port_ok = match &self.allowed_ports {
+            Some(ports) => ports.contains(&(target_port as i32)),
+            None => true,
+        };
+        let domain_ok = match &self.allowed_domains {
+            Some(domains) => domains.iter().any(|d| domain_matches(d, target_addr)),
+            None => true,
+        };
+        port_ok && domain_ok
+    }
+}
+
+// "203.0.113.0/24" 形式、またはプレフィックスなしの単一IPアドレスをパース
+fn parse_cidr(s: &str) -> Option<IpNet> {
+    s.parse::<IpNet>()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+// パターン "*.example.com" はサブドメインのみ、それ以外は完全一致 (大文字小文字を区別しない)
+fn domain_matches(pattern: &str, host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host
+            .strip_suffix(suffix)
+            .is_some_and(|rest| rest.ends_with('.')),
+        None => host == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unrestricted_allows_everything() {
+        let r = TokenRestrictions::default();
+        assert!(r.validate().is_ok());
+        assert!(r.allows_client("198.51.100.7".parse().unwrap()));
+        assert!(r.allows_agent("agent_abc", "JP"));
+        assert!(r.allows_target("example.com", 443));
+    }
+
+    #[test]
+    fn test_allows_client_cidr() {
+        let r = TokenRestrictions {
+            allowed_cidrs: Some(vec!["203.0.113.0/24".into(), "2001:db8::1".into()]),
+            ..Default::default()
+        };
+        assert!(r.allows_client("203.0.113.42".parse().unwrap()));
+        assert!(r.allows_client("2001:db8::1".parse().unwrap()));
+        assert!(!r.allows_client("203.0.114.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_allows_agent_by_country_and_id() {
+        let r = TokenRestrictions {
+            allowed_countries: Some(vec!["jp".into()]),
+            allowed_agents: Some(vec!["agent_a".into(), "agent_b".into()]),
+            ..Default::default()
+        };
+        assert!(r.allows_agent("agent_a", "JP"));
+        assert!(!r.allows_agent("agent_a", "US"));
+        assert!(!r.allows_agent("agent_c", "JP"));
+    }
+
+    #[test]
+    fn test_allows_target_port_and_domain() {
+        let r = TokenRestrictions {
+            allowed_ports: Some(vec![443]),
+            allowed_domains: Some(vec!["example.com".into(), "*.example.org".into()]),
+            ..Default::default()
+        };
+        assert!(r.allows_target("example.com", 443));
+        assert!(r.allows_target("API.example.org", 443));
+        assert!(!r.allows_target("example.org", 443));
+        assert!(!r.allows_target("badexample.org", 443));
+        assert!(!r.allows_target("example.com", 80));
+        assert!(!r.allows_target("93.184.216.34", 443));
+    }
+
+    #[test]
+    fn test_validate_rejects_invalid_values() {
+        let bad = [
+            TokenRestrictions {
+                allowed_countries: Some(vec!["JPN".into()]),
+                ..Default::default()
+            },
+            TokenRestrictions {
+                allowed_ports: Some(vec![0]),
+                ..Default::default()
+            },
+            TokenRestrictions {
+                allowed_cidrs: Some(vec!["10.0.0.0/33".into()]),
+                ..Default::default()
+            },
+            TokenRestrictions {
+                max_sessions: Some(0),
+                ..Default::default()
+            },
+        ];
+        for r in bad {
+            assert!(r.validate().is_err(), "{:?}", r);
+        }
+    }
+}
@@ -1,14 +1,25 @@
-use crate::agent::{AgentConnection, AgentMap, AgentMetadata};
+use crate::agent::{AgentCapacity, AgentConnection, AgentMap, AgentMetadata, LoadReport};
+use crate::events::{Event, EventBus};
+use crate::geoip::{GeoIp, GeoMismatch};
+use crate::repository::{
+    get_agent, record_agent_connected, record_agent_disconnected, update_agent_metadata,
+    AgentRecord,
+};
 use crate::{CommandResponseMap, PendingMap, WsSink, WsStream};
 use crate::{PendingSender, Settings};
 use anyhow::{anyhow, Result};
+use chrono::Utc;
 use common::Payload;
 use futures::{SinkExt, StreamExt};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::net::IpAddr;
 use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
 use tokio_tungstenite::{accept_async, tungstenite::protocol::Message};
+use uuid::Uuid;
 
 // WebSocket経由でペイロードを送信するヘルパー関数
 // JSONシリアライズ → Textメッセージとして送信
@@ -19,13 +30,37 @@ pub(crate) async fn send_message(sink: &Mutex<WsSink>, payload: Payload) -> Resu
     Ok(())
 }
 
+// 登録を拒否したエージェントに理由を含む InitResponse を送信する
+async fn reject_init(
+    sink: WsSink,
+    metadata: AgentMetadata,
+    capacity: AgentCapacity,
+    message: &str,
+) -> Result<()> {
+    let temp_conn = AgentConnection::new(sink, metadata, capacity, false, Vec::new());
+    send_message(
+        &temp_conn.sink,
+        Payload::InitResponse {
+            success: false,
+            message: Some(message.to_string()),
+        },
+    )
+    .await
+}
+
 // エージェントからの初期化リクエストを処理し、AgentMap に登録して InitResponse を送信
+// 登録した接続を返す (record は過去に接続したことがある場合の永続化された状態)
+#[allow(clippy::too_many_arguments)]
 async fn handle_init_request(
     agent_id: String,
     sink: WsSink,
     agents: Arc<AgentMap>,
     metadata: AgentMetadata,
-) -> Result<()> {
+    capacity: AgentCapacity,
+    record: Option<AgentRecord>,
+    enrollment_key: Option<&str>,
+    settings: &Settings,
+) -> Result<Arc<AgentConnection>> {
     info!(
         "[Init] Received init-request from agent. Agent ID: {} | Metadata: {:?}",
         agent_id, metadata
@@ -33,25 +68,50 @@ async fn handle_init_request(
 
     // エージェントIDのフォーマットチェック
     if !agent_id.starts_with("agent_") {
-        let temp_conn = Arc::new(AgentConnection {
-            sink: Mutex::new(sink),
+        reject_init(
+            sink,
             metadata,
-        });
-        send_message(
-            &temp_conn.sink,
-            Payload::InitResponse {
-                success: false,
-                message: Some("Agent ID must start with 'agent_'".to_string()),
-            },
+            capacity,
+            "Agent ID must start with 'agent_'",
         )
         .await?;
         return Err(anyhow!("Invalid agent ID format: {}", agent_id));
     }
+    // 登録キーが設定されている場合、一致しないエージェントは登録しない
+    if let Some(expected) = settings.agent_enrollment_key.as_deref() {
+        if !enrollment_key_matches(expected, enrollment_key) {
+            reject_init(sink, metadata, capacity, "Invalid enrollment key").await?;
+            return Err(anyhow!(
+                "Rejected agent with invalid enrollment key: {}",
+                agent_id
+            ));
+        }
+    }
+    // BANされたエージェントは登録しない
+    if record.as_ref().is_some_and(|r| r.banned_at.is_some()) {
+        reject_init(sink, metadata, capacity, "Agent is banned").await?;
+        return Err(anyhow!("Rejected banned agent: {}", agent_id));
+    }
+    // 申告された国コードがGeoIPの検証結果と一致しないエージェントは登録しない (設定時のみ)
+    if let Some(geo) = metadata.geo.as_ref().filter(|g| {
+        settings.geoip.reject_country_mismatch && g.mismatches.contains(&GeoMismatch::CountryCode)
+    }) {
+        let message = format!(
+            "Reported country {} does not match {} for {}",
+            metadata.country_code, geo.location.country_code, geo.ip
+        );
+        reject_init(sink, metadata, capacity, &message).await?;
+        return Err(anyhow!("Rejected agent {}: {}", agent_id, message));
+    }
 
-    let agent_conn = Arc::new(AgentConnection {
-        sink: Mutex::new(sink),
-        metadata,
-    });
+    // 無効化の状態とラベルは前回から引き継ぐ
+    let (disabled, labels) = match record {
+        Some(rec) => (rec.disabled_at.is_some(), rec.labels),
+        None => (false, Vec::new()),
+    };
+    let agent_conn = Arc::new(AgentConnection::new(
+        sink, metadata, capacity, disabled, labels,
+    ));
     agents.insert(agent_id.clone(), agent_conn.clone());
     // 初期化レスポンス送信
     send_message(
@@ -66,7 +126,13 @@ async fn handle_init_request(
         "[Init] Agent registered successfully. Agent ID: {}",
         agent_id
     );
-    Ok(())
+    Ok(agent_conn)
+}
+
+// 登録キーの照合 (比較時間から一致した長さが推測されないようハッシュ値を比較する)
+fn enrollment_key_matches(expected: &str, provided: Option<&str>) -> bool {
+    let provided = provided.unwrap_or_default();
+    !provided.is_empty() && Sha256::digest(expected) == Sha256::digest(provided)
 }
 
 // エージェントから受信した ConnectResponse を、PendingMap 経由で送信元に通知
@@ -167,15 +233,84 @@ async fn handle_command_response(
     Ok(())
 }
 
+// 接続中のエージェントから受信したメタデータ (出口IPの再検出結果) で AgentMetadata を置き換える
+// GeoIPで再検証し、出口IPが変わった場合は変更履歴に記録してイベントを配信する
+#[allow(clippy::too_many_arguments)]
+async fn handle_metadata_update(
+    pool: &PgPool,
+    agent_id: &str,
+    connection_id: Uuid,
+    agent_conn: &AgentConnection,
+    metadata: AgentMetadata,
+    peer: Option<IpAddr>,
+    geoip: &GeoIp,
+    events: &EventBus,
+    reject_country_mismatch: bool,
+) {
+    let mut metadata = AgentMetadata {
+        geo: geoip.verify(peer, &metadata),
+        ..metadata
+    };
+    if let Some(geo) = metadata.geo.clone() {
+        geo.fill_missing(&mut metadata);
+        if !geo.mismatches.is_empty() {
+            warn!(
+                "[{}] Updated location does not match GeoIP for {} ({:?}): {:?}",
+                agent_id, geo.ip, geo.source, geo.mismatches
+            );
+        }
+        // 初期化時と同様に、国コードが一致しないエージェントは切断する (設定時のみ)
+        if reject_country_mismatch && geo.mismatches.contains(&GeoMismatch::CountryCode) {
+            warn!(
+                "[{}] Disconnecting agent: reported country {} does not match {}",
+                agent_id, metadata.country_code, geo.location.country_code
+            );
+            agent_conn.disconnect();
+            return;
+        }
+    }
+    let previous = agent_conn.set_metadata(metadata.clone());
+    if *previous == metadata {
+        debug!("[{}] Metadata unchanged", agent_id);
+        return;
+    }
+    if previous.ip != metadata.ip {
+        info!(
+            "[{}] Exit IP changed: {} ({}) -> {} ({})",
+            agent_id,
+            previous.ip,
+            previous.verified_country_code(),
+            metadata.ip,
+            metadata.verified_country_code()
+        );
+        events.publish(Event::AgentIpChanged {
+            agent_id: agent_id.to_string(),
+            old_ip: previous.ip.clone(),
+            new_ip: metadata.ip.clone(),
+            country_code: metadata.verified_country_code().to_string(),
+        });
+    }
+    if let Err(e) =
+        update_agent_metadata(pool, agent_id, connection_id, &metadata, Utc::now()).await
+    {
+        error!("[{}] Failed to record metadata update: {}", agent_id, e);
+    }
+}
+
 // 各エージェントとの WebSocket 接続のイベントループ
+#[allow(clippy::too_many_arguments)]
 async fn handle_agent_connection(
+    pool: PgPool,
     stream: TcpStream,
     agents: Arc<AgentMap>,
     pending: PendingMap,
     command_responses: CommandResponseMap,
+    events: EventBus,
+    geoip: Arc<GeoIp>,
+    settings: Arc<Settings>,
 ) {
     // ハンドシェイク実施
-    // let peer_addr = stream.peer_addr().ok();
+    let peer_addr = stream.peer_addr().ok();
     if let Err(e) = async {
         let ws_stream = accept_async(stream).await.map_err(|e| {
             error!("[Control] WebSocket handshake failed: {:?}", e);
@@ -190,9 +325,13 @@ async fn handle_agent_connection(
         }
         let init_msg = init_msg.unwrap();
         let init_payload: Payload = match init_msg {
-            Ok(Message::Text(text)) => serde_json::from_str(&text).map_err(|e| {
-                error!("Failed to parse JSON from agent: {}", text);
-                e
+            // 本文は登録キーを含むためログに出力しない
+            Ok(Message::Text(text)) => serde_json::from_str(&text).inspect_err(|e| {
+                error!(
+                    "Failed to parse JSON from agent ({} bytes): {}",
+                    text.len(),
+                    e
+                );
             })?,
             _ => {
                 error!("Expected init-request but got non-text message");
@@ -214,6 +353,9 @@ async fn handle_agent_connection(
             hostname,
             kernel_version,
             username,
+            max_tunnels,
+            max_bytes_per_second,
+            enrollment_key,
         ) = if let Payload::InitRequest {
             agent_id,
             ip,
@@ -228,6 +370,9 @@ async fn handle_agent_connection(
             hostname,
             kernel_version,
             username,
+            max_tunnels,
+            max_bytes_per_second,
+            enrollment_key,
         } = init_payload
         {
             (
@@ -244,16 +389,15 @@ async fn handle_agent_connection(
                 hostname,
                 kernel_version,
                 username,
+                max_tunnels,
+                max_bytes_per_second,
+                enrollment_key,
             )
         } else {
             error!("Expected init-request but got: {:?}", init_payload);
             return Ok(());
         };
 
-        info!(
-            "[Init] Received init-request from agent. Agent ID: {}",
-            agent_id
-        );
         // エージェント登録＆初期化レスポンス送信
         let metadata = AgentMetadata {
             ip,
@@ -268,17 +412,91 @@ async fn handle_agent_connection(
             hostname,
             kernel_version,
             username,
+            geo: None,
         };
-        handle_init_request(agent_id.clone(), sink, agents.clone(), metadata).await?;
+        // 接続元アドレスと申告されたIPをGeoIPデータベースで検証する
+        let mut metadata = AgentMetadata {
+            geo: geoip.verify(peer_addr.map(|a| a.ip()), &metadata),
+            ..metadata
+        };
+        // エージェントが地理情報を取得できなかった場合は検証結果で補完する
+        if let Some(geo) = metadata.geo.clone() {
+            if geo.fill_missing(&mut metadata) {
+                info!(
+                    "[{}] Filled in missing geo data from GeoIP for {}",
+                    agent_id, geo.ip
+                );
+            }
+        }
+        if let Some(geo) = metadata.geo.as_ref().filter(|g| !g.mismatches.is_empty()) {
+            warn!(
+                "[{}] Reported location does not match GeoIP for {} ({:?}): {:?}",
+                agent_id, geo.ip, geo.source, geo.mismatches
+            );
+        }
+        let capacity = AgentCapacity {
+            max_tunnels,
+            max_bytes_per_second,
+        };
+        let record = get_agent(&pool, &agent_id).await?;
+        let agent_conn = handle_init_request(
+            agent_id.clone(),
+            sink,
+            agents.clone(),
+            metadata,
+            capacity,
+            record,
+            enrollment_key.as_deref(),
+            &settings,
+        )
+        .await?;
+        // 接続をDBに記録 (失敗してもエージェントは利用可能とする)
+        let connection_id = Uuid::now_v7();
+        if let Err(e) = record_agent_connected(
+            &pool,
+            &agent_id,
+            connection_id,
+            peer_addr.map(|a| a.to_string()).as_deref(),
+            &agent_conn.metadata(),
+            Utc::now(),
+        )
+        .await
+        {
+            error!("[{}] Failed to record agent connection: {}", agent_id, e);
+        }
+        events.publish(Event::AgentConnected {
+            agent_id: agent_id.clone(),
+            country_code: agent_conn.metadata().verified_country_code().to_string(),
+        });
 
         // その後のメッセージを処理するループ
-        while let Some(message) = stream.next().await {
+        loop {
+            // 管理者による切断・ドレイン完了の通知があれば接続を閉じる
+            let message = tokio::select! {
+                message = stream.next() => message,
+                _ = agent_conn.disconnected() => {
+                    info!("[{}] Disconnecting agent", agent_id);
+                    if let Err(e) = agent_conn.sink.lock().await.close().await {
+                        debug!("[{}] Failed to close WebSocket: {:?}", agent_id, e);
+                    }
+                    break;
+                }
+            };
+            let Some(message) = message else {
+                break;
+            };
+            agent_conn.touch(Utc::now());
             match message {
                 Ok(Message::Text(text)) => {
                     let payload: Payload = match serde_json::from_str(&text) {
                         Ok(p) => p,
                         Err(e) => {
-                            error!("Failed to parse JSON from agent: {}, {:?}", text, e);
+                            // 本文は転送データを含むためログに出力しない
+                            error!(
+                                "Failed to parse JSON from agent ({} bytes): {}",
+                                text.len(),
+                                e
+                            );
                             continue;
                         }
                     };
@@ -305,6 +523,60 @@ async fn handle_agent_connection(
                                 error!("Error handling command-response: {:?}", e);
                             }
                         }
+                        // 出口IPの再検出によるメタデータの更新
+                        Payload::MetadataUpdate {
+                            ip,
+                            remote_host,
+                            country_code,
+                            city,
+                            region,
+                            asn,
+                            asn_org,
+                        } => {
+                            let current = agent_conn.metadata();
+                            let metadata = AgentMetadata {
+                                ip,
+                                remote_host,
+                                country_code,
+                                city,
+                                region,
+                                asn,
+                                asn_org,
+                                ..(*current).clone()
+                            };
+                            handle_metadata_update(
+                                &pool,
+                                &agent_id,
+                                connection_id,
+                                &agent_conn,
+                                metadata,
+                                peer_addr.map(|a| a.ip()),
+                                &geoip,
+                                &events,
+                                settings.geoip.reject_country_mismatch,
+                            )
+                            .await;
+                        }
+                        // 負荷レポートの記録 (エージェントの選択と容量の判定に使用)
+                        Payload::LoadReport {
+                            cpu_percent,
+                            memory_used_bytes,
+                            memory_total_bytes,
+                            rx_bytes_per_second,
+                            tx_bytes_per_second,
+                        } => {
+                            debug!("[{}] Received load-report", agent_id);
+                            agent_conn.record_load(
+                                LoadReport {
+                                    cpu_percent,
+                                    memory_used_bytes,
+                                    memory_total_bytes,
+                                    rx_bytes_per_second,
+                                    tx_bytes_per_second,
+                                },
+                                Utc::now(),
+                            );
+                        }
                         _ => {
                             debug!("Received unexpected message from agent: {:?}", payload);
                         }
@@ -321,7 +593,19 @@ async fn handle_agent_connection(
         }
         info!("[{}] Connection closed", agent_id);
         // エージェント切断時は AgentMap から削除
-        agents.remove(&agent_id);
+        // 同じIDで再接続済みの場合は新しい接続を残す
+        if agents
+            .remove_if(&agent_id, |_, conn| Arc::ptr_eq(conn, &agent_conn))
+            .is_some()
+        {
+            events.publish(Event::AgentDisconnected {
+                agent_id: agent_id.clone(),
+            });
+        }
+        if let Err(e) = record_agent_disconnected(&pool, &agent_id, connection_id, Utc::now()).await
+        {
+            error!("[{}] Failed to record agent disconnection: {}", agent_id, e);
+        }
         Ok::<(), anyhow::Error>(())
     }
     .await
@@ -332,9 +616,12 @@ async fn handle_agent_connection(
 
 // WebSocket サーバーを起動し、エージェントからの接続を待ち受ける
 pub(crate) async fn run_websocket_server(
+    pool: PgPool,
     agents: Arc<AgentMap>,
     pending: PendingMap,
     command_responses: CommandResponseMap,
+    events: EventBus,
+    geoip: Arc<GeoIp>,
     settings: Arc<Settings>,
 ) -> Result<()> {
     let addr = format!("{}:{}", settings.bind_address, settings.websocket_port);
@@ -343,13 +630,25 @@ pub(crate) async fn run_websocket_server(
     loop {
         let (stream, addr) = listener.accept().await?;
         info!("[Control] New WebSocket connection from {}", addr);
+        let pool_clone = pool.clone();
         let agents_clone = agents.clone();
         let pending_clone = pending.clone();
         let command_responses_clone = command_responses.clone();
-        let _settings_clone = settings.clone(); // 現在未使用だが将来のためにクローン
+        let events_clone = events.clone();
+        let geoip_clone = geoip.clone();
+        let settings_clone = settings.clone();
         tokio::spawn(async move {
-            handle_agent_connection(stream, agents_clone, pending_clone, command_responses_clone)
-                .await;
+            handle_agent_connection(
+                pool_clone,
+                stream,
+                agents_clone,
+                pending_clone,
+                command_responses_clone,
+                events_clone,
+                geoip_clone,
+                settings_clone,
+            )
+            .await;
         });
     }
 }